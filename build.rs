@@ -0,0 +1,405 @@
+//! Generates the pin assignments and hardware defaults in `src/board.rs` from
+//! a board file: `boards/microbit-v2.toml` by default,
+//! `boards/nrf52833-dk.toml` with the `board-nrf52833-dk` feature, or any
+//! file named by `MICRO_COMPASS_BOARD`.
+//!
+//! Only the part of TOML the board files need is understood: `[sections]`,
+//! and `key = value` with strings, numbers, booleans and one-line arrays of
+//! strings.
+//!
+//! Also picks the firmware's defmt log level, which is fixed at compile time:
+//! debug in dev builds, info in release builds, trace in both with the
+//! `verbose-log` feature, or any `DEFMT_LOG` filter in `MICRO_COMPASS_LOG`,
+//! e.g. `warn,micro_compass::sensor=debug`.
+
+use std::{collections::BTreeMap, env, fmt::Write as _, fs, path::PathBuf};
+
+type Table = BTreeMap<String, Value>;
+
+#[derive(Debug)]
+enum Value {
+    String(String),
+    Number(String),
+    Bool,
+    Array(Vec<String>),
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=MICRO_COMPASS_BOARD");
+    let path = match env::var("MICRO_COMPASS_BOARD") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) if env::var_os("CARGO_FEATURE_BOARD_NRF52833_DK").is_some() => {
+            PathBuf::from("boards/nrf52833-dk.toml")
+        }
+        Err(_) => PathBuf::from("boards/microbit-v2.toml"),
+    };
+    println!("cargo:rerun-if-changed={}", path.display());
+    let text = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("reading board file {}: {e}", path.display()));
+    let board = parse(&text).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    let code = generate(&board).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("board.rs");
+    fs::write(out, code).unwrap();
+
+    println!("cargo:rerun-if-env-changed=MICRO_COMPASS_LOG");
+    let level = match env::var("MICRO_COMPASS_LOG") {
+        Ok(filter) => filter,
+        Err(_) if env::var_os("CARGO_FEATURE_VERBOSE_LOG").is_some() => String::from("trace"),
+        Err(_) if env::var("PROFILE").is_ok_and(|profile| profile == "release") => {
+            String::from("info")
+        }
+        Err(_) => String::from("debug"),
+    };
+    // Overrides the dependencies' `DEFMT_LOG` from .cargo/config.toml for
+    // this crate alone
+    println!("cargo:rustc-env=DEFMT_LOG={level}");
+}
+
+/// Sections by name, keys outside any section under ""
+fn parse(text: &str) -> Result<BTreeMap<String, Table>, String> {
+    let mut sections = BTreeMap::<String, Table>::new();
+    let mut section = String::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let at = |e: &str| format!("line {}: {e}", number + 1);
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| at("expected key = value"))?;
+        let value = parse_value(value.trim()).ok_or_else(|| at("unsupported value"))?;
+        sections
+            .entry(section.clone())
+            .or_default()
+            .insert(key.trim().to_string(), value);
+    }
+    Ok(sections)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    if let Some(string) = parse_string(value) {
+        return Some(Value::String(string));
+    }
+    if let Some(items) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        return items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(parse_string)
+            .collect::<Option<_>>()
+            .map(Value::Array);
+    }
+    match value {
+        "true" | "false" => Some(Value::Bool),
+        _ => value
+            .parse::<f64>()
+            .ok()
+            .map(|_| Value::Number(value.to_string())),
+    }
+}
+
+fn parse_string(value: &str) -> Option<String> {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .map(str::to_string)
+}
+
+/// `P0.16` as the peripheral's field name, `P0_16`, checked against the
+/// nRF52833's pins
+fn pin(name: &str) -> Result<String, String> {
+    let (port, number) = port_pin(name)?;
+    Ok(format!("p.P{port}_{number:02}.degrade()"))
+}
+
+/// `P0.16` as its port and its number in the port, `(0, 16)`, checked
+/// against the nRF52833's pins
+fn port_pin(name: &str) -> Result<(u8, u8), String> {
+    let invalid = || format!("invalid pin {name:?}, expected e.g. \"P0.16\"");
+    let (port, number) = name
+        .strip_prefix('P')
+        .and_then(|n| n.split_once('.'))
+        .ok_or_else(invalid)?;
+    let number: u8 = number.parse().map_err(|_| invalid())?;
+    let (port, valid) = match port {
+        "0" => (0, number < 32),
+        "1" => (1, number < 10),
+        _ => (0, false),
+    };
+    if !valid {
+        return Err(invalid());
+    }
+    Ok((port, number))
+}
+
+fn generate(board: &BTreeMap<String, Table>) -> Result<String, String> {
+    let empty = Table::new();
+    let pins = board.get("pins").unwrap_or(&empty);
+    let defaults = board.get("defaults").unwrap_or(&empty);
+
+    let required = |key: &str| match pins.get(key) {
+        Some(Value::String(name)) => pin(name),
+        _ => Err(format!("[pins] needs {key} = \"P<port>.<pin>\"")),
+    };
+    // Pins for features that aren't enabled don't have to be given
+    let for_feature = |key: &str| match pins.get(key) {
+        Some(Value::String(name)) => pin(name),
+        _ => Ok(format!(
+            "compile_error!(\"the board file has no {key} pin in [pins]\")"
+        )),
+    };
+    let optional = |key: &str| match pins.get(key) {
+        Some(Value::String(name)) => pin(name).map(|pin| format!("Some({pin})")),
+        None => Ok("None".to_string()),
+        _ => Err(format!("[pins] {key} must be a pin name")),
+    };
+    let matrix = |key: &str| match pins.get(key) {
+        Some(Value::Array(names)) if names.len() == 5 => names
+            .iter()
+            .map(|name| pin(name))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|pins| format!("[{}]", pins.join(", "))),
+        _ => Err(format!("[pins] needs {key} = [five pin names]")),
+    };
+    // The same pins as `(port, pin)`, for code that drives them directly
+    let matrix_port_pins = |key: &str| match pins.get(key) {
+        Some(Value::Array(names)) if names.len() == 5 => names
+            .iter()
+            .map(|name| port_pin(name).map(|(port, pin)| format!("({port}, {pin})")))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|pins| format!("[{}]", pins.join(", "))),
+        _ => Err(format!("[pins] needs {key} = [five pin names]")),
+    };
+    let number = |key: &str| match defaults.get(key) {
+        Some(Value::Number(number)) if number.contains('.') => Ok(number.clone()),
+        Some(Value::Number(number)) => Ok(format!("{number}.0")),
+        None => Ok("0.0".to_string()),
+        _ => Err(format!("[defaults] {key} must be a number")),
+    };
+    let level = |key: &str, default: &'static str| match pins.get(key) {
+        Some(Value::String(level)) if level == "high" => Ok("High"),
+        Some(Value::String(level)) if level == "low" => Ok("Low"),
+        None => Ok(default),
+        _ => Err(format!("[pins] {key} must be \"high\" or \"low\"")),
+    };
+    // Any size, as long as both sides are given
+    let external_matrix = match (
+        pins.get("external_matrix_rows"),
+        pins.get("external_matrix_cols"),
+    ) {
+        (None, None) => None,
+        (Some(Value::Array(rows)), Some(Value::Array(cols)))
+            if !rows.is_empty() && (1..=16).contains(&cols.len()) =>
+        {
+            let pins = |names: &[String]| {
+                names
+                    .iter()
+                    .map(|name| pin(name))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|pins| pins.join(", "))
+            };
+            Some((rows.len(), cols.len(), pins(rows)?, pins(cols)?))
+        }
+        _ => {
+            return Err(
+                "[pins] external_matrix_rows and external_matrix_cols must both be \
+                 given, as a pin name per row and per column, up to 16 columns"
+                    .to_string(),
+            )
+        }
+    };
+    let button_pull = match pins.get("button_pull") {
+        Some(Value::String(pull)) if pull == "up" => "Up",
+        Some(Value::String(pull)) if pull == "none" => "None",
+        None => "None",
+        _ => return Err("[pins] button_pull must be \"up\" or \"none\"".to_string()),
+    };
+
+    let mut code = String::new();
+    writeln!(code, "// Generated by build.rs from the board file").unwrap();
+    writeln!(code, "pub const BUTTON_PULL: Pull = Pull::{button_pull};").unwrap();
+    writeln!(
+        code,
+        "pub const DEFAULT_DECLINATION: f32 = {};",
+        number("declination")?
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "pub const STROBE_BEARING: f32 = {};",
+        number("strobe_bearing")?
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "pub const BUTTON_A_PIN: (u8, u8) = {:?};",
+        match pins.get("button_a") {
+            Some(Value::String(name)) => port_pin(name)?,
+            _ => return Err("[pins] needs button_a = \"P<port>.<pin>\"".to_string()),
+        }
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "pub const MATRIX_ROW_PINS: [(u8, u8); MATRIX_ROWS] = {};",
+        matrix_port_pins("rows")?
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "pub const MATRIX_COL_PINS: [(u8, u8); MATRIX_COLS] = {};",
+        matrix_port_pins("cols")?
+    )
+    .unwrap();
+    let (external_rows, external_cols) = external_matrix
+        .as_ref()
+        .map_or((0, 0), |&(rows, cols, ..)| (rows, cols));
+    writeln!(
+        code,
+        "pub const EXTERNAL_MATRIX_ROWS: usize = {external_rows};"
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "pub const EXTERNAL_MATRIX_COLS: usize = {external_cols};"
+    )
+    .unwrap();
+    writeln!(
+        code,
+        "pub const EXTERNAL_MATRIX_POLARITY: Polarity = Polarity {{ row_on: Level::{}, col_on: Level::{} }};",
+        level("external_matrix_row_on", "High")?,
+        level("external_matrix_col_on", "Low")?,
+    )
+    .unwrap();
+    write!(
+        code,
+        "
+impl Board {{
+    pub fn new(p: Peripherals) -> Self {{
+        Self {{
+            wdt: p.WDT,
+            matrix: Pins {{ rows: {rows}, cols: {cols} }},
+            matrix_scan: MatrixScan {{
+                timer: p.TIMER3,
+                pwm: p.PWM2,
+                pwm_last: p.PWM3,
+                gpiote: [
+                    p.GPIOTE_CH0.degrade(),
+                    p.GPIOTE_CH1.degrade(),
+                    p.GPIOTE_CH2.degrade(),
+                    p.GPIOTE_CH3.degrade(),
+                    p.GPIOTE_CH4.degrade(),
+                ],
+                ppi: [
+                    p.PPI_CH4.degrade(),
+                    p.PPI_CH5.degrade(),
+                    p.PPI_CH6.degrade(),
+                    p.PPI_CH7.degrade(),
+                    p.PPI_CH8.degrade(),
+                    p.PPI_CH9.degrade(),
+                ],
+            }},
+            external_matrix: {external_matrix},
+            strobe: {strobe},
+            button_a: {button_a},
+            button_b: {button_b},
+            console: Uart {{
+                uarte: p.UARTE0,
+                timer: p.TIMER2,
+                ppi_ch1: p.PPI_CH2,
+                ppi_ch2: p.PPI_CH3,
+                ppi_group: p.PPI_GROUP1,
+                rx: {console_rx},
+                tx: {console_tx},
+            }},
+            touch: {touch},
+            speaker_pwm: p.PWM0,
+            speaker: {speaker},
+            haptic: {haptic},
+            #[cfg(feature = \"gps\")]
+            gps: GpsUart {{
+                uarte: p.UARTE1,
+                timer: p.TIMER1,
+                ppi_ch1: p.PPI_CH0,
+                ppi_ch2: p.PPI_CH1,
+                ppi_group: p.PPI_GROUP0,
+                rx: {gps_rx},
+            }},
+            #[cfg(feature = \"rgb\")]
+            rgb_spi: p.SPI2,
+            #[cfg(feature = \"rgb\")]
+            rgb: {rgb},
+            #[cfg(feature = \"servo\")]
+            servo_pwm: p.PWM1,
+            #[cfg(feature = \"servo\")]
+            servo: {servo},
+            #[cfg(feature = \"servo\")]
+            servo_right: {servo_right},
+            #[cfg(all(feature = \"analog\", not(feature = \"servo\")))]
+            analog_pwm: p.PWM1,
+            #[cfg(feature = \"analog\")]
+            analog: {analog},
+            #[cfg(feature = \"compass-module\")]
+            module_twis: p.TWISPI1,
+            #[cfg(feature = \"compass-module\")]
+            module_sda: {module_sda},
+            #[cfg(feature = \"compass-module\")]
+            module_scl: {module_scl},
+            saadc: p.SAADC,
+            nvmc: p.NVMC,
+            radio: p.RADIO,
+            sensor_int: {sensor_int},
+            sensor_bus: BusPins {{
+                twi: p.TWISPI0,
+                sda: {sda},
+                scl: {scl},
+            }},
+        }}
+    }}
+}}
+",
+        rows = matrix("rows")?,
+        cols = matrix("cols")?,
+        external_matrix = external_matrix.map_or("None".to_string(), |(.., rows, cols)| format!(
+            "Some(Pins {{ rows: [{rows}], cols: [{cols}] }})"
+        )),
+        strobe = optional("strobe")?,
+        button_a = required("button_a")?,
+        button_b = required("button_b")?,
+        console_rx = required("console_rx")?,
+        console_tx = required("console_tx")?,
+        touch = optional("touch")?,
+        speaker = optional("speaker")?,
+        haptic = optional("haptic")?,
+        gps_rx = for_feature("gps_rx")?,
+        rgb = for_feature("rgb")?,
+        servo = for_feature("servo")?,
+        servo_right = for_feature("servo_right")?,
+        analog = for_feature("analog")?,
+        module_sda = for_feature("module_sda")?,
+        module_scl = for_feature("module_scl")?,
+        sensor_int = required("sensor_int")?,
+        sda = required("sda")?,
+        scl = required("scl")?,
+    )
+    .unwrap();
+    Ok(code)
+}
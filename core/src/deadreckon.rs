@@ -0,0 +1,111 @@
+//! Position estimates without GPS.
+
+use micromath::F32Ext;
+
+/// Position relative to where the estimate was last reset, advanced one
+/// stride along the current heading for each step. Errors accumulate with
+/// distance, a few percent of it with a well-measured stride.
+pub struct DeadReckoning {
+    /// Meters east of the start
+    east: f32,
+    /// Meters north of the start
+    north: f32,
+    steps: u32,
+}
+
+impl DeadReckoning {
+    pub const fn new() -> Self {
+        Self {
+            east: 0.0,
+            north: 0.0,
+            steps: 0,
+        }
+    }
+
+    /// Advance one step of `stride_m` meters towards `heading`, degrees from
+    /// true north
+    pub fn step(&mut self, heading: f32, stride_m: f32) {
+        let heading = heading.to_radians();
+        self.east += stride_m * heading.sin();
+        self.north += stride_m * heading.cos();
+        self.steps += 1;
+    }
+
+    /// Meters east and north of the start
+    pub fn position(&self) -> [f32; 2] {
+        [self.east, self.north]
+    }
+
+    /// Steps taken since the start
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Make the current position the new start
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for DeadReckoning {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close([east, north]: [f32; 2], [to_east, to_north]: [f32; 2]) -> bool {
+        (east - to_east).abs() < 1e-3 && (north - to_north).abs() < 1e-3
+    }
+
+    #[test]
+    fn cardinal_steps() {
+        for (heading, position) in [
+            (0.0, [0.0, 1.0]),
+            (90.0, [1.0, 0.0]),
+            (180.0, [0.0, -1.0]),
+            (270.0, [-1.0, 0.0]),
+        ] {
+            let mut dead_reckoning = DeadReckoning::new();
+            dead_reckoning.step(heading, 1.0);
+            assert!(close(dead_reckoning.position(), position), "{heading}");
+        }
+    }
+
+    #[test]
+    fn round_a_square() {
+        let mut dead_reckoning = DeadReckoning::new();
+        for heading in [0.0, 90.0, 180.0, 270.0] {
+            for _ in 0..10 {
+                dead_reckoning.step(heading, 0.75);
+            }
+        }
+        assert!(close(dead_reckoning.position(), [0.0, 0.0]));
+        assert_eq!(dead_reckoning.steps(), 40);
+    }
+
+    #[test]
+    fn stride_scales() {
+        let mut dead_reckoning = DeadReckoning::new();
+        dead_reckoning.step(45.0, 2.0);
+        let half = 2.0 * core::f32::consts::FRAC_1_SQRT_2;
+        assert!(close(dead_reckoning.position(), [half, half]));
+        dead_reckoning.step(45.0, 0.5);
+        let more = 2.5 * core::f32::consts::FRAC_1_SQRT_2;
+        assert!(close(dead_reckoning.position(), [more, more]));
+    }
+
+    #[test]
+    fn reset() {
+        let mut dead_reckoning = DeadReckoning::new();
+        dead_reckoning.step(30.0, 1.0);
+        dead_reckoning.reset();
+        assert_eq!(dead_reckoning.position(), [0.0, 0.0]);
+        assert_eq!(dead_reckoning.steps(), 0);
+        dead_reckoning.step(90.0, 1.0);
+        assert!(close(dead_reckoning.position(), [1.0, 0.0]));
+    }
+}
@@ -0,0 +1,178 @@
+//! Residual deviation left by the installation.
+//!
+//! Calibration takes out the hard and soft iron the board carries with it,
+//! but an engine block or a steel hull swings the field differently on every
+//! heading. A deviation table, filled in by swinging the vehicle against known
+//! bearings, corrects what is left.
+
+use micromath::F32Ext;
+
+use crate::heading::normalize_heading;
+
+/// Headings the table holds a correction for, every 22.5°
+pub const POINTS: usize = 16;
+
+/// Degrees between neighbouring points
+pub const STEP: f32 = 360.0 / POINTS as f32;
+
+/// Largest correction accepted, degrees. Anything more means the board is
+/// mounted too close to iron to be any use.
+pub const MAX_DEVIATION: f32 = 45.0;
+
+/// Corrections at [`POINTS`] compass headings, added to the heading to give
+/// the magnetic one, east positive. Points without a correction are skipped
+/// over, so a table swung on 8 headings 45° apart works as well as one on all
+/// 16; between points the correction is interpolated linearly.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DeviationTable {
+    /// NaN where no correction is set
+    corrections: [f32; POINTS],
+}
+
+impl DeviationTable {
+    pub const EMPTY: Self = Self {
+        corrections: [f32::NAN; POINTS],
+    };
+
+    /// The point at `heading` in degrees, which has to fall on one
+    pub fn point(heading: f32) -> Option<usize> {
+        let index = (heading / STEP).round();
+        let on_point = (index * STEP - heading).abs() < 0.01;
+        (on_point && (0.0..=360.0).contains(&heading)).then_some(index as usize % POINTS)
+    }
+
+    /// The compass heading of a point, degrees
+    pub fn heading(point: usize) -> f32 {
+        point as f32 * STEP
+    }
+
+    pub fn get(&self, point: usize) -> Option<f32> {
+        Some(self.corrections[point]).filter(|correction| correction.is_finite())
+    }
+
+    /// Set or, with `None`, clear the correction at a point
+    pub fn set(&mut self, point: usize, correction: Option<f32>) {
+        self.corrections[point] = correction.unwrap_or(f32::NAN);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..POINTS).all(|point| self.get(point).is_none())
+    }
+
+    /// Correction for a compass heading in degrees, zero with an empty table
+    pub fn deviation(&self, heading: f32) -> f32 {
+        let position = normalize_heading(heading) / STEP;
+        let below = position as usize % POINTS;
+        // The nearest points set either side, going round the circle
+        let Some(before) = (0..POINTS)
+            .map(|back| (below + POINTS - back) % POINTS)
+            .find(|&point| self.get(point).is_some())
+        else {
+            return 0.0;
+        };
+        let after = (1..=POINTS)
+            .map(|ahead| (below + ahead) % POINTS)
+            .find(|&point| self.get(point).is_some())
+            .unwrap_or(before);
+
+        let mut from_before = position - before as f32;
+        if from_before < 0.0 {
+            from_before += POINTS as f32;
+        }
+        // A single point set spans the whole circle
+        let span = match (after + POINTS - before) % POINTS {
+            0 => POINTS,
+            span => span,
+        };
+        let (start, end) = (self.corrections[before], self.corrections[after]);
+        start + (end - start) * from_before / span as f32
+    }
+
+    /// The magnetic heading for a compass heading, degrees
+    pub fn correct(&self, heading: f32) -> f32 {
+        normalize_heading(heading + self.deviation(heading))
+    }
+
+    /// Pack for storage, each correction as the bits of an `f32`
+    pub fn to_bits(&self) -> [u32; POINTS] {
+        self.corrections.map(f32::to_bits)
+    }
+
+    /// Unpack from [`DeviationTable::to_bits`], dropping corrections out of
+    /// range, e.g. from erased flash
+    pub fn from_bits(bits: [u32; POINTS]) -> Self {
+        Self {
+            corrections: bits.map(|bits| {
+                Some(f32::from_bits(bits))
+                    .filter(|correction| correction.abs() <= MAX_DEVIATION)
+                    .unwrap_or(f32::NAN)
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn empty_table() {
+        assert!(DeviationTable::EMPTY.is_empty());
+        assert_eq!(DeviationTable::EMPTY.deviation(123.0), 0.0);
+    }
+
+    #[test]
+    fn points() {
+        assert_eq!(DeviationTable::point(22.5), Some(1));
+        assert_eq!(DeviationTable::point(360.0), Some(0));
+        assert_eq!(DeviationTable::point(10.0), None);
+        assert_eq!(DeviationTable::point(-22.5), None);
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let mut table = DeviationTable::EMPTY;
+        table.set(2, Some(2.0));
+        table.set(4, Some(-2.0));
+        assert!(close(table.deviation(45.0), 2.0));
+        assert!(close(table.deviation(67.5), 0.0));
+        assert!(close(table.deviation(78.75), -1.0));
+    }
+
+    #[test]
+    fn interpolates_across_north() {
+        let mut table = DeviationTable::EMPTY;
+        table.set(15, Some(4.0));
+        table.set(1, Some(-2.0));
+        assert!(close(table.deviation(0.0), 1.0));
+        assert!(close(table.deviation(360.0), 1.0));
+        assert!(close(table.deviation(348.75), 2.5));
+        assert!(close(table.deviation(11.25), -0.5));
+        // The correction takes the heading back past north
+        let deviation = 4.0 - 6.0 * (359.0 / STEP - 15.0) / 2.0;
+        assert!(close(table.correct(359.0), 359.0 + deviation - 360.0));
+    }
+
+    #[test]
+    fn single_point_spans_circle() {
+        let mut table = DeviationTable::EMPTY;
+        table.set(8, Some(3.0));
+        for heading in [0.0, 90.0, 180.0, 270.0, 359.9] {
+            assert!(close(table.deviation(heading), 3.0));
+        }
+    }
+
+    #[test]
+    fn bits_drop_out_of_range() {
+        let mut table = DeviationTable::EMPTY;
+        table.set(3, Some(-5.0));
+        let mut bits = table.to_bits();
+        bits[7] = u32::MAX;
+        bits[9] = 90.0f32.to_bits();
+        assert_eq!(DeviationTable::from_bits(bits).to_bits(), table.to_bits());
+    }
+}
@@ -0,0 +1,138 @@
+//! Heading from the accelerometer and magnetometer.
+//!
+//! Both readings are in the flat board frame, already turned for the mounting
+//! and, for the field, calibrated. Angles are in degrees clockwise unless
+//! noted.
+
+use micromath::F32Ext;
+
+pub fn compute_heading(
+    accel_x: f32,
+    accel_y: f32,
+    accel_z: f32,
+    mag_x: f32,
+    mag_y: f32,
+    mag_z: f32,
+) -> f32 {
+    let (pitch, roll) = compute_pitch_roll(accel_x, accel_y, accel_z);
+
+    // Tilt compensation
+    let mag_xh = mag_x * roll.cos() + mag_y * roll.sin() * pitch.sin() - mag_z * pitch.cos();
+    let mag_yh = mag_y * pitch.cos() + mag_z * pitch.sin();
+
+    // Comput heading using atan2
+    let mut heading = mag_yh.atan2(mag_xh).to_degrees();
+
+    // Convert range from -180 to 180 into 0 to 360
+    if heading < 0.0 {
+        heading += 360.0;
+    }
+
+    heading
+}
+
+/// Pitch and roll, in radians, from the gravity vector
+pub fn compute_pitch_roll(accel_x: f32, accel_y: f32, accel_z: f32) -> (f32, f32) {
+    // Normalize accelerometer values
+    let accel_norm = (accel_x * accel_x + accel_y * accel_y + accel_z * accel_z).sqrt();
+    let ax = accel_x / accel_norm;
+    let ay = accel_y / accel_norm;
+    let az = accel_z / accel_norm;
+
+    // Compute pitch and roll angles
+    let pitch = (-ax).asin();
+    let roll = ay.atan2(az);
+    (pitch, roll)
+}
+
+/// Wrap a heading into the range 0 to 360
+pub fn normalize_heading(heading: f32) -> f32 {
+    let heading = heading % 360.0;
+    if heading < 0.0 {
+        heading + 360.0
+    } else {
+        heading
+    }
+}
+
+/// Signed shortest angular difference `a - b`, in the range -180 to 180
+pub fn angle_diff(a: f32, b: f32) -> f32 {
+    let mut diff = (a - b) % 360.0;
+    if diff > 180.0 {
+        diff -= 360.0;
+    } else if diff <= -180.0 {
+        diff += 360.0;
+    }
+    diff
+}
+
+/// Map heading to the four main cardinal directions (N, E, S, W)
+pub fn get_cardinal_direction(heading: f32) -> &'static str {
+    match heading {
+        h if !(45.0..315.0).contains(&h) => "N",
+        h if (45.0..135.0).contains(&h) => "E",
+        h if (135.0..225.0).contains(&h) => "S",
+        h if (225.0..315.0).contains(&h) => "W",
+        _ => "?", // Fallback (should never happen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn flat_heading() {
+        let flat = |mag_x, mag_y| compute_heading(0.0, 0.0, 1000.0, mag_x, mag_y, 0.0);
+        assert!(close(flat(1.0, 0.0), 0.0));
+        assert!(close(flat(0.0, 1.0), 90.0));
+        assert!(close(flat(-1.0, 0.0), 180.0));
+        // Never negative
+        assert!(close(flat(0.0, -1.0), 270.0));
+    }
+
+    #[test]
+    fn pitch_and_roll() {
+        let (pitch, roll) = compute_pitch_roll(0.0, 0.0, 1000.0);
+        assert!(close(pitch, 0.0) && close(roll, 0.0));
+        let (pitch, _) = compute_pitch_roll(-1000.0, 0.0, 0.0);
+        assert!(close(pitch.to_degrees(), 90.0));
+        let (_, roll) = compute_pitch_roll(0.0, 1000.0, 1000.0);
+        assert!(close(roll.to_degrees(), 45.0));
+    }
+
+    #[test]
+    fn normalizes() {
+        assert_eq!(normalize_heading(-90.0), 270.0);
+        assert_eq!(normalize_heading(360.0), 0.0);
+        assert_eq!(normalize_heading(725.0), 5.0);
+    }
+
+    #[test]
+    fn shortest_difference() {
+        assert_eq!(angle_diff(10.0, 350.0), 20.0);
+        assert_eq!(angle_diff(350.0, 10.0), -20.0);
+        // Half way round either way counts as clockwise
+        assert_eq!(angle_diff(180.0, 0.0), 180.0);
+        assert_eq!(angle_diff(0.0, 180.0), 180.0);
+    }
+
+    #[test]
+    fn cardinals() {
+        for (heading, direction) in [
+            (0.0, "N"),
+            (44.9, "N"),
+            (45.0, "E"),
+            (135.0, "S"),
+            (225.0, "W"),
+            (315.0, "N"),
+            (359.9, "N"),
+        ] {
+            assert_eq!(get_cardinal_direction(heading), direction, "{heading}");
+        }
+    }
+}
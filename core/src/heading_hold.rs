@@ -0,0 +1,176 @@
+//! Closed-loop heading hold.
+//!
+//! A PID controller turns the error between a target heading and the
+//! measured one into a steering command, so the compass can keep a small
+//! boat or robot on course through a rudder servo or two drive motors.
+
+use crate::heading::angle_diff;
+
+/// Largest gain accepted for any term
+pub const MAX_GAIN: f32 = 1.0;
+
+/// Proportional, integral and derivative gains, in full steering per degree
+/// of error, per degree-second of it, and per degree per second of turn
+#[derive(Clone, Copy, PartialEq)]
+pub struct Gains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl Gains {
+    /// Hard over at 50° off, damped against the turn, and no integral until
+    /// tuned for the vehicle, as a steady wind or current calls for
+    pub const DEFAULT: Self = Self {
+        kp: 0.02,
+        ki: 0.0,
+        kd: 0.005,
+    };
+
+    /// Whether all three are within 0 to [`MAX_GAIN`]
+    pub fn is_valid(&self) -> bool {
+        [self.kp, self.ki, self.kd]
+            .iter()
+            .all(|gain| (0.0..=MAX_GAIN).contains(gain))
+    }
+}
+
+/// Keeps a heading by PID control. The output is the steering, from -1 hard
+/// to the left (anticlockwise) to 1 hard to the right.
+pub struct PidHold {
+    gains: Gains,
+    target: Option<f32>,
+    /// Sum of the error over time, degree-seconds
+    integral: f32,
+    last_heading: Option<f32>,
+}
+
+impl PidHold {
+    pub const fn new(gains: Gains) -> Self {
+        Self {
+            gains,
+            target: None,
+            integral: 0.0,
+            last_heading: None,
+        }
+    }
+
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.gains = gains;
+        self.integral = 0.0;
+    }
+
+    /// Start afresh, e.g. after the heading was lost for a while
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.integral = 0.0;
+        self.last_heading = None;
+    }
+
+    /// Steering to bring `heading` round to `target`, `dt` seconds after the
+    /// last update. A new target starts the integral over.
+    pub fn update(&mut self, target: f32, heading: f32, dt: f32) -> f32 {
+        if self.target != Some(target) {
+            self.target = Some(target);
+            self.integral = 0.0;
+        }
+        // Positive with the target clockwise of the heading
+        let error = angle_diff(target, heading);
+
+        // Damped on the rate of turn rather than of the error, so a change of
+        // target doesn't kick the steering
+        let rate = match self.last_heading {
+            Some(last) if dt > 0.0 => angle_diff(heading, last) / dt,
+            _ => 0.0,
+        };
+        self.last_heading = Some(heading);
+
+        let steering = self.gains.kp * error - self.gains.kd * rate;
+        // Only wind the integral up while the steering isn't already hard
+        // over, so it doesn't overshoot by the time the vehicle comes round
+        if self.gains.ki > 0.0 {
+            let integral = self.integral + error * dt;
+            if (steering + self.gains.ki * integral).abs() < 1.0 {
+                self.integral = integral;
+            }
+        }
+        (steering + self.gains.ki * self.integral).clamp(-1.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P: Gains = Gains {
+        kp: 0.01,
+        ki: 0.0,
+        kd: 0.0,
+    };
+
+    #[test]
+    fn steers_towards_target() {
+        let mut hold = PidHold::new(P);
+        assert!(hold.update(30.0, 0.0, 0.1) > 0.0);
+        hold.reset();
+        assert!(hold.update(0.0, 30.0, 0.1) < 0.0);
+    }
+
+    #[test]
+    fn shortest_way_round() {
+        let mut hold = PidHold::new(P);
+        // 20° anticlockwise across north, not 340° clockwise
+        assert!((hold.update(350.0, 10.0, 0.1) + 0.2).abs() < 1e-4);
+        hold.reset();
+        // Across south, either side of ±180°
+        assert!((hold.update(170.0, -170.0, 0.1) + 0.2).abs() < 1e-4);
+        hold.reset();
+        assert!((hold.update(-170.0, 170.0, 0.1) - 0.2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn hard_over() {
+        let mut hold = PidHold::new(P);
+        assert_eq!(hold.update(170.0, 0.0, 0.1), 1.0);
+        assert_eq!(hold.update(190.0, 0.0, 0.1), -1.0);
+    }
+
+    #[test]
+    fn damped_across_north() {
+        let mut hold = PidHold::new(Gains { kd: 0.01, ..P });
+        hold.update(90.0, 355.0, 1.0);
+        // Turning 10°/s clockwise, not 350°/s back
+        let steering = hold.update(90.0, 5.0, 1.0);
+        assert!((steering - (0.85 - 0.1)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn integral() {
+        let mut hold = PidHold::new(Gains {
+            kp: 0.0,
+            ki: 0.01,
+            kd: 0.0,
+        });
+        assert!((hold.update(10.0, 0.0, 1.0) - 0.1).abs() < 1e-4);
+        assert!((hold.update(10.0, 0.0, 1.0) - 0.2).abs() < 1e-4);
+        // A new target starts it over
+        assert!((hold.update(20.0, 0.0, 1.0) - 0.2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn no_windup_when_hard_over() {
+        let mut hold = PidHold::new(Gains { ki: 0.01, ..P });
+        for _ in 0..10 {
+            assert_eq!(hold.update(150.0, 0.0, 1.0), 1.0);
+        }
+        // Not wound up meanwhile, so only this second's error has built up
+        assert!((hold.update(150.0, 145.0, 1.0) - (0.05 + 0.05)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn gains_range() {
+        assert!(Gains::DEFAULT.is_valid());
+        assert!(!Gains { kp: -0.1, ..P }.is_valid());
+        assert!(!Gains { kd: 1.5, ..P }.is_valid());
+    }
+}
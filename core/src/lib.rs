@@ -0,0 +1,19 @@
+//! Hardware-independent code shared by the `micro-compass` firmware and its
+//! host tools.
+
+#![cfg_attr(not(test), no_std)]
+// Tested on the host, where the float methods come from std, not micromath
+#![cfg_attr(test, allow(unused_imports))]
+
+pub mod deadreckon;
+pub mod deviation;
+pub mod heading;
+pub mod heading_hold;
+pub mod log_format;
+pub mod matrix;
+pub mod mavlink;
+pub mod median;
+pub mod pipeline;
+pub mod protocol;
+pub mod sun;
+pub mod units;
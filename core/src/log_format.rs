@@ -0,0 +1,333 @@
+//! Flash log record formats.
+//!
+//! [`Record`]s are normally stored at a fixed [`RECORD_LEN`] bytes each. For
+//! high-rate logging the delta format stores most samples as a 4 byte
+//! [`DeltaEncoder`] frame instead, roughly a third of the fixed size:
+//!
+//! | Frame    | Bytes | Layout                                                      |
+//! | -------- | ----- | ----------------------------------------------------------- |
+//! | keyframe | 11    | `0xFF`, then the fixed-size record without padding          |
+//! | delta    | 4     | time delta (ms, < 255), heading delta (0.1°), pitch, roll (°) |
+//!
+//! Heading deltas are taken against the previously *decoded* heading, so
+//! quantization error does not accumulate. A keyframe is written whenever a
+//! delta would not fit, and every [`KEYFRAME_INTERVAL`] frames so a reader can
+//! resynchronize after a damaged frame.
+//!
+//! A stream read straight out of flash ends at the first erased keyframe (all
+//! `0xFF`), so trailing unused flash can be passed to [`DeltaDecoder`] as is.
+
+/// Size of a fixed-size record in flash, padded to the flash word size
+pub const RECORD_LEN: usize = 12;
+
+/// Size of the largest delta-format frame
+pub const MAX_FRAME_LEN: usize = KEYFRAME_LEN;
+
+/// Marks a keyframe, delta frames never start with this byte
+const KEYFRAME_TAG: u8 = 0xFF;
+
+/// Value of erased flash, marking the end of a stream
+const ERASED: u8 = 0xFF;
+
+const KEYFRAME_LEN: usize = 11;
+const DELTA_LEN: usize = 4;
+
+/// Frames between forced keyframes
+pub const KEYFRAME_INTERVAL: u32 = 64;
+
+/// A logged heading sample
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Record {
+    /// Milliseconds since boot
+    pub timestamp_ms: u32,
+    /// Heading in hundredths of a degree, 0 to 35999
+    pub heading_cdeg: u16,
+    /// Pitch in hundredths of a degree
+    pub pitch_cdeg: i16,
+    /// Roll in hundredths of a degree
+    pub roll_cdeg: i16,
+}
+
+impl Record {
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let mut buf = [0u8; RECORD_LEN];
+        buf[..KEYFRAME_LEN - 1].copy_from_slice(&self.pack());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; RECORD_LEN]) -> Self {
+        let mut packed = [0u8; KEYFRAME_LEN - 1];
+        packed.copy_from_slice(&buf[..KEYFRAME_LEN - 1]);
+        Self::unpack(&packed)
+    }
+
+    fn pack(&self) -> [u8; KEYFRAME_LEN - 1] {
+        let mut buf = [0u8; KEYFRAME_LEN - 1];
+        buf[0..4].copy_from_slice(&self.timestamp_ms.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.heading_cdeg.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.pitch_cdeg.to_le_bytes());
+        buf[8..10].copy_from_slice(&self.roll_cdeg.to_le_bytes());
+        buf
+    }
+
+    fn unpack(buf: &[u8; KEYFRAME_LEN - 1]) -> Self {
+        Self {
+            timestamp_ms: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            heading_cdeg: u16::from_le_bytes([buf[4], buf[5]]),
+            pitch_cdeg: i16::from_le_bytes([buf[6], buf[7]]),
+            roll_cdeg: i16::from_le_bytes([buf[8], buf[9]]),
+        }
+    }
+}
+
+/// Encodes records into the delta format
+pub struct DeltaEncoder {
+    /// The previous record as the decoder will reconstruct it
+    last: Option<Record>,
+    since_keyframe: u32,
+}
+
+impl DeltaEncoder {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            since_keyframe: 0,
+        }
+    }
+
+    /// Encode `record` into `out`, returning the frame length
+    pub fn encode(&mut self, record: &Record, out: &mut [u8; MAX_FRAME_LEN]) -> usize {
+        if self.since_keyframe < KEYFRAME_INTERVAL {
+            if let Some(frame) = self.last.and_then(|last| delta_frame(&last, record)) {
+                let (bytes, decoded) = frame;
+                out[..DELTA_LEN].copy_from_slice(&bytes);
+                self.last = Some(decoded);
+                self.since_keyframe += 1;
+                return DELTA_LEN;
+            }
+        }
+
+        out[0] = KEYFRAME_TAG;
+        out[1..KEYFRAME_LEN].copy_from_slice(&record.pack());
+        self.last = Some(*record);
+        self.since_keyframe = 0;
+        KEYFRAME_LEN
+    }
+}
+
+impl Default for DeltaEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Build a delta frame from `last` to `record`, along with the record the
+/// decoder will reconstruct from it, or `None` if the change is too large
+fn delta_frame(last: &Record, record: &Record) -> Option<([u8; DELTA_LEN], Record)> {
+    let dt = record.timestamp_ms.checked_sub(last.timestamp_ms)?;
+    if dt >= KEYFRAME_TAG as u32 {
+        return None;
+    }
+
+    // Shortest way round, in tenths of a degree
+    let mut dh = record.heading_cdeg as i32 - last.heading_cdeg as i32;
+    if dh > 18000 {
+        dh -= 36000;
+    } else if dh < -18000 {
+        dh += 36000;
+    }
+    let dh = i8::try_from(div_round(dh, 10)).ok()?;
+
+    let pitch = i8::try_from(div_round(record.pitch_cdeg as i32, 100)).ok()?;
+    let roll = i8::try_from(div_round(record.roll_cdeg as i32, 100)).ok()?;
+
+    let bytes = [dt as u8, dh as u8, pitch as u8, roll as u8];
+    Some((bytes, apply_delta(last, &bytes)))
+}
+
+/// Reconstruct the record following `last` from a delta frame
+fn apply_delta(last: &Record, frame: &[u8; DELTA_LEN]) -> Record {
+    let heading = last.heading_cdeg as i32 + frame[1] as i8 as i32 * 10;
+    Record {
+        timestamp_ms: last.timestamp_ms + frame[0] as u32,
+        heading_cdeg: heading.rem_euclid(36000) as u16,
+        pitch_cdeg: frame[2] as i8 as i16 * 100,
+        roll_cdeg: frame[3] as i8 as i16 * 100,
+    }
+}
+
+fn div_round(value: i32, divisor: i32) -> i32 {
+    if value >= 0 {
+        (value + divisor / 2) / divisor
+    } else {
+        (value - divisor / 2) / divisor
+    }
+}
+
+/// Error decoding a delta-format stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The stream ended part way through a frame
+    Truncated,
+    /// A delta frame appeared before the first keyframe
+    MissingKeyframe,
+}
+
+/// Decodes a delta-format stream back into records
+pub struct DeltaDecoder<'a> {
+    data: &'a [u8],
+    last: Option<Record>,
+}
+
+impl<'a> DeltaDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, last: None }
+    }
+}
+
+impl Iterator for DeltaDecoder<'_> {
+    type Item = Result<Record, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tag = *self.data.first()?;
+        let len = if tag == KEYFRAME_TAG {
+            KEYFRAME_LEN
+        } else {
+            DELTA_LEN
+        };
+        if self.data.iter().take(len).all(|&b| b == ERASED) {
+            self.data = &[];
+            return None;
+        }
+        if self.data.len() < len {
+            self.data = &[];
+            return Some(Err(DecodeError::Truncated));
+        }
+        let (frame, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        let record = if tag == KEYFRAME_TAG {
+            let mut packed = [0u8; KEYFRAME_LEN - 1];
+            packed.copy_from_slice(&frame[1..]);
+            Record::unpack(&packed)
+        } else {
+            let Some(last) = self.last else {
+                return Some(Err(DecodeError::MissingKeyframe));
+            };
+            let mut delta = [0u8; DELTA_LEN];
+            delta.copy_from_slice(frame);
+            apply_delta(&last, &delta)
+        };
+        self.last = Some(record);
+        Some(Ok(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records a delta frame carries exactly: heading on a tenth of a degree,
+    /// pitch and roll on whole degrees
+    fn records(count: u32) -> impl Iterator<Item = Record> {
+        (0..count).map(|i| Record {
+            timestamp_ms: 1000 + i * 200,
+            // Across north and back again
+            heading_cdeg: ((35_500 + i as i32 * 130) % 36_000) as u16,
+            pitch_cdeg: (i as i16 % 7 - 3) * 100,
+            roll_cdeg: (i as i16 % 5) * 100,
+        })
+    }
+
+    /// Encode `records`, each frame separately
+    fn encode(records: impl Iterator<Item = Record>) -> Vec<Vec<u8>> {
+        let mut encoder = DeltaEncoder::new();
+        records
+            .map(|record| {
+                let mut out = [0; MAX_FRAME_LEN];
+                let len = encoder.encode(&record, &mut out);
+                out[..len].to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let frames = encode(records(200));
+        // One keyframe to start, then one every KEYFRAME_INTERVAL deltas
+        let keyframes = frames.iter().filter(|frame| frame[0] == KEYFRAME_TAG);
+        assert_eq!(keyframes.count(), 4);
+        let stream = frames.concat();
+        let decoded: Vec<_> = DeltaDecoder::new(&stream).collect();
+        assert_eq!(decoded, records(200).map(Ok).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keyframe_when_delta_does_not_fit() {
+        let first = Record {
+            timestamp_ms: 0,
+            heading_cdeg: 0,
+            pitch_cdeg: 0,
+            roll_cdeg: 0,
+        };
+        let late = Record {
+            timestamp_ms: 300,
+            ..first
+        };
+        let turned = Record {
+            heading_cdeg: 9000,
+            ..late
+        };
+        let frames = encode([first, late, turned].into_iter());
+        assert!(frames.iter().all(|frame| frame.len() == KEYFRAME_LEN));
+    }
+
+    #[test]
+    fn delta_rounds_against_decoded() {
+        let frames = encode((0..10).map(|i| Record {
+            timestamp_ms: i * 100,
+            heading_cdeg: i as u16 * 14,
+            pitch_cdeg: 149,
+            roll_cdeg: -151,
+        }));
+        let stream = frames.concat();
+        for (i, record) in DeltaDecoder::new(&stream).enumerate() {
+            let record = record.unwrap();
+            // Rounding error never builds up past half a step
+            assert!((i32::from(record.heading_cdeg) - i as i32 * 14).abs() <= 5);
+            if i > 0 {
+                assert_eq!((record.pitch_cdeg, record.roll_cdeg), (100, -200));
+            }
+        }
+    }
+
+    #[test]
+    fn erased_tail_ends_stream() {
+        let mut stream = encode(records(10)).concat();
+        stream.extend([ERASED; 64]);
+        let decoded: Vec<_> = DeltaDecoder::new(&stream).collect();
+        assert_eq!(decoded, records(10).map(Ok).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let stream = encode(records(3)).concat();
+        let decoded: Vec<_> = DeltaDecoder::new(&stream[..stream.len() - 1]).collect();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[2], Err(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn resync_at_next_keyframe() {
+        let frames = encode(records(100));
+        // The first keyframe is lost
+        let stream = frames[1..].concat();
+        let decoded: Vec<_> = DeltaDecoder::new(&stream).collect();
+        let deltas = KEYFRAME_INTERVAL as usize;
+        assert!(decoded[..deltas]
+            .iter()
+            .all(|record| *record == Err(DecodeError::MissingKeyframe)));
+        let expected: Vec<_> = records(100).skip(deltas + 1).map(Ok).collect();
+        assert_eq!(decoded[deltas..], expected[..]);
+    }
+}
@@ -0,0 +1,116 @@
+//! What the 5x5 LED matrix shows for a heading, as the (row, col) of each lit
+//! LED with row 0 at the top, and a needle for a matrix of any size.
+
+use micromath::F32Ext;
+
+use crate::heading::get_cardinal_direction;
+
+/// The outer ring of LEDs, clockwise from the top middle
+pub const RING: [(usize, usize); 16] = [
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 4),
+    (2, 4),
+    (3, 4),
+    (4, 4),
+    (4, 3),
+    (4, 2),
+    (4, 1),
+    (4, 0),
+    (3, 0),
+    (2, 0),
+    (1, 0),
+    (0, 0),
+    (0, 1),
+];
+
+/// An arrow for N, E, S or W, pointing up, right, down or left. Anything else
+/// is a dot in the middle.
+pub fn arrow(direction: &str) -> [(usize, usize); 7] {
+    match direction {
+        // North: Arrow pointing up
+        "N" => [(0, 2), (1, 1), (1, 2), (1, 3), (2, 2), (3, 2), (4, 2)],
+        // South: Arrow pointing down
+        "S" => [(0, 2), (1, 2), (2, 2), (3, 1), (3, 2), (3, 3), (4, 2)],
+        // East: Arrow pointing right
+        "E" => [(2, 0), (2, 1), (2, 2), (2, 3), (2, 4), (1, 3), (3, 3)],
+        // West: Arrow pointing left
+        "W" => [(2, 0), (1, 1), (3, 1), (2, 1), (2, 2), (2, 3), (2, 4)],
+        _ => [(2, 2), (2, 2), (2, 2), (2, 2), (2, 2), (2, 2), (2, 2)], // Default center dot
+    }
+}
+
+/// The arrow for the cardinal direction nearest `angle`
+pub fn cardinal_arrow(angle: f32) -> [(usize, usize); 7] {
+    arrow(get_cardinal_direction(angle))
+}
+
+/// The dot on the outer ring nearest `angle`, in 22.5° steps
+pub fn ring_dot(angle: f32) -> (usize, usize) {
+    let step = 360.0 / RING.len() as f32;
+    let index = ((angle + step / 2.0) / step) as usize % RING.len();
+    RING[index]
+}
+
+/// A needle from the middle to the edge of a `ROWS` x `COLS` matrix,
+/// pointing `angle` degrees clockwise from the top, one bit per column with
+/// the leftmost column in bit `COLS - 1`
+pub fn needle<const ROWS: usize, const COLS: usize>(angle: f32) -> [u16; ROWS] {
+    let mut frame = [0; ROWS];
+    if ROWS == 0 || COLS == 0 {
+        return frame;
+    }
+    // The middle falls between LEDs on an even side
+    let (mid_row, mid_col) = ((ROWS - 1) as f32 / 2.0, (COLS - 1) as f32 / 2.0);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let steps = ROWS.max(COLS);
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let row = (mid_row - t * mid_row * cos).round();
+        let col = (mid_col + t * mid_col * sin).round();
+        if (0.0..ROWS as f32).contains(&row) && (0.0..COLS as f32).contains(&col) {
+            frame[row as usize] |= 1 << (COLS - 1 - col as usize);
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_dots() {
+        assert_eq!(ring_dot(0.0), (0, 2));
+        assert_eq!(ring_dot(11.0), (0, 2));
+        assert_eq!(ring_dot(12.0), (0, 3));
+        assert_eq!(ring_dot(90.0), (2, 4));
+        assert_eq!(ring_dot(180.0), (4, 2));
+        assert_eq!(ring_dot(270.0), (2, 0));
+        // Back round to the top
+        assert_eq!(ring_dot(355.0), (0, 2));
+    }
+
+    #[test]
+    fn arrows() {
+        assert_eq!(cardinal_arrow(10.0), arrow("N"));
+        assert_eq!(cardinal_arrow(100.0), arrow("E"));
+        assert_eq!(cardinal_arrow(200.0), arrow("S"));
+        assert_eq!(cardinal_arrow(300.0), arrow("W"));
+        assert_eq!(arrow("?"), [(2, 2); 7]);
+        for direction in ["N", "E", "S", "W"] {
+            assert!(arrow(direction).contains(&(2, 2)));
+        }
+    }
+
+    #[test]
+    fn needles() {
+        assert_eq!(needle::<5, 5>(0.0), [0b00100, 0b00100, 0b00100, 0, 0]);
+        assert_eq!(needle::<5, 5>(90.0), [0, 0, 0b00111, 0, 0]);
+        assert_eq!(needle::<5, 5>(180.0), [0, 0, 0b00100, 0b00100, 0b00100]);
+        // As long as the others, so short of the corner
+        assert_eq!(needle::<5, 5>(135.0), [0, 0, 0b00100, 0b00010, 0]);
+        assert_eq!(needle::<0, 0>(0.0), []);
+    }
+}
@@ -0,0 +1,268 @@
+//! A minimal MAVLink 1 encoder, for the few messages a ground station or an
+//! autopilot needs to take the board for an external compass.
+//!
+//! Each message is laid out as the MAVLink common dialect has it, fields
+//! sorted largest type first, and framed as
+//!
+//! ```text
+//! 0xFE <len> <seq> <system> <component> <message id> <payload> <crc lo> <crc hi>
+//! ```
+//!
+//! with the CRC-16/MCRF4XX of everything after the start byte, followed by
+//! the message's CRC extra byte.
+
+/// Start of a MAVLink 1 frame
+const MAGIC: u8 = 0xFE;
+
+/// Start byte, length, sequence, system, component and message id, then the
+/// CRC after the payload
+const OVERHEAD: usize = 8;
+
+/// Longest payload of the messages here, STATUSTEXT's
+const MAX_PAYLOAD: usize = 51;
+
+/// Longest frame [`Encoder::encode`] writes
+pub const MAX_FRAME: usize = OVERHEAD + MAX_PAYLOAD;
+
+/// Characters a STATUSTEXT carries, longer text is cut short
+pub const STATUS_TEXT_LEN: usize = 50;
+
+/// `MAV_TYPE_GENERIC`, `MAV_AUTOPILOT_INVALID` and `MAV_STATE_ACTIVE`: not a
+/// vehicle or an autopilot, just a component that is up and running
+const MAV_TYPE_GENERIC: u8 = 0;
+const MAV_AUTOPILOT_INVALID: u8 = 8;
+const MAV_STATE_ACTIVE: u8 = 4;
+/// Protocol version in the heartbeat
+const MAVLINK_VERSION: u8 = 3;
+
+/// `MAV_SEVERITY_INFO`
+const SEVERITY_INFO: u8 = 6;
+
+pub enum Message<'a> {
+    /// HEARTBEAT (0), which ground stations want once a second before they
+    /// show anything from a system
+    Heartbeat,
+    /// ATTITUDE (30), radians and radians per second, the yaw from -π to π
+    Attitude {
+        time_boot_ms: u32,
+        roll: f32,
+        pitch: f32,
+        yaw: f32,
+        roll_speed: f32,
+        pitch_speed: f32,
+        yaw_speed: f32,
+    },
+    /// VFR_HUD (74) with only the heading, whole degrees 0 to 359, filled in
+    VfrHud { heading: i16 },
+    /// STATUSTEXT (253) at info severity
+    StatusText(&'a str),
+}
+
+impl Message<'_> {
+    /// Message id and CRC extra byte, which stands in for the message's
+    /// definition so both ends are checked to agree on it
+    fn id_and_crc_extra(&self) -> (u8, u8) {
+        match self {
+            Message::Heartbeat => (0, 50),
+            Message::Attitude { .. } => (30, 39),
+            Message::VfrHud { .. } => (74, 20),
+            Message::StatusText(_) => (253, 83),
+        }
+    }
+
+    /// Writes the payload, returning its length
+    fn payload(&self, out: &mut [u8; MAX_PAYLOAD]) -> usize {
+        let mut writer = Writer { out, len: 0 };
+        match *self {
+            Message::Heartbeat => {
+                // custom_mode
+                writer.put(&0u32.to_le_bytes());
+                writer.put(&[
+                    MAV_TYPE_GENERIC,
+                    MAV_AUTOPILOT_INVALID,
+                    // base_mode
+                    0,
+                    MAV_STATE_ACTIVE,
+                    MAVLINK_VERSION,
+                ]);
+            }
+            Message::Attitude {
+                time_boot_ms,
+                roll,
+                pitch,
+                yaw,
+                roll_speed,
+                pitch_speed,
+                yaw_speed,
+            } => {
+                writer.put(&time_boot_ms.to_le_bytes());
+                for value in [roll, pitch, yaw, roll_speed, pitch_speed, yaw_speed] {
+                    writer.put(&value.to_le_bytes());
+                }
+            }
+            Message::VfrHud { heading } => {
+                // airspeed, groundspeed, alt and climb
+                for _ in 0..4 {
+                    writer.put(&0f32.to_le_bytes());
+                }
+                writer.put(&heading.to_le_bytes());
+                // throttle
+                writer.put(&0u16.to_le_bytes());
+            }
+            Message::StatusText(text) => {
+                writer.put(&[SEVERITY_INFO]);
+                let text = &text.as_bytes()[..text.len().min(STATUS_TEXT_LEN)];
+                writer.put(text);
+                // NUL padded
+                writer.put(&[0; STATUS_TEXT_LEN][text.len()..]);
+            }
+        }
+        writer.len
+    }
+}
+
+struct Writer<'a> {
+    out: &'a mut [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.out[self.len..][..bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+}
+
+/// Frames messages from one system and component, counting them in sequence
+pub struct Encoder {
+    system: u8,
+    component: u8,
+    sequence: u8,
+}
+
+impl Encoder {
+    pub const fn new(system: u8, component: u8) -> Self {
+        Self {
+            system,
+            component,
+            sequence: 0,
+        }
+    }
+
+    /// Frame `message` into `out`, returning the frame
+    pub fn encode<'a>(&mut self, message: &Message, out: &'a mut [u8; MAX_FRAME]) -> &'a [u8] {
+        let (id, crc_extra) = message.id_and_crc_extra();
+        let mut payload = [0; MAX_PAYLOAD];
+        let len = message.payload(&mut payload);
+        out[..6].copy_from_slice(&[
+            MAGIC,
+            len as u8,
+            self.sequence,
+            self.system,
+            self.component,
+            id,
+        ]);
+        out[6..][..len].copy_from_slice(&payload[..len]);
+        let crc = out[1..6 + len]
+            .iter()
+            .chain(&[crc_extra])
+            .fold(0xFFFF, |crc, &byte| crc_accumulate(crc, byte));
+        out[6 + len..][..2].copy_from_slice(&crc.to_le_bytes());
+        self.sequence = self.sequence.wrapping_add(1);
+        &out[..OVERHEAD + len]
+    }
+}
+
+/// One byte into the CRC-16/MCRF4XX (X.25 without the final inversion)
+/// MAVLink checks frames with
+fn crc_accumulate(crc: u16, byte: u8) -> u16 {
+    let mut tmp = byte ^ (crc & 0xFF) as u8;
+    tmp ^= tmp << 4;
+    let tmp = u16::from(tmp);
+    (crc >> 8) ^ (tmp << 8) ^ (tmp << 3) ^ (tmp >> 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_check_value() {
+        let crc = b"123456789"
+            .iter()
+            .fold(0xFFFF, |crc, &byte| crc_accumulate(crc, byte));
+        assert_eq!(crc, 0x6F91);
+    }
+
+    #[test]
+    fn heartbeat_frame() {
+        let mut encoder = Encoder::new(1, 1);
+        let mut out = [0; MAX_FRAME];
+        assert_eq!(
+            encoder.encode(&Message::Heartbeat, &mut out),
+            [0xFE, 9, 0, 1, 1, 0, 0, 0, 0, 0, 0, 8, 0, 4, 3, 0x6A, 0x5B]
+        );
+    }
+
+    #[test]
+    fn frames() {
+        let mut encoder = Encoder::new(42, 200);
+        let mut out = [0; MAX_FRAME];
+        let attitude = Message::Attitude {
+            time_boot_ms: 1234,
+            roll: 0.1,
+            pitch: -0.2,
+            yaw: 3.0,
+            roll_speed: 0.0,
+            pitch_speed: 0.0,
+            yaw_speed: 0.5,
+        };
+        let messages = [
+            (attitude, 30, 39, 28),
+            (Message::VfrHud { heading: 271 }, 74, 20, 20),
+            (Message::StatusText("calibrated"), 253, 83, 51),
+        ];
+        for (sequence, (message, id, crc_extra, len)) in messages.iter().enumerate() {
+            let frame = encoder.encode(message, &mut out);
+            assert_eq!(frame.len(), OVERHEAD + len);
+            assert_eq!(
+                frame[..6],
+                [MAGIC, *len as u8, sequence as u8, 42, 200, *id]
+            );
+            let crc = frame[1..6 + len]
+                .iter()
+                .chain(&[*crc_extra])
+                .fold(0xFFFF, |crc, &byte| crc_accumulate(crc, byte));
+            assert_eq!(frame[6 + len..], crc.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn vfr_hud_heading() {
+        let mut out = [0; MAX_FRAME];
+        let frame = Encoder::new(1, 1).encode(&Message::VfrHud { heading: 271 }, &mut out);
+        assert_eq!(frame[6 + 16..][..2], 271i16.to_le_bytes());
+    }
+
+    #[test]
+    fn status_text_cut_short() {
+        let text = "x".repeat(STATUS_TEXT_LEN + 10);
+        let mut out = [0; MAX_FRAME];
+        let frame = Encoder::new(1, 1).encode(&Message::StatusText(&text), &mut out);
+        assert_eq!(frame.len(), MAX_FRAME);
+        assert_eq!(frame[6], SEVERITY_INFO);
+        assert!(frame[7..][..STATUS_TEXT_LEN]
+            .iter()
+            .all(|&byte| byte == b'x'));
+    }
+
+    #[test]
+    fn sequence_wraps() {
+        let mut encoder = Encoder::new(1, 1);
+        let mut out = [0; MAX_FRAME];
+        for _ in 0..256 {
+            encoder.encode(&Message::Heartbeat, &mut out);
+        }
+        assert_eq!(encoder.encode(&Message::Heartbeat, &mut out)[2], 0);
+    }
+}
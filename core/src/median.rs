@@ -0,0 +1,93 @@
+//! Median filtering of magnetometer samples.
+
+/// Longest window supported
+pub const MAX_WINDOW: usize = 5;
+
+/// Running median of the last few magnetometer samples on each axis, so a
+/// single spike from a nearby motor or a garbled I2C read doesn't swing the
+/// needle. Unlike averaging, an outlier has no effect at all as long as it is
+/// outnumbered.
+pub struct MedianFilter {
+    samples: [[f32; MAX_WINDOW]; 3],
+    /// Samples filtered over, 1 (off) to [`MAX_WINDOW`]
+    window: usize,
+    next: usize,
+    len: usize,
+}
+
+impl MedianFilter {
+    pub const fn new(window: usize) -> Self {
+        Self {
+            samples: [[0.0; MAX_WINDOW]; 3],
+            window: if window == 0 { 1 } else { window },
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Filter over `window` samples from now on, starting afresh
+    pub fn set_window(&mut self, window: usize) {
+        *self = Self::new(window);
+    }
+
+    /// Add a sample and return the median of each axis over the window, or of
+    /// the samples so far while it fills
+    pub fn update(&mut self, sample: [f32; 3]) -> [f32; 3] {
+        for (axis, value) in self.samples.iter_mut().zip(sample) {
+            axis[self.next] = value;
+        }
+        self.next = (self.next + 1) % self.window;
+        self.len = (self.len + 1).min(self.window);
+
+        let mut median = [0.0; 3];
+        for (median, axis) in median.iter_mut().zip(&self.samples) {
+            let mut sorted = [0.0; MAX_WINDOW];
+            let sorted = &mut sorted[..self.len];
+            sorted.copy_from_slice(&axis[..self.len]);
+            sorted.sort_unstable_by(f32::total_cmp);
+            *median = sorted[self.len / 2];
+        }
+        median
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_spike() {
+        let mut filter = MedianFilter::new(3);
+        filter.update([10.0, 20.0, 30.0]);
+        filter.update([11.0, 21.0, 31.0]);
+        assert_eq!(filter.update([500.0, -500.0, 31.0]), [11.0, 20.0, 31.0]);
+        assert_eq!(filter.update([12.0, 22.0, 32.0]), [12.0, 21.0, 31.0]);
+    }
+
+    #[test]
+    fn filling() {
+        let mut filter = MedianFilter::new(5);
+        assert_eq!(filter.update([1.0; 3]), [1.0; 3]);
+        // The upper of the two middle samples
+        assert_eq!(filter.update([3.0; 3]), [3.0; 3]);
+        assert_eq!(filter.update([2.0; 3]), [2.0; 3]);
+    }
+
+    #[test]
+    fn window_off() {
+        for window in [0, 1] {
+            let mut filter = MedianFilter::new(window);
+            filter.update([1.0; 3]);
+            assert_eq!(filter.update([9.0; 3]), [9.0; 3]);
+        }
+    }
+
+    #[test]
+    fn set_window_starts_afresh() {
+        let mut filter = MedianFilter::new(3);
+        filter.update([1.0; 3]);
+        filter.update([1.0; 3]);
+        filter.set_window(3);
+        assert_eq!(filter.update([7.0; 3]), [7.0; 3]);
+    }
+}
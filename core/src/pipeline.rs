@@ -0,0 +1,417 @@
+//! The path from magnetometer readings to the heading shown, as a chain of
+//! [`Stage`]s each working on the same [`Frame`].
+//!
+//! The stages here need nothing from the board: the median and averaging
+//! filters, the tilt-compensated heading, the deviation table, declination
+//! and units. The firmware puts its own in between, for the calibration,
+//! the mounting, gravity, the gyroscope, the GPS course and the hold through
+//! acceleration, and the host simulator runs these alone. A new filter or
+//! correction is a new [`Stage`] put in its place in the list [`run`] is
+//! given, with no change to the loop around it.
+
+use core::fmt;
+
+use crate::{
+    deviation::DeviationTable,
+    heading::{compute_heading, normalize_heading},
+    median::MedianFilter,
+    protocol::Reference,
+    units::HeadingUnit,
+};
+
+/// Most magnetometer readings a frame holds
+pub const MAX_SAMPLES: usize = 16;
+
+/// Most stages a [`Trace`] keeps
+pub const MAX_STAGES: usize = 16;
+
+/// Readings on their way through the stages, filled in stage by stage, with
+/// `X` whatever else the firmware's or a tool's own stages go by
+pub struct Frame<X> {
+    /// The magnetometer readings taken for this frame, nT
+    samples: [[f32; 3]; MAX_SAMPLES],
+    len: usize,
+    /// Acceleration in the flat board frame, mg
+    pub accel: [f32; 3],
+    /// The field, nT: the readings averaged, then calibrated and in the flat
+    /// board frame as the stages go
+    pub mag: [f32; 3],
+    /// Down, for tilt compensation, in the flat board frame, mg
+    pub gravity: [f32; 3],
+    /// Magnetic heading, degrees
+    pub heading: f32,
+    /// From true north, degrees
+    pub true_heading: f32,
+    /// The heading in the configured reference and unit
+    pub output: f32,
+    pub input: X,
+}
+
+impl<X> Frame<X> {
+    pub fn new(accel: [f32; 3], input: X) -> Self {
+        Self {
+            samples: [[0.0; 3]; MAX_SAMPLES],
+            len: 0,
+            accel,
+            mag: [0.0; 3],
+            gravity: accel,
+            heading: 0.0,
+            true_heading: 0.0,
+            output: 0.0,
+            input,
+        }
+    }
+
+    /// Add a magnetometer reading, `false` if [`MAX_SAMPLES`] are already in
+    pub fn push(&mut self, field: [f32; 3]) -> bool {
+        let Some(sample) = self.samples.get_mut(self.len) else {
+            return false;
+        };
+        *sample = field;
+        self.len += 1;
+        true
+    }
+
+    pub fn samples(&self) -> &[[f32; 3]] {
+        &self.samples[..self.len]
+    }
+}
+
+/// The settings the stages here go by
+pub trait Config {
+    fn deviation(&self) -> &DeviationTable;
+
+    /// Degrees, east positive
+    fn declination(&self) -> f32;
+
+    /// The north [`Frame::output`] is from
+    fn reference(&self) -> Reference;
+
+    fn unit(&self) -> HeadingUnit;
+}
+
+/// What a stage made of a frame, for inspection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Probe {
+    /// nT
+    Field([f32; 3]),
+    /// mg
+    Gravity([f32; 3]),
+    /// Degrees
+    Heading(f32),
+    Output(f32, HeadingUnit),
+}
+
+/// One step from readings to heading, going by a context `C`
+pub trait Stage<X, C> {
+    /// Its name in a [`Trace`]
+    fn name(&self) -> &'static str;
+
+    fn apply(&mut self, frame: &mut Frame<X>, context: &C);
+
+    /// The part of `frame` it works on, after [`Self::apply`]
+    fn probe(&self, frame: &Frame<X>) -> Probe {
+        Probe::Heading(frame.heading)
+    }
+}
+
+/// Take `frame` through `stages` in order, adding what each made of it to
+/// `trace`
+pub fn run<X, C>(
+    stages: &mut [&mut dyn Stage<X, C>],
+    frame: &mut Frame<X>,
+    context: &C,
+    trace: &mut Trace,
+) {
+    for stage in stages {
+        stage.apply(frame, context);
+        trace.push(stage.name(), stage.probe(frame));
+    }
+}
+
+/// Each stage's name and what it made of a frame, in order
+pub struct Trace {
+    steps: [(&'static str, Probe); MAX_STAGES],
+    len: usize,
+}
+
+impl Trace {
+    pub const fn new() -> Self {
+        Self {
+            steps: [("", Probe::Heading(0.0)); MAX_STAGES],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Past [`MAX_STAGES`] the step is left out
+    fn push(&mut self, name: &'static str, probe: Probe) {
+        if let Some(step) = self.steps.get_mut(self.len) {
+            *step = (name, probe);
+            self.len += 1;
+        }
+    }
+
+    /// The steps as lines such as `tilt 93.4°`
+    pub fn lines(&self) -> impl Iterator<Item = Step> + '_ {
+        self.steps[..self.len]
+            .iter()
+            .map(|&(name, probe)| Step { name, probe })
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A stage's name and what it made of a frame
+pub struct Step {
+    pub name: &'static str,
+    pub probe: Probe,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name;
+        match self.probe {
+            Probe::Field([x, y, z]) => write!(f, "{name} {x:.0} {y:.0} {z:.0} nT"),
+            Probe::Gravity([x, y, z]) => write!(f, "{name} {x:.0} {y:.0} {z:.0} mg"),
+            Probe::Heading(heading) => write!(f, "{name} {heading:.1}°"),
+            Probe::Output(output, unit) => write!(
+                f,
+                "{name} {output:.decimals$} {unit}",
+                decimals = unit.decimals(),
+                unit = unit.name()
+            ),
+        }
+    }
+}
+
+/// Spikes taken out of each reading by a running median over the last few
+pub struct Median(pub MedianFilter);
+
+impl<X, C> Stage<X, C> for Median {
+    fn name(&self) -> &'static str {
+        "median"
+    }
+
+    fn apply(&mut self, frame: &mut Frame<X>, _context: &C) {
+        for sample in &mut frame.samples[..frame.len] {
+            *sample = self.0.update(*sample);
+        }
+    }
+
+    fn probe(&self, frame: &Frame<X>) -> Probe {
+        Probe::Field(frame.samples().last().copied().unwrap_or_default())
+    }
+}
+
+/// The frame's readings averaged into one field
+pub struct Average;
+
+impl<X, C> Stage<X, C> for Average {
+    fn name(&self) -> &'static str {
+        "average"
+    }
+
+    fn apply(&mut self, frame: &mut Frame<X>, _context: &C) {
+        let samples = frame.samples();
+        if samples.is_empty() {
+            return;
+        }
+        let mut sum = [0.0; 3];
+        for sample in samples {
+            for (sum, value) in sum.iter_mut().zip(sample) {
+                *sum += value;
+            }
+        }
+        let count = samples.len() as f32;
+        frame.mag = sum.map(|sum| sum / count);
+    }
+
+    fn probe(&self, frame: &Frame<X>) -> Probe {
+        Probe::Field(frame.mag)
+    }
+}
+
+/// The tilt-compensated heading
+pub struct Tilt;
+
+impl<X, C> Stage<X, C> for Tilt {
+    fn name(&self) -> &'static str {
+        "tilt"
+    }
+
+    fn apply(&mut self, frame: &mut Frame<X>, _context: &C) {
+        let [grav_x, grav_y, grav_z] = frame.gravity;
+        let [mag_x, mag_y, mag_z] = frame.mag;
+        frame.heading = compute_heading(grav_x, grav_y, grav_z, mag_x, mag_y, mag_z);
+    }
+}
+
+/// What the installation still adds after calibration
+pub struct Deviation;
+
+impl<X, C: Config> Stage<X, C> for Deviation {
+    fn name(&self) -> &'static str {
+        "deviation"
+    }
+
+    fn apply(&mut self, frame: &mut Frame<X>, context: &C) {
+        frame.heading = context.deviation().correct(frame.heading);
+    }
+}
+
+/// From true north as well
+pub struct Declination;
+
+impl<X, C: Config> Stage<X, C> for Declination {
+    fn name(&self) -> &'static str {
+        "declination"
+    }
+
+    fn apply(&mut self, frame: &mut Frame<X>, context: &C) {
+        frame.true_heading = normalize_heading(frame.heading + context.declination());
+    }
+
+    fn probe(&self, frame: &Frame<X>) -> Probe {
+        Probe::Heading(frame.true_heading)
+    }
+}
+
+/// The heading from the configured north in the configured unit, the last
+/// one it was converted to
+pub struct Units(pub HeadingUnit);
+
+impl<X, C: Config> Stage<X, C> for Units {
+    fn name(&self) -> &'static str {
+        "units"
+    }
+
+    fn apply(&mut self, frame: &mut Frame<X>, context: &C) {
+        let heading = match context.reference() {
+            Reference::Magnetic => frame.heading,
+            Reference::True => frame.true_heading,
+        };
+        self.0 = context.unit();
+        frame.output = self.0.from_degrees(heading);
+    }
+
+    fn probe(&self, frame: &Frame<X>) -> Probe {
+        Probe::Output(frame.output, self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Settings {
+        deviation: DeviationTable,
+        declination: f32,
+        reference: Reference,
+        unit: HeadingUnit,
+    }
+
+    impl Config for Settings {
+        fn deviation(&self) -> &DeviationTable {
+            &self.deviation
+        }
+
+        fn declination(&self) -> f32 {
+            self.declination
+        }
+
+        fn reference(&self) -> Reference {
+            self.reference
+        }
+
+        fn unit(&self) -> HeadingUnit {
+            self.unit
+        }
+    }
+
+    const FLAT: [f32; 3] = [0.0, 0.0, 1000.0];
+
+    const SETTINGS: Settings = Settings {
+        deviation: DeviationTable::EMPTY,
+        declination: 10.0,
+        reference: Reference::True,
+        unit: HeadingUnit::Mils,
+    };
+
+    #[test]
+    fn full_run() {
+        let mut median = Median(MedianFilter::new(3));
+        let mut stages: [&mut dyn Stage<(), Settings>; 6] = [
+            &mut median,
+            &mut Average,
+            &mut Tilt,
+            &mut Deviation,
+            &mut Declination,
+            &mut Units(HeadingUnit::Degrees),
+        ];
+        let mut frame = Frame::new(FLAT, ());
+        for field in [[0.0, 20_000.0, 0.0]; 4] {
+            assert!(frame.push(field));
+        }
+        let mut trace = Trace::new();
+        run(&mut stages, &mut frame, &SETTINGS, &mut trace);
+        assert!((frame.heading - 90.0).abs() < 0.1);
+        assert!((frame.true_heading - 100.0).abs() < 0.1);
+        assert!((frame.output - 100.0 * 6400.0 / 360.0).abs() < 1.0);
+
+        let names: [&str; 6] = core::array::from_fn(|i| trace.lines().nth(i).unwrap().name);
+        assert_eq!(
+            names,
+            [
+                "median",
+                "average",
+                "tilt",
+                "deviation",
+                "declination",
+                "units"
+            ]
+        );
+        assert_eq!(
+            trace.lines().last().unwrap().to_string(),
+            format!("units {:.0} mils", frame.output)
+        );
+    }
+
+    #[test]
+    fn median_then_average() {
+        let mut median = Median(MedianFilter::new(3));
+        let mut stages: [&mut dyn Stage<(), Settings>; 2] = [&mut median, &mut Average];
+        let mut frame = Frame::new(FLAT, ());
+        for x in [100.0, 100.0, 5000.0, 100.0, 400.0] {
+            frame.push([x, 0.0, 0.0]);
+        }
+        run(&mut stages, &mut frame, &SETTINGS, &mut Trace::new());
+        // The spike is gone: 100, 100, 100, 100 and 400
+        assert_eq!(frame.mag, [160.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn frame_full() {
+        let mut frame = Frame::new(FLAT, ());
+        for _ in 0..MAX_SAMPLES {
+            assert!(frame.push([1.0; 3]));
+        }
+        assert!(!frame.push([1.0; 3]));
+        assert_eq!(frame.samples().len(), MAX_SAMPLES);
+    }
+
+    #[test]
+    fn no_readings() {
+        let mut frame = Frame::new(FLAT, ());
+        frame.mag = [1.0, 2.0, 3.0];
+        Stage::<(), Settings>::apply(&mut Average, &mut frame, &SETTINGS);
+        assert_eq!(frame.mag, [1.0, 2.0, 3.0]);
+    }
+}
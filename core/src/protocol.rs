@@ -0,0 +1,244 @@
+//! Binary telemetry protocol.
+//!
+//! Each [`Message`] is serialized with [postcard] and framed with COBS, so
+//! frames contain no zero bytes and each one ends with a single `0x00`. A
+//! reader joining part way through the stream drops bytes up to the next
+//! `0x00` and is in sync from then on.
+//!
+//! Postcard encodes the variant index as a varint (one byte here), then the
+//! fields in order: `f32` as 4 bytes little endian, `bool` as one byte,
+//! `Option<T>` as `0x00` or `0x01` followed by the value, and arrays as their
+//! elements with no length prefix. Unsigned integers are varints, 7 bits per
+//! byte, least significant first.
+//!
+//! | Index | Message              | Fields                                                                                            |
+//! | ----- | -------------------- | ------------------------------------------------------------------------------------------------- |
+//! | 0     | `Heading`            | `heading`, `reference`, `display_reference`, `magnetic_heading`, `true_heading?`, `pitch`, `roll` |
+//! | 1     | `RawSample`          | `accel[3]` (mg), `mag[3]` (nT)                                                                    |
+//! | 2     | `Calibration`        | `calibrating`, `calibrated`, `offset[3]` (nT), `soft_iron[3][3]`                                  |
+//! | 3     | `Tilt`               | `pitch`, `roll`, `orientation`                                                                    |
+//! | 4     | `Position`           | `steps` (varint), `east`, `north` (m)                                                             |
+//! | 5     | `FreeFall`           | none                                                                                              |
+//! | 6     | `RateOfTurn`         | `rate` (°/s, positive clockwise)                                                                  |
+//! | 7     | `HeadingStats`       | `mean`, `min`, `max`, `variance` (°²)                                                             |
+//! | 8     | `Battery`            | `millivolts` (varint), `percent` (one byte)                                                       |
+//! | 9     | `CalibrationQuality` | `score` (one byte, 0 to 100), `coverage`, `residual` (fractions), `kept`                          |
+//! | 10    | `Attitude`           | `quaternion[4]` (`w`, `x`, `y`, `z`), `euler[3]?` (yaw, pitch, roll)                              |
+//! | 11    | `Timestamp`          | `ms` (varint, since boot)                                                                         |
+//!
+//! [`Reference`]s and [`Orientation`]s are encoded as their variant index,
+//! e.g. `0` magnetic and `1` true. Angles are in degrees. New messages are
+//! only ever added at the end, so existing indices keep their meaning.
+//!
+//! [postcard]: https://docs.rs/postcard
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the serial protocol: this binary telemetry and the console's
+/// commands and replies. Raised whenever a change would break a host tool
+/// written against the last one, such as a field changing meaning or a
+/// reply changing format. Adding messages at the end or new commands
+/// doesn't.
+/// The firmware reports it in reply to `hello`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Longest encoded frame, including COBS overhead and the `0x00` terminator
+pub const MAX_FRAME_LEN: usize = 64;
+
+/// Which north a heading is measured from
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Reference {
+    Magnetic,
+    /// Magnetic plus the configured declination
+    True,
+}
+
+/// Which side of the board faces up, from gravity alone
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Orientation {
+    /// Lying flat, display up
+    FaceUp,
+    /// Lying flat, display down
+    FaceDown,
+    /// Standing on the edge connector
+    LogoUp,
+    /// Hanging from the edge connector
+    LogoDown,
+    /// Standing on its right edge (button B side)
+    LeftUp,
+    /// Standing on its left edge (button A side)
+    RightUp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    /// Attitude from one sensor reading
+    Heading {
+        /// Relative to `reference`, picked for telemetry independently of
+        /// the display
+        heading: f32,
+        reference: Reference,
+        /// North shown on the display, for information
+        display_reference: Reference,
+        magnetic_heading: f32,
+        /// `None` without a declination
+        true_heading: Option<f32>,
+        pitch: f32,
+        roll: f32,
+    },
+    /// Raw sensor values from the same reading as the preceding `Heading`
+    RawSample { accel: [f32; 3], mag: [f32; 3] },
+    /// Sent at startup and whenever calibration starts or finishes
+    Calibration {
+        /// The figure-8 calibration is in progress
+        calibrating: bool,
+        /// `offset` and `scale` come from a completed calibration rather than
+        /// the identity defaults
+        calibrated: bool,
+        offset: [f32; 3],
+        /// Rows of the matrix applied after removing `offset`
+        soft_iron: [[f32; 3]; 3],
+    },
+    /// Attitude in tilt-only mode, sent instead of `Heading`
+    Tilt {
+        pitch: f32,
+        roll: f32,
+        orientation: Orientation,
+    },
+    /// Dead-reckoned position from steps and heading, relative to where it
+    /// was last reset, following `Heading` and `RawSample`
+    Position { steps: u32, east: f32, north: f32 },
+    /// The board was dropped, sent once as the fall is detected
+    FreeFall,
+    /// How fast the heading is changing, following `Position`
+    RateOfTurn { rate: f32 },
+    /// Spread of the heading over the last window of samples, every few
+    /// seconds
+    HeadingStats {
+        mean: f32,
+        min: f32,
+        max: f32,
+        variance: f32,
+    },
+    /// Supply voltage and estimated charge left, every minute
+    Battery { millivolts: u16, percent: u8 },
+    /// How good a just finished figure-8 calibration is. Below a score of 50
+    /// it is not kept.
+    CalibrationQuality {
+        score: u8,
+        /// Fraction of the sphere of directions covered
+        coverage: f32,
+        /// RMS error of the corrected field strength, relative to the
+        /// expected field strength
+        residual: f32,
+        kept: bool,
+    },
+    /// Orientation for use as an AHRS, at its own configured rate. The
+    /// quaternion is built from the heading, pitch and roll of the same
+    /// reading, applied as yaw, pitch and roll in that order.
+    Attitude {
+        quaternion: [f32; 4],
+        /// Yaw (the telemetry heading), pitch and roll, if asked for
+        euler: Option<[f32; 3]>,
+    },
+    /// When the sensor reading behind the following `Heading` or `Tilt` was
+    /// taken, milliseconds since boot, for working out rates and gaps
+    Timestamp { ms: u32 },
+    /// Two magnetometers' headings started or stopped disagreeing by more
+    /// than the configured limit
+    CrossCheck {
+        fault: bool,
+        /// The heading now comes from the backup magnetometer
+        backup: bool,
+        /// Backup heading less the primary's, degrees
+        difference: f32,
+    },
+}
+
+impl Message {
+    /// Serialize and COBS-frame the message into `buf`, returning the frame
+    pub fn encode<'a>(&self, buf: &'a mut [u8; MAX_FRAME_LEN]) -> &'a [u8] {
+        // Every message fits in `MAX_FRAME_LEN`
+        postcard::to_slice_cobs(self, buf).unwrap()
+    }
+
+    /// Decode one frame, with or without its `0x00` terminator. The frame is
+    /// decoded in place.
+    pub fn decode(frame: &mut [u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes_cobs(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: Message) {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let frame = message.encode(&mut buf);
+        // Ends on the one zero byte
+        assert_eq!(frame.iter().position(|&b| b == 0), Some(frame.len() - 1));
+        let mut copy = [0; MAX_FRAME_LEN];
+        copy[..frame.len()].copy_from_slice(frame);
+        assert_eq!(Message::decode(&mut copy[..frame.len()]).unwrap(), message);
+        // Without the terminator too
+        copy[..frame.len()].copy_from_slice(frame);
+        assert_eq!(
+            Message::decode(&mut copy[..frame.len() - 1]).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(Message::Heading {
+            heading: 123.5,
+            reference: Reference::True,
+            display_reference: Reference::Magnetic,
+            magnetic_heading: 120.0,
+            true_heading: Some(123.5),
+            pitch: -1.5,
+            roll: 0.0,
+        });
+        round_trip(Message::Tilt {
+            pitch: 10.0,
+            roll: -20.0,
+            orientation: Orientation::LeftUp,
+        });
+        round_trip(Message::Battery {
+            millivolts: 2950,
+            percent: 80,
+        });
+        round_trip(Message::FreeFall);
+        round_trip(Message::Attitude {
+            quaternion: [1.0, 0.0, 0.0, 0.0],
+            euler: None,
+        });
+    }
+
+    #[test]
+    fn largest_fits() {
+        round_trip(Message::Calibration {
+            calibrating: false,
+            calibrated: true,
+            offset: [f32::MAX; 3],
+            soft_iron: [[f32::MIN; 3]; 3],
+        });
+    }
+
+    #[test]
+    fn variant_index_first() {
+        let mut buf = [0; MAX_FRAME_LEN];
+        // COBS code byte, then the index
+        assert_eq!(Message::FreeFall.encode(&mut buf), [0x02, 5, 0x00]);
+        assert_eq!(
+            Message::Timestamp { ms: 300 }.encode(&mut buf),
+            [0x04, 11, 0xAC, 0x02, 0x00]
+        );
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(Message::decode(&mut [0x03, 0xFF, 0xFF, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Where the sun is in the sky, to check the heading against.
+//!
+//! Low-precision formulas from the Astronomical Almanac, good to about 0.01°
+//! between 1950 and 2050, far better than any compass. Refraction is
+//! ignored, it only lifts the sun near the horizon.
+
+use micromath::F32Ext;
+
+/// Days from the Unix epoch to J2000.0, noon on 1 January 2000
+const J2000_UNIX_DAYS: f64 = 10_957.5;
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// The sun's direction from a place on Earth, in degrees
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SunPosition {
+    /// Clockwise from true north
+    pub azimuth: f32,
+    /// Above the horizon, negative below it
+    pub elevation: f32,
+}
+
+/// Days from 1970-01-01 to a date in the proleptic Gregorian calendar, with
+/// `month` 1 to 12
+pub fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
+    // Howard Hinnant's algorithm, counting years from March so the leap day
+    // comes last
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Where the sun is at `unix_seconds` seen from `latitude` and `longitude`,
+/// in degrees north and east
+pub fn position(unix_seconds: i64, latitude: f32, longitude: f32) -> SunPosition {
+    // Days since J2000.0, kept in f64 until the angles are wrapped, as f32
+    // would lose minutes
+    let n = unix_seconds as f64 / SECONDS_PER_DAY - J2000_UNIX_DAYS;
+
+    // Mean longitude and mean anomaly
+    let mean_longitude = wrap(280.460 + 0.985_647_4 * n, 360.0);
+    let anomaly = wrap(357.528 + 0.985_600_3 * n, 360.0).to_radians();
+    // Ecliptic longitude and the obliquity of the ecliptic
+    let ecliptic =
+        (mean_longitude + 1.915 * anomaly.sin() + 0.020 * (2.0 * anomaly).sin()).to_radians();
+    let obliquity = (23.439 - 0.000_000_4 * n as f32).to_radians();
+
+    let right_ascension = (obliquity.cos() * ecliptic.sin()).atan2(ecliptic.cos());
+    let declination = (obliquity.sin() * ecliptic.sin()).asin();
+
+    // Greenwich mean sidereal time, then the local hour angle
+    let sidereal = wrap(280.460_618_37 + 360.985_647_366_29 * n, 360.0);
+    let hour_angle = (sidereal + longitude).to_radians() - right_ascension;
+
+    let latitude = latitude.to_radians();
+    let elevation = (latitude.sin() * declination.sin()
+        + latitude.cos() * declination.cos() * hour_angle.cos())
+    .asin();
+    let azimuth = (-hour_angle.sin())
+        .atan2(declination.tan() * latitude.cos() - latitude.sin() * hour_angle.cos());
+
+    let mut azimuth = azimuth.to_degrees();
+    if azimuth < 0.0 {
+        azimuth += 360.0;
+    }
+    SunPosition {
+        azimuth,
+        elevation: elevation.to_degrees(),
+    }
+}
+
+/// `value` wrapped into 0 to `range`, as f32 now it's small
+fn wrap(value: f64, range: f64) -> f32 {
+    let wrapped = value % range;
+    (if wrapped < 0.0 {
+        wrapped + range
+    } else {
+        wrapped
+    }) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 1, 1), 10_957);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+
+    /// Meeus, Astronomical Algorithms, example 25.a: on 1992 October 13.0 TD
+    /// the sun is at right ascension 13h 13m 31.4s, declination -7° 47' 01",
+    /// seen from Sydney at 48.02° azimuth, 54.81° elevation
+    #[test]
+    fn ephemeris() {
+        // ΔT was 59 s
+        let unix_seconds = days_from_civil(1992, 10, 13) * 86_400 - 59;
+        let sun = position(unix_seconds, -33.8688, 151.2093);
+        assert!((sun.azimuth - 48.02).abs() < 0.05, "{sun:?}");
+        assert!((sun.elevation - 54.81).abs() < 0.05, "{sun:?}");
+    }
+
+    #[test]
+    fn below_horizon_at_midnight() {
+        // Local midnight in London, a week after the June solstice
+        let unix_seconds = days_from_civil(2024, 6, 28) * 86_400;
+        let sun = position(unix_seconds, 51.5, 0.0);
+        assert!(sun.elevation < -10.0, "{sun:?}");
+        assert!(sun.azimuth < 10.0 || sun.azimuth > 350.0, "{sun:?}");
+    }
+}
@@ -0,0 +1,108 @@
+//! Heading units.
+//!
+//! Headings are computed in degrees and only converted for output. NATO mils
+//! divide a turn into 6400, so one mil is about a meter at a kilometer, and
+//! grads divide it into 400.
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeadingUnit {
+    Degrees,
+    Radians,
+    /// NATO mils, 6400 to a turn
+    Mils,
+    /// Gradians, 400 to a turn
+    Grads,
+}
+
+impl HeadingUnit {
+    pub const ALL: [Self; 4] = [Self::Degrees, Self::Radians, Self::Mils, Self::Grads];
+
+    /// One full turn in this unit
+    pub const fn full_turn(self) -> f32 {
+        match self {
+            Self::Degrees => 360.0,
+            Self::Radians => core::f32::consts::TAU,
+            Self::Mils => 6400.0,
+            Self::Grads => 400.0,
+        }
+    }
+
+    pub fn from_degrees(self, degrees: f32) -> f32 {
+        degrees * (self.full_turn() / 360.0)
+    }
+
+    /// Decimal places resolving about a tenth of a degree
+    pub const fn decimals(self) -> usize {
+        match self {
+            Self::Degrees | Self::Grads => 1,
+            Self::Radians => 3,
+            Self::Mils => 0,
+        }
+    }
+
+    /// Name used on the serial console
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Degrees => "degrees",
+            Self::Radians => "radians",
+            Self::Mils => "mils",
+            Self::Grads => "grads",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|unit| unit.name() == name)
+    }
+
+    /// `degrees` converted to this unit, formatted to [`Self::decimals`]
+    pub const fn format(self, degrees: f32) -> FormattedHeading {
+        FormattedHeading {
+            degrees,
+            unit: self,
+        }
+    }
+}
+
+/// A heading that displays in its unit, see [`HeadingUnit::format`]
+#[derive(Clone, Copy, Debug)]
+pub struct FormattedHeading {
+    degrees: f32,
+    unit: HeadingUnit,
+}
+
+impl fmt::Display for FormattedHeading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.unit.from_degrees(self.degrees);
+        write!(f, "{:.*}", self.unit.decimals(), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_turn() {
+        for unit in HeadingUnit::ALL {
+            assert!((unit.from_degrees(360.0) / unit.full_turn() - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(HeadingUnit::Degrees.format(90.04).to_string(), "90.0");
+        assert_eq!(HeadingUnit::Radians.format(180.0).to_string(), "3.142");
+        assert_eq!(HeadingUnit::Mils.format(90.0).to_string(), "1600");
+        assert_eq!(HeadingUnit::Grads.format(270.0).to_string(), "300.0");
+    }
+
+    #[test]
+    fn names() {
+        for unit in HeadingUnit::ALL {
+            assert_eq!(HeadingUnit::from_name(unit.name()), Some(unit));
+        }
+        assert_eq!(HeadingUnit::from_name("turns"), None);
+    }
+}
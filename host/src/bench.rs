@@ -0,0 +1,319 @@
+//! Scores the heading smoothing options against each other on recorded or
+//! scripted data, so the defaults come from measurements.
+//!
+//! Each option runs over the same readings. Its output is compared with the
+//! true heading: a script's own, noise free, or for a capture, which has no
+//! truth, the circular mean of the raw headings over [`REFERENCE_SAMPLES`]
+//! either side, which doesn't lag. Two numbers come out of it:
+//!
+//! - lag: how far behind the truth the output runs while it turns, the shift
+//!   that matches them best
+//! - RMS error: how far off the needle is, lag and noise together
+//!
+//! and the RMS error left once the lag is taken out, the noise that gets
+//! through.
+
+use std::time::Duration;
+
+use micro_compass_core::{
+    heading::{angle_diff, compute_heading, normalize_heading},
+    median::MedianFilter,
+};
+
+use crate::simulate::{self, Reading, MEDIAN_WINDOW};
+
+/// Raw headings either side of each one averaged into a capture's reference
+const REFERENCE_SAMPLES: usize = 5;
+
+/// Longest lag looked for, in readings
+const MAX_LAG: usize = 50;
+
+/// The lag is measured where the truth turns at least this much from one
+/// reading to the next, degrees, as there's nothing to lag behind otherwise
+const TURNING: f32 = 1.0;
+
+/// Iterations the Madgwick filter runs on the first reading, so it starts
+/// settled rather than turning from north
+const MADGWICK_WARM_UP: usize = 1000;
+
+/// A smoothing option, turning readings into headings
+trait Filter {
+    fn update(&mut self, reading: &Reading, dt: f32) -> f32;
+}
+
+/// No smoothing, the tilt-compensated heading of each reading
+struct Raw;
+
+impl Filter for Raw {
+    fn update(&mut self, reading: &Reading, _dt: f32) -> f32 {
+        heading(reading.accel, reading.mag)
+    }
+}
+
+/// The firmware's median filter on the field, see `set median`
+struct Median(MedianFilter);
+
+impl Filter for Median {
+    fn update(&mut self, reading: &Reading, _dt: f32) -> f32 {
+        heading(reading.accel, self.0.update(reading.mag))
+    }
+}
+
+/// Exponential moving average of the heading, taking `alpha` of each new one,
+/// averaged as a unit vector so it doesn't swing through south at north
+struct Ema {
+    alpha: f32,
+    vector: Option<(f32, f32)>,
+}
+
+impl Filter for Ema {
+    fn update(&mut self, reading: &Reading, _dt: f32) -> f32 {
+        let (sin, cos) = heading(reading.accel, reading.mag).to_radians().sin_cos();
+        let (s, c) = self.vector.get_or_insert((sin, cos));
+        *s += self.alpha * (sin - *s);
+        *c += self.alpha * (cos - *c);
+        normalize_heading(s.atan2(*c).to_degrees())
+    }
+}
+
+/// Madgwick's gradient descent orientation filter with `beta` in rad/s, fed
+/// no rate of turn, as the board has no gyroscope without the `gyro`
+/// feature. Each reading then pulls the orientation towards the one the
+/// accelerometer and magnetometer give by at most `beta` per second.
+struct Madgwick {
+    beta: f32,
+    /// Orientation quaternion, w first
+    q: [f32; 4],
+    settled: bool,
+}
+
+impl Madgwick {
+    fn new(beta: f32) -> Self {
+        Self {
+            beta,
+            q: [1.0, 0.0, 0.0, 0.0],
+            settled: false,
+        }
+    }
+
+    /// One step of the filter, as in Madgwick's MARG update with the
+    /// gyroscope terms zero
+    fn step(&mut self, accel: [f32; 3], mag: [f32; 3], dt: f32) {
+        let (Some([ax, ay, az]), Some([mx, my, mz])) = (unit(accel), unit(mag)) else {
+            return;
+        };
+        let [q0, q1, q2, q3] = self.q;
+
+        // The earth's field, turned into the earth frame, has no east part
+        let hx = mx * (q0 * q0 + q1 * q1 - q2 * q2 - q3 * q3)
+            + 2.0 * my * (q1 * q2 - q0 * q3)
+            + 2.0 * mz * (q0 * q2 + q1 * q3);
+        let hy = 2.0 * mx * (q0 * q3 + q1 * q2)
+            + my * (q0 * q0 - q1 * q1 + q2 * q2 - q3 * q3)
+            + 2.0 * mz * (q2 * q3 - q0 * q1);
+        let bx = (hx * hx + hy * hy).sqrt();
+        let bz = 2.0 * mx * (q1 * q3 - q0 * q2)
+            + 2.0 * my * (q0 * q1 + q2 * q3)
+            + mz * (q0 * q0 - q1 * q1 - q2 * q2 + q3 * q3);
+
+        // Where gravity and the field should be measured, less where they are
+        let fg = [
+            2.0 * (q1 * q3 - q0 * q2) - ax,
+            2.0 * (q0 * q1 + q2 * q3) - ay,
+            2.0 * (0.5 - q1 * q1 - q2 * q2) - az,
+        ];
+        let fb = [
+            2.0 * bx * (0.5 - q2 * q2 - q3 * q3) + 2.0 * bz * (q1 * q3 - q0 * q2) - mx,
+            2.0 * bx * (q1 * q2 - q0 * q3) + 2.0 * bz * (q0 * q1 + q2 * q3) - my,
+            2.0 * bx * (q0 * q2 + q1 * q3) + 2.0 * bz * (0.5 - q1 * q1 - q2 * q2) - mz,
+        ];
+        // The Jacobians' transposes times the errors
+        let gradient = [
+            -2.0 * q2 * fg[0] + 2.0 * q1 * fg[1] - 2.0 * bz * q2 * fb[0]
+                + (-2.0 * bx * q3 + 2.0 * bz * q1) * fb[1]
+                + 2.0 * bx * q2 * fb[2],
+            2.0 * q3 * fg[0] + 2.0 * q0 * fg[1] - 4.0 * q1 * fg[2]
+                + 2.0 * bz * q3 * fb[0]
+                + (2.0 * bx * q2 + 2.0 * bz * q0) * fb[1]
+                + (2.0 * bx * q3 - 4.0 * bz * q1) * fb[2],
+            -2.0 * q0 * fg[0] + 2.0 * q3 * fg[1] - 4.0 * q2 * fg[2]
+                + (-4.0 * bx * q2 - 2.0 * bz * q0) * fb[0]
+                + (2.0 * bx * q1 + 2.0 * bz * q3) * fb[1]
+                + (2.0 * bx * q0 - 4.0 * bz * q2) * fb[2],
+            2.0 * q1 * fg[0]
+                + 2.0 * q2 * fg[1]
+                + (-4.0 * bx * q3 + 2.0 * bz * q1) * fb[0]
+                + (-2.0 * bx * q0 + 2.0 * bz * q2) * fb[1]
+                + 2.0 * bx * q1 * fb[2],
+        ];
+        let Some(gradient) = unit(gradient) else {
+            return;
+        };
+        let q = core::array::from_fn(|i| self.q[i] - self.beta * gradient[i] * dt);
+        if let Some(q) = unit(q) {
+            self.q = q;
+        }
+    }
+}
+
+impl Filter for Madgwick {
+    fn update(&mut self, reading: &Reading, dt: f32) -> f32 {
+        if !self.settled {
+            for _ in 0..MADGWICK_WARM_UP {
+                self.step(reading.accel, reading.mag, dt);
+            }
+            self.settled = true;
+        }
+        self.step(reading.accel, reading.mag, dt);
+        let [q0, q1, q2, q3] = self.q;
+        // Yaw of the sensor frame from the earth frame, the other way round
+        // to the heading
+        let yaw = (2.0 * (q1 * q2 - q0 * q3)).atan2(2.0 * (q0 * q0 + q1 * q1) - 1.0);
+        normalize_heading(yaw.to_degrees())
+    }
+}
+
+/// `v` scaled to length 1, `None` if it has none
+fn unit<const N: usize>(v: [f32; N]) -> Option<[f32; N]> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    (norm > 0.0).then(|| v.map(|x| x / norm))
+}
+
+fn heading(accel: [f32; 3], mag: [f32; 3]) -> f32 {
+    let [ax, ay, az] = accel;
+    let [mx, my, mz] = mag;
+    compute_heading(ax, ay, az, mx, my, mz)
+}
+
+/// Every option scored, by name
+fn filters() -> Vec<(String, Box<dyn Filter>)> {
+    let mut filters: Vec<(String, Box<dyn Filter>)> = vec![(String::from("none"), Box::new(Raw))];
+    for window in [3, 5] {
+        let default = if window == MEDIAN_WINDOW {
+            " (default)"
+        } else {
+            ""
+        };
+        filters.push((
+            format!("median {window}{default}"),
+            Box::new(Median(MedianFilter::new(window))),
+        ));
+    }
+    for alpha in [0.5, 0.3, 0.1] {
+        filters.push((
+            format!("ema {alpha}"),
+            Box::new(Ema {
+                alpha,
+                vector: None,
+            }),
+        ));
+    }
+    for beta in [2.0, 1.0, 0.5] {
+        filters.push((format!("madgwick {beta}"), Box::new(Madgwick::new(beta))));
+    }
+    filters
+}
+
+/// How an option did on one dataset
+struct Score {
+    /// `None` if the truth never turns
+    lag: Option<Duration>,
+    rms: f32,
+    rms_without_lag: f32,
+}
+
+/// RMS of the differences between `output` and `truth` at the readings
+/// `at`, with the output shifted `lag` readings earlier
+fn rms(output: &[f32], truth: &[f32], lag: usize, at: &[usize]) -> f32 {
+    let squares: Vec<f32> = at
+        .iter()
+        .filter_map(|&i| Some(angle_diff(*output.get(i + lag)?, truth[i]).powi(2)))
+        .collect();
+    if squares.is_empty() {
+        return 0.0;
+    }
+    (squares.iter().sum::<f32>() / squares.len() as f32).sqrt()
+}
+
+fn score(output: &[f32], truth: &[f32], interval: Duration) -> Score {
+    let all: Vec<usize> = (0..truth.len()).collect();
+    let turning: Vec<usize> = (1..truth.len())
+        .filter(|&i| angle_diff(truth[i], truth[i - 1]).abs() >= TURNING)
+        .collect();
+    let lag = (!turning.is_empty()).then(|| {
+        (0..=MAX_LAG)
+            .map(|lag| (lag, rms(output, truth, lag, &turning)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0, |(lag, _)| lag)
+    });
+    Score {
+        lag: lag.map(|lag| interval * lag as u32),
+        rms: rms(output, truth, 0, &all),
+        rms_without_lag: rms(output, truth, lag.unwrap_or(0), &all),
+    }
+}
+
+/// The circular mean of the raw headings around each one
+fn reference(raw: &[f32]) -> Vec<f32> {
+    (0..raw.len())
+        .map(|i| {
+            let around =
+                &raw[i.saturating_sub(REFERENCE_SAMPLES)..raw.len().min(i + REFERENCE_SAMPLES + 1)];
+            let (sin, cos) = around.iter().fold((0.0, 0.0), |(sin, cos), h| {
+                let (s, c) = h.to_radians().sin_cos();
+                (sin + s, cos + c)
+            });
+            normalize_heading(sin.atan2(cos).to_degrees())
+        })
+        .collect()
+}
+
+/// Every reading of a capture or script, with the disturbances in the file
+/// `disturb` added if given
+fn readings(path: &str, disturb: Option<&str>) -> Result<(Vec<Reading>, Duration), String> {
+    let mut sensor = simulate::open(path, disturb)?;
+    let interval = sensor.interval();
+    Ok((std::iter::from_fn(|| sensor.read()).collect(), interval))
+}
+
+/// Run every option over each capture or script, disturbed by the file
+/// `disturb` if given, and print a table of scores for each
+pub fn run(paths: &[&str], disturb: Option<&str>) -> Result<(), String> {
+    for path in paths {
+        let (readings, interval) = readings(path, disturb)?;
+        let truth = match readings.iter().map(|r| r.truth).collect::<Option<Vec<_>>>() {
+            Some(truth) => truth,
+            // From the capture as it was, so the disturbances count as error
+            None => {
+                let (clean, _) = self::readings(path, None)?;
+                let raw: Vec<f32> = clean
+                    .iter()
+                    .map(|reading| heading(reading.accel, reading.mag))
+                    .collect();
+                reference(&raw)
+            }
+        };
+
+        println!("{path}: {} readings", readings.len());
+        println!(
+            "{:<20} {:>8} {:>8} {:>14}",
+            "filter", "lag ms", "RMS °", "RMS no lag °"
+        );
+        for (name, mut filter) in filters() {
+            let output: Vec<f32> = readings
+                .iter()
+                .map(|reading| filter.update(reading, interval.as_secs_f32()))
+                .collect();
+            let score = score(&output, &truth, interval);
+            let lag = score
+                .lag
+                .map_or(String::from("-"), |lag| lag.as_millis().to_string());
+            println!(
+                "{name:<20} {lag:>8} {:>8.2} {:>14.2}",
+                score.rms, score.rms_without_lag
+            );
+        }
+        println!();
+    }
+    Ok(())
+}
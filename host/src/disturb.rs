@@ -0,0 +1,216 @@
+//! Magnetic disturbances added to a mock sensor's field, so the firmware's
+//! filtering and interference handling can be tried against the same fault
+//! every run.
+//!
+//! A disturbance file has one disturbance per line:
+//!
+//! ```text
+//! # <step|ramp|noise> <from seconds> <to seconds> <axes> <nT>
+//! step 2 4 x 5000
+//! ramp 6 10 xy 8000
+//! noise 12 13 z 3000
+//! ```
+//!
+//! Between the two times, on each of the axes named, of the flat board
+//! frame, a `step` adds the field given, a `ramp` adds a field rising from
+//! nothing to the one given, and a `noise` burst adds noise with that
+//! standard deviation. Each is gone again after its end time. Disturbances
+//! that overlap add up.
+
+use std::{fs, time::Duration};
+
+use crate::simulate::{MockSensor, Noise, Reading};
+
+/// What a disturbance does to the field while it lasts
+#[derive(Clone, Copy)]
+enum Kind {
+    Step,
+    Ramp,
+    Noise,
+}
+
+struct Disturbance {
+    kind: Kind,
+    from: f32,
+    to: f32,
+    /// Which of x, y and z it is added to
+    axes: [bool; 3],
+    nt: f32,
+}
+
+/// The disturbances from a file, in the order given
+pub struct Disturbances {
+    list: Vec<Disturbance>,
+    noise: Noise,
+}
+
+impl Disturbances {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+        Self::parse(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut list = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at = |e: &str| format!("line {}: {e}", number + 1);
+            let usage = "expected <step|ramp|noise> <from> <to> <axes> <nT>";
+            let [kind, from, to, axes, nt] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(at(usage));
+            };
+            let kind = match kind {
+                "step" => Kind::Step,
+                "ramp" => Kind::Ramp,
+                "noise" => Kind::Noise,
+                _ => return Err(at(usage)),
+            };
+            let number = |value: &str| value.parse::<f32>().map_err(|_| at(usage));
+            let (from, to, nt) = (number(from)?, number(to)?, number(nt)?);
+            if to <= from {
+                return Err(at("a disturbance must end after it starts"));
+            }
+            if axes.is_empty() || !axes.chars().all(|axis| "xyz".contains(axis)) {
+                return Err(at("axes must be some of x, y and z"));
+            }
+            list.push(Disturbance {
+                kind,
+                from,
+                to,
+                axes: ['x', 'y', 'z'].map(|axis| axes.contains(axis)),
+                nt,
+            });
+        }
+        Ok(Self {
+            list,
+            noise: Noise::new(0x9E37_79B9),
+        })
+    }
+
+    /// The field added at `seconds`, nT
+    pub fn field(&mut self, seconds: f32) -> [f32; 3] {
+        let mut field = [0.0; 3];
+        for disturbance in &self.list {
+            if !(disturbance.from..disturbance.to).contains(&seconds) {
+                continue;
+            }
+            for (axis, added) in field.iter_mut().enumerate() {
+                if !disturbance.axes[axis] {
+                    continue;
+                }
+                *added += match disturbance.kind {
+                    Kind::Step => disturbance.nt,
+                    Kind::Ramp => {
+                        disturbance.nt * (seconds - disturbance.from)
+                            / (disturbance.to - disturbance.from)
+                    }
+                    Kind::Noise => disturbance.nt * self.noise.sample(),
+                };
+            }
+        }
+        field
+    }
+}
+
+/// A mock sensor with [`Disturbances`] added to its field, timed from its
+/// first reading. A script's true heading is left as it was.
+pub struct Disturbed {
+    sensor: Box<dyn MockSensor>,
+    disturbances: Disturbances,
+    step: u32,
+}
+
+impl Disturbed {
+    pub fn new(sensor: Box<dyn MockSensor>, disturbances: Disturbances) -> Self {
+        Self {
+            sensor,
+            disturbances,
+            step: 0,
+        }
+    }
+}
+
+impl MockSensor for Disturbed {
+    fn read(&mut self) -> Option<Reading> {
+        let mut reading = self.sensor.read()?;
+        let seconds = self.step as f32 * self.interval().as_secs_f32();
+        self.step += 1;
+        for (mag, added) in reading.mag.iter_mut().zip(self.disturbances.field(seconds)) {
+            *mag += added;
+        }
+        Some(reading)
+    }
+
+    fn interval(&self) -> Duration {
+        self.sensor.interval()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// North, level, forever
+    struct Still;
+
+    impl MockSensor for Still {
+        fn read(&mut self) -> Option<Reading> {
+            Some(Reading {
+                accel: [0.0, 0.0, 1000.0],
+                mag: [40000.0, 0.0, 0.0],
+                truth: Some(0.0),
+            })
+        }
+
+        fn interval(&self) -> Duration {
+            Duration::from_millis(500)
+        }
+    }
+
+    #[test]
+    fn step_and_ramp() {
+        let mut disturbances = Disturbances::parse("step 1 3 x 100\nramp 2 4 xz 1000\n").unwrap();
+        assert_eq!(disturbances.field(0.5), [0.0; 3]);
+        assert_eq!(disturbances.field(1.0), [100.0, 0.0, 0.0]);
+        // Overlapping, they add up
+        assert_eq!(disturbances.field(2.5), [350.0, 0.0, 250.0]);
+        assert_eq!(disturbances.field(3.0), [500.0, 0.0, 500.0]);
+        assert_eq!(disturbances.field(4.0), [0.0; 3]);
+    }
+
+    #[test]
+    fn noise_only_on_its_axes() {
+        let mut disturbances = Disturbances::parse("noise 0 1 y 3000").unwrap();
+        let field = disturbances.field(0.5);
+        assert_eq!((field[0], field[2]), (0.0, 0.0));
+        assert_ne!(field[1], 0.0);
+        assert_eq!(disturbances.field(1.5), [0.0; 3]);
+    }
+
+    #[test]
+    fn rejects() {
+        for line in [
+            "step 1 3 x",
+            "spike 1 3 x 100",
+            "step 3 1 x 100",
+            "step 1 3 w 100",
+        ] {
+            let e = Disturbances::parse(&format!("# comment\n{line}"))
+                .err()
+                .unwrap();
+            assert!(e.starts_with("line 2:"), "{line}: {e}");
+        }
+    }
+
+    #[test]
+    fn disturbed_readings() {
+        let disturbances = Disturbances::parse("step 0.5 1 z -5000").unwrap();
+        let mut sensor = Disturbed::new(Box::new(Still), disturbances);
+        let z: Vec<f32> = (0..4).map(|_| sensor.read().unwrap().mag[2]).collect();
+        assert_eq!(z, [0.0, -5000.0, 0.0, 0.0]);
+        assert_eq!(sensor.read().unwrap().truth, Some(0.0));
+    }
+}
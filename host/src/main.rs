@@ -0,0 +1,274 @@
+//! Host-side companion tool for `micro-compass`.
+//!
+//! ```text
+//! compass-cli decode <fixed|delta> <file>
+//! compass-cli telemetry <file>
+//! compass-cli simulate <file> [arrow|degrees] [--disturb <file>]
+//! compass-cli replay <file> [--disturb <file>]
+//! compass-cli bench <file>... [--disturb <file>]
+//! compass-cli merge <file>...
+//! ```
+//!
+//! `decode` turns a flash log dump into CSV on stdout. `telemetry` prints the
+//! messages in a binary telemetry stream, from a capture or straight from the
+//! serial port. `simulate` runs the heading pipeline without hardware, see
+//! [`simulate`]. `replay` sends the `HDG` lines of a text telemetry capture
+//! back out on stdout at the pace they came in, for a board in `replay on`
+//! mode. `bench` scores the heading smoothing options on captures and
+//! scripts, see [`bench`]. `merge` puts the logs of boards timed in radio
+//! sync mode on one timeline. `--disturb` adds magnetic disturbances to the
+//! readings simulated, replayed or benchmarked, see [`disturb`].
+
+mod bench;
+mod disturb;
+mod simulate;
+
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Write as _},
+    process::ExitCode,
+    thread,
+    time::Duration,
+};
+
+use micro_compass_core::{
+    log_format::{DeltaDecoder, Record, RECORD_LEN},
+    protocol::Message,
+};
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let disturb = args
+        .iter()
+        .position(|arg| arg == "--disturb")
+        .filter(|&at| at + 1 < args.len())
+        .map(|at| {
+            args.remove(at);
+            args.remove(at)
+        });
+    let disturb = disturb.as_deref();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["decode", format, path] if disturb.is_none() => decode(format, path),
+        ["telemetry", path] if disturb.is_none() => telemetry(path),
+        ["simulate", path] => simulate::run(path, "arrow", disturb),
+        ["simulate", path, mode] => simulate::run(path, mode, disturb),
+        ["replay", path] => replay(path, disturb),
+        ["bench", ref paths @ ..] if !paths.is_empty() => bench::run(paths, disturb),
+        ["merge", ref paths @ ..] if !paths.is_empty() && disturb.is_none() => merge(paths),
+        _ => Err(String::from(
+            "usage: compass-cli decode <fixed|delta> <file>\n       compass-cli telemetry <file>\n       compass-cli simulate <file> [arrow|degrees] [--disturb <file>]\n       compass-cli replay <file> [--disturb <file>]\n       compass-cli bench <file>... [--disturb <file>]\n       compass-cli merge <file>...",
+        )),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// nRF52833 flash page size, the unit the log ring advances by
+const PAGE_LEN: usize = 4096;
+
+fn decode(format: &str, path: &str) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let records = records(format, &data)?;
+
+    println!("timestamp_ms,heading_deg,pitch_deg,roll_deg");
+    for record in &records {
+        print_record(record);
+    }
+    Ok(())
+}
+
+/// The records in a flash log dump, oldest first
+fn records(format: &str, data: &[u8]) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    match format {
+        "fixed" => {
+            for chunk in data.chunks_exact(RECORD_LEN) {
+                records.push(Record::from_bytes(chunk.try_into().unwrap()));
+            }
+        }
+        "delta" => {
+            // The firmware writes the pages as a ring, each starting with a
+            // keyframe. The oldest follows the erased page after the newest.
+            let pages: Vec<&[u8]> = data.chunks(PAGE_LEN).collect();
+            let erased = |page: &[u8]| page.iter().all(|&b| b == 0xFF);
+            let start = (0..pages.len())
+                .find(|&i| erased(pages[i]) && i > 0 && !erased(pages[i - 1]))
+                .map_or(0, |i| i + 1);
+            for page in pages[start..].iter().chain(&pages[..start]) {
+                for record in DeltaDecoder::new(page) {
+                    records.push(record.map_err(|e| format!("corrupt log: {e:?}"))?);
+                }
+            }
+        }
+        _ => return Err(format!("unknown log format `{format}`")),
+    }
+    Ok(records)
+}
+
+fn print_record(record: &Record) {
+    println!(
+        "{},{:.2},{:.2},{:.2}",
+        record.timestamp_ms,
+        record.heading_cdeg as f32 / 100.0,
+        record.pitch_cdeg as f32 / 100.0,
+        record.roll_cdeg as f32 / 100.0
+    );
+}
+
+fn telemetry(path: &str) -> Result<(), String> {
+    let file = fs::File::open(path).map_err(|e| format!("failed to open {path}: {e}"))?;
+    let mut reader = BufReader::new(file);
+    let mut frame = Vec::new();
+    // The first frame is usually partial when joining a live stream
+    let mut synced = false;
+
+    loop {
+        frame.clear();
+        let len = reader
+            .read_until(0, &mut frame)
+            .map_err(|e| format!("failed to read {path}: {e}"))?;
+        if len == 0 {
+            return Ok(());
+        }
+        match Message::decode(&mut frame) {
+            Ok(message) => {
+                synced = true;
+                println!("{message:?}");
+            }
+            Err(e) if synced => eprintln!("bad frame: {e}"),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Merge the logs of several boards into one CSV in time order, each record
+/// marked with the name of the file it came from. A log is either `decode`'s
+/// CSV or a capture of `log dump`'s `LOG` lines. Boards in radio sync mode
+/// log on the clock board's time, so their records line up.
+fn merge(paths: &[&str]) -> Result<(), String> {
+    let mut records = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+        let board = std::path::Path::new(path)
+            .file_stem()
+            .map_or(*path, |stem| stem.to_str().unwrap_or(path));
+        for line in text.lines() {
+            let line = line.trim();
+            let fields = line.strip_prefix("LOG,").unwrap_or(line);
+            let Some((timestamp, rest)) = fields.split_once(',') else {
+                continue;
+            };
+            // Skips the CSV header and `LOG,end`
+            let Ok(timestamp) = timestamp.parse::<u32>() else {
+                continue;
+            };
+            records.push((timestamp, board, rest.to_string()));
+        }
+    }
+    // Stable, so records at the same time keep the order of the files given
+    records.sort_by_key(|&(timestamp, ..)| timestamp);
+
+    println!("timestamp_ms,board,heading,pitch_deg,roll_deg");
+    for (timestamp, board, rest) in records {
+        println!("{timestamp},{board},{rest}");
+    }
+    Ok(())
+}
+
+/// Text telemetry lines come at most 5 a second
+const REPLAY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Fields of an `HDG` line before the raw field, counting `HDG`
+const HDG_MAG_AT: usize = 9;
+
+fn replay(path: &str, disturb: Option<&str>) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let mut disturbances = disturb.map(disturb::Disturbances::open).transpose()?;
+    let mut out = io::stdout();
+    for (n, line) in text
+        .lines()
+        .filter(|line| line.starts_with("HDG,"))
+        .enumerate()
+    {
+        let mut line = line.to_string();
+        if let Some(disturbances) = &mut disturbances {
+            let added = disturbances.field(n as f32 * REPLAY_INTERVAL.as_secs_f32());
+            let mut fields: Vec<String> = line.split(',').map(String::from).collect();
+            for (field, added) in fields.iter_mut().skip(HDG_MAG_AT).zip(added) {
+                let value = field
+                    .parse::<f32>()
+                    .map_err(|_| format!("malformed HDG line {}", n + 1))?;
+                *field = format!("{:.1}", value + added);
+            }
+            line = fields.join(",");
+        }
+        // The console takes CR or LF line endings
+        write!(out, "{line}\r\n")
+            .and_then(|()| out.flush())
+            .map_err(|e| format!("failed to write: {e}"))?;
+        thread::sleep(REPLAY_INTERVAL);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use micro_compass_core::log_format::{DeltaEncoder, MAX_FRAME_LEN};
+
+    use super::*;
+
+    /// Steps the delta format carries exactly
+    fn sample(n: u32) -> Record {
+        Record {
+            timestamp_ms: 1000 + 100 * n,
+            heading_cdeg: ((35_900 + 20 * n) % 36_000) as u16,
+            pitch_cdeg: 200,
+            roll_cdeg: -300,
+        }
+    }
+
+    /// A page of the ring, starting on a keyframe like the firmware's
+    fn page(records: &[Record]) -> Vec<u8> {
+        let mut encoder = DeltaEncoder::new();
+        let mut page = Vec::new();
+        for record in records {
+            let mut frame = [0; MAX_FRAME_LEN];
+            let len = encoder.encode(record, &mut frame);
+            page.extend_from_slice(&frame[..len]);
+        }
+        page.resize(PAGE_LEN, 0xFF);
+        page
+    }
+
+    #[test]
+    fn fixed_round_trip() {
+        let written: Vec<Record> = (0..4).map(sample).collect();
+        let data: Vec<u8> = written.iter().flat_map(Record::to_bytes).collect();
+        assert_eq!(records("fixed", &data).unwrap(), written);
+    }
+
+    #[test]
+    fn delta_oldest_first() {
+        let written: Vec<Record> = (0..20).map(sample).collect();
+        // Wrapped round: the newest page first, then the erased one, then
+        // the oldest
+        let data = [
+            page(&written[10..]),
+            vec![0xFF; PAGE_LEN],
+            page(&written[..10]),
+        ]
+        .concat();
+        assert_eq!(records("delta", &data).unwrap(), written);
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert!(records("csv", &[]).is_err());
+    }
+}
@@ -0,0 +1,414 @@
+//! The firmware's heading pipeline run on the host against a mock sensor, with
+//! the LED matrix drawn in the terminal. The stages are those in `core` that
+//! need nothing from the board, from the median filter to the units, at the
+//! firmware's default settings. The mock's readings are already calibrated
+//! and in the flat board frame, so the firmware's calibration, mounting and
+//! gravity stages have nothing to do and are left out, as are the gyroscope,
+//! the GPS course and the hold through acceleration.
+//!
+//! The mock either replays the raw readings in the `HDG` lines of a serial
+//! capture, or makes them up from a script of headings:
+//!
+//! ```text
+//! # <seconds> <heading> [noise in nT]
+//! 0 0
+//! 4 360 300
+//! 6 90
+//! ```
+//!
+//! The heading moves linearly between keyframes, on a flat board in a
+//! horizontal field, with the noise of the keyframe it comes from.
+
+use std::{
+    fs,
+    io::{self, IsTerminal as _, Write as _},
+    thread,
+    time::Duration,
+};
+
+use micro_compass_core::{
+    deviation::DeviationTable,
+    heading::{get_cardinal_direction, normalize_heading},
+    matrix,
+    median::MedianFilter,
+    pipeline::{
+        self, Average, Config, Declination, Deviation, Frame, Median, Stage, Tilt, Trace, Units,
+    },
+    protocol::Reference,
+    units::HeadingUnit,
+};
+
+use crate::disturb::{Disturbances, Disturbed};
+
+/// The firmware's default median window, see `set median`
+pub const MEDIAN_WINDOW: usize = 3;
+
+/// Horizontal field strength of the made-up readings, nT
+const FIELD_NT: f32 = 40_000.0;
+
+/// Made-up readings come at the firmware's full rate
+const SCRIPT_INTERVAL: Duration = Duration::from_millis(100);
+/// Telemetry lines come at most 5 a second
+const RECORDING_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A sensor's readings in the flat board frame: acceleration in mg and the
+/// calibrated magnetic field in nT
+pub struct Reading {
+    pub accel: [f32; 3],
+    pub mag: [f32; 3],
+    /// The heading the readings were made up from, free of noise, `None` for
+    /// a capture
+    pub truth: Option<f32>,
+}
+
+/// Stands in for the accelerometer and magnetometer
+pub trait MockSensor {
+    /// The next reading, `None` once there are no more
+    fn read(&mut self) -> Option<Reading>;
+
+    /// Time between readings
+    fn interval(&self) -> Duration;
+}
+
+/// Readings taken from a capture of the serial telemetry. The firmware sends
+/// the field uncalibrated, so headings only match a calibrated board's if it
+/// had little to correct.
+struct Recording {
+    readings: std::vec::IntoIter<Reading>,
+}
+
+impl Recording {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut readings = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let Some(fields) = line.trim().strip_prefix("HDG,") else {
+                continue;
+            };
+            // Heading, both references, pitch and roll come first
+            let values = fields
+                .split(',')
+                .skip(5)
+                .take(6)
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .filter(|values| values.len() == 6)
+                .ok_or_else(|| format!("line {}: malformed HDG line", number + 1))?;
+            readings.push(Reading {
+                accel: [values[0], values[1], values[2]],
+                mag: [values[3], values[4], values[5]],
+                truth: None,
+            });
+        }
+        Ok(Self {
+            readings: readings.into_iter(),
+        })
+    }
+}
+
+impl MockSensor for Recording {
+    fn read(&mut self) -> Option<Reading> {
+        self.readings.next()
+    }
+
+    fn interval(&self) -> Duration {
+        RECORDING_INTERVAL
+    }
+}
+
+/// Heading at a time, and the noise from then until the next keyframe
+struct Keyframe {
+    seconds: f32,
+    heading: f32,
+    noise_nt: f32,
+}
+
+/// Readings made up from keyframes
+struct Script {
+    keyframes: Vec<Keyframe>,
+    step: u32,
+    noise: Noise,
+}
+
+/// Roughly normal noise with a standard deviation of 1, the same sequence
+/// every run for a given seed
+pub struct Noise(u32);
+
+impl Noise {
+    pub fn new(seed: u32) -> Self {
+        Self(seed)
+    }
+
+    pub fn sample(&mut self) -> f32 {
+        // Twelve uniform samples sum to a variance of 1
+        (0..12)
+            .map(|_| {
+                // xorshift32
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 17;
+                self.0 ^= self.0 << 5;
+                self.0 as f32 / u32::MAX as f32
+            })
+            .sum::<f32>()
+            - 6.0
+    }
+}
+
+impl Script {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut keyframes = Vec::<Keyframe>::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at = |e: &str| format!("line {}: {e}", number + 1);
+            let values = line
+                .split_whitespace()
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| at("expected <seconds> <heading> [noise]"))?;
+            let (seconds, heading, noise_nt) = match values[..] {
+                [seconds, heading] => (seconds, heading, 0.0),
+                [seconds, heading, noise] => (seconds, heading, noise),
+                _ => return Err(at("expected <seconds> <heading> [noise]")),
+            };
+            if keyframes.last().is_some_and(|last| seconds <= last.seconds) {
+                return Err(at("keyframes must be in time order"));
+            }
+            keyframes.push(Keyframe {
+                seconds,
+                heading,
+                noise_nt,
+            });
+        }
+        if keyframes.is_empty() {
+            return Err(String::from("the script has no keyframes"));
+        }
+        Ok(Self {
+            keyframes,
+            step: 0,
+            noise: Noise::new(0x2545_F491),
+        })
+    }
+}
+
+impl MockSensor for Script {
+    fn read(&mut self) -> Option<Reading> {
+        let seconds = self.step as f32 * SCRIPT_INTERVAL.as_secs_f32();
+        let next = self.keyframes.iter().position(|k| k.seconds > seconds);
+        let (heading, noise_nt) = match next {
+            Some(0) => (self.keyframes[0].heading, self.keyframes[0].noise_nt),
+            Some(i) => {
+                let (from, to) = (&self.keyframes[i - 1], &self.keyframes[i]);
+                let share = (seconds - from.seconds) / (to.seconds - from.seconds);
+                (
+                    from.heading + share * (to.heading - from.heading),
+                    from.noise_nt,
+                )
+            }
+            None if seconds <= self.keyframes.last()?.seconds => {
+                let last = self.keyframes.last()?;
+                (last.heading, last.noise_nt)
+            }
+            None => return None,
+        };
+        self.step += 1;
+        let radians = heading.to_radians();
+        let mut mag = [FIELD_NT * radians.cos(), FIELD_NT * radians.sin(), 0.0];
+        for axis in &mut mag {
+            *axis += noise_nt * self.noise.sample();
+        }
+        Some(Reading {
+            accel: [0.0, 0.0, 1000.0],
+            mag,
+            truth: Some(normalize_heading(heading)),
+        })
+    }
+
+    fn interval(&self) -> Duration {
+        SCRIPT_INTERVAL
+    }
+}
+
+/// The mock sensor for a capture or a script, told apart by their `HDG`
+/// lines, with the disturbances in the file `disturb` added if given
+pub fn open(path: &str, disturb: Option<&str>) -> Result<Box<dyn MockSensor>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("failed to read {path}: {e}"))?;
+    let sensor: Box<dyn MockSensor> = if text.lines().any(|l| l.starts_with("HDG,")) {
+        Box::new(Recording::parse(&text)?)
+    } else {
+        Box::new(Script::parse(&text)?)
+    };
+    Ok(match disturb {
+        Some(disturb) => Box::new(Disturbed::new(sensor, Disturbances::open(disturb)?)),
+        None => sensor,
+    })
+}
+
+/// The firmware's default settings: no deviation table or declination, and
+/// the magnetic heading in degrees
+struct Defaults;
+
+impl Config for Defaults {
+    fn deviation(&self) -> &DeviationTable {
+        &DeviationTable::EMPTY
+    }
+
+    fn declination(&self) -> f32 {
+        0.0
+    }
+
+    fn reference(&self) -> Reference {
+        Reference::Magnetic
+    }
+
+    fn unit(&self) -> HeadingUnit {
+        HeadingUnit::Degrees
+    }
+}
+
+/// The firmware's heading pipeline, from the median filter to the units
+struct Pipeline {
+    median: Median,
+    units: Units,
+    trace: Trace,
+}
+
+impl Pipeline {
+    fn new() -> Self {
+        Self {
+            median: Median(MedianFilter::new(MEDIAN_WINDOW)),
+            units: Units(HeadingUnit::Degrees),
+            trace: Trace::new(),
+        }
+    }
+
+    /// The heading shown for `reading`
+    fn heading(&mut self, reading: &Reading) -> f32 {
+        let mut stages: [&mut dyn Stage<(), Defaults>; 6] = [
+            &mut self.median,
+            &mut Average,
+            &mut Tilt,
+            &mut Deviation,
+            &mut Declination,
+            &mut self.units,
+        ];
+        let mut frame = Frame::new(reading.accel, ());
+        frame.push(reading.mag);
+        self.trace.clear();
+        pipeline::run(&mut stages, &mut frame, &Defaults, &mut self.trace);
+        frame.output
+    }
+}
+
+/// Run the pipeline over a capture or a script until its readings run out,
+/// showing the needle in `mode`, `arrow` or `degrees` like the `mode` command
+pub fn run(path: &str, mode: &str, disturb: Option<&str>) -> Result<(), String> {
+    let mut sensor = open(path, disturb)?;
+    let degrees = match mode {
+        "arrow" => false,
+        "degrees" => true,
+        _ => return Err(format!("unknown display mode `{mode}`")),
+    };
+
+    let mut pipeline = Pipeline::new();
+    let terminal = io::stdout().is_terminal();
+    let mut first = true;
+    while let Some(reading) = sensor.read() {
+        let heading = pipeline.heading(&reading);
+
+        let mut lit = [[false; 5]; 5];
+        if degrees {
+            let (row, col) = matrix::ring_dot(heading);
+            lit[row][col] = true;
+        } else {
+            for (row, col) in matrix::cardinal_arrow(heading) {
+                lit[row][col] = true;
+            }
+        }
+
+        let mut frame = String::new();
+        // Draw over the last frame in a terminal, one after another otherwise
+        if terminal && !first {
+            frame.push_str("\x1b[6A");
+        }
+        for row in lit {
+            for on in row {
+                frame.push_str(if on { " #" } else { " ." });
+            }
+            frame.push('\n');
+        }
+        frame.push_str(&format!(
+            "{heading:6.1}° ({})\x1b[K\n",
+            get_cardinal_direction(heading)
+        ));
+        if !terminal {
+            frame = frame.replace("\x1b[K", "");
+        }
+        io::stdout()
+            .write_all(frame.as_bytes())
+            .map_err(|e| e.to_string())?;
+        first = false;
+        if terminal {
+            thread::sleep(sensor.interval());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_heading_comes_back() {
+        let capture = "boot\n\
+                       HDG,90.0,90.0,90.0,0.0,0.0,0,0,1000,0,40000,0\n\
+                       HDG,90.0,90.0,90.0,0.0,0.0,0,0,1000,0,40000,0\n";
+        let mut recording = Recording::parse(capture).unwrap();
+        let mut pipeline = Pipeline::new();
+        let mut count = 0;
+        while let Some(reading) = recording.read() {
+            assert_eq!(reading.accel, [0.0, 0.0, 1000.0]);
+            assert_eq!(reading.mag, [0.0, 40000.0, 0.0]);
+            assert_eq!(reading.truth, None);
+            assert!((pipeline.heading(&reading) - 90.0).abs() < 0.1);
+            count += 1;
+        }
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn malformed_capture_line() {
+        let e = Recording::parse("HDG,1,2,3,4,5,6,7\n").err().unwrap();
+        assert!(e.starts_with("line 1:"));
+    }
+
+    #[test]
+    fn script_settles_on_truth() {
+        let mut script = Script::parse("# turning east\n0 0\n2 90\n4 90\n").unwrap();
+        let mut pipeline = Pipeline::new();
+        let mut last = None;
+        while let Some(reading) = script.read() {
+            last = Some((pipeline.heading(&reading), reading.truth.unwrap()));
+        }
+        let (heading, truth) = last.unwrap();
+        assert_eq!(truth, 90.0);
+        assert!((heading - truth).abs() < 0.1);
+    }
+
+    #[test]
+    fn script_out_of_order() {
+        assert!(Script::parse("2 0\n1 90\n").is_err());
+        assert!(Script::parse("# nothing\n").is_err());
+    }
+
+    #[test]
+    fn noise_repeats() {
+        let (mut a, mut b) = (Noise::new(1), Noise::new(1));
+        for _ in 0..10 {
+            assert_eq!(a.sample(), b.sample());
+        }
+    }
+}
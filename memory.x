@@ -2,6 +2,10 @@
 
 MEMORY
 {
-    FLASH : ORIGIN = 0x00000000, LENGTH = 512K
+    FLASH : ORIGIN = 0x00000000, LENGTH = 320K
+    /* Reserved for the sample log, see `src/logger.rs` */
+    LOG : ORIGIN = 0x00050000, LENGTH = 160K
+    /* Reserved for persistent data, see `src/storage.rs` */
+    STORAGE : ORIGIN = 0x00078000, LENGTH = 32K
     RAM : ORIGIN = 0x20000000, LENGTH = 128K
 }
@@ -0,0 +1,103 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Instant};
+
+use crate::normalize_heading;
+
+/// Longest the heading may stay outside the sector before the alarm goes off,
+/// so a boat yawing at anchor or a gust doesn't set it off
+const DELAY: Duration = Duration::from_secs(5);
+
+/// Degrees back inside the sector the heading has to come before the alarm
+/// stops, so it doesn't chatter at the edge
+const HYSTERESIS: f32 = 2.0;
+
+/// Set while the alarm is going off, for the speaker to sound it
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// The headings allowed, clockwise from `from` to `to` in whole degrees, so
+/// 350 to 10 is the 20° either side of north
+#[derive(Clone, Copy, PartialEq)]
+pub struct Sector {
+    pub from: u16,
+    pub to: u16,
+}
+
+impl Sector {
+    /// Pack for storage, `u32::MAX` being no sector
+    pub fn to_bits(sector: Option<Self>) -> u32 {
+        sector.map_or(u32::MAX, |sector| {
+            u32::from(sector.from) | u32::from(sector.to) << 16
+        })
+    }
+
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        let sector = Self {
+            from: bits as u16,
+            to: (bits >> 16) as u16,
+        };
+        (sector.from < 360 && sector.to < 360 && sector.from != sector.to).then_some(sector)
+    }
+
+    /// Whether `heading` is inside, with `margin` degrees taken off each end
+    fn contains(self, heading: f32, margin: f32) -> bool {
+        let width = normalize_heading(f32::from(self.to) - f32::from(self.from));
+        let offset = normalize_heading(heading - f32::from(self.from));
+        (margin..=width - margin).contains(&offset)
+    }
+}
+
+/// Goes off once the heading has stayed outside the [`Sector`] for
+/// [`DELAY`], as an anchor or course alarm, and stops once it is back inside
+pub struct HeadingAlarm {
+    outside_since: Option<Instant>,
+}
+
+impl HeadingAlarm {
+    pub const fn new() -> Self {
+        Self {
+            outside_since: None,
+        }
+    }
+
+    /// Stop the alarm while there is no heading to check, e.g. in tilt-only
+    /// mode or during a calibration
+    pub fn silence(&mut self) {
+        self.outside_since = None;
+        ACTIVE.store(false, Ordering::Relaxed);
+    }
+
+    /// Feed the heading in degrees, returning whether the alarm is going off
+    pub fn update(&mut self, heading: f32, sector: Option<Sector>) -> bool {
+        let Some(sector) = sector else {
+            self.silence();
+            return false;
+        };
+        let was_active = active();
+        let margin = if was_active { HYSTERESIS } else { 0.0 };
+        let is_active = if sector.contains(heading, margin) {
+            self.outside_since = None;
+            false
+        } else {
+            self.outside_since
+                .get_or_insert_with(Instant::now)
+                .elapsed()
+                >= DELAY
+        };
+        match (was_active, is_active) {
+            (false, true) => warn!(
+                "alarm: heading {} outside {} to {}",
+                heading, sector.from, sector.to
+            ),
+            (true, false) => info!("alarm: heading {} back inside", heading),
+            _ => {}
+        }
+        ACTIVE.store(is_active, Ordering::Relaxed);
+        is_active
+    }
+}
@@ -0,0 +1,40 @@
+#[cfg(not(feature = "servo"))]
+use embassy_nrf::{
+    peripherals::PWM1,
+    pwm::{Prescaler, SimplePwm},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+
+use crate::normalize_heading;
+
+/// 16 MHz PWM clock, so a period of 3600 counts, a tenth of a degree each,
+/// repeats at about 4.4 kHz, well above what the RC filter lets through
+#[cfg(not(feature = "servo"))]
+const STEPS: u16 = 3600;
+
+/// Latest heading, `None` when there is none, e.g. in tilt-only mode
+pub static HEADING: Signal<CriticalSectionRawMutex, Option<f32>> = Signal::new();
+
+/// Counts of a `period` the output is high for: none at north, rising to all
+/// but one count just short of 360°, so the filtered voltage is 3.3 V ×
+/// heading / 360. None without a heading too.
+pub fn high_counts(heading: Option<f32>, period: u16) -> u16 {
+    heading.map_or(0, |heading| {
+        (normalize_heading(heading) / 360.0 * f32::from(period)) as u16
+    })
+}
+
+/// Drive the analog output with a PWM whose duty follows the [`HEADING`]s,
+/// for an RC low-pass filter to turn into a voltage. With the `servo`
+/// feature the servo's task drives it instead, as a third output at 50 Hz.
+#[cfg(not(feature = "servo"))]
+#[embassy_executor::task]
+pub async fn analog_task(mut pwm: SimplePwm<'static, PWM1>) {
+    pwm.set_prescaler(Prescaler::Div1);
+    pwm.set_max_duty(STEPS);
+    loop {
+        let heading = HEADING.wait().await;
+        // The output is low for the duty and high for the rest of the period
+        pwm.set_duty(0, STEPS - high_counts(heading, STEPS));
+    }
+}
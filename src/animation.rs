@@ -0,0 +1,224 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_time::Timer;
+
+use crate::{
+    board::{MATRIX_COLS, MATRIX_ROWS},
+    display::OnBoardLedMatrix,
+    frame_buffer::FRAMES,
+    matrix_scan::{self, Duties},
+    night,
+};
+
+/// A 5x5 picture for the LED matrix, one bit per column with the leftmost
+/// column in bit 4
+pub type Frame = [u8; MATRIX_ROWS];
+
+/// Shown for success, e.g. once the power-on self-test has passed
+pub const TICK: Frame = [0b00000, 0b00001, 0b00010, 0b10100, 0b01000];
+
+/// Shown for failure, e.g. while the sensor is being restarted
+pub const CROSS: Frame = [0b10001, 0b01010, 0b00100, 0b01010, 0b10001];
+
+/// Time for one pass over the rows, the shortest a frame can be shown, ms
+const SCAN_MS: u32 = 10;
+
+/// How long each row is lit per pass, ms
+const ROW_MS: u32 = SCAN_MS / MATRIX_ROWS as u32;
+const _: () = assert!(ROW_MS * 1000 == matrix_scan::ROW_US as u32);
+
+/// How the frames are spread over one run of an animation
+#[derive(Clone, Copy)]
+pub enum Easing {
+    Linear,
+    /// Slowing down towards the end
+    EaseOut,
+    /// Slow at both ends, fastest in the middle
+    EaseInOut,
+}
+
+impl Easing {
+    /// Map the fraction of a run's time gone by to how far through its frames
+    /// it is, both 0 to 1
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            Self::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Repeat {
+    Once,
+    Times(u32),
+    Forever,
+}
+
+/// A sequence of frames, each with how long it is shown in ms, before easing
+pub struct Animation<'a> {
+    pub frames: &'a [(Frame, u32)],
+    pub easing: Easing,
+    pub repeat: Repeat,
+}
+
+impl Animation<'_> {
+    /// Length of one run, ms
+    fn run_ms(&self) -> u32 {
+        self.frames.iter().map(|&(_, ms)| ms).sum()
+    }
+
+    /// The frame showing `elapsed_ms` after the start, `None` once it's over
+    pub fn frame_at(&self, elapsed_ms: u32) -> Option<&Frame> {
+        let run_ms = self.run_ms();
+        if run_ms == 0 {
+            return None;
+        }
+        let runs = match self.repeat {
+            Repeat::Once => Some(1),
+            Repeat::Times(runs) => Some(runs),
+            Repeat::Forever => None,
+        };
+        if runs.is_some_and(|runs| elapsed_ms / run_ms >= runs) {
+            return None;
+        }
+
+        let t = (elapsed_ms % run_ms) as f32 / run_ms as f32;
+        let at = (self.easing.apply(t) * run_ms as f32) as u32;
+        let mut end = 0;
+        for (frame, ms) in self.frames {
+            end += ms;
+            if at < end {
+                return Some(frame);
+            }
+        }
+        self.frames.last().map(|(frame, _)| frame)
+    }
+
+    /// Play it through on the matrix, which for [`Repeat::Forever`] never
+    /// ends, blank once it has
+    pub async fn play(&self, matrix: &mut OnBoardLedMatrix) {
+        let mut elapsed_ms = 0;
+        while let Some(frame) = self.frame_at(elapsed_ms) {
+            show(matrix, frame, SCAN_MS).await;
+            elapsed_ms += SCAN_MS;
+        }
+        blank(matrix);
+    }
+}
+
+/// Show a frame for `ms`, lighting one row at a time, fast enough not to
+/// flicker. Scanned in hardware, it stays on afterwards until the next frame
+/// or [`blank`].
+pub async fn show(matrix: &mut OnBoardLedMatrix, frame: &Frame, ms: u32) {
+    if scan_in_hardware(&frame_duties(frame), ms).await {
+        return;
+    }
+    for _ in 0..ms / SCAN_MS {
+        scan(matrix, frame).await;
+    }
+}
+
+/// Show the front frame of [`FRAMES`] for `ms`. Scanned in software, it is
+/// taken afresh for each pass over the rows, so a frame presented meanwhile
+/// shows from the next pass on, and whole.
+pub async fn show_front(matrix: &mut OnBoardLedMatrix, ms: u32) {
+    if scan_in_hardware(&frame_duties(&FRAMES.front()), ms).await {
+        return;
+    }
+    for _ in 0..ms / SCAN_MS {
+        scan(matrix, &FRAMES.front()).await;
+    }
+}
+
+/// Hand the scan to [`matrix_scan`] for as many whole passes as fit in `ms`,
+/// `false` if there's no hardware scanner and it is up to the caller. The
+/// scan carries on afterwards, so the next frame follows without a gap.
+async fn scan_in_hardware(duties: &Duties, ms: u32) -> bool {
+    let passes = ms / SCAN_MS;
+    if passes == 0 {
+        return true;
+    }
+    if !matrix_scan::start(duties) {
+        return false;
+    }
+    Timer::after_millis((passes * SCAN_MS).into()).await;
+    #[cfg(feature = "energy")]
+    crate::energy::record_leds(
+        passes,
+        duties.iter().flatten().map(|&us| u32::from(us)).sum(),
+    );
+    true
+}
+
+/// Turn every LED off, stopping the hardware scan so the matrix driver has
+/// the pins again
+pub fn blank(matrix: &mut OnBoardLedMatrix) {
+    matrix_scan::stop();
+    matrix.blank();
+}
+
+/// Each lit LED on for the share of its row's time the [`brightness`] gives
+fn frame_duties(frame: &Frame) -> Duties {
+    let lit_us = matrix_scan::ROW_US * u16::from(brightness()) / u16::from(GREY_LEVELS);
+    frame.map(|bits| {
+        core::array::from_fn(|c| {
+            if bits & (1 << (MATRIX_COLS - 1 - c)) != 0 {
+                lit_us
+            } else {
+                0
+            }
+        })
+    })
+}
+
+/// One pass over the rows, [`SCAN_MS`] long, each row lit for the share of
+/// its time the [`brightness`] gives
+async fn scan(matrix: &mut OnBoardLedMatrix, frame: &Frame) {
+    let lit_us = ROW_MS * 1000 * u32::from(brightness()) / u32::from(GREY_LEVELS);
+    matrix
+        .scan(&frame.map(u16::from), ROW_MS * 1000, lit_us)
+        .await;
+}
+
+/// Brightness of everything shown, 1 to [`GREY_LEVELS`]
+static BRIGHTNESS: AtomicU8 = AtomicU8::new(GREY_LEVELS);
+
+pub fn set_brightness(level: u8) {
+    BRIGHTNESS.store(level.clamp(1, GREY_LEVELS), Ordering::Relaxed);
+}
+
+/// The brightness set, or the night one while in night mode
+pub fn brightness() -> u8 {
+    if night::active() {
+        night::BRIGHTNESS
+    } else {
+        BRIGHTNESS.load(Ordering::Relaxed)
+    }
+}
+
+/// Brightness of each LED, by row and column, from 0 for off to
+/// [`GREY_LEVELS`] for full
+pub type Greys = [[u8; MATRIX_COLS]; MATRIX_ROWS];
+
+/// Brightness steps each row's time is split into
+pub const GREY_LEVELS: u8 = 8;
+
+/// Show LEDs at different brightnesses for `ms`, scanning rows like
+/// [`show`] and, within each row's time, keeping every LED lit for as many
+/// steps as its level
+pub async fn show_greys(matrix: &mut OnBoardLedMatrix, greys: &Greys, ms: u32) {
+    let step_us = ROW_MS * 1000 / u32::from(GREY_LEVELS);
+    let brightness = u16::from(brightness());
+    // Dimmed with the rest of the display, but not out
+    let dimmed = |level: u8| (u16::from(level) * brightness).div_ceil(u16::from(GREY_LEVELS)) as u8;
+    let duties = greys.map(|levels| levels.map(|level| u16::from(dimmed(level)) * step_us as u16));
+    if scan_in_hardware(&duties, ms).await {
+        return;
+    }
+    let levels = greys.map(|levels| levels.map(dimmed));
+    for _ in 0..ms / SCAN_MS {
+        matrix.scan_levels(&levels, GREY_LEVELS, step_us).await;
+    }
+}
@@ -0,0 +1,19 @@
+use micromath::F32Ext;
+
+/// Unit quaternion `[w, x, y, z]` for an attitude given as yaw, pitch and
+/// roll in degrees, applied in that order (Z, then Y, then X)
+pub fn quaternion(yaw: f32, pitch: f32, roll: f32) -> [f32; 4] {
+    let half = |degrees: f32| {
+        let radians = degrees.to_radians() / 2.0;
+        (radians.cos(), radians.sin())
+    };
+    let (cy, sy) = half(yaw);
+    let (cp, sp) = half(pitch);
+    let (cr, sr) = half(roll);
+    [
+        cr * cp * cy + sr * sp * sy,
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+    ]
+}
@@ -0,0 +1,250 @@
+use core::cell::Cell;
+
+use defmt::Format;
+use embassy_nrf::{
+    peripherals::PWM0,
+    pwm::{Prescaler, SimplePwm},
+};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Delay, Duration, Instant, Timer};
+use embedded_hal_async::delay::DelayNs;
+use micromath::F32Ext;
+
+use crate::{alarm, race_timer};
+
+/// Pitch of each beep
+const TONE_HZ: u32 = 2000;
+
+/// Length of each beep
+const BEEP_MS: u32 = 30;
+
+/// Gap between beeps when on target, and when facing directly away from it
+const MIN_INTERVAL_MS: f32 = 100.0;
+const MAX_INTERVAL_MS: f32 = 1500.0;
+
+/// Length of each click, and the gap between clicks within a period
+const CLICK_MS: u32 = 10;
+const CLICK_GAP_MS: u32 = 200;
+
+/// One group of clicks is played per period
+const CLICK_PERIOD_MS: u64 = 2500;
+
+/// Within this many degrees of a stored bearing the clicks give way to a
+/// single long tone
+const ON_TARGET_DEGREES: f32 = 5.0;
+const ON_TARGET_TONE_MS: u32 = 500;
+
+/// Length of each Geiger click
+const GEIGER_CLICK_MS: u32 = 3;
+
+/// Geiger clicks per second on average, facing away from the target and on
+/// it
+const GEIGER_MIN_RATE: f32 = 2.0;
+const GEIGER_MAX_RATE: f32 = 40.0;
+
+/// Geiger click pitch facing away from the target and on it
+const GEIGER_MIN_HZ: f32 = 600.0;
+const GEIGER_MAX_HZ: f32 = 3000.0;
+
+/// The two pitches the heading alarm warbles between, each for
+/// [`ALARM_TONE_MS`], then a gap of [`ALARM_GAP_MS`]
+const ALARM_HZ: [u32; 2] = [2500, 1600];
+const ALARM_TONE_MS: u32 = 150;
+const ALARM_GAP_MS: u32 = 300;
+
+/// Beeps on reaching the end of an orienteering leg
+const ARRIVED_BEEPS: u32 = 3;
+const ARRIVED_BEEP_MS: u32 = 200;
+
+/// What the speaker conveys
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum AudioMode {
+    /// While following a stored bearing, beeps speed up as the heading
+    /// approaches it
+    Proximity,
+    /// Clicks each period, one for north, two for east, three for south and
+    /// four for west, or a long tone when on a stored bearing
+    Clicks,
+    /// While following a stored bearing, random clicks like a Geiger counter's,
+    /// more of them and higher pitched the closer the heading is to it
+    Geiger,
+}
+
+static AUDIO_MODE: Mutex<CriticalSectionRawMutex, Cell<AudioMode>> =
+    Mutex::new(Cell::new(AudioMode::Proximity));
+
+pub fn set_audio_mode(mode: AudioMode) {
+    AUDIO_MODE.lock(|current| current.set(mode));
+}
+
+fn audio_mode() -> AudioMode {
+    AUDIO_MODE.lock(Cell::get)
+}
+
+/// Signed difference between the target bearing and the current heading, or
+/// `None` to silence the speaker
+pub static TARGET_ERROR: Signal<CriticalSectionRawMutex, Option<f32>> = Signal::new();
+
+/// Raised on reaching the end of an orienteering leg
+pub static ARRIVED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Heading shown on the display, or `None` when there is none, e.g. in
+/// tilt-only mode
+pub static HEADING: Signal<CriticalSectionRawMutex, Option<f32>> = Signal::new();
+
+/// "Sound compass": drive the micro:bit v2 speaker (P0.00) with beeps whose
+/// rate increases as the heading approaches the target bearing, with clicks
+/// counting out the quadrant, or with Geiger counter clicks, depending on the
+/// [`AudioMode`]. A race countdown, then a heading alarm going off, drown out
+/// all of them.
+#[embassy_executor::task]
+pub async fn audio_task(mut pwm: SimplePwm<'static, PWM0>) {
+    // 1 MHz PWM clock, so the counter top sets the tone directly
+    pwm.set_prescaler(Prescaler::Div16);
+    pwm.set_period(TONE_HZ);
+    pwm.set_duty(0, 0);
+
+    let mut error = None;
+    let mut heading = None;
+    let mut random = Random(0x2545_F491);
+    loop {
+        // Keep going with the last known values until new ones arrive
+        if let Some(latest) = TARGET_ERROR.try_take() {
+            error = latest;
+        }
+        if let Some(latest) = HEADING.try_take() {
+            heading = latest;
+        }
+
+        // The start sequence is timed to the second, so it has the speaker
+        // to itself
+        if let Some(gun) = race_timer::gun() {
+            if Instant::now() > gun {
+                // Started, and beeped for it. Clears the countdown once the
+                // start has been shown.
+                let _ = race_timer::remaining();
+                Delay.delay_ms(100).await;
+                continue;
+            }
+            let seconds = gun.saturating_duration_since(Instant::now()).as_secs();
+            Timer::at(gun - Duration::from_secs(seconds)).await;
+            if race_timer::gun() == Some(gun) {
+                if let Some(ms) = race_timer::beep_ms(seconds) {
+                    beep(&mut pwm, ms).await;
+                }
+            }
+            continue;
+        }
+
+        if ARRIVED.try_take().is_some() {
+            for _ in 0..ARRIVED_BEEPS {
+                beep(&mut pwm, ARRIVED_BEEP_MS).await;
+                Delay.delay_ms(ARRIVED_BEEP_MS).await;
+            }
+            continue;
+        }
+
+        if alarm::active() {
+            for hz in ALARM_HZ {
+                pwm.set_period(hz);
+                beep(&mut pwm, ALARM_TONE_MS).await;
+            }
+            pwm.set_period(TONE_HZ);
+            Delay.delay_ms(ALARM_GAP_MS).await;
+            continue;
+        }
+
+        match audio_mode() {
+            AudioMode::Proximity => {
+                let Some(error) = error else {
+                    error = TARGET_ERROR.wait().await;
+                    continue;
+                };
+                beep(&mut pwm, BEEP_MS).await;
+                Delay.delay_ms(beep_interval_ms(error)).await;
+            }
+            AudioMode::Clicks => {
+                let Some(heading) = heading else {
+                    heading = HEADING.wait().await;
+                    continue;
+                };
+                let start = Instant::now();
+                match error {
+                    Some(error) if error.abs() <= ON_TARGET_DEGREES => {
+                        beep(&mut pwm, ON_TARGET_TONE_MS).await
+                    }
+                    _ => {
+                        for _ in 0..quadrant_clicks(heading) {
+                            beep(&mut pwm, CLICK_MS).await;
+                            Delay.delay_ms(CLICK_GAP_MS).await;
+                        }
+                    }
+                }
+                let elapsed = start.elapsed().as_millis();
+                Delay
+                    .delay_ms(CLICK_PERIOD_MS.saturating_sub(elapsed) as u32)
+                    .await;
+            }
+            AudioMode::Geiger => {
+                let Some(error) = error else {
+                    error = TARGET_ERROR.wait().await;
+                    continue;
+                };
+                let closeness = geiger_closeness(error);
+                pwm.set_period(lerp(GEIGER_MIN_HZ, GEIGER_MAX_HZ, closeness) as u32);
+                beep(&mut pwm, GEIGER_CLICK_MS).await;
+                // Exponentially distributed gaps, as between decays, averaging
+                // the rate for this error
+                let rate = lerp(GEIGER_MIN_RATE, GEIGER_MAX_RATE, closeness);
+                let gap_ms = -(1.0 - random.next()).ln() / rate * 1000.0;
+                Delay.delay_ms(gap_ms.min(MAX_INTERVAL_MS) as u32).await;
+                pwm.set_period(TONE_HZ);
+            }
+        }
+    }
+}
+
+/// 0 facing directly away from the target to 1 on it, squared so the sound
+/// changes most over the last few degrees
+fn geiger_closeness(error: f32) -> f32 {
+    let closeness = 1.0 - (error.abs() / 180.0).min(1.0);
+    closeness * closeness
+}
+
+fn lerp(from: f32, to: f32, share: f32) -> f32 {
+    from + (to - from) * share
+}
+
+/// xorshift32, for the spacing of Geiger clicks
+struct Random(u32);
+
+impl Random {
+    /// Uniform in `[0, 1)`
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+async fn beep(pwm: &mut SimplePwm<'static, PWM0>, ms: u32) {
+    pwm.set_duty(0, pwm.max_duty() / 2);
+    Delay.delay_ms(ms).await;
+    pwm.set_duty(0, 0);
+}
+
+/// Interval between beeps, shrinking linearly as `error` approaches zero
+fn beep_interval_ms(error: f32) -> u32 {
+    let off_target = (error.abs() / 180.0).min(1.0);
+    (MIN_INTERVAL_MS + (MAX_INTERVAL_MS - MIN_INTERVAL_MS) * off_target) as u32
+}
+
+/// 1 for north, 2 for east, 3 for south, 4 for west, each quadrant centered on
+/// its cardinal direction
+fn quadrant_clicks(heading: f32) -> usize {
+    ((heading + 45.0) / 90.0) as usize % 4 + 1
+}
@@ -0,0 +1,87 @@
+use core::cell::Cell;
+
+use defmt::{info, Format};
+use embassy_nrf::saadc::Saadc;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Timer};
+
+use crate::telemetry;
+
+/// How often the supply voltage is measured
+const MEASURE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Full scale of the SAADC on the VDD channel: 0.6 V internal reference with
+/// a gain of 1/6, at 12 bits
+const FULL_SCALE_MV: i32 = 3600;
+const FULL_SCALE_COUNTS: i32 = 4096;
+
+/// Charge left in two alkaline AAA cells against VDD, mV. The micro:bit runs
+/// them through a diode, and from USB VDD sits above the top of the table.
+const DISCHARGE_CURVE: [(u16, u8); 6] = [
+    (3000, 100),
+    (2800, 80),
+    (2600, 50),
+    (2400, 20),
+    (2200, 5),
+    (2000, 0),
+];
+
+/// At or below this the display shows a low battery icon now and then
+pub const LOW_PERCENT: u8 = 20;
+
+#[derive(Clone, Copy, Debug, Format)]
+pub struct Battery {
+    pub millivolts: u16,
+    /// Estimated charge left, 0 to 100
+    pub percent: u8,
+}
+
+impl Battery {
+    pub fn is_low(&self) -> bool {
+        self.percent <= LOW_PERCENT
+    }
+}
+
+/// Latest measurement, `None` until the first one
+static LEVEL: Mutex<CriticalSectionRawMutex, Cell<Option<Battery>>> = Mutex::new(Cell::new(None));
+
+pub fn level() -> Option<Battery> {
+    LEVEL.lock(|level| level.get())
+}
+
+/// Estimate the charge left from the supply voltage
+fn percent(millivolts: u16) -> u8 {
+    let (top, full) = DISCHARGE_CURVE[0];
+    if millivolts >= top {
+        return full;
+    }
+    for pair in DISCHARGE_CURVE.windows(2) {
+        let [(high_mv, high), (low_mv, low)] = [pair[0], pair[1]];
+        if millivolts >= low_mv {
+            let fraction = (millivolts - low_mv) as f32 / (high_mv - low_mv) as f32;
+            return low + (fraction * (high - low) as f32) as u8;
+        }
+    }
+    0
+}
+
+/// Measure VDD every [`MEASURE_INTERVAL`], keeping the latest reading for
+/// [`level`] and reporting it in telemetry
+#[embassy_executor::task]
+pub async fn battery_task(mut saadc: Saadc<'static, 1>) {
+    saadc.calibrate().await;
+    loop {
+        let mut buf = [0i16; 1];
+        saadc.sample(&mut buf).await;
+        let millivolts = (buf[0].max(0) as i32 * FULL_SCALE_MV / FULL_SCALE_COUNTS) as u16;
+        let battery = Battery {
+            millivolts,
+            percent: percent(millivolts),
+        };
+        info!("battery: {}", battery);
+        LEVEL.lock(|level| level.set(Some(battery)));
+        // Dropped if the telemetry task has fallen behind
+        let _ = telemetry::EVENTS.try_send(telemetry::Event::Battery(battery));
+        Timer::after(MEASURE_INTERVAL).await;
+    }
+}
@@ -0,0 +1,112 @@
+use defmt::{info, warn};
+
+use crate::storage::{self, Storage};
+
+/// Maximum number of bearings (route legs) that can be stored
+pub const MAX_BEARINGS: usize = 8;
+
+/// Marks a valid record, erased flash reads back as `0xFFFF_FFFF`
+const MAGIC: u32 = 0xBEA2_0001;
+
+/// magic (4) + count (1) + active (1) + padding (2) + bearings
+const RECORD_LEN: usize = 8 + MAX_BEARINGS * 4;
+
+const _: () = assert!(RECORD_LEN <= storage::MAX_RECORD_LEN);
+
+/// Sequence of locked bearings, e.g. the legs of a route, persisted to flash.
+///
+/// Button A locks the current heading as the next leg, button B cycles
+/// through the stored legs and back to the plain compass.
+pub struct BearingMemory {
+    bearings: [f32; MAX_BEARINGS],
+    count: usize,
+    /// Index of the leg being followed, `None` when showing the plain compass
+    active: Option<usize>,
+}
+
+impl BearingMemory {
+    pub const fn new() -> Self {
+        Self {
+            bearings: [0.0; MAX_BEARINGS],
+            count: 0,
+            active: None,
+        }
+    }
+
+    /// Restore stored bearings, starting empty if flash holds no valid record
+    pub fn load(storage: &mut Storage<'_>) -> Self {
+        let mut memory = Self::new();
+        let mut buf = [0u8; RECORD_LEN];
+        if let Err(e) = storage.load(storage::BEARINGS_PAGE, &mut buf) {
+            warn!("failed to read stored bearings: {}", e);
+            return memory;
+        }
+
+        let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let count = buf[4] as usize;
+        if magic != MAGIC || count > MAX_BEARINGS {
+            info!("no stored bearings");
+            return memory;
+        }
+
+        let active = buf[5] as usize;
+        memory.count = count;
+        memory.active = (active < count).then_some(active);
+        for (i, bearing) in memory.bearings.iter_mut().take(count).enumerate() {
+            let at = 8 + i * 4;
+            *bearing = f32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        }
+        info!("restored {} stored bearings", count);
+        memory
+    }
+
+    /// Persist the stored bearings and the active leg
+    pub fn save(&self) {
+        let mut buf = [0u8; RECORD_LEN];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4] = self.count as u8;
+        buf[5] = self.active.map_or(u8::MAX, |i| i as u8);
+        for (i, bearing) in self.bearings.iter().take(self.count).enumerate() {
+            let at = 8 + i * 4;
+            buf[at..at + 4].copy_from_slice(&bearing.to_le_bytes());
+        }
+        storage::save(storage::BEARINGS_PAGE, &buf);
+    }
+
+    /// Bearing currently being followed, if any
+    pub fn active(&self) -> Option<f32> {
+        self.active.map(|i| self.bearings[i])
+    }
+
+    /// Append `heading` as a new leg and start following it. When memory is
+    /// full the last leg is replaced.
+    pub fn lock(&mut self, heading: f32) {
+        if self.count < MAX_BEARINGS {
+            self.count += 1;
+        }
+        let index = self.count - 1;
+        self.bearings[index] = heading;
+        self.active = Some(index);
+    }
+
+    /// Advance to the next stored leg, returning to the plain compass after
+    /// the last one
+    pub fn next(&mut self) {
+        self.active = match self.active {
+            None if self.count > 0 => Some(0),
+            Some(i) if i + 1 < self.count => Some(i + 1),
+            _ => None,
+        };
+    }
+
+    /// Go back to the plain compass, keeping the stored legs
+    pub fn deactivate(&mut self) {
+        self.active = None;
+    }
+
+    /// Forget all stored bearings
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.active = None;
+    }
+}
@@ -0,0 +1,79 @@
+use defmt::info;
+use embassy_time::{Duration, Instant};
+use micromath::F32Ext;
+
+use crate::normalize_heading;
+
+/// Degrees the bezel turns per flick, one LED round the matrix's ring
+pub const STEP: f32 = 22.5;
+
+/// Tilt to one side, degrees, that starts a flick
+const FLICK_DEG: f32 = 25.0;
+
+/// Back within this of level, degrees, ends it
+const LEVEL_DEG: f32 = 10.0;
+
+/// A flick has to be back level within this, so tilting the board over and
+/// holding it there, e.g. to read it, turns nothing
+const FLICK_TIME: Duration = Duration::from_millis(700);
+
+/// A virtual version of a baseplate compass's rotating bezel: a marker on
+/// the ring round the needle, set to a bearing by tipping the board briefly
+/// to one side and back. Each flick with the right edge going down turns it
+/// a [`STEP`] clockwise, with the left edge going down anticlockwise. Facing
+/// the bearing brings the marker to the top.
+pub struct Bezel {
+    /// Degrees from north, `None` until the first heading, which it starts
+    /// from
+    bearing: Option<f32>,
+    /// Which way the board is tipped, clockwise or not, and since when
+    tipped: Option<(bool, Instant)>,
+}
+
+impl Bezel {
+    pub const fn new() -> Self {
+        Self {
+            bearing: None,
+            tipped: None,
+        }
+    }
+
+    /// Feed the acceleration in the flat board frame, mg, and the heading.
+    /// The bearing, once turned by a flick.
+    pub fn update(&mut self, accel: [f32; 3], heading: f32) -> Option<f32> {
+        let bearing = *self
+            .bearing
+            .get_or_insert_with(|| (heading / STEP).round() * STEP % 360.0);
+        let [x, _, z] = accel;
+        // Positive with the right edge raised
+        let side = x.atan2(z).to_degrees();
+        match self.tipped {
+            None if side.abs() >= FLICK_DEG => {
+                self.tipped = Some((side < 0.0, Instant::now()));
+                None
+            }
+            Some((clockwise, since)) if side.abs() <= LEVEL_DEG => {
+                self.tipped = None;
+                if since.elapsed() > FLICK_TIME {
+                    return None;
+                }
+                let step = if clockwise { STEP } else { -STEP };
+                let bearing = normalize_heading(bearing + step);
+                self.bearing = Some(bearing);
+                info!("bezel: {}°", bearing);
+                Some(bearing)
+            }
+            _ => None,
+        }
+    }
+
+    /// Where the marker goes, degrees clockwise from straight ahead
+    pub fn marker(&self, heading: f32) -> Option<f32> {
+        self.bearing
+            .map(|bearing| normalize_heading(bearing - heading))
+    }
+
+    pub fn bearing(&self) -> Option<f32> {
+        self.bearing
+    }
+}
@@ -0,0 +1,109 @@
+use defmt::info;
+use micromath::F32Ext;
+
+use crate::{angle_diff, console, normalize_heading};
+
+/// Turntable steps between positions
+const STEP: f32 = 45.0;
+
+/// Positions visited, once around the turntable
+const POSITIONS: usize = 8;
+
+/// Headings averaged at each position
+const SAMPLES: usize = 20;
+
+/// Guided accuracy check for validating firmware changes: the board is placed
+/// on a turntable at 0°, 45°, ... 315°, button A is pressed at each position
+/// and the heading error there is reported over the serial console, followed
+/// by a summary.
+///
+/// Errors are measured from the heading at 0°, so the turntable's zero mark
+/// doesn't need to point north. What shows up is how the error varies with
+/// heading, e.g. a sinusoid from leftover hard iron.
+pub struct BiasCheck {
+    position: usize,
+    /// Heading measured at 0°
+    zero: f32,
+    errors: [f32; POSITIONS],
+    /// Sums of the sines and cosines of the headings averaged so far, while
+    /// recording a position
+    recording: Option<(f32, f32, usize)>,
+    /// The next position is still to be announced
+    prompt: bool,
+}
+
+impl BiasCheck {
+    pub fn new() -> Self {
+        info!("bias check started");
+        Self {
+            position: 0,
+            zero: 0.0,
+            errors: [0.0; POSITIONS],
+            recording: None,
+            prompt: true,
+        }
+    }
+
+    /// Start averaging headings for the current position
+    pub fn record(&mut self) {
+        if self.recording.is_none() {
+            self.recording = Some((0.0, 0.0, 0));
+        }
+    }
+
+    /// Feed a magnetic heading, in degrees. Returns `true` once every
+    /// position has been recorded and the report sent.
+    pub fn update(&mut self, heading: f32) -> bool {
+        if self.prompt {
+            self.prompt = false;
+            console::reply(format_args!(
+                "bias: turn to {:.0}, press A",
+                self.position as f32 * STEP
+            ));
+        }
+        let Some((sin, cos, count)) = &mut self.recording else {
+            return false;
+        };
+        let radians = heading.to_radians();
+        *sin += radians.sin();
+        *cos += radians.cos();
+        *count += 1;
+        if *count < SAMPLES {
+            return false;
+        }
+
+        // Circular mean, so headings either side of north don't average to south
+        let measured = normalize_heading(sin.atan2(*cos).to_degrees());
+        self.recording = None;
+        let expected = self.position as f32 * STEP;
+        if self.position == 0 {
+            self.zero = measured;
+        }
+        let error = angle_diff(angle_diff(measured, self.zero), expected);
+        self.errors[self.position] = error;
+        console::reply(format_args!(
+            "bias: {expected:.0} measured {measured:.1} error {error:.1}"
+        ));
+
+        self.position += 1;
+        if self.position < POSITIONS {
+            self.prompt = true;
+            return false;
+        }
+        self.report();
+        true
+    }
+
+    fn report(&self) {
+        // The 0° position is the reference, its error is zero by definition
+        let errors = &self.errors[1..];
+        let n = errors.len() as f32;
+        let mean = errors.iter().sum::<f32>() / n;
+        let rms = (errors.iter().map(|e| e * e).sum::<f32>() / n).sqrt();
+        let max = errors.iter().fold(0.0f32, |max, e| max.max(e.abs()));
+        info!("bias check complete");
+        console::reply(format_args!(
+            "bias: mean {mean:.1} rms {rms:.1} max {max:.1}"
+        ));
+    }
+}
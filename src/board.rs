@@ -0,0 +1,149 @@
+#[cfg(any(feature = "servo", feature = "analog"))]
+use embassy_nrf::peripherals::PWM1;
+#[cfg(feature = "rgb")]
+use embassy_nrf::peripherals::SPI2;
+#[cfg(feature = "compass-module")]
+use embassy_nrf::peripherals::TWISPI1;
+#[cfg(feature = "gps")]
+use embassy_nrf::peripherals::{PPI_CH0, PPI_CH1, PPI_GROUP0, TIMER1, UARTE1};
+use embassy_nrf::{
+    gpio::{AnyPin, Level, Pin as _, Pull},
+    gpiote::{self, Channel as _},
+    pac,
+    peripherals::{
+        NVMC, PPI_CH2, PPI_CH3, PPI_GROUP1, PWM0, PWM2, PWM3, RADIO, SAADC, TIMER2, TIMER3, UARTE0,
+        WDT,
+    },
+    ppi::{self, ConfigurableChannel as _},
+    Peripherals,
+};
+
+use crate::{
+    led_matrix::{Pins, Polarity},
+    sensor::BusPins,
+};
+
+// Also generated: `BUTTON_PULL`, how the buttons, which pull their pins low
+// when pressed, are held high otherwise; the defaults `DEFAULT_DECLINATION`
+// and `STROBE_BEARING` in degrees; `BUTTON_A_PIN`, `MATRIX_ROW_PINS` and
+// `MATRIX_COL_PINS`, button A's and the LED matrix's pins as `(port, pin)`
+// for [`registers`]; the external
+// matrix's size,
+// `EXTERNAL_MATRIX_ROWS` and `EXTERNAL_MATRIX_COLS`, 0 without one, and its
+// `EXTERNAL_MATRIX_POLARITY`; and `Board::new`.
+
+/// The level a pressed button pulls its pin to
+pub const BUTTON_PRESSED: Level = Level::Low;
+
+/// Size of the board's own LED matrix, the same on every nRF52833 board
+/// the firmware runs on
+pub const MATRIX_ROWS: usize = 5;
+pub const MATRIX_COLS: usize = 5;
+
+/// A pin's port registers and its number in the port, from its `(port,
+/// pin)`, for code that drives pins with no executor left to own them
+pub fn registers((port, pin): (u8, u8)) -> (pac::gpio::Gpio, usize) {
+    let port = match port {
+        0 => pac::P0,
+        _ => pac::P1,
+    };
+    (port, pin.into())
+}
+
+/// Everything the firmware drives and the pins it's wired to, taken from the
+/// peripherals once at startup. The pins come from the board file read by
+/// build.rs, so another nRF52833 board needs another board file and no code
+/// changes.
+pub struct Board {
+    pub wdt: WDT,
+    /// The 5x5 LED matrix, rows active high and columns active low
+    pub matrix: Pins<MATRIX_ROWS, MATRIX_COLS>,
+    pub matrix_scan: MatrixScan,
+    /// Another LED matrix of any size, wired straight to GPIOs, if there is
+    /// one
+    pub external_matrix: Option<Pins<EXTERNAL_MATRIX_ROWS, EXTERNAL_MATRIX_COLS>>,
+    /// Pulses each time the heading crosses the strobe bearing, if wired
+    pub strobe: Option<AnyPin>,
+    pub button_a: AnyPin,
+    pub button_b: AnyPin,
+    /// The serial console and telemetry
+    pub console: Uart,
+    /// A capacitive touch pad with an external pull-up, if there is one
+    pub touch: Option<AnyPin>,
+    pub speaker_pwm: PWM0,
+    pub speaker: Option<AnyPin>,
+    /// A vibration motor or buzzer, pulsed on coming onto north or a bearing
+    pub haptic: Option<AnyPin>,
+    #[cfg(feature = "gps")]
+    pub gps: GpsUart,
+    /// WS2812 data, driven by SPI
+    #[cfg(feature = "rgb")]
+    pub rgb_spi: SPI2,
+    #[cfg(feature = "rgb")]
+    pub rgb: AnyPin,
+    /// An RC servo's signal, or the left motor's speed controller's in
+    /// differential steering, and the right motor's
+    #[cfg(feature = "servo")]
+    pub servo_pwm: PWM1,
+    #[cfg(feature = "servo")]
+    pub servo: AnyPin,
+    #[cfg(feature = "servo")]
+    pub servo_right: AnyPin,
+    /// The heading as a PWM duty, for an RC filter to turn into a voltage.
+    /// With the servo it is the servo's PWM's third output.
+    #[cfg(all(feature = "analog", not(feature = "servo")))]
+    pub analog_pwm: PWM1,
+    #[cfg(feature = "analog")]
+    pub analog: AnyPin,
+    /// The I2C bus another microcontroller reads the heading over
+    #[cfg(feature = "compass-module")]
+    pub module_twis: TWISPI1,
+    #[cfg(feature = "compass-module")]
+    pub module_sda: AnyPin,
+    #[cfg(feature = "compass-module")]
+    pub module_scl: AnyPin,
+    pub saadc: SAADC,
+    pub nvmc: NVMC,
+    pub radio: RADIO,
+    /// The LSM303AGR's interrupt line, active low
+    pub sensor_int: AnyPin,
+    /// The I2C bus to the LSM303AGR and anything else on it
+    pub sensor_bus: BusPins,
+}
+
+/// What scans the LED matrix in hardware, see [`crate::matrix_scan`]
+pub struct MatrixScan {
+    /// Counts the rows
+    pub timer: TIMER3,
+    /// The first four columns, and the rest
+    pub pwm: PWM2,
+    pub pwm_last: PWM3,
+    /// One per row
+    pub gpiote: [gpiote::AnyChannel; MATRIX_ROWS],
+    /// One per row, and one more to count them
+    pub ppi: [ppi::AnyConfigurableChannel; MATRIX_ROWS + 1],
+}
+
+/// A buffered UART for the console
+pub struct Uart {
+    pub uarte: UARTE0,
+    pub timer: TIMER2,
+    pub ppi_ch1: PPI_CH2,
+    pub ppi_ch2: PPI_CH3,
+    pub ppi_group: PPI_GROUP1,
+    pub rx: AnyPin,
+    pub tx: AnyPin,
+}
+
+/// A receive-only buffered UART for the GPS
+#[cfg(feature = "gps")]
+pub struct GpsUart {
+    pub uarte: UARTE1,
+    pub timer: TIMER1,
+    pub ppi_ch1: PPI_CH0,
+    pub ppi_ch2: PPI_CH1,
+    pub ppi_group: PPI_GROUP0,
+    pub rx: AnyPin,
+}
+
+include!(concat!(env!("OUT_DIR"), "/board.rs"));
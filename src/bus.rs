@@ -0,0 +1,81 @@
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_nrf::{
+    peripherals::TWISPI0,
+    twim::{self, Twim},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_time::Instant;
+use embedded_hal::i2c::{ErrorType, Operation};
+use embedded_hal_async::i2c::I2c;
+
+use crate::{loop_timing, sensor::BusPins, Irqs};
+
+/// The internal I2C bus, shared by the LSM303AGR and any other devices added
+/// to it, each through its own [`Device`]. Transfers from different devices
+/// take turns.
+pub static BUS: Mutex<CriticalSectionRawMutex, SharedBus> = Mutex::new(SharedBus {
+    twim: None,
+    pins: [0; 2],
+});
+
+/// One device's handle on [`BUS`]
+pub type Device = I2cDevice<'static, CriticalSectionRawMutex, SharedBus>;
+
+/// The TWIM driving the bus, dropped and recreated around [`recover`]
+pub struct SharedBus {
+    twim: Option<Twim<'static, TWISPI0>>,
+    /// SDA and SCL, see [`BusPins::pin_ports`]
+    pins: [u8; 2],
+}
+
+/// A new handle on the bus for a device. Transfers fail as if nothing
+/// answered until [`start`] has run.
+pub fn device() -> Device {
+    I2cDevice::new(&BUS)
+}
+
+/// Start the bus on its pins, once the power-on self-test is done with them
+pub async fn start(pins: BusPins) {
+    let mut bus = BUS.lock().await;
+    bus.pins = pins.pin_ports();
+    bus.twim = Some(twim(pins));
+}
+
+/// Free the bus when the sensor stops answering, see [`BusPins::recover`].
+/// Other devices' transfers wait until it is running again.
+pub async fn recover() {
+    let mut bus = BUS.lock().await;
+    // Releases the pins
+    bus.twim = None;
+    // SAFETY: the TWIM that owned them was just dropped, and holding the lock
+    // keeps every device off the bus until the new one is in place
+    let mut pins = unsafe { BusPins::steal(bus.pins) };
+    pins.recover();
+    bus.twim = Some(twim(pins));
+}
+
+fn twim(pins: BusPins) -> Twim<'static, TWISPI0> {
+    Twim::new(pins.twi, Irqs, pins.sda, pins.scl, twim::Config::default())
+}
+
+impl ErrorType for SharedBus {
+    type Error = twim::Error;
+}
+
+impl I2c for SharedBus {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let start = Instant::now();
+        let result = match &mut self.twim {
+            Some(twim) => twim.transaction(address, operations).await,
+            None => Err(twim::Error::AddressNack),
+        };
+        loop_timing::record_i2c(start.elapsed());
+        #[cfg(feature = "energy")]
+        crate::energy::record_i2c(start.elapsed());
+        result
+    }
+}
@@ -0,0 +1,116 @@
+use defmt::Format;
+use embassy_futures::select::{select, Either};
+use embassy_nrf::gpio::Input;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+/// Time to wait for contact bounce to settle after an edge
+const DEBOUNCE: Duration = Duration::from_millis(20);
+
+/// Longest gap between two clicks of the same button that makes them a
+/// double click, which is also how long a click waits to be reported
+const DOUBLE_CLICK: Duration = Duration::from_millis(300);
+
+/// Holding a button at least this long reports a long press
+const LONG_PRESS: Duration = Duration::from_millis(1000);
+
+/// Holding it at least this long reports a very long press, e.g. button A on
+/// its own puts the board to sleep
+const VERY_LONG_PRESS: Duration = Duration::from_millis(3000);
+
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum Button {
+    A,
+    B,
+    /// Both buttons held down together
+    AB,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum Press {
+    Click,
+    /// Two clicks within [`DOUBLE_CLICK`], never for [`Button::AB`]
+    DoubleClick,
+    /// Held for [`LONG_PRESS`]
+    Long,
+    /// Held for [`VERY_LONG_PRESS`]
+    VeryLong,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub struct Event {
+    pub button: Button,
+    pub press: Press,
+}
+
+/// Button events, in the order they happened
+pub static BUTTONS: Channel<CriticalSectionRawMutex, Event, 4> = Channel::new();
+
+/// Watch buttons A and B (active low, externally pulled up) and publish each
+/// press to [`BUTTONS`] once it is released. The pins are waited on through
+/// GPIOTE, so the task sleeps until an edge rather than polling.
+#[embassy_executor::task]
+pub async fn buttons_task(mut a: Input<'static>, mut b: Input<'static>) {
+    loop {
+        let first = press(&mut a, &mut b).await;
+        if first.press != Press::Click || first.button == Button::AB {
+            send(first);
+            continue;
+        }
+        // A click may be the first of two
+        let next = select(a.wait_for_low(), b.wait_for_low());
+        if with_timeout(DOUBLE_CLICK, next).await.is_err() {
+            send(first);
+            continue;
+        }
+        let second = press(&mut a, &mut b).await;
+        if second == first {
+            send(Event {
+                button: first.button,
+                press: Press::DoubleClick,
+            });
+        } else {
+            send(first);
+            send(second);
+        }
+    }
+}
+
+/// Wait for a button to go down and both to come back up, noting if the
+/// other button joined in and for how long they were held
+async fn press(a: &mut Input<'_>, b: &mut Input<'_>) -> Event {
+    let pressed = match select(a.wait_for_low(), b.wait_for_low()).await {
+        Either::First(_) => Button::A,
+        Either::Second(_) => Button::B,
+    };
+    let start = Instant::now();
+    Timer::after(DEBOUNCE).await;
+
+    let mut both = false;
+    loop {
+        both |= a.is_low() && b.is_low();
+        if a.is_high() && b.is_high() {
+            break;
+        }
+        select(a.wait_for_any_edge(), b.wait_for_any_edge()).await;
+        Timer::after(DEBOUNCE).await;
+    }
+
+    let held = start.elapsed();
+    Event {
+        button: if both { Button::AB } else { pressed },
+        press: if held >= VERY_LONG_PRESS {
+            Press::VeryLong
+        } else if held >= LONG_PRESS {
+            Press::Long
+        } else {
+            Press::Click
+        },
+    }
+}
+
+fn send(event: Event) {
+    // Dropped if the main loop has fallen behind
+    let _ = BUTTONS.try_send(event);
+    trace!("button {:?} sent", event);
+}
@@ -0,0 +1,405 @@
+use core::fmt::Write;
+
+use defmt::{info, Format};
+use embassy_time::{Duration, Instant};
+use heapless::String;
+use micromath::F32Ext;
+
+use crate::console;
+
+/// Marks a valid record, erased flash reads back as `0xFFFF_FFFF`
+const MAGIC: u32 = 0xCA1B_0002;
+
+/// Records from before full soft-iron matrices: per-axis scale only
+const MAGIC_DIAGONAL: u32 = 0xCA1B_0001;
+
+/// magic (4) + offset (3 * 4) + soft-iron matrix (9 * 4) + field strength (4)
+pub const RECORD_LEN: usize = 56;
+
+/// Smallest field range (nT) accepted on each axis, less than this means the
+/// board was not rotated enough to see both ends of the axis
+const MIN_RANGE: f32 = 20_000.0;
+
+/// Directions seen from the center of the samples are sorted into 4 bins
+/// around each end of each axis, full coverage being all of them
+const COVERAGE_BINS: u32 = 24;
+
+/// Relative RMS error of the corrected field strength that scores zero
+const MAX_RESIDUAL: f32 = 0.25;
+
+/// Lowest [`Quality::score`] of a calibration worth keeping
+pub const MIN_SCORE: u8 = 50;
+
+/// Samples in each window of background auto-calibration, about 2 minutes at
+/// the usual 5 readings a second
+const AUTO_WINDOW: u32 = 600;
+
+/// Fraction of the way the offsets move towards the latest estimate per
+/// window
+const AUTO_GAIN: f32 = 0.25;
+
+/// Shortest time between saving auto-calibrated offsets, to spare the flash
+const AUTO_SAVE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Hard-iron offsets and a soft-iron correction matrix applied to raw
+/// magnetometer readings
+#[derive(Clone, Copy, PartialEq)]
+pub struct Calibration {
+    offset: [f32; 3],
+    soft_iron: [[f32; 3]; 3],
+    /// Expected magnitude of the corrected field, nT
+    field_strength: f32,
+}
+
+impl Calibration {
+    pub const IDENTITY: Self = Self {
+        offset: [0.0; 3],
+        soft_iron: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        field_strength: 0.0,
+    };
+
+    pub const fn new(offset: [f32; 3], soft_iron: [[f32; 3]; 3], field_strength: f32) -> Self {
+        Self {
+            offset,
+            soft_iron,
+            field_strength,
+        }
+    }
+
+    /// Read back a flash [`Calibration::record`], `None` if it holds none
+    pub fn from_record(buf: &[u8; RECORD_LEN]) -> Option<Self> {
+        match u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) {
+            MAGIC => {
+                let rows: [f32; 9] = read_f32s(&buf[16..52]);
+                Some(Self {
+                    offset: read_f32s(&buf[4..16]),
+                    soft_iron: core::array::from_fn(|i| {
+                        [rows[i * 3], rows[i * 3 + 1], rows[i * 3 + 2]]
+                    }),
+                    field_strength: read_f32s::<1>(&buf[52..56])[0],
+                })
+            }
+            MAGIC_DIAGONAL => {
+                let scale: [f32; 3] = read_f32s(&buf[16..28]);
+                let mut calibration = Self::IDENTITY;
+                calibration.offset = read_f32s(&buf[4..16]);
+                for (i, row) in calibration.soft_iron.iter_mut().enumerate() {
+                    row[i] = scale[i];
+                }
+                Some(calibration)
+            }
+            _ => None,
+        }
+    }
+
+    /// The flash record [`Calibration::from_record`] reads back
+    pub fn record(&self) -> [u8; RECORD_LEN] {
+        let mut buf = [0u8; RECORD_LEN];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        write_f32s(&mut buf[4..16], &self.offset);
+        write_f32s(&mut buf[16..52], self.soft_iron.as_flattened());
+        write_f32s(&mut buf[52..56], &[self.field_strength]);
+        buf
+    }
+
+    /// Whether this comes from a completed calibration
+    pub fn is_calibrated(&self) -> bool {
+        *self != Self::IDENTITY
+    }
+
+    pub fn offset(&self) -> [f32; 3] {
+        self.offset
+    }
+
+    pub fn soft_iron(&self) -> [[f32; 3]; 3] {
+        self.soft_iron
+    }
+
+    pub fn field_strength(&self) -> f32 {
+        self.field_strength
+    }
+
+    /// Move the hard-iron offsets `gain` of the way towards `offset`, taking
+    /// the field strength from `range` if there is none yet
+    fn refine(&mut self, offset: [f32; 3], range: [f32; 3], gain: f32) {
+        self.offset =
+            core::array::from_fn(|i| self.offset[i] + (offset[i] - self.offset[i]) * gain);
+        if self.field_strength == 0.0 {
+            self.field_strength = (range[0] + range[1] + range[2]) / 6.0;
+        }
+    }
+
+    /// Correct a raw magnetometer reading
+    pub fn apply(&self, mag: [f32; 3]) -> [f32; 3] {
+        let centered: [f32; 3] = core::array::from_fn(|i| mag[i] - self.offset[i]);
+        self.soft_iron
+            .map(|row| row[0] * centered[0] + row[1] * centered[1] + row[2] * centered[2])
+    }
+
+    /// Reply with the calibration as the `cal import` commands that restore
+    /// it, so it can be backed up or loaded onto another board
+    pub async fn export(&self) {
+        let [x, y, z] = self.offset;
+        let mut lines: [String<{ console::MAX_REPLY }>; 5] = Default::default();
+        let _ = write!(lines[0], "cal import offset {x:.1} {y:.1} {z:.1}");
+        for (i, [a, b, c]) in self.soft_iron.into_iter().enumerate() {
+            let _ = write!(lines[1 + i], "cal import row {i} {a:.6} {b:.6} {c:.6}");
+        }
+        let _ = write!(lines[4], "cal import field {:.1}", self.field_strength);
+        for line in lines {
+            // Wait for room, there are more lines than the queue holds
+            console::REPLIES.send(line).await;
+        }
+    }
+}
+
+/// One line of a calibration sent with `cal import`
+#[derive(Clone, Copy)]
+pub enum Part {
+    /// Hard-iron offsets, nT
+    Offset([f32; 3]),
+    /// A row, 0 to 2, of the soft-iron matrix
+    Row(usize, [f32; 3]),
+    /// Expected magnitude of the corrected field, nT
+    FieldStrength(f32),
+}
+
+/// Smallest soft-iron determinant accepted, anything closer to zero would
+/// flatten the corrected field onto a plane
+const MIN_DETERMINANT: f32 = 1e-3;
+
+/// Puts together a calibration from the lines of a `cal import`, which may
+/// come in any order
+pub struct Import {
+    offset: Option<[f32; 3]>,
+    rows: [Option<[f32; 3]>; 3],
+    field_strength: Option<f32>,
+}
+
+impl Import {
+    pub const fn new() -> Self {
+        Self {
+            offset: None,
+            rows: [None; 3],
+            field_strength: None,
+        }
+    }
+
+    /// Add `part`, giving the calibration once it is complete and starting
+    /// afresh for the next one
+    pub fn add(&mut self, part: Part) -> Result<Option<Calibration>, &'static str> {
+        match part {
+            Part::Offset(offset) => self.offset = Some(offset),
+            Part::Row(row, values) => self.rows[row] = Some(values),
+            Part::FieldStrength(field_strength) => self.field_strength = Some(field_strength),
+        }
+        let (Some(offset), [Some(a), Some(b), Some(c)], Some(field_strength)) =
+            (self.offset, self.rows, self.field_strength)
+        else {
+            return Ok(None);
+        };
+        *self = Self::new();
+        let soft_iron = [a, b, c];
+        if determinant(&soft_iron).abs() < MIN_DETERMINANT {
+            return Err("soft-iron matrix is singular");
+        }
+        Ok(Some(Calibration::new(offset, soft_iron, field_strength)))
+    }
+}
+
+fn determinant(m: &[[f32; 3]; 3]) -> f32 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// How well a calibration fits the samples it was computed from
+#[derive(Clone, Copy, Debug, Format)]
+pub struct Quality {
+    /// 0 to 100, from both of the below
+    pub score: u8,
+    /// Fraction of the sphere of directions seen, 0 to 1
+    pub coverage: f32,
+    /// RMS error of the corrected field strength over the samples, relative
+    /// to the expected field strength
+    pub residual: f32,
+}
+
+impl Quality {
+    /// Whether the calibration is good enough to replace the stored one
+    pub fn is_acceptable(&self) -> bool {
+        self.score >= MIN_SCORE
+    }
+}
+
+/// Collects the extremes of the magnetic field on each axis while the board
+/// is rotated through a figure-8, and which directions have been seen
+pub struct Calibrator {
+    min: [f32; 3],
+    max: [f32; 3],
+    /// One bit per coverage bin
+    bins: u32,
+    /// The latest sample in each coverage bin, spread over the sphere, to
+    /// check the fit against
+    samples: [[f32; 3]; COVERAGE_BINS as usize],
+}
+
+impl Calibrator {
+    pub const fn new() -> Self {
+        Self {
+            min: [f32::MAX; 3],
+            max: [f32::MIN; 3],
+            bins: 0,
+            samples: [[0.0; 3]; COVERAGE_BINS as usize],
+        }
+    }
+
+    pub fn add_sample(&mut self, mag: [f32; 3]) {
+        self.min = core::array::from_fn(|i| self.min[i].min(mag[i]));
+        self.max = core::array::from_fn(|i| self.max[i].max(mag[i]));
+
+        // Until every axis has some range the center is too far off for the
+        // direction to mean much
+        if (0..3).all(|i| self.max[i] - self.min[i] >= MIN_RANGE / 2.0) {
+            let direction: [f32; 3] =
+                core::array::from_fn(|i| mag[i] - (self.max[i] + self.min[i]) / 2.0);
+            let bin = bin(direction);
+            self.bins |= 1 << bin;
+            self.samples[bin as usize] = mag;
+        }
+    }
+
+    /// Fraction of the sphere of directions seen so far, 0 to 1
+    pub fn coverage(&self) -> f32 {
+        self.bins.count_ones() as f32 / COVERAGE_BINS as f32
+    }
+
+    /// Compute the calibration and how good it is, or `None` if some axis was
+    /// not covered
+    pub fn finish(&self) -> Option<(Calibration, Quality)> {
+        let range: [f32; 3] = core::array::from_fn(|i| self.max[i] - self.min[i]);
+        if range.iter().any(|&r| r < MIN_RANGE) {
+            return None;
+        }
+
+        let mean_range = (range[0] + range[1] + range[2]) / 3.0;
+        let mut soft_iron = Calibration::IDENTITY.soft_iron;
+        for (i, row) in soft_iron.iter_mut().enumerate() {
+            row[i] = mean_range / range[i];
+        }
+        let calibration = Calibration {
+            offset: core::array::from_fn(|i| (self.max[i] + self.min[i]) / 2.0),
+            soft_iron,
+            field_strength: mean_range / 2.0,
+        };
+        Some((calibration, self.quality(&calibration)))
+    }
+
+    fn quality(&self, calibration: &Calibration) -> Quality {
+        let mut sum_squares = 0.0;
+        let mut count = 0;
+        for (bin, &sample) in self.samples.iter().enumerate() {
+            if self.bins & (1 << bin) == 0 {
+                continue;
+            }
+            let [x, y, z] = calibration.apply(sample);
+            let error = ((x * x + y * y + z * z).sqrt() - calibration.field_strength)
+                / calibration.field_strength;
+            sum_squares += error * error;
+            count += 1;
+        }
+        let residual = if count == 0 {
+            1.0
+        } else {
+            (sum_squares / count as f32).sqrt()
+        };
+        let coverage = self.coverage();
+        let fit = 1.0 - (residual / MAX_RESIDUAL).min(1.0);
+        Quality {
+            score: (100.0 * coverage * fit).round() as u8,
+            coverage,
+            residual,
+        }
+    }
+}
+
+/// Refines the hard-iron offsets during normal use from the extremes of the
+/// raw field over a rolling window of two halves, each [`AUTO_WINDOW`]
+/// samples long
+pub struct AutoCalibrator {
+    /// Extremes over the previous and the current half
+    previous: Option<([f32; 3], [f32; 3])>,
+    current: Calibrator,
+    samples: u32,
+    saved: Instant,
+}
+
+impl AutoCalibrator {
+    pub fn new() -> Self {
+        Self {
+            previous: None,
+            current: Calibrator::new(),
+            samples: 0,
+            saved: Instant::now(),
+        }
+    }
+
+    /// Take a raw magnetometer reading. At the end of each half window
+    /// `calibration` is refined if the board was turned enough, returning
+    /// whether it is time to save it.
+    pub fn update(&mut self, mag: [f32; 3], calibration: &mut Calibration) -> bool {
+        self.current.add_sample(mag);
+        self.samples += 1;
+        if self.samples < AUTO_WINDOW {
+            return false;
+        }
+
+        let (mut min, mut max) = (self.current.min, self.current.max);
+        if let Some((previous_min, previous_max)) = self.previous {
+            min = core::array::from_fn(|i| min[i].min(previous_min[i]));
+            max = core::array::from_fn(|i| max[i].max(previous_max[i]));
+        }
+        self.previous = Some((self.current.min, self.current.max));
+        self.current = Calibrator::new();
+        self.samples = 0;
+
+        let range: [f32; 3] = core::array::from_fn(|i| max[i] - min[i]);
+        if range.iter().any(|&r| r < MIN_RANGE) {
+            return false;
+        }
+        let offset = core::array::from_fn(|i| (max[i] + min[i]) / 2.0);
+        calibration.refine(offset, range, AUTO_GAIN);
+        info!("auto-calibration offset {}", calibration.offset);
+
+        if self.saved.elapsed() < AUTO_SAVE_INTERVAL {
+            return false;
+        }
+        self.saved = Instant::now();
+        true
+    }
+}
+
+/// Coverage bin of a direction: the end of the axis it is closest to, then
+/// which side of the other two axes it is on
+fn bin(direction: [f32; 3]) -> u32 {
+    let mut axis = 0;
+    for i in 1..3 {
+        if direction[i].abs() > direction[axis].abs() {
+            axis = i;
+        }
+    }
+    let negative = |i: usize| (direction[i] < 0.0) as u32;
+    axis as u32 * 8 + negative(axis) * 4 + negative((axis + 1) % 3) * 2 + negative((axis + 2) % 3)
+}
+
+fn read_f32s<const N: usize>(buf: &[u8]) -> [f32; N] {
+    core::array::from_fn(|i| {
+        f32::from_le_bytes([buf[i * 4], buf[i * 4 + 1], buf[i * 4 + 2], buf[i * 4 + 3]])
+    })
+}
+
+fn write_f32s(buf: &mut [u8], values: &[f32]) {
+    for (chunk, value) in buf.chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+}
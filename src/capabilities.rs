@@ -0,0 +1,58 @@
+use core::fmt::Write;
+
+use heapless::String;
+use micro_compass_core::protocol::PROTOCOL_VERSION;
+
+use crate::console;
+
+/// The Cargo features this firmware was built with, by name
+const FEATURES: &[(&str, bool)] = &[
+    ("gps", cfg!(feature = "gps")),
+    ("fault-injection", cfg!(feature = "fault-injection")),
+    ("rgb", cfg!(feature = "rgb")),
+    ("servo", cfg!(feature = "servo")),
+    ("analog", cfg!(feature = "analog")),
+    ("compass-module", cfg!(feature = "compass-module")),
+    ("oled", cfg!(feature = "oled")),
+    ("lis3mdl", cfg!(feature = "lis3mdl")),
+    ("qmc5883l", cfg!(feature = "qmc5883l")),
+    ("mmc5603", cfg!(feature = "mmc5603")),
+    ("redundant-mag", cfg!(feature = "redundant-mag")),
+    ("gyro", cfg!(feature = "gyro")),
+    ("board-nrf52833-dk", cfg!(feature = "board-nrf52833-dk")),
+    ("trace", cfg!(feature = "trace")),
+    ("energy", cfg!(feature = "energy")),
+    ("verbose-log", cfg!(feature = "verbose-log")),
+];
+
+/// Reply to `hello` with the firmware's name and version and the protocol
+/// version, for a host tool to check before anything else
+pub fn hello() {
+    console::reply(format_args!(
+        "{} {} protocol {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        PROTOCOL_VERSION
+    ));
+}
+
+/// Reply to `capabilities` with the features built in, as many to a
+/// `features` line as fit, `features none` without any
+pub async fn report() {
+    let mut line: String<{ console::MAX_REPLY }> = String::new();
+    let _ = line.push_str("features");
+    let mut any = false;
+    for &(name, _) in FEATURES.iter().filter(|(_, enabled)| *enabled) {
+        if line.len() + 1 + name.len() > console::MAX_REPLY {
+            console::REPLIES.send(line).await;
+            line = String::new();
+            let _ = line.push_str("features");
+        }
+        let _ = write!(line, " {name}");
+        any = true;
+    }
+    if !any {
+        let _ = line.push_str(" none");
+    }
+    console::REPLIES.send(line).await;
+}
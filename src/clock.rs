@@ -0,0 +1,22 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+
+/// UTC as Unix seconds at a moment since boot. The board has no real-time
+/// clock, so this stays unset until the time is given on the console or by
+/// the GPS.
+static SET_AT: Mutex<CriticalSectionRawMutex, Cell<Option<(i64, Instant)>>> =
+    Mutex::new(Cell::new(None));
+
+/// It is `unix_seconds` UTC now
+pub fn set(unix_seconds: i64) {
+    SET_AT.lock(|set_at| set_at.set(Some((unix_seconds, Instant::now()))));
+}
+
+/// UTC as Unix seconds, once the time has been set
+pub fn now() -> Option<i64> {
+    SET_AT
+        .lock(Cell::get)
+        .map(|(seconds, at)| seconds + at.elapsed().as_secs() as i64)
+}
@@ -0,0 +1,90 @@
+use defmt::debug;
+
+use crate::compass_module::{CompassModule, Reading, MAX_REPLY};
+
+/// A CMPS12's 7-bit address, 0xC0 in its datasheet's 8-bit form
+pub const ADDRESS: u8 = 0x60;
+
+/// Reported in register 0, where a CMPS12 has its firmware version
+const VERSION: u8 = 1;
+
+/// Registers as a CMPS12 numbers them. Those left out, the raw magnetometer,
+/// accelerometer and gyroscope readings and the temperature, read as zero.
+const BEARING_8: usize = 0x01;
+const BEARING_TENTHS: usize = 0x02;
+const PITCH: usize = 0x04;
+const ROLL: usize = 0x05;
+const BEARING_SIXTEENTHS: usize = 0x1A;
+const PITCH_16: usize = 0x1C;
+const CALIBRATION: usize = 0x1E;
+const REGISTERS: usize = 0x1F;
+
+/// Calibration state in register 0x1E, two bits each for the system, the
+/// gyroscope, the accelerometer and the magnetometer, 3 for calibrated
+const SYSTEM_CALIBRATED: u8 = 0b11 << 6;
+const ACCEL_CALIBRATED: u8 = 0b11 << 2;
+const MAG_CALIBRATED: u8 = 0b11;
+
+/// A CMPS12: write the register number, then read on from it
+pub struct Cmps12 {
+    /// Next register read
+    register: usize,
+}
+
+impl Cmps12 {
+    pub const fn new() -> Self {
+        Self { register: 0 }
+    }
+}
+
+impl CompassModule for Cmps12 {
+    /// Commands written to register 0, such as the calibration sequence, are
+    /// ignored
+    fn write(&mut self, bytes: &[u8]) {
+        self.register = usize::from(bytes[0]);
+        if bytes.len() > 1 {
+            debug!("cmps12: ignored command {=[u8]:#x}", bytes);
+        }
+    }
+
+    fn read(&mut self, reading: Option<&Reading>, reply: &mut [u8; MAX_REPLY]) -> usize {
+        let map = reading.map_or([0; REGISTERS], registers);
+        // Past the end the controller reads zeros
+        let from = self.register.min(REGISTERS);
+        let len = REGISTERS - from;
+        reply[..len].copy_from_slice(&map[from..]);
+        len
+    }
+
+    fn sent(&mut self, sent: usize) {
+        self.register = (self.register + sent).min(REGISTERS);
+    }
+}
+
+/// The register map for `reading`
+fn registers(reading: &Reading) -> [u8; REGISTERS] {
+    let mut map = [0; REGISTERS];
+    map[0] = VERSION;
+    if let Some(heading) = reading.heading {
+        map[BEARING_8] = (heading * 256.0 / 360.0) as u8;
+        let tenths = (heading * 10.0) as u16 % 3600;
+        map[BEARING_TENTHS..][..2].copy_from_slice(&tenths.to_be_bytes());
+        let sixteenths = (heading * 16.0) as u16 % 5760;
+        map[BEARING_SIXTEENTHS..][..2].copy_from_slice(&sixteenths.to_be_bytes());
+    }
+    map[PITCH] = reading.pitch.clamp(-90.0, 90.0) as i8 as u8;
+    map[ROLL] = reading.roll.clamp(-90.0, 90.0) as i8 as u8;
+    map[PITCH_16..][..2].copy_from_slice(&(reading.pitch as i16).to_be_bytes());
+    map[CALIBRATION] = ACCEL_CALIBRATED
+        | if reading.calibrated {
+            MAG_CALIBRATED
+        } else {
+            0
+        }
+        | if reading.calibrated && reading.heading.is_some() {
+            SYSTEM_CALIBRATED
+        } else {
+            0
+        };
+    map
+}
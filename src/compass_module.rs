@@ -0,0 +1,90 @@
+use core::cell::Cell;
+
+use defmt::warn;
+use embassy_nrf::{
+    peripherals::TWISPI1,
+    twis::{self, Twis},
+};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+use crate::{cmps12::Cmps12, hmc6352::Hmc6352};
+
+/// What the emulated modules report, from the latest sample
+#[derive(Clone, Copy)]
+pub struct Reading {
+    /// Degrees, `None` without the magnetometer, e.g. in tilt-only mode
+    pub heading: Option<f32>,
+    /// Degrees
+    pub pitch: f32,
+    /// Degrees
+    pub roll: f32,
+    /// The magnetometer has a completed calibration
+    pub calibrated: bool,
+}
+
+static READING: Mutex<CriticalSectionRawMutex, Cell<Option<Reading>>> = Mutex::new(Cell::new(None));
+
+pub fn update(reading: Reading) {
+    READING.lock(|cell| cell.set(Some(reading)));
+}
+
+/// Longest reply any module gives to one read
+pub const MAX_REPLY: usize = 32;
+
+/// An off-the-shelf I2C compass module the board can pass for, at the
+/// module's own address, so sketches and flight controllers written for it
+/// work unchanged
+pub trait CompassModule {
+    /// The controller wrote `bytes`: a command, or a register number and
+    /// maybe data for it
+    fn write(&mut self, bytes: &[u8]);
+
+    /// Reply to a read with what was last asked for, from the latest
+    /// `reading`, `None` until there is one. Returns the reply's length.
+    fn read(&mut self, reading: Option<&Reading>, reply: &mut [u8; MAX_REPLY]) -> usize;
+
+    /// `sent` bytes of the last reply were clocked out, the rest left unread
+    fn sent(&mut self, _sent: usize) {}
+}
+
+/// Answer on the edge connector's I2C pins as a CMPS12 and an HMC6352 at
+/// once, one on each of the TWIS's two addresses
+#[embassy_executor::task]
+pub async fn compass_module_task(mut twis: Twis<'static, TWISPI1>) {
+    let mut cmps12 = Cmps12::new();
+    let mut hmc6352 = Hmc6352::new();
+    let mut written = [0u8; 4];
+    let mut reply = [0u8; MAX_REPLY];
+    loop {
+        let command = match twis.listen(&mut written).await {
+            Ok(command) => command,
+            Err(e) => {
+                warn!("compass module: {}", e);
+                continue;
+            }
+        };
+        // Addresses in the order the TWIS was configured with
+        let module: &mut dyn CompassModule = match twis.address_match_index() {
+            0 => &mut cmps12,
+            _ => &mut hmc6352,
+        };
+        let len = match command {
+            twis::Command::Write(len) | twis::Command::WriteRead(len) => len,
+            twis::Command::Read => 0,
+        };
+        if len > 0 {
+            module.write(&written[..len]);
+        }
+        if let twis::Command::Write(_) = command {
+            continue;
+        }
+        let reading = READING.lock(Cell::get);
+        let len = module.read(reading.as_ref(), &mut reply);
+        match twis.respond_to_read(&reply[..len]).await {
+            Ok(sent) => module.sent(sent),
+            // Read on past the end of the reply
+            Err(twis::Error::OverRead) => module.sent(len),
+            Err(e) => warn!("compass module: {}", e),
+        }
+    }
+}
@@ -0,0 +1,879 @@
+use core::fmt::Write;
+
+use defmt::{info, warn};
+use embassy_nrf::{
+    buffered_uarte::BufferedUarteRx,
+    peripherals::{TIMER2, UARTE0},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embedded_io_async::Read as _;
+use heapless::String;
+use micro_compass_core::{
+    deviation::{self, DeviationTable},
+    heading_hold::Gains,
+    protocol::Reference,
+    sun,
+    units::HeadingUnit,
+};
+
+use crate::{
+    alarm, animation,
+    audio::AudioMode,
+    calibration::{self, Calibration},
+    logger::LogMode,
+    motioncal,
+    mounting::MountMode,
+    name::DeviceName,
+    orienteering,
+    profiles::ProfileName,
+    radio::RadioMode,
+    reboot, replay,
+    sensor::{AccelPower, ACCEL_RATES_HZ},
+    settings::{DisplayMode, NightMode, Palette, Steering},
+    telemetry::{AttitudeOutput, OutputFormat},
+};
+
+/// Longest command line accepted, room for a replayed `HDG` line
+const MAX_LINE: usize = 96;
+
+/// Longest reply line
+pub const MAX_REPLY: usize = 64;
+
+/// Requests from the serial console, carried out by the main loop
+pub enum Command {
+    /// Reply with the firmware and protocol versions
+    Hello,
+    /// Reply with the features built in
+    ReportCapabilities,
+    SetName(DeviceName),
+    EraseLog,
+    SetLogMode(LogMode),
+    SetLogInterval(u32),
+    DumpLog,
+    /// Reply with what each stage of the heading pipeline made of the last
+    /// reading
+    ReportPipeline,
+    /// Reply with the two magnetometers and how well they agree
+    #[cfg(all(
+        feature = "redundant-mag",
+        any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+    ))]
+    ReportRedundancy,
+    /// Reply with every setting
+    ReportSettings,
+    /// Go back to the defaults
+    ResetSettings,
+    SetDeclination(f32),
+    /// A point of the deviation table and its correction, `None` to clear it
+    SetDeviation(usize, Option<f32>),
+    ClearDeviation,
+    /// Sector the heading alarm allows, `None` to turn it off
+    SetAlarm(Option<alarm::Sector>),
+    SetMagOdr(u16),
+    SetAccelOdr(u16),
+    SetAccelPower(AccelPower),
+    SetMagLowPower(bool),
+    /// The LSM303AGR magnetometer's low-pass filter
+    SetMagLowPass(bool),
+    /// High-pass filter what the tap detector sees
+    SetTapHighPass(bool),
+    SetHighPassCutoff(u8),
+    /// Leave readings far from 1 g out of tilt compensation
+    SetVibrationRejection(bool),
+    StartCalibration,
+    SetDisplayMode(DisplayMode),
+    /// Display mode to start in, `None` for the last state
+    SetStartup(Option<DisplayMode>),
+    SetNightMode(NightMode),
+    /// Gains of the heading hold
+    SetGains(Gains),
+    SetSteering(Steering),
+    SetPalette(Palette),
+    SetDisplayReference(Reference),
+    SetTelemetryReference(Reference),
+    SetOutputFormat(OutputFormat),
+    SetAttitudeOutput(AttitudeOutput),
+    /// Attitude messages per second
+    SetAttitudeRate(u8),
+    SetHeadingUnit(HeadingUnit),
+    SetTiltOnly(bool),
+    SetAudioMode(AudioMode),
+    SetRadioMode(RadioMode),
+    SetRadioGroup(u8),
+    /// Offset from the clock board's time, in [`RadioMode::Sync`]
+    ReportSync,
+    /// Turn the bezel by tipping the board, and show its marker
+    SetBezel(bool),
+    ReportBezel,
+    ReportSteps,
+    SetStride(f32),
+    /// Magnetometer samples the median is taken over, 1 for none
+    SetMagMedian(u8),
+    /// Magnetometer samples averaged per heading
+    SetMagAverage(u8),
+    /// Slow down while the board lies still
+    SetAdaptiveRate(bool),
+    /// Seconds without use before going idle, 0 for never
+    SetIdleTimeout(u32),
+    /// Degrees the heading has to move before it is reported again, 0 for
+    /// every sample
+    SetHeadingThreshold(u8),
+    /// Degrees either side of the target the haptic output pulses within, 0
+    /// for never
+    SetHapticWindow(u8),
+    /// Degrees the backup magnetometer may disagree by before it is a fault
+    SetRedundancyLimit(u8),
+    /// Display brightness, 1 to 8, or `None` to follow the ambient light
+    SetBrightness(Option<u8>),
+    /// Needle redraws per second
+    SetRefreshRate(u8),
+    /// Fastest the needle swings, degrees per second, or `None` to jump
+    SetDamping(Option<u16>),
+    /// Blink the subsystem health on a corner of the LED matrix
+    SetHeartbeat(bool),
+    SetAutoCalibration(bool),
+    SetMounting(MountMode),
+    ResetSteps,
+    #[cfg(feature = "gps")]
+    AddWaypoint(crate::geo::Position),
+    /// Add the current GPS position as a waypoint
+    #[cfg(feature = "gps")]
+    AddWaypointHere,
+    #[cfg(feature = "gps")]
+    NextWaypoint,
+    #[cfg(feature = "gps")]
+    ClearWaypoints,
+    /// A calibration computed on the host, e.g. by MotionCal
+    SetCalibration(Calibration),
+    /// Reply with the calibration as `cal import` lines
+    ExportCalibration,
+    ListProfiles,
+    /// Switch to a calibration profile, made afresh if it doesn't exist
+    SelectProfile(ProfileName),
+    DeleteProfile(ProfileName),
+    /// One line of a calibration, applied once all of them have arrived
+    ImportCalibration(calibration::Part),
+    /// Walk a bearing, in degrees from the display's north, for a number of
+    /// paces, `None` to stop
+    SetLeg(Option<(f32, u32)>),
+    /// Start, stop or sync the race countdown
+    Race(RaceCommand),
+    /// Start, stop or list the magnetic anomaly survey
+    Survey(SurveyCommand),
+    StartBiasCheck,
+    StopBiasCheck,
+    /// Latitude and longitude for the sun check, degrees
+    SetLocation(Option<[f32; 2]>),
+    /// UTC as Unix seconds
+    SetTime(i64),
+    StartSunCheck,
+    StopSunCheck,
+    /// Take readings from `HDG` lines on the console instead of the sensors
+    SetReplay(bool),
+    /// Send defmt logs over the UART instead of telemetry
+    SetUartLog(bool),
+    /// Reset, into the bootloader's update mode or this firmware again
+    Reboot(reboot::Target),
+}
+
+pub enum RaceCommand {
+    Start,
+    Stop,
+    /// Round to the nearest minute
+    Sync,
+}
+
+pub enum SurveyCommand {
+    Start,
+    Stop,
+    /// Reply with the points logged
+    List,
+}
+
+pub static COMMANDS: Channel<CriticalSectionRawMutex, Command, 2> = Channel::new();
+
+/// Replies to console commands, written back out by the telemetry task
+pub static REPLIES: Channel<CriticalSectionRawMutex, String<MAX_REPLY>, 4> = Channel::new();
+
+/// Read commands from the micro:bit's USB serial port, one per line:
+///
+/// - `hello`: reply with the firmware version and the protocol version
+/// - `capabilities`: reply with the Cargo features built in
+/// - `pipeline`: reply with each heading pipeline stage's output for the
+///   last reading
+/// - `redundancy`: with the `redundant-mag` feature, reply with the two
+///   magnetometers, how far apart their headings are and which is in use
+/// - `name <name>`: rename the device
+/// - `log erase`: erase the flash log
+/// - `log start [motion]`, `log stop`: log every sample, only while moving, or
+///   nothing
+/// - `log interval <seconds>`: shortest time between logged samples
+/// - `log dump`: send the flash log back, oldest record first
+/// - `log uart <on|off>`: send the defmt logs over the UART in place of
+///   telemetry and replies, for reading with `defmt-print`
+/// - `settings dump`: list every setting, `settings reset`: restore the defaults
+/// - `set declination <degrees>`: magnetic declination, east positive
+/// - `deviation <heading> <degrees|none>`, `deviation clear`: the correction
+///   for the installation's deviation at a compass heading in 22.5° steps
+/// - `alarm <from> <to>`, `alarm off`: sound the alarm while the heading stays
+///   outside the sector clockwise from `from` to `to`
+/// - `set odr <hz>`: magnetometer output data rate, 10, 20, 50 or 100
+/// - `set accel <hz>`: accelerometer output data rate, 10, 25, 50, 100, 200
+///   or 400
+/// - `set power accel <high|normal|low>`, `set power mag <high|low>`:
+///   resolution traded for current draw
+/// - `set lowpass <on|off>`: the LSM303AGR magnetometer's low-pass filter,
+///   less noise for more lag
+/// - `set hpf tap <on|off>`, `set hpf cutoff <0-3>`: the accelerometer's
+///   high-pass filter in front of the tap and wake detectors
+/// - `set vibration <on|off>`: hold the tilt while the board is being shaken
+///   or accelerated, e.g. on a bicycle
+/// - `set stride <meters>`: distance per step for dead reckoning
+/// - `set median <1|3|5>`: magnetometer samples the median is taken over
+/// - `set average <samples>`: magnetometer samples averaged per heading
+/// - `set adaptive <on|off>`: slow down while the board lies still
+/// - `set idle <seconds>`: time without use before the display blanks, 0 for
+///   never
+/// - `set threshold <degrees>`: only send and log a heading once it has
+///   moved this far or into another cardinal direction, 0 for every sample
+/// - `set brightness <auto|1-8>`: display brightness, or follow the ambient
+///   light
+/// - `set refresh <hz>`: needle redraws per second, 10 to 50
+/// - `set damping <off|degrees per second>`: fastest the needle swings
+/// - `set haptic <degrees>`: pulse the haptic output on coming within this
+///   far of north or the active bearing, 0 for never
+/// - `set redundancy <degrees>`: how far the two magnetometers' headings may
+///   differ before it is a fault, 5 to 90
+/// - `set heartbeat <on|off>`: blink the subsystem health on a corner of the
+///   display
+/// - `set autocal <on|off>`: refine the hard-iron offsets during normal use
+/// - `mount <auto|flat|upside-down|vertical>`, `mount <x> <y> <z>`: how the
+///   board is mounted, following the side facing up, as a preset or the sensor
+///   axis each board axis reads, e.g. `mount x -z y`
+/// - `cal start`: start a magnetometer calibration
+/// - `profile list`: list the calibration profiles, `profile use <name>`:
+///   switch to one, made afresh if it doesn't exist, `profile delete <name>`:
+///   free one
+/// - `cal export`: list the calibration as the `cal import` lines that
+///   restore it
+/// - `cal import <offset <x> <y> <z>|row <0-2> <a> <b> <c>|field <nT>>`: one
+///   part of a calibration, applied once all five lines have arrived
+/// - `bias <start|stop>`: guided accuracy check on a turntable
+/// - `leg <bearing> <paces>`, `leg stop`: walk a bearing for a number of
+///   paces
+/// - `reboot [bootloader]`: reset, or start the bootloader's firmware update
+///   mode
+/// - `race <start|stop|sync>`: 5-4-1-0 start countdown, sync rounding it to
+///   the nearest minute
+/// - `survey <start|stop|list>`: log the field at each step and mark the
+///   anomalies, list replying with the points
+/// - `set location <latitude> <longitude>`, `set location none`: where the
+///   board is, for the sun check without a GPS
+/// - `time <yyyy-mm-dd> <hh:mm[:ss]>`: the date and time, UTC
+/// - `sun <check|stop>`: check the heading against the sun's azimuth
+/// - `pid <kp> <ki> <kd>`: gains of the heading hold steering the servo
+///   output towards the active bearing
+/// - `steering <rudder|differential>`: whether the servo output drives a
+///   rudder servo or two drive motors
+/// - `set palette <rainbow|cardinal>`: how the RGB LEDs color the heading
+/// - `mode <arrow|degrees|trail>`: what the display shows
+/// - `startup <last|arrow|degrees|trail>`: start as the board was left, or
+///   always in one display mode following no stored bearing
+/// - `night <on|off|auto>`: night mode, or with the ambient light
+/// - `set reference <display|telemetry> <magnetic|true>`: north used by the
+///   display or by telemetry and the log
+/// - `format <text|nmea|binary|motioncal|mavlink|json>`: telemetry output format
+/// - `units <degrees|radians|mils|grads>`: heading unit in text output
+/// - `attitude <off|quaternion|euler>`: also send the orientation as a
+///   quaternion, optionally with Euler angles
+/// - `attitude rate <hz>`: attitude messages per second, 1 to 10
+/// - `tilt <on|off>`: report only pitch, roll and which side is up, without
+///   the magnetometer
+/// - `audio <proximity|clicks|geiger>`: what the speaker conveys
+/// - `radio <off|send|receive|compare>`: broadcast the heading to other
+///   boards, show theirs, or both, to match another board's heading
+/// - `radio <clock|sync>`: beacon this board's time, or log on the time of
+///   the group's clock board
+/// - `sync`: report the offset from the clock board's time
+/// - `bezel [on|off]`: report the bezel's bearing, or show the bezel and turn
+///   it by tipping the board to one side and back
+/// - `radio group <0-255>`: boards only hear others in the same group
+/// - `steps [reset]`: report or reset the step count
+/// - `waypoint <latitude> <longitude>|here`: with the `gps` feature, store a
+///   waypoint, in decimal degrees or the current position, and navigate to it
+/// - `waypoint next|clear`: move on to the next stored waypoint, or forget
+///   them all
+/// - `replay <on|off>`: take readings from text telemetry `HDG` lines sent
+///   to the console instead of the sensors
+///
+/// Each command is answered with `ok` or `error: <reason>`, except replayed
+/// `HDG` lines, which aren't answered. MotionCal's binary calibration packets
+/// are accepted in between lines.
+#[embassy_executor::task]
+pub async fn console_task(mut rx: BufferedUarteRx<'static, UARTE0, TIMER2>) {
+    let mut line: String<MAX_LINE> = String::new();
+    let mut buf = [0u8; 1];
+
+    loop {
+        if rx.read(&mut buf).await.is_err() {
+            warn!("console uart read error");
+            line.clear();
+            continue;
+        }
+
+        let mut byte = buf[0];
+        if line.is_empty() && byte == motioncal::PACKET_HEADER[0] {
+            match read_packet(&mut rx).await {
+                // Not a packet after all, just a line starting with that byte
+                Some(next) => {
+                    let _ = line.push(byte as char);
+                    byte = next;
+                }
+                None => continue,
+            }
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                if !line.is_empty() {
+                    handle(line.trim());
+                }
+                line.clear();
+            }
+            b => {
+                if line.push(b as char).is_err() {
+                    warn!("console line too long");
+                    line.clear();
+                }
+            }
+        }
+    }
+}
+
+fn handle(line: &str) {
+    if line.starts_with("HDG,") {
+        match replay::parse(line) {
+            Some(reading) => replay::feed(reading),
+            None => warn!("bad replayed line"),
+        }
+        return;
+    }
+    let command = match parse(line) {
+        Ok(command) => command,
+        Err(reason) => {
+            info!("bad command `{}`: {}", line, reason);
+            reply(format_args!("error: {reason}"));
+            return;
+        }
+    };
+    // Commands are rare, the main loop keeps up easily
+    let _ = COMMANDS.try_send(command);
+    trace!("command sent");
+}
+
+fn parse(line: &str) -> Result<Command, &'static str> {
+    let mut words = line.split_whitespace();
+    let command = match (words.next(), words.next()) {
+        (Some("name"), Some(_)) => {
+            // The name is the rest of the line, spaces included
+            let name = line["name".len()..].trim();
+            return String::try_from(name)
+                .map(Command::SetName)
+                .map_err(|_| "name too long");
+        }
+        (Some("hello"), None) => Command::Hello,
+        (Some("capabilities"), None) => Command::ReportCapabilities,
+        (Some("pipeline"), None) => Command::ReportPipeline,
+        #[cfg(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        ))]
+        (Some("redundancy"), None) => Command::ReportRedundancy,
+        (Some("settings"), Some("dump")) => Command::ReportSettings,
+        (Some("settings"), Some("reset")) => Command::ResetSettings,
+        (Some("log"), Some("erase")) => Command::EraseLog,
+        (Some("log"), Some("start")) => match words.next() {
+            None => Command::SetLogMode(LogMode::Continuous),
+            Some("motion") => Command::SetLogMode(LogMode::Motion),
+            Some(_) => return Err("expected log start [motion]"),
+        },
+        (Some("log"), Some("stop")) => Command::SetLogMode(LogMode::Off),
+        (Some("log"), Some("interval")) => {
+            let seconds = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|&seconds| seconds <= crate::settings::MAX_LOG_INTERVAL_S)
+                .ok_or("interval must be 0 to 3600 s")?;
+            Command::SetLogInterval(seconds)
+        }
+        (Some("log"), Some("dump")) => Command::DumpLog,
+        (Some("log"), Some("uart")) => match words.next() {
+            Some("on") => Command::SetUartLog(true),
+            Some("off") => Command::SetUartLog(false),
+            _ => return Err("expected log uart <on|off>"),
+        },
+        (Some("set"), Some("declination")) => {
+            let degrees: f32 = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .ok_or("expected degrees")?;
+            if !(-180.0..=180.0).contains(&degrees) {
+                return Err("declination out of range");
+            }
+            Command::SetDeclination(degrees)
+        }
+        (Some("alarm"), Some("off")) => Command::SetAlarm(None),
+        (Some("alarm"), Some(from)) => {
+            let sector = alarm::Sector {
+                from: from.parse().map_err(|_| "expected degrees")?,
+                to: words
+                    .next()
+                    .and_then(|to| to.parse().ok())
+                    .ok_or("expected alarm <from> <to>")?,
+            };
+            if sector.from >= 360 || sector.to >= 360 || sector.from == sector.to {
+                return Err("expected two different headings, 0 to 359");
+            }
+            Command::SetAlarm(Some(sector))
+        }
+        (Some("deviation"), Some("clear")) => Command::ClearDeviation,
+        (Some("deviation"), Some(heading)) => {
+            let point = heading
+                .parse()
+                .ok()
+                .and_then(DeviationTable::point)
+                .ok_or("expected a heading in 22.5 degree steps")?;
+            let correction = match words.next() {
+                Some("none") => None,
+                Some(degrees) => {
+                    let degrees: f32 = degrees.parse().map_err(|_| "expected degrees")?;
+                    if degrees.abs() > deviation::MAX_DEVIATION {
+                        return Err("deviation out of range");
+                    }
+                    Some(degrees)
+                }
+                None => return Err("expected deviation <heading> <degrees|none>"),
+            };
+            Command::SetDeviation(point, correction)
+        }
+        (Some("set"), Some("odr")) => {
+            let hz = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|hz| lsm303agr::MagOutputDataRate::from_hertz(*hz).is_some())
+                .ok_or("odr must be 10, 20, 50 or 100")?;
+            Command::SetMagOdr(hz)
+        }
+        (Some("set"), Some("accel")) => {
+            let hz = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|hz| ACCEL_RATES_HZ.contains(hz))
+                .ok_or("accel odr must be 10, 25, 50, 100, 200 or 400")?;
+            Command::SetAccelOdr(hz)
+        }
+        (Some("set"), Some("power")) => match (words.next(), words.next()) {
+            (Some("accel"), Some("high")) => Command::SetAccelPower(AccelPower::HighResolution),
+            (Some("accel"), Some("normal")) => Command::SetAccelPower(AccelPower::Normal),
+            (Some("accel"), Some("low")) => Command::SetAccelPower(AccelPower::LowPower),
+            (Some("mag"), Some("high")) => Command::SetMagLowPower(false),
+            (Some("mag"), Some("low")) => Command::SetMagLowPower(true),
+            (Some("mag"), Some("normal")) => return Err("the magnetometer has no normal mode"),
+            _ => return Err("expected set power <accel|mag> <high|normal|low>"),
+        },
+        (Some("set"), Some("lowpass")) => match words.next() {
+            Some("on") => Command::SetMagLowPass(true),
+            Some("off") => Command::SetMagLowPass(false),
+            _ => return Err("expected set lowpass <on|off>"),
+        },
+        (Some("set"), Some("hpf")) => match (words.next(), words.next()) {
+            (Some("tap"), Some("on")) => Command::SetTapHighPass(true),
+            (Some("tap"), Some("off")) => Command::SetTapHighPass(false),
+            (Some("cutoff"), Some(value)) => Command::SetHighPassCutoff(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|&cutoff| cutoff <= crate::sensor::MAX_HIGH_PASS_CUTOFF)
+                    .ok_or("cutoff must be 0 to 3")?,
+            ),
+            _ => return Err("expected set hpf <tap <on|off>|cutoff <0-3>>"),
+        },
+        (Some("set"), Some("vibration")) => match words.next() {
+            Some("on") => Command::SetVibrationRejection(true),
+            Some("off") => Command::SetVibrationRejection(false),
+            _ => return Err("expected set vibration <on|off>"),
+        },
+        (Some("set"), Some("median")) => {
+            let window = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|window| matches!(window, 1 | 3 | 5))
+                .ok_or("median must be over 1, 3 or 5 samples")?;
+            Command::SetMagMedian(window)
+        }
+        (Some("set"), Some("average")) => {
+            let samples = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|samples| crate::settings::MAG_AVERAGE_RANGE.contains(samples))
+                .ok_or("average must be over 1 to 16 samples")?;
+            Command::SetMagAverage(samples)
+        }
+        (Some("set"), Some("adaptive")) => match words.next() {
+            Some("on") => Command::SetAdaptiveRate(true),
+            Some("off") => Command::SetAdaptiveRate(false),
+            _ => return Err("expected set adaptive <on|off>"),
+        },
+        (Some("set"), Some("idle")) => {
+            let seconds = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|&seconds| seconds <= crate::settings::MAX_IDLE_TIMEOUT_S)
+                .ok_or("idle timeout must be 0 to 3600 s")?;
+            Command::SetIdleTimeout(seconds)
+        }
+        (Some("set"), Some("threshold")) => {
+            let degrees = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|&degrees| degrees <= crate::heading_change::MAX_THRESHOLD)
+                .ok_or("threshold must be 0 to 180 degrees")?;
+            Command::SetHeadingThreshold(degrees)
+        }
+        (Some("set"), Some("haptic")) => {
+            let degrees = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|&degrees| degrees <= crate::haptic::MAX_WINDOW)
+                .ok_or("haptic window must be 0 to 45 degrees")?;
+            Command::SetHapticWindow(degrees)
+        }
+        (Some("set"), Some("redundancy")) => {
+            let degrees = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|degrees| crate::settings::REDUNDANCY_RANGE.contains(degrees))
+                .ok_or("redundancy limit must be 5 to 90 degrees")?;
+            Command::SetRedundancyLimit(degrees)
+        }
+        (Some("set"), Some("brightness")) => match words.next() {
+            Some("auto") => Command::SetBrightness(None),
+            level => Command::SetBrightness(Some(
+                level
+                    .and_then(|value| value.parse().ok())
+                    .filter(|level| (1..=animation::GREY_LEVELS).contains(level))
+                    .ok_or("expected set brightness <auto|1-8>")?,
+            )),
+        },
+        (Some("set"), Some("refresh")) => {
+            let hz = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|hz| crate::display::REFRESH_RANGE_HZ.contains(hz))
+                .ok_or("refresh must be 10 to 50 Hz")?;
+            Command::SetRefreshRate(hz)
+        }
+        (Some("set"), Some("damping")) => match words.next() {
+            Some("off") => Command::SetDamping(None),
+            rate => Command::SetDamping(Some(
+                rate.and_then(|value| value.parse().ok())
+                    .filter(|rate| crate::needle::DAMPING_RANGE.contains(rate))
+                    .ok_or("expected set damping <off|10-720>")?,
+            )),
+        },
+        (Some("set"), Some("heartbeat")) => match words.next() {
+            Some("on") => Command::SetHeartbeat(true),
+            Some("off") => Command::SetHeartbeat(false),
+            _ => return Err("expected set heartbeat <on|off>"),
+        },
+        (Some("set"), Some("palette")) => match words.next() {
+            Some("rainbow") => Command::SetPalette(Palette::Rainbow),
+            Some("cardinal") => Command::SetPalette(Palette::Cardinal),
+            _ => return Err("expected set palette <rainbow|cardinal>"),
+        },
+        (Some("set"), Some("autocal")) => match words.next() {
+            Some("on") => Command::SetAutoCalibration(true),
+            Some("off") => Command::SetAutoCalibration(false),
+            _ => return Err("expected set autocal <on|off>"),
+        },
+        (Some("set"), Some("stride")) => {
+            let stride = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|stride| crate::settings::STRIDE_RANGE.contains(stride))
+                .ok_or("stride must be 0.2 to 2 m")?;
+            Command::SetStride(stride)
+        }
+        (Some("set"), Some("reference")) => {
+            let target = words.next();
+            let reference = match words.next() {
+                Some("magnetic") => Reference::Magnetic,
+                Some("true") => Reference::True,
+                _ => return Err("reference must be magnetic or true"),
+            };
+            match target {
+                Some("display") => Command::SetDisplayReference(reference),
+                Some("telemetry") => Command::SetTelemetryReference(reference),
+                _ => return Err("expected display or telemetry"),
+            }
+        }
+        (Some("mount"), Some(first)) => {
+            let mounting = MountMode::parse(core::iter::once(first).chain(words.by_ref()))
+                .ok_or("expected auto, flat, upside-down, vertical or three distinct axes")?;
+            Command::SetMounting(mounting)
+        }
+        (Some("cal"), Some("start")) => Command::StartCalibration,
+        (Some("cal"), Some("export")) => Command::ExportCalibration,
+        (Some("profile"), Some("list")) => Command::ListProfiles,
+        (Some("profile"), Some(action @ ("use" | "delete"))) => {
+            let name = words
+                .next()
+                .and_then(|name| String::try_from(name).ok())
+                .ok_or("expected a profile name of up to 8 characters")?;
+            match action {
+                "use" => Command::SelectProfile(name),
+                _ => Command::DeleteProfile(name),
+            }
+        }
+        (Some("cal"), Some("import")) => {
+            let part = words.next();
+            let row = match part {
+                Some("row") => Some(
+                    words
+                        .next()
+                        .and_then(|row| row.parse::<usize>().ok())
+                        .filter(|&row| row < 3)
+                        .ok_or("row must be 0 to 2")?,
+                ),
+                _ => None,
+            };
+            let mut values = [0.0f32; 3];
+            let count = match part {
+                Some("offset" | "row") => 3,
+                Some("field") => 1,
+                _ => return Err("expected cal import <offset|row|field> <values>"),
+            };
+            for value in &mut values[..count] {
+                *value = words
+                    .next()
+                    .and_then(|value| value.parse::<f32>().ok())
+                    .filter(|value| value.is_finite())
+                    .ok_or("expected numbers")?;
+            }
+            Command::ImportCalibration(match (part, row) {
+                (_, Some(row)) => calibration::Part::Row(row, values),
+                (Some("offset"), _) => calibration::Part::Offset(values),
+                _ if values[0] >= 0.0 => calibration::Part::FieldStrength(values[0]),
+                _ => return Err("field strength must not be negative"),
+            })
+        }
+        (Some("leg"), Some("stop")) => Command::SetLeg(None),
+        (Some("leg"), Some(bearing)) => {
+            let bearing: f32 = bearing.parse().map_err(|_| "expected degrees")?;
+            let paces: u32 = words
+                .next()
+                .and_then(|paces| paces.parse().ok())
+                .ok_or("expected leg <bearing> <paces>")?;
+            if !(0.0..360.0).contains(&bearing) || !(1..=orienteering::MAX_PACES).contains(&paces) {
+                return Err("leg out of range");
+            }
+            Command::SetLeg(Some((bearing, paces)))
+        }
+        (Some("reboot"), None) => Command::Reboot(reboot::Target::Application),
+        (Some("reboot"), Some("bootloader")) => Command::Reboot(reboot::Target::Bootloader),
+        (Some("race"), Some("start")) => Command::Race(RaceCommand::Start),
+        (Some("race"), Some("stop")) => Command::Race(RaceCommand::Stop),
+        (Some("race"), Some("sync")) => Command::Race(RaceCommand::Sync),
+        (Some("survey"), Some("start")) => Command::Survey(SurveyCommand::Start),
+        (Some("survey"), Some("stop")) => Command::Survey(SurveyCommand::Stop),
+        (Some("survey"), Some("list")) => Command::Survey(SurveyCommand::List),
+        (Some("bias"), Some("start")) => Command::StartBiasCheck,
+        (Some("bias"), Some("stop")) => Command::StopBiasCheck,
+        (Some("sun"), Some("check")) => Command::StartSunCheck,
+        (Some("sun"), Some("stop")) => Command::StopSunCheck,
+        (Some("set"), Some("location")) => match (words.next(), words.next()) {
+            (Some("none"), None) => Command::SetLocation(None),
+            (Some(latitude), Some(longitude)) => {
+                let latitude: f32 = latitude.parse().map_err(|_| "expected degrees")?;
+                let longitude: f32 = longitude.parse().map_err(|_| "expected degrees")?;
+                if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                    return Err("location out of range");
+                }
+                Command::SetLocation(Some([latitude, longitude]))
+            }
+            _ => return Err("expected set location <latitude> <longitude>"),
+        },
+        (Some("time"), Some(date)) => Command::SetTime(
+            parse_time(date, words.next()).ok_or("expected yyyy-mm-dd hh:mm[:ss]")?,
+        ),
+        (Some("tilt"), Some("on")) => Command::SetTiltOnly(true),
+        (Some("tilt"), Some("off")) => Command::SetTiltOnly(false),
+        (Some("audio"), Some("proximity")) => Command::SetAudioMode(AudioMode::Proximity),
+        (Some("audio"), Some("clicks")) => Command::SetAudioMode(AudioMode::Clicks),
+        (Some("audio"), Some("geiger")) => Command::SetAudioMode(AudioMode::Geiger),
+        (Some("radio"), Some("off")) => Command::SetRadioMode(RadioMode::Off),
+        (Some("radio"), Some("send")) => Command::SetRadioMode(RadioMode::Send),
+        (Some("radio"), Some("receive")) => Command::SetRadioMode(RadioMode::Receive),
+        (Some("radio"), Some("compare")) => Command::SetRadioMode(RadioMode::Compare),
+        (Some("radio"), Some("clock")) => Command::SetRadioMode(RadioMode::Clock),
+        (Some("radio"), Some("sync")) => Command::SetRadioMode(RadioMode::Sync),
+        (Some("radio"), Some("group")) => {
+            let group = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .ok_or("group must be 0 to 255")?;
+            Command::SetRadioGroup(group)
+        }
+        #[cfg(feature = "gps")]
+        (Some("waypoint"), Some("here")) => Command::AddWaypointHere,
+        #[cfg(feature = "gps")]
+        (Some("waypoint"), Some("next")) => Command::NextWaypoint,
+        #[cfg(feature = "gps")]
+        (Some("waypoint"), Some("clear")) => Command::ClearWaypoints,
+        #[cfg(feature = "gps")]
+        (Some("waypoint"), Some(latitude)) => {
+            let latitude = latitude
+                .parse()
+                .ok()
+                .filter(|lat: &f64| (-90.0..=90.0).contains(lat));
+            let longitude = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|lon: &f64| (-180.0..=180.0).contains(lon));
+            match (latitude, longitude) {
+                (Some(latitude), Some(longitude)) => Command::AddWaypoint(crate::geo::Position {
+                    latitude,
+                    longitude,
+                }),
+                _ => return Err("expected latitude and longitude"),
+            }
+        }
+        (Some("sync"), None) => Command::ReportSync,
+        (Some("bezel"), None) => Command::ReportBezel,
+        (Some("bezel"), Some("on")) => Command::SetBezel(true),
+        (Some("bezel"), Some("off")) => Command::SetBezel(false),
+        (Some("steps"), None) => Command::ReportSteps,
+        (Some("steps"), Some("reset")) => Command::ResetSteps,
+        (Some("units"), Some(unit)) => HeadingUnit::from_name(unit)
+            .map(Command::SetHeadingUnit)
+            .ok_or("units must be degrees, radians, mils or grads")?,
+        (Some("format"), Some(format)) => Command::SetOutputFormat(match format {
+            "text" => OutputFormat::Text,
+            "nmea" => OutputFormat::Nmea,
+            "binary" => OutputFormat::Binary,
+            "motioncal" => OutputFormat::MotionCal,
+            "mavlink" => OutputFormat::Mavlink,
+            "json" => OutputFormat::Json,
+            _ => return Err("format must be text, nmea, binary, motioncal, mavlink or json"),
+        }),
+        (Some("attitude"), Some("rate")) => {
+            let hz = words
+                .next()
+                .and_then(|value| value.parse().ok())
+                .filter(|hz| (1..=crate::telemetry::MAX_ATTITUDE_RATE_HZ).contains(hz))
+                .ok_or("attitude rate must be 1 to 10 Hz")?;
+            Command::SetAttitudeRate(hz)
+        }
+        (Some("attitude"), Some(output)) => Command::SetAttitudeOutput(match output {
+            "off" => AttitudeOutput::Off,
+            "quaternion" => AttitudeOutput::Quaternion,
+            "euler" => AttitudeOutput::Euler,
+            _ => return Err("attitude must be off, quaternion or euler"),
+        }),
+        (Some("mode"), Some("arrow")) => Command::SetDisplayMode(DisplayMode::Arrow),
+        (Some("mode"), Some("degrees")) => Command::SetDisplayMode(DisplayMode::Degrees),
+        (Some("mode"), Some("trail")) => Command::SetDisplayMode(DisplayMode::Trail),
+        (Some("startup"), Some(mode)) => Command::SetStartup(match mode {
+            "last" => None,
+            "arrow" => Some(DisplayMode::Arrow),
+            "degrees" => Some(DisplayMode::Degrees),
+            "trail" => Some(DisplayMode::Trail),
+            _ => return Err("startup must be last, arrow, degrees or trail"),
+        }),
+        (Some("pid"), Some(kp)) => {
+            let gain = |value: Option<&str>| {
+                value
+                    .and_then(|value| value.parse().ok())
+                    .ok_or("expected pid <kp> <ki> <kd>")
+            };
+            let gains = Gains {
+                kp: gain(Some(kp))?,
+                ki: gain(words.next())?,
+                kd: gain(words.next())?,
+            };
+            if !gains.is_valid() {
+                return Err("gains must be 0 to 1");
+            }
+            Command::SetGains(gains)
+        }
+        (Some("steering"), Some("rudder")) => Command::SetSteering(Steering::Rudder),
+        (Some("steering"), Some("differential")) => Command::SetSteering(Steering::Differential),
+        (Some("night"), Some("off")) => Command::SetNightMode(NightMode::Off),
+        (Some("night"), Some("on")) => Command::SetNightMode(NightMode::On),
+        (Some("night"), Some("auto")) => Command::SetNightMode(NightMode::Auto),
+        (Some("replay"), Some("on")) => Command::SetReplay(true),
+        (Some("replay"), Some("off")) => Command::SetReplay(false),
+        _ => return Err("unknown command"),
+    };
+    match words.next() {
+        Some(_) => Err("unexpected argument"),
+        None => Ok(command),
+    }
+}
+
+/// Read the rest of a MotionCal calibration packet, whose first byte has just
+/// arrived. If the second byte doesn't match the header it is returned to be
+/// handled as part of a command line.
+async fn read_packet(rx: &mut BufferedUarteRx<'static, UARTE0, TIMER2>) -> Option<u8> {
+    let mut packet = [0u8; motioncal::PACKET_LEN];
+    packet[0] = motioncal::PACKET_HEADER[0];
+    if rx.read_exact(&mut packet[1..2]).await.is_err() {
+        warn!("console uart read error");
+        return None;
+    }
+    if packet[1] != motioncal::PACKET_HEADER[1] {
+        return Some(packet[1]);
+    }
+    if rx.read_exact(&mut packet[2..]).await.is_err() {
+        warn!("console uart read error");
+        return None;
+    }
+
+    match motioncal::parse_packet(&packet) {
+        Some(calibration) => {
+            let _ = COMMANDS.try_send(Command::SetCalibration(calibration));
+            trace!("command sent");
+        }
+        None => warn!("bad motioncal packet"),
+    }
+    None
+}
+
+/// Unix seconds from a UTC date and time, `yyyy-mm-dd` and `hh:mm[:ss]`
+fn parse_time(date: &str, time: Option<&str>) -> Option<i64> {
+    let mut date = date.split('-').map(str::parse::<u32>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time?.split(':').map(str::parse::<u32>);
+    let (hour, minute) = (time.next()?.ok()?, time.next()?.ok()?);
+    let second = time.next().unwrap_or(Ok(0)).ok()?;
+    if date.next().is_some()
+        || time.next().is_some()
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+    let days = sun::days_from_civil(i32::try_from(year).ok()?, month, day);
+    Some(days * 86_400 + i64::from(hour * 3600 + minute * 60 + second))
+}
+
+/// Send a line back to the console
+pub fn reply(args: core::fmt::Arguments) {
+    let mut line = String::new();
+    // Replies are short, a failed write would only truncate one
+    let _ = line.write_fmt(args);
+    let _ = REPLIES.try_send(line);
+}
@@ -0,0 +1,64 @@
+use embassy_time::{Duration, Instant};
+
+use crate::{angle_diff, gps::CourseFix, normalize_heading};
+
+/// Below this speed the course over ground is too noisy to use
+const MIN_SPEED_MPS: f32 = 1.5;
+
+/// At and above this speed the course gets [`MAX_WEIGHT`]
+const FULL_SPEED_MPS: f32 = 5.0;
+
+/// Share of the fused heading taken from the course at full speed. The
+/// magnetometer keeps the rest, so turns still show up before the GPS
+/// catches up.
+const MAX_WEIGHT: f32 = 0.7;
+
+/// Fixes older than this are ignored
+const MAX_AGE: Duration = Duration::from_secs(2);
+
+/// Blends the GPS course over ground into the magnetic heading while moving,
+/// steadying it against magnetic disturbances. This assumes the board points
+/// the way it travels, e.g. mounted on a bike or carried in front.
+pub struct CourseFusion {
+    latest: Option<(CourseFix, Instant)>,
+}
+
+impl CourseFusion {
+    pub const fn new() -> Self {
+        Self { latest: None }
+    }
+
+    pub fn observe(&mut self, fix: CourseFix) {
+        self.latest = Some((fix, Instant::now()));
+    }
+
+    /// A recent fix moving fast enough for its course to be used
+    fn usable(&self) -> Option<CourseFix> {
+        self.latest
+            .filter(|(fix, at)| at.elapsed() <= MAX_AGE && fix.speed_mps >= MIN_SPEED_MPS)
+            .map(|(fix, _)| fix)
+    }
+
+    /// Whether [`Self::fuse`] has a course to go by
+    pub fn has_course(&self) -> bool {
+        self.usable().is_some()
+    }
+
+    /// Fuse the magnetic `heading` with the latest course, which is relative
+    /// to true north and converted with `declination`. While `dynamic` the
+    /// magnetic heading is thrown off by the tilt, so the course is taken
+    /// alone.
+    pub fn fuse(&self, heading: f32, declination: f32, dynamic: bool) -> f32 {
+        let Some(fix) = self.usable() else {
+            return heading;
+        };
+
+        let ramp = (fix.speed_mps - MIN_SPEED_MPS) / (FULL_SPEED_MPS - MIN_SPEED_MPS);
+        let weight = match dynamic {
+            true => 1.0,
+            false => MAX_WEIGHT * ramp.min(1.0),
+        };
+        let course = fix.course - declination;
+        normalize_heading(heading + weight * angle_diff(course, heading))
+    }
+}
@@ -0,0 +1,92 @@
+use defmt::info;
+use micromath::F32Ext;
+
+use crate::{angle_diff, gps::CourseFix, normalize_heading};
+
+/// Slowest speed at which the course over ground is trusted
+const MIN_SPEED_MPS: f32 = 1.5;
+
+/// Largest change in course between fixes that still counts as moving straight
+const STRAIGHT_TOLERANCE: f32 = 5.0;
+
+/// Number of straight-line fixes averaged into a candidate offset
+const REQUIRED_FIXES: u32 = 30;
+
+/// Learns the mounting/deviation offset between the magnetic heading and the
+/// GPS course over ground, taken back to magnetic north, while moving
+/// straight above a speed threshold. The learned offset is only applied once
+/// the user confirms it, and is then kept in the settings.
+pub struct OffsetLearner {
+    sum_sin: f32,
+    sum_cos: f32,
+    fixes: u32,
+    last_course: Option<f32>,
+    candidate: Option<f32>,
+    offset: f32,
+}
+
+impl OffsetLearner {
+    pub const fn new() -> Self {
+        Self {
+            sum_sin: 0.0,
+            sum_cos: 0.0,
+            fixes: 0,
+            last_course: None,
+            candidate: None,
+            offset: 0.0,
+        }
+    }
+
+    /// Compare a GPS fix with the magnetic heading measured at the same time,
+    /// `declination` degrees east. Turning or slowing down restarts the
+    /// averaging.
+    pub fn observe(&mut self, heading: f32, fix: CourseFix, declination: f32) {
+        let straight = self
+            .last_course
+            .is_some_and(|last| angle_diff(fix.course, last).abs() <= STRAIGHT_TOLERANCE);
+        self.last_course = Some(fix.course);
+
+        if fix.speed_mps < MIN_SPEED_MPS || !straight {
+            self.restart();
+            return;
+        }
+
+        // Average on the unit circle so offsets around ±180° don't cancel out
+        let diff = angle_diff(fix.course - declination, heading).to_radians();
+        self.sum_sin += diff.sin();
+        self.sum_cos += diff.cos();
+        self.fixes += 1;
+
+        if self.fixes >= REQUIRED_FIXES {
+            let candidate = self.sum_sin.atan2(self.sum_cos).to_degrees();
+            info!("learned heading offset {}°, hold A to apply", candidate);
+            self.candidate = Some(candidate);
+            self.restart();
+        }
+    }
+
+    /// Apply the pending candidate offset, returning it to be saved, `None`
+    /// if there is none
+    pub fn confirm(&mut self) -> Option<f32> {
+        let candidate = self.candidate.take()?;
+        info!("applying heading offset {}°", candidate);
+        self.offset = candidate;
+        Some(candidate)
+    }
+
+    /// Use an offset confirmed before, e.g. from the saved settings
+    pub fn set_offset(&mut self, offset: f32) {
+        self.offset = offset;
+    }
+
+    /// Correct `heading` by the confirmed offset
+    pub fn apply(&self, heading: f32) -> f32 {
+        normalize_heading(heading + self.offset)
+    }
+
+    fn restart(&mut self) {
+        self.sum_sin = 0.0;
+        self.sum_cos = 0.0;
+        self.fixes = 0;
+    }
+}
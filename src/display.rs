@@ -0,0 +1,350 @@
+use core::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Instant;
+use heapless::String;
+use micro_compass_core::matrix;
+
+use crate::{
+    animation,
+    board::{EXTERNAL_MATRIX_COLS, EXTERNAL_MATRIX_ROWS, MATRIX_COLS, MATRIX_ROWS},
+    frame_buffer::FRAMES,
+    health,
+    led_matrix::{LedMatrix, Picture},
+    mounting::Mounting,
+    night,
+    settings::DisplayMode,
+    trail,
+};
+
+/// Attitude and calibration state, for displays with room to show them
+#[derive(Clone, Copy)]
+#[cfg_attr(not(feature = "oled"), allow(dead_code))] // Only the OLED shows it
+pub struct Status {
+    /// Degrees
+    pub pitch: f32,
+    /// Degrees
+    pub roll: f32,
+    pub calibrating: bool,
+    /// The magnetometer has a completed calibration
+    pub calibrated: bool,
+}
+
+/// Something the compass can be shown on. The main loop only talks to
+/// displays through this, so a new one needs an implementation and nothing
+/// else; two displays side by side are a pair, which shows everything on
+/// both.
+pub trait CompassDisplay {
+    /// A needle pointing `angle` degrees clockwise from straight ahead, 0 to
+    /// 360
+    async fn draw_needle(&mut self, angle: f32);
+
+    /// A short message, e.g. a step count or a calibration score
+    async fn draw_text(&mut self, text: &str);
+
+    /// Shown alongside the needle where there is room, ignored by default
+    async fn show_status(&mut self, _status: &Status) {}
+
+    /// The bezel's marker, `marker` degrees clockwise from straight ahead,
+    /// drawn with the needles that follow, or none. Ignored by default.
+    async fn show_bezel(&mut self, _marker: Option<f32>) {}
+}
+
+impl<A: CompassDisplay, B: CompassDisplay> CompassDisplay for (A, B) {
+    async fn draw_needle(&mut self, angle: f32) {
+        self.0.draw_needle(angle).await;
+        self.1.draw_needle(angle).await;
+    }
+
+    async fn draw_text(&mut self, text: &str) {
+        self.0.draw_text(text).await;
+        self.1.draw_text(text).await;
+    }
+
+    async fn show_status(&mut self, status: &Status) {
+        self.0.show_status(status).await;
+        self.1.show_status(status).await;
+    }
+
+    async fn show_bezel(&mut self, marker: Option<f32>) {
+        self.0.show_bezel(marker).await;
+        self.1.show_bezel(marker).await;
+    }
+}
+
+impl<T: CompassDisplay> CompassDisplay for &mut T {
+    async fn draw_needle(&mut self, angle: f32) {
+        (**self).draw_needle(angle).await;
+    }
+
+    async fn draw_text(&mut self, text: &str) {
+        (**self).draw_text(text).await;
+    }
+
+    async fn show_status(&mut self, status: &Status) {
+        (**self).show_status(status).await;
+    }
+
+    async fn show_bezel(&mut self, marker: Option<f32>) {
+        (**self).show_bezel(marker).await;
+    }
+}
+
+/// Show `number` in decimal
+pub async fn show_number(display: &mut impl CompassDisplay, number: u32) {
+    let mut text: String<10> = String::new();
+    // Ten digits fit any u32
+    let _ = write!(text, "{number}");
+    display.draw_text(&text).await;
+}
+
+/// The board's own 5x5 matrix
+pub type OnBoardLedMatrix = LedMatrix<'static, MATRIX_ROWS, MATRIX_COLS>;
+
+/// The 5x5 LED matrix, borrowed for as long as it is drawn on
+pub struct Matrix<'a> {
+    matrix: &'a mut OnBoardLedMatrix,
+    mode: DisplayMode,
+    mounting: Mounting,
+    /// The bezel marker's LED on the ring
+    bezel: Option<(usize, usize)>,
+}
+
+impl<'a> Matrix<'a> {
+    /// Needles drawn as `mode` says, turned for the `mounting`
+    pub fn new(matrix: &'a mut OnBoardLedMatrix, mode: DisplayMode, mounting: Mounting) -> Self {
+        Self {
+            matrix,
+            mode,
+            mounting,
+            bezel: None,
+        }
+    }
+}
+
+impl Matrix<'_> {
+    /// Light `leds`, on screen, with the heartbeat over them while it blinks
+    async fn draw(&mut self, lit: &[(usize, usize)]) {
+        let heartbeat = health::led_lit().then_some(&health::LED);
+        let bezel = self.bezel.as_ref().filter(|_| bezel_lit());
+        FRAMES.present(|frame| {
+            for &(row, col) in lit.iter().chain(heartbeat).chain(bezel) {
+                frame[row] |= 1 << (MATRIX_COLS - 1 - col);
+            }
+        });
+        animation::show_front(self.matrix, frame_ms()).await;
+    }
+}
+
+impl CompassDisplay for Matrix<'_> {
+    /// An arrow for the nearest cardinal direction, or a dot on the outer
+    /// ring in 22.5° steps, on its own or with a fading trail. Only ever the
+    /// dot at night.
+    async fn draw_needle(&mut self, angle: f32) {
+        let mode = if night::active() {
+            DisplayMode::Degrees
+        } else {
+            self.mode
+        };
+        match mode {
+            DisplayMode::Arrow => {
+                let arrow = matrix::arrow(crate::get_cardinal_direction(angle));
+                self.draw(&arrow.map(|led| self.mounting.screen(led))).await;
+            }
+            DisplayMode::Degrees => {
+                let dot = matrix::ring_dot(angle);
+                self.draw(&[self.mounting.screen(dot)]).await;
+            }
+            DisplayMode::Trail => {
+                trail::push(angle);
+                let mut greys = [[0; MATRIX_COLS]; MATRIX_ROWS];
+                for (row, levels) in trail::greys().iter().enumerate() {
+                    for (col, &level) in levels.iter().enumerate() {
+                        let (row, col) = self.mounting.screen((row, col));
+                        greys[row][col] = level;
+                    }
+                }
+                if let Some((row, col)) = self.bezel.filter(|_| bezel_lit()) {
+                    greys[row][col] = animation::GREY_LEVELS;
+                }
+                if health::led_lit() {
+                    let (row, col) = health::LED;
+                    greys[row][col] = animation::GREY_LEVELS;
+                }
+                animation::show_greys(self.matrix, &greys, frame_ms()).await;
+            }
+        }
+    }
+
+    /// A dot on the outer ring, blinking so it isn't taken for the needle
+    async fn show_bezel(&mut self, marker: Option<f32>) {
+        self.bezel = marker.map(|marker| self.mounting.screen(matrix::ring_dot(marker)));
+    }
+
+    /// One character at a time. Only digits fit, anything else shows as a
+    /// blank.
+    async fn draw_text(&mut self, text: &str) {
+        for c in text.chars() {
+            let glyph = c.to_digit(10).map_or([0; MATRIX_ROWS], digit);
+            animation::show(self.matrix, &glyph, DIGIT_MS).await;
+            animation::show(self.matrix, &[0; MATRIX_ROWS], DIGIT_GAP_MS).await;
+        }
+    }
+}
+
+/// Half the time the bezel marker blinks at, ms
+const BEZEL_BLINK_MS: u64 = 250;
+
+fn bezel_lit() -> bool {
+    (Instant::now().as_millis() / BEZEL_BLINK_MS).is_multiple_of(2)
+}
+
+/// The glyph for a digit 0 to 9, in columns 1 to 3
+pub fn digit(digit: u32) -> animation::Frame {
+    DIGITS[digit as usize].map(|bits| bits << 1)
+}
+
+/// The board file's external matrix, 0x0 without one
+pub type ExternalLedMatrix = LedMatrix<'static, EXTERNAL_MATRIX_ROWS, EXTERNAL_MATRIX_COLS>;
+
+/// What the external matrix shows
+enum External {
+    Needle(Picture<EXTERNAL_MATRIX_ROWS>),
+    /// Shown a digit at a time, then blank until the next needle
+    Text(String<10>),
+}
+
+static EXTERNAL: Signal<CriticalSectionRawMutex, External> = Signal::new();
+
+/// The external matrix from the board file, drawn on by
+/// [`external_matrix_task`], so drawing on it never holds the main loop up.
+/// Without one in the board file, drawing on it does nothing.
+pub struct ExternalMatrix;
+
+impl CompassDisplay for ExternalMatrix {
+    /// A line from the middle to the edge
+    async fn draw_needle(&mut self, angle: f32) {
+        if EXTERNAL_MATRIX_ROWS != 0 {
+            EXTERNAL.signal(External::Needle(matrix::needle::<
+                EXTERNAL_MATRIX_ROWS,
+                EXTERNAL_MATRIX_COLS,
+            >(angle)));
+        }
+    }
+
+    /// Digits only, like the 5x5 matrix
+    async fn draw_text(&mut self, text: &str) {
+        if EXTERNAL_MATRIX_ROWS != 0 {
+            let mut digits = String::new();
+            // Cut short rather than not shown
+            for c in text.chars() {
+                let _ = digits.push(c);
+            }
+            EXTERNAL.signal(External::Text(digits));
+        }
+    }
+}
+
+/// Keep the external matrix scanned, showing the latest of what
+/// [`ExternalMatrix`] drew
+#[embassy_executor::task]
+pub async fn external_matrix_task(mut external: ExternalLedMatrix) {
+    let blank = [0; EXTERNAL_MATRIX_ROWS];
+    let mut picture = blank;
+    loop {
+        match EXTERNAL.try_take() {
+            None => external.show(&picture, LEDS_MS as u32).await,
+            Some(External::Needle(needle)) => picture = needle,
+            Some(External::Text(text)) => {
+                picture = blank;
+                for c in text.chars() {
+                    if EXTERNAL.signaled() {
+                        break;
+                    }
+                    let glyph = c.to_digit(10).map_or(blank, external_digit);
+                    external.show(&glyph, DIGIT_MS).await;
+                    external.show(&blank, DIGIT_GAP_MS).await;
+                }
+            }
+        }
+    }
+}
+
+/// [`DIGITS`] in the middle of the external matrix, nothing if it's too
+/// small for them
+fn external_digit(digit: u32) -> Picture<EXTERNAL_MATRIX_ROWS> {
+    let mut picture = [0; EXTERNAL_MATRIX_ROWS];
+    if EXTERNAL_MATRIX_ROWS < 5 || EXTERNAL_MATRIX_COLS < 3 {
+        return picture;
+    }
+    let top = (EXTERNAL_MATRIX_ROWS - 5) / 2;
+    // Leftmost column of the three in the middle, counted from the left
+    let left = (EXTERNAL_MATRIX_COLS - 3) / 2;
+    for (row, bits) in DIGITS[digit as usize].iter().enumerate() {
+        picture[top + row] = u16::from(*bits) << (EXTERNAL_MATRIX_COLS - 3 - left);
+    }
+    picture
+}
+
+/// Display an arrow on the LED matrix for N, E, S, W, turned to face whoever
+/// is looking at the display
+pub async fn arrow(matrix: &mut OnBoardLedMatrix, direction: &str, mounting: &Mounting) {
+    let arrow = matrix::arrow(direction);
+    leds(matrix, &arrow.map(|led| mounting.screen(led))).await;
+}
+
+/// How long [`leds`] keeps its pattern up before returning
+pub const LEDS_MS: u64 = 100;
+
+/// Needle redraws per second, from one per [`LEDS_MS`] up to five
+pub const REFRESH_RANGE_HZ: core::ops::RangeInclusive<u8> = 10..=50;
+
+static REFRESH_HZ: AtomicU8 = AtomicU8::new(10);
+
+pub fn set_refresh(hz: u8) {
+    REFRESH_HZ.store(hz, Ordering::Relaxed);
+}
+
+/// Needle redraws in each [`LEDS_MS`] the main loop gives the display, each
+/// shown for [`frame_ms`], so the needle moves at the refresh rate whatever
+/// the sensor rate
+pub fn frames_per_draw() -> u32 {
+    (u32::from(REFRESH_HZ.load(Ordering::Relaxed)) * LEDS_MS as u32 / 1000).max(1)
+}
+
+fn frame_ms() -> u32 {
+    LEDS_MS as u32 / frames_per_draw()
+}
+
+/// Light up the given (row, col) LEDs on the matrix
+pub async fn leds(matrix: &mut OnBoardLedMatrix, leds: &[(usize, usize)]) {
+    // Composed whole before it is shown, rather than pin by pin on the
+    // matrix
+    FRAMES.present(|frame| {
+        for &(row, col) in leds {
+            frame[row] |= 1 << (MATRIX_COLS - 1 - col);
+        }
+    });
+    animation::show_front(matrix, LEDS_MS as u32).await;
+}
+
+/// 3x5 digits, one bit per column with the leftmost column in bit 2
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b011, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// How long each digit of a number is shown, and the gap after it
+const DIGIT_MS: u32 = 600;
+const DIGIT_GAP_MS: u32 = 150;
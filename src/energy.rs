@@ -0,0 +1,110 @@
+//! Where the power goes, built with the `energy` feature: time spent with the
+//! CPU running, on I2C transfers and with LEDs lit, turned into an estimate
+//! of the current each draws from typical datasheet figures. The estimates
+//! are rough, but they move when a change makes the board busier, which is
+//! what they are for.
+
+use core::cell::Cell;
+
+use cortex_m::peripheral::DWT;
+use defmt::info;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
+
+/// How often the breakdown is reported
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// CPU clock, counted by the cycle counter only while the CPU runs
+const CPU_HZ: u64 = 64_000_000;
+
+/// nRF52833 running from flash with the DC/DC converter, µA
+const CPU_ACTIVE_UA: u64 = 3_300;
+
+/// System ON idle with the RTC running, µA
+const CPU_IDLE_UA: u64 = 3;
+
+/// TWIM plus the sensor answering, µA
+const I2C_UA: u64 = 600;
+
+/// One matrix LED while lit, µA
+const LED_UA: u64 = 4_000;
+
+/// Time spent in each state since the last report, µs
+#[derive(Clone, Copy)]
+struct Totals {
+    i2c_us: u64,
+    /// Summed over the LEDs, so two lit for 1 ms count 2000
+    led_us: u64,
+}
+
+static TOTALS: Mutex<CriticalSectionRawMutex, Cell<Totals>> = Mutex::new(Cell::new(Totals {
+    i2c_us: 0,
+    led_us: 0,
+}));
+
+/// Start the cycle counter, which stops while the CPU sleeps waiting for an
+/// event, so it counts the time it is awake
+pub fn init() {
+    // SAFETY: only the trace enable and the cycle counter are touched, which
+    // nothing else uses
+    let mut peripherals = unsafe { cortex_m::Peripherals::steal() };
+    peripherals.DCB.enable_trace();
+    peripherals.DWT.enable_cycle_counter();
+}
+
+/// Count an I2C transfer that took `duration`
+pub fn record_i2c(duration: Duration) {
+    add(|totals| totals.i2c_us += duration.as_micros());
+}
+
+/// Count `leds` LEDs lit together for `us`
+pub fn record_leds(leds: u32, us: u32) {
+    add(|totals| totals.led_us += u64::from(leds) * u64::from(us));
+}
+
+fn add(f: impl FnOnce(&mut Totals)) {
+    TOTALS.lock(|totals| {
+        let mut current = totals.get();
+        f(&mut current);
+        totals.set(current);
+    });
+}
+
+/// Report the share of the time spent in each state and the current each is
+/// estimated to draw, every [`REPORT_INTERVAL`]
+#[embassy_executor::task]
+pub async fn energy_task() {
+    let mut since = Instant::now();
+    let mut cycles = DWT::cycle_count();
+    loop {
+        Timer::after(REPORT_INTERVAL).await;
+        let now = DWT::cycle_count();
+        // The counter wraps about every 67 s, well after each report
+        let active_us = u64::from(now.wrapping_sub(cycles)) * 1_000_000 / CPU_HZ;
+        cycles = now;
+        let elapsed_us = since.elapsed().as_micros().max(1);
+        since = Instant::now();
+        let totals = TOTALS.lock(|totals| {
+            totals.replace(Totals {
+                i2c_us: 0,
+                led_us: 0,
+            })
+        });
+
+        let active_us = active_us.min(elapsed_us);
+        let cpu_ua =
+            (active_us * CPU_ACTIVE_UA + (elapsed_us - active_us) * CPU_IDLE_UA) / elapsed_us;
+        let i2c_ua = totals.i2c_us * I2C_UA / elapsed_us;
+        let led_ua = totals.led_us * LED_UA / elapsed_us;
+        info!(
+            "energy: cpu active {}‰ ~{} µA, i2c {}‰ ~{} µA, leds {} lit on average ~{} µA, total ~{} µA",
+            active_us * 1000 / elapsed_us,
+            cpu_ua,
+            totals.i2c_us * 1000 / elapsed_us,
+            i2c_ua,
+            totals.led_us as f32 / elapsed_us as f32,
+            led_ua,
+            cpu_ua + i2c_ua + led_ua
+        );
+    }
+}
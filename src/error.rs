@@ -0,0 +1,131 @@
+use defmt::Format;
+use embedded_hal::i2c::{self, ErrorKind, NoAcknowledgeSource};
+
+/// Something that went wrong, logged over defmt with enough detail to tell
+/// what was being done and why it failed, e.g. `Sensor(ReadMagnetometer,
+/// DataNack)`
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum Error {
+    /// An I2C transfer to the LSM303AGR or another sensor failed
+    Sensor(SensorOp, BusError),
+    Storage(StorageError),
+    /// Writing to the WS2812 LEDs or the OLED failed
+    #[cfg(any(feature = "rgb", feature = "oled"))]
+    Display,
+    Radio(RadioOp),
+    /// Writing telemetry or reading the GPS over a UART failed
+    Serial(SerialOp),
+}
+
+/// What the sensor was being asked to do
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum SensorOp {
+    ConfigureTap,
+    ConfigureFreeFall,
+    ConfigureWake,
+    ConfigureHighPass,
+    Init,
+    EnableInterrupt,
+    ConfigureAccelerometer,
+    ConfigureMagnetometer,
+    EnterContinuousMode,
+    SetLowPassFilter,
+    /// The power-on self-test, which talks to the sensor directly
+    SelfTest,
+    #[cfg(feature = "gyro")]
+    ConfigureGyroscope,
+    ReadAccelerometer,
+    ReadMagnetometer,
+    #[cfg(feature = "gyro")]
+    ReadGyroscope,
+    /// Switching between the full and the low magnetometer rate
+    SetRate,
+    Idle,
+    Wake,
+    PowerDown,
+}
+
+/// Why an I2C transfer failed
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum BusError {
+    /// Nothing answered at the address, e.g. the sensor is holding the bus
+    AddressNack,
+    DataNack,
+    ArbitrationLoss,
+    Overrun,
+    /// Misplaced START or STOP, or a timeout
+    Bus,
+    /// The driver rejected the data it was given
+    InvalidData,
+    /// The transfer never finished and the supervisor gave up on it
+    Stalled,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum StorageError {
+    /// The address is outside flash
+    OutOfBounds,
+    /// The address or length isn't a multiple of 4 bytes
+    Unaligned,
+    /// Too many flash operations waiting, e.g. during a long erase
+    QueueFull,
+    /// Longer than [`crate::storage::MAX_RECORD_LEN`]
+    RecordTooLong,
+    /// A record doesn't match its checksum
+    Corrupt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum RadioOp {
+    Transmit,
+    Receive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum SerialOp {
+    SendTelemetry,
+    SendReply,
+    #[cfg(feature = "gps")]
+    ReadGps,
+}
+
+impl Error {
+    /// For `map_err` on raw I2C transfers to the sensor
+    pub fn bus<E: i2c::Error>(op: SensorOp) -> impl FnOnce(E) -> Self {
+        move |e| Self::Sensor(op, BusError::from_kind(e.kind()))
+    }
+
+    /// For `map_err` on calls into the `lsm303agr` driver
+    pub fn sensor<E: i2c::Error>(op: SensorOp) -> impl FnOnce(lsm303agr::Error<E>) -> Self {
+        move |e| {
+            let cause = match e {
+                lsm303agr::Error::Comm(e) => BusError::from_kind(e.kind()),
+                lsm303agr::Error::InvalidInputData => BusError::InvalidData,
+            };
+            Self::Sensor(op, cause)
+        }
+    }
+}
+
+impl BusError {
+    fn from_kind(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data) => Self::DataNack,
+            ErrorKind::NoAcknowledge(_) => Self::AddressNack,
+            ErrorKind::ArbitrationLoss => Self::ArbitrationLoss,
+            ErrorKind::Overrun => Self::Overrun,
+            ErrorKind::Bus => Self::Bus,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl From<embassy_nrf::nvmc::Error> for Error {
+    fn from(e: embassy_nrf::nvmc::Error) -> Self {
+        Self::Storage(match e {
+            embassy_nrf::nvmc::Error::OutOfBounds => StorageError::OutOfBounds,
+            embassy_nrf::nvmc::Error::Unaligned => StorageError::Unaligned,
+        })
+    }
+}
@@ -0,0 +1,117 @@
+use defmt::warn;
+use embassy_time::Timer;
+use embedded_hal::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+use embedded_hal_async::i2c::I2c;
+
+/// Chance, out of 1000 transactions, of each injected fault
+const NACK_PER_MILLE: u32 = 5;
+const TIMEOUT_PER_MILLE: u32 = 2;
+const GARBAGE_PER_MILLE: u32 = 5;
+const DISCONNECT_PER_MILLE: u32 = 1;
+
+/// How long an injected timeout stalls the bus for
+const TIMEOUT_MS: u64 = 50;
+
+/// Transactions failed in a row while the sensor is "disconnected"
+const DISCONNECT_TRANSACTIONS: u32 = 100;
+
+/// An error from the wrapped bus, or one injected by [`FaultyI2c`]
+#[derive(Debug)]
+pub enum FaultError<E> {
+    Bus(E),
+    Nack,
+    Timeout,
+}
+
+impl<E: embedded_hal::i2c::Error> embedded_hal::i2c::Error for FaultError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Bus(e) => e.kind(),
+            Self::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+            Self::Timeout => ErrorKind::Other,
+        }
+    }
+}
+
+/// Fault-injection layer between the sensor driver and the I2C bus. Randomly
+/// fails transactions with NACKs and timeouts, corrupts read data, and
+/// simulates the sensor disconnecting for a while and reconnecting, so
+/// error handling can be soak-tested.
+pub struct FaultyI2c<I2C> {
+    i2c: I2C,
+    rng: u32,
+    disconnected_for: u32,
+}
+
+impl<I2C> FaultyI2c<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self {
+            i2c,
+            rng: 0x2545_F491,
+            disconnected_for: 0,
+        }
+    }
+
+    /// xorshift32, plenty for picking faults
+    fn next_random(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    fn roll(&mut self, per_mille: u32) -> bool {
+        self.next_random() % 1000 < per_mille
+    }
+}
+
+impl<I2C: I2c> ErrorType for FaultyI2c<I2C> {
+    type Error = FaultError<I2C::Error>;
+}
+
+impl<I2C: I2c> I2c for FaultyI2c<I2C> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if self.disconnected_for > 0 {
+            self.disconnected_for -= 1;
+            if self.disconnected_for == 0 {
+                warn!("fault injection: sensor reconnected");
+            }
+            return Err(FaultError::Nack);
+        }
+        if self.roll(DISCONNECT_PER_MILLE) {
+            warn!("fault injection: sensor disconnected");
+            self.disconnected_for = DISCONNECT_TRANSACTIONS;
+            return Err(FaultError::Nack);
+        }
+        if self.roll(NACK_PER_MILLE) {
+            warn!("fault injection: NACK");
+            return Err(FaultError::Nack);
+        }
+        if self.roll(TIMEOUT_PER_MILLE) {
+            warn!("fault injection: timeout");
+            Timer::after_millis(TIMEOUT_MS).await;
+            return Err(FaultError::Timeout);
+        }
+
+        self.i2c
+            .transaction(address, operations)
+            .await
+            .map_err(FaultError::Bus)?;
+
+        if self.roll(GARBAGE_PER_MILLE) {
+            warn!("fault injection: garbage data");
+            for operation in operations.iter_mut() {
+                if let Operation::Read(buf) = operation {
+                    for byte in buf.iter_mut() {
+                        *byte = self.next_random() as u8;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
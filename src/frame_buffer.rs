@@ -0,0 +1,47 @@
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+use crate::animation::Frame;
+
+/// The LED matrix's two frames: the front one being scanned out, and the back
+/// one the next is composed in. Presenting swaps them with one atomic store,
+/// so the scan only ever sees whole frames, never an arrow half way from one
+/// direction to the next.
+pub struct FrameBuffers {
+    frames: Mutex<CriticalSectionRawMutex, RefCell<[Frame; 2]>>,
+    /// Index of the front frame
+    front: AtomicUsize,
+}
+
+pub static FRAMES: FrameBuffers = FrameBuffers::new();
+
+impl FrameBuffers {
+    const fn new() -> Self {
+        Self {
+            frames: Mutex::new(RefCell::new([[0; 5]; 2])),
+            front: AtomicUsize::new(0),
+        }
+    }
+
+    /// Compose the next frame in the back buffer, starting from blank, then
+    /// swap it to the front
+    pub fn present(&self, compose: impl FnOnce(&mut Frame)) {
+        let back = 1 - self.front.load(Ordering::Acquire);
+        self.frames.lock(|frames| {
+            let frame = &mut frames.borrow_mut()[back];
+            *frame = [0; 5];
+            compose(frame);
+        });
+        self.front.store(back, Ordering::Release);
+    }
+
+    /// A copy of the frame at the front
+    pub fn front(&self) -> Frame {
+        let front = self.front.load(Ordering::Acquire);
+        self.frames.lock(|frames| frames.borrow()[front])
+    }
+}
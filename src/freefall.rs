@@ -0,0 +1,43 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Duration;
+use embedded_hal_async::i2c::I2c;
+
+use crate::tap::ACCEL_ADDRESS;
+
+// Accelerometer interrupt generator 1 registers
+const INT1_CFG_A: u8 = 0x30;
+const INT1_THS_A: u8 = 0x32;
+const INT1_DURATION_A: u8 = 0x33;
+
+/// All three axes below the threshold at once (AND of the low events), the
+/// free-fall recognition described in the datasheet
+const INT1_CFG_FREE_FALL: u8 = 0b1001_0101;
+
+/// 16 mg per LSB at the default ±2 g full scale: every axis under 350 mg
+const FREE_FALL_THRESHOLD: u8 = 22;
+
+/// At 50 Hz ODR one LSB is 20 ms, so the board must fall for 60 ms (about
+/// 2 cm) before the interrupt fires
+const FREE_FALL_DURATION: u8 = 3;
+
+/// The interrupt holds the sensor line low until the fall ends, taps only
+/// pulse it. A low this long is taken as a fall, so drops of roughly 20 cm
+/// or more are reported.
+pub const MIN_FALL_LOW: Duration = Duration::from_millis(150);
+
+/// Signalled by the sensor interrupt task when the board is dropped
+pub static FREE_FALL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Configure the accelerometer's interrupt generator 1 to detect free fall.
+/// Like [`crate::tap::configure_click`] this must run before the driver
+/// configures the sensor, which then routes the interrupt to the sensor line.
+pub async fn configure_free_fall<I: I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    for (register, value) in [
+        (INT1_CFG_A, INT1_CFG_FREE_FALL),
+        (INT1_THS_A, FREE_FALL_THRESHOLD),
+        (INT1_DURATION_A, FREE_FALL_DURATION),
+    ] {
+        i2c.write(ACCEL_ADDRESS, &[register, value]).await?;
+    }
+    Ok(())
+}
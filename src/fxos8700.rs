@@ -0,0 +1,210 @@
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+
+use crate::{
+    error::{Error, SensorOp},
+    sensor::{self, AccelPower, Accelerometer, Bus, Magnetometer},
+    settings::Settings,
+};
+
+/// With SA0 and SA1 low, where the micro:bit revisions carrying it put it.
+/// It shares this with the LSM303AGR's magnetometer, which is never there
+/// alongside it.
+const ADDRESS: u8 = 0x1E;
+
+const WHO_AM_I: u8 = 0x0D;
+const ID: u8 = 0xC7;
+
+const STATUS: u8 = 0x00;
+/// X, Y and Z, 14 bits left-justified, big-endian
+const OUT_X_MSB: u8 = 0x01;
+const XYZ_DATA_CFG: u8 = 0x0E;
+const CTRL_REG1: u8 = 0x2A;
+const CTRL_REG2: u8 = 0x2B;
+const M_DR_STATUS: u8 = 0x32;
+/// X, Y and Z, 16 bits big-endian
+const M_OUT_X_MSB: u8 = 0x33;
+const M_CTRL_REG1: u8 = 0x5B;
+
+/// X, Y and Z all have new data, in either status register
+const ZYXDR: u8 = 0b0000_1000;
+
+/// ±2 g
+const FS_2G: u8 = 0x00;
+const ACTIVE: u8 = 0x01;
+/// Low noise, fine at ±2 g
+const LNOISE: u8 = 0x04;
+/// Accelerometer oversampling in each power mode, MODS bits in CTRL_REG2
+const MODS_NORMAL: u8 = 0b00;
+const MODS_HIGH_RESOLUTION: u8 = 0b10;
+const MODS_LOW_POWER: u8 = 0b11;
+/// Software reset, then wait for it
+const RST: u8 = 0x40;
+const RESET_MS: u64 = 1;
+/// Most oversampling for the magnetometer, and either hybrid mode with both
+/// sensors or the accelerometer alone
+const M_OS_MAX: u8 = 0b111 << 2;
+const M_HMS_HYBRID: u8 = 0b11;
+const M_HMS_ACCEL_ONLY: u8 = 0b00;
+
+/// 14-bit accelerometer output at ±2 g, 4096 LSB/g
+const MG_PER_LSB: f32 = 1000.0 / 4096.0;
+/// 0.1 µT per LSB
+const NT_PER_LSB: f32 = 100.0;
+
+/// Output data rates in hybrid mode, each sensor at the rate, with their DR
+/// bits in CTRL_REG1, slowest first. The accelerometer never drops below
+/// 25 Hz.
+const RATES: [(u16, u8); 3] = [(25, 0b100), (50, 0b011), (100, 0b010)];
+
+/// An NXP FXOS8700CQ, the accelerometer and magnetometer on some micro:bit
+/// revisions in place of the LSM303AGR. Its interrupts are left off, so it
+/// doesn't report taps or falls, and only buttons and commands wake the
+/// board from idle.
+pub struct Fxos8700 {
+    i2c: Bus,
+    /// DR bits in CTRL_REG1, and which sensors measure
+    rate: u8,
+    hms: u8,
+    /// Accelerometer oversampling, MODS bits in CTRL_REG2
+    mods: u8,
+}
+
+/// Whether an FXOS8700 answers with its ID
+pub async fn answers<I: I2c>(i2c: &mut I) -> bool {
+    let mut id = [0];
+    i2c.write_read(ADDRESS, &[WHO_AM_I], &mut id).await.is_ok() && id[0] == ID
+}
+
+impl Fxos8700 {
+    /// `None` unless an FXOS8700 answers, otherwise configured to measure
+    /// both sensors at the magnetometer's configured rate, each in its
+    /// configured power mode
+    pub async fn probe(settings: &Settings) -> Result<Option<Self>, Error> {
+        let mut i2c = sensor::device();
+        if !answers(&mut i2c).await {
+            return Ok(None);
+        }
+        let mut imu = Self {
+            i2c,
+            rate: RATES[0].1,
+            hms: M_HMS_HYBRID,
+            mods: mods(settings.accel_power),
+        };
+        // The reset isn't acknowledged
+        let _ = imu.i2c.write(ADDRESS, &[CTRL_REG2, RST]).await;
+        Timer::after_millis(RESET_MS).await;
+        let op = SensorOp::Init;
+        imu.write(XYZ_DATA_CFG, FS_2G, op).await?;
+        imu.write(CTRL_REG2, imu.mods, op).await?;
+        imu.set_rate(settings.mag_odr_hz, settings.mag_low_power)
+            .await?;
+        Ok(Some(imu))
+    }
+
+    async fn write(&mut self, register: u8, value: u8, op: SensorOp) -> Result<(), Error> {
+        self.i2c
+            .write(ADDRESS, &[register, value])
+            .await
+            .map_err(Error::bus(op))
+    }
+
+    /// Apply the rate and sensors, which can only change in standby
+    async fn configure(&mut self, op: SensorOp) -> Result<(), Error> {
+        self.write(CTRL_REG1, 0, op).await?;
+        self.write(M_CTRL_REG1, M_OS_MAX | self.hms, op).await?;
+        self.write(CTRL_REG1, self.rate << 3 | LNOISE | ACTIVE, op)
+            .await
+    }
+
+    /// The latest X, Y and Z from the output registers at `out`, `None` if
+    /// `status` says there hasn't been a new one
+    async fn read(&mut self, status: u8, out: u8, op: SensorOp) -> Result<Option<[i16; 3]>, Error> {
+        let mut ready = [0];
+        self.i2c
+            .write_read(ADDRESS, &[status], &mut ready)
+            .await
+            .map_err(Error::bus(op))?;
+        if ready[0] & ZYXDR == 0 {
+            return Ok(None);
+        }
+        let mut buf = [0u8; 6];
+        self.i2c
+            .write_read(ADDRESS, &[out], &mut buf)
+            .await
+            .map_err(Error::bus(op))?;
+        Ok(Some([0, 1, 2].map(|axis| {
+            i16::from_be_bytes([buf[2 * axis], buf[2 * axis + 1]])
+        })))
+    }
+}
+
+fn mods(power: AccelPower) -> u8 {
+    match power {
+        AccelPower::HighResolution => MODS_HIGH_RESOLUTION,
+        AccelPower::Normal => MODS_NORMAL,
+        AccelPower::LowPower => MODS_LOW_POWER,
+    }
+}
+
+impl Accelerometer for Fxos8700 {
+    async fn acceleration(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        let accel = self
+            .read(STATUS, OUT_X_MSB, SensorOp::ReadAccelerometer)
+            .await?;
+        Ok(accel.map(|accel| accel.map(|a| (a >> 2) as f32 * MG_PER_LSB)))
+    }
+
+    /// In hybrid mode the accelerometer measures at the magnetometer's rate,
+    /// so only the oversampling changes. That too only changes in standby.
+    async fn set_accel_rate(&mut self, _hz: u16, power: AccelPower) -> Result<(), Error> {
+        self.mods = mods(power);
+        let op = SensorOp::SetRate;
+        self.write(CTRL_REG1, 0, op).await?;
+        self.write(CTRL_REG2, self.mods, op).await?;
+        self.configure(op).await
+    }
+
+    /// Not wired up, see [`Fxos8700`]
+    async fn set_wake_on_motion(&mut self, _enabled: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        self.write(CTRL_REG1, 0, SensorOp::PowerDown).await
+    }
+}
+
+impl Magnetometer for Fxos8700 {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        let field = self
+            .read(M_DR_STATUS, M_OUT_X_MSB, SensorOp::ReadMagnetometer)
+            .await?;
+        Ok(field.map(|field| field.map(|m| m as f32 * NT_PER_LSB)))
+    }
+
+    /// 25, 50 or 100 Hz, for the accelerometer as well
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error> {
+        self.rate = if low_power {
+            RATES[0].1
+        } else {
+            RATES
+                .into_iter()
+                .find(|&(rate_hz, _)| rate_hz >= hz)
+                .unwrap_or(RATES[RATES.len() - 1])
+                .1
+        };
+        self.configure(SensorOp::SetRate).await
+    }
+
+    /// Leave the accelerometer measuring alone
+    async fn sleep(&mut self) -> Result<(), Error> {
+        self.hms = M_HMS_ACCEL_ONLY;
+        self.configure(SensorOp::Idle).await
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        self.hms = M_HMS_HYBRID;
+        self.configure(SensorOp::Wake).await
+    }
+}
@@ -0,0 +1,66 @@
+use micromath::F32Ext;
+
+/// Mean Earth radius, m
+const EARTH_RADIUS_M: f32 = 6_371_000.0;
+
+/// Below this distance, in radians of arc (about 10 km), bearings and
+/// distances use a flat-Earth approximation. The great-circle formulas take
+/// differences of nearly equal sines and cosines there, which `micromath`'s
+/// approximations aren't precise enough for.
+const FLAT_LIMIT: f32 = 0.0016;
+
+/// A point on the Earth's surface, degrees. `f64` keeps about a centimeter of
+/// resolution, where `f32` would lose a couple of meters.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Position {
+    /// North positive
+    pub latitude: f64,
+    /// East positive
+    pub longitude: f64,
+}
+
+impl Position {
+    /// Initial great-circle bearing to `to`, degrees from true north, and the
+    /// distance in meters
+    pub fn bearing_and_distance(&self, to: &Position) -> (f32, f32) {
+        // Differences are small, compute them before dropping to `f32`
+        let d_lat = (to.latitude - self.latitude) as f32;
+        let d_lon = wrap_longitude(to.longitude - self.longitude) as f32;
+        let lat1 = (self.latitude as f32).to_radians();
+        let lat2 = (to.latitude as f32).to_radians();
+        let d_lat = d_lat.to_radians();
+        let d_lon = d_lon.to_radians();
+
+        // Equirectangular approximation
+        let x = d_lon * ((lat1 + lat2) / 2.0).cos();
+        let arc = (x * x + d_lat * d_lat).sqrt();
+        if arc < FLAT_LIMIT {
+            return (
+                crate::normalize_heading(x.atan2(d_lat).to_degrees()),
+                arc * EARTH_RADIUS_M,
+            );
+        }
+
+        // Haversine distance and initial bearing
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        let arc = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+        let y = d_lon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+        (
+            crate::normalize_heading(y.atan2(x).to_degrees()),
+            arc * EARTH_RADIUS_M,
+        )
+    }
+}
+
+/// Wrap a longitude difference into -180 to 180, for routes across the
+/// antimeridian
+fn wrap_longitude(degrees: f64) -> f64 {
+    if degrees > 180.0 {
+        degrees - 360.0
+    } else if degrees < -180.0 {
+        degrees + 360.0
+    } else {
+        degrees
+    }
+}
@@ -0,0 +1,141 @@
+use defmt::{debug, warn};
+use embassy_nrf::{
+    buffered_uarte::BufferedUarteRx,
+    peripherals::{TIMER1, UARTE1},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+
+use micro_compass_core::sun;
+
+use crate::{
+    clock,
+    error::{Error, SerialOp},
+    geo::Position,
+    nmea,
+};
+
+/// Longest NMEA sentence accepted, with some slack over the standard's limit
+const MAX_SENTENCE: usize = nmea::MAX_SENTENCE + 14;
+
+/// Meters per second in one knot
+const KNOTS_TO_MPS: f32 = 0.514_444;
+
+/// Position, course and speed over ground from a valid GPS fix
+#[derive(Clone, Copy)]
+pub struct CourseFix {
+    pub position: Position,
+    /// Degrees from true north
+    pub course: f32,
+    pub speed_mps: f32,
+    /// UTC as Unix seconds, if the receiver sent the date and time
+    pub utc: Option<i64>,
+}
+
+/// Latest fix reported by the GPS receiver
+pub static COURSE: Signal<CriticalSectionRawMutex, CourseFix> = Signal::new();
+
+/// Read NMEA sentences from a serial GPS and publish the position and course
+/// over ground from each valid `RMC` sentence to [`COURSE`].
+#[embassy_executor::task]
+pub async fn gps_task(mut rx: BufferedUarteRx<'static, UARTE1, TIMER1>) {
+    let mut sentence = [0u8; MAX_SENTENCE];
+    let mut len = 0;
+    let mut byte = [0u8; 1];
+
+    loop {
+        if rx.read(&mut byte).await.is_err() {
+            warn!("{}", Error::Serial(SerialOp::ReadGps));
+            len = 0;
+            continue;
+        }
+
+        match byte[0] {
+            b'$' => {
+                sentence[0] = b'$';
+                len = 1;
+            }
+            b'\r' | b'\n' if len > 0 => {
+                if let Some(fix) = parse_rmc(&sentence[..len]) {
+                    debug!("gps course {} speed {}", fix.course, fix.speed_mps);
+                    if let Some(utc) = fix.utc {
+                        clock::set(utc);
+                    }
+                    COURSE.signal(fix);
+                    trace!("course fix published");
+                }
+                len = 0;
+            }
+            b if len > 0 && len < MAX_SENTENCE => {
+                sentence[len] = b;
+                len += 1;
+            }
+            // Overlong or no start marker yet, drop until the next `$`
+            _ => len = 0,
+        }
+    }
+}
+
+/// Parse a `$xxRMC` sentence, returning `None` for other sentences, bad
+/// checksums, and fixes flagged as invalid
+fn parse_rmc(sentence: &[u8]) -> Option<CourseFix> {
+    let sentence = core::str::from_utf8(sentence).ok()?;
+    let body = verify_checksum(sentence)?;
+
+    let mut fields = body.split(',');
+    let talker = fields.next()?;
+    if talker.len() != 5 || !talker.ends_with("RMC") {
+        return None;
+    }
+    let time = fields.next()?;
+    if fields.next()? != "A" {
+        return None;
+    }
+    let latitude = coordinate(fields.next()?, fields.next()?, 'N', 'S')?;
+    let longitude = coordinate(fields.next()?, fields.next()?, 'E', 'W')?;
+    let speed_knots: f32 = fields.next()?.parse().ok()?;
+    let course: f32 = fields.next()?.parse().ok()?;
+    let utc = fields.next().and_then(|date| unix_time(date, time));
+
+    Some(CourseFix {
+        position: Position {
+            latitude,
+            longitude,
+        },
+        course,
+        speed_mps: speed_knots * KNOTS_TO_MPS,
+        utc,
+    })
+}
+
+/// Unix seconds from NMEA's `ddmmyy` date and `hhmmss[.ss]` time fields,
+/// taking the year to be in this century
+fn unix_time(date: &str, time: &str) -> Option<i64> {
+    let field = |text: &str, at: usize| text.get(at..at + 2)?.parse::<u32>().ok();
+    let (day, month, year) = (field(date, 0)?, field(date, 2)?, field(date, 4)?);
+    let (hour, minute, second) = (field(time, 0)?, field(time, 2)?, field(time, 4)?);
+    if !(1..=12).contains(&month) {
+        return None;
+    }
+    let days = sun::days_from_civil(2000 + year as i32, month, day);
+    Some(days * 86_400 + i64::from(hour * 3600 + minute * 60 + second))
+}
+
+/// Degrees from NMEA's `[d]ddmm.mmmm` and hemisphere fields, negative in the
+/// `negative` hemisphere
+fn coordinate(value: &str, hemisphere: &str, positive: char, negative: char) -> Option<f64> {
+    let raw: f64 = value.parse().ok()?;
+    let degrees = (raw / 100.0) as u32 as f64;
+    let degrees = degrees + (raw - degrees * 100.0) / 60.0;
+    match hemisphere.chars().next()? {
+        c if c == positive => Some(degrees),
+        c if c == negative => Some(-degrees),
+        _ => None,
+    }
+}
+
+/// Check the `*hh` checksum of `$body*hh`, returning `body`
+fn verify_checksum(sentence: &str) -> Option<&str> {
+    let (body, checksum) = sentence.strip_prefix('$')?.split_once('*')?;
+    let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+    (nmea::checksum(body) == expected).then_some(body)
+}
@@ -0,0 +1,79 @@
+use embassy_time::{Duration, Instant};
+use micromath::F32Ext;
+
+use crate::{angle_diff, normalize_heading};
+
+/// Share of the gap to the magnetic heading closed per second. The gyroscope
+/// carries the heading through fast turns, the magnetometer pulls it back
+/// over a few seconds so gyroscope drift never builds up.
+const CORRECTION_PER_S: f32 = 0.5;
+
+/// Share of each reading taken into the bias while the board lies still
+const BIAS_SMOOTHING: f32 = 0.05;
+
+/// Readings further apart than this aren't integrated, the heading restarts
+/// from the magnetometer's, e.g. after a calibration or a spell idle
+const MAX_GAP: Duration = Duration::from_secs(1);
+
+/// Complementary filter between the tilt-compensated magnetic heading and a
+/// gyroscope's rate of turn about the vertical
+pub struct GyroFusion {
+    last: Option<(f32, Instant)>,
+    /// Gyroscope reading when still, °/s
+    bias: [f32; 3],
+}
+
+impl GyroFusion {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            bias: [0.0; 3],
+        }
+    }
+
+    /// Fuse the magnetic `heading` with the gyroscope's `rate` in °/s,
+    /// turned into the board frame like `accel`, which gives the vertical.
+    /// Without a rate the magnetic heading is passed through. While
+    /// `dynamic` the magnetic heading is thrown off by the tilt, so the
+    /// gyroscope carries on alone.
+    pub fn fuse(
+        &mut self,
+        heading: f32,
+        rate: Option<[f32; 3]>,
+        accel: [f32; 3],
+        still: bool,
+        dynamic: bool,
+    ) -> f32 {
+        let now = Instant::now();
+        let Some(rate) = rate else {
+            self.last = None;
+            return heading;
+        };
+        if still {
+            for (bias, rate) in self.bias.iter_mut().zip(rate) {
+                *bias += BIAS_SMOOTHING * (rate - *bias);
+            }
+        }
+
+        let fused = match self.last {
+            Some((last, at)) if now - at <= MAX_GAP => {
+                let dt = (now - at).as_micros() as f32 / 1_000_000.0;
+                let norm = accel.iter().map(|a| a * a).sum::<f32>().sqrt().max(1.0);
+                // Headings go clockwise seen from above, so turning about the
+                // upward axis the accelerometer measures takes them back
+                let up_rate: f32 = (0..3)
+                    .map(|i| (rate[i] - self.bias[i]) * accel[i] / norm)
+                    .sum();
+                let predicted = last - up_rate * dt;
+                let gain = match dynamic {
+                    true => 0.0,
+                    false => (CORRECTION_PER_S * dt).min(1.0),
+                };
+                normalize_heading(predicted + gain * angle_diff(heading, predicted))
+            }
+            _ => heading,
+        };
+        self.last = Some((fused, now));
+        fused
+    }
+}
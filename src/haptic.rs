@@ -0,0 +1,86 @@
+use core::cell::Cell;
+
+use embassy_nrf::gpio;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+
+/// Length of each pulse
+const PULSE_MS: u64 = 150;
+
+/// Degrees past the window the heading has to move out before coming back
+/// pulses again, so jitter at its edge doesn't
+const HYSTERESIS: f32 = 3.0;
+
+/// Shortest time between pulses, however often the heading swings through
+const COOLDOWN: Duration = Duration::from_secs(2);
+
+/// Widest window that can be set, degrees either side
+pub const MAX_WINDOW: u8 = 45;
+
+/// Degrees either side of the target that count as on it, 0 for never
+static WINDOW: Mutex<CriticalSectionRawMutex, Cell<u8>> = Mutex::new(Cell::new(5));
+
+pub fn set_window(degrees: u8) {
+    WINDOW.lock(|window| window.set(degrees));
+}
+
+fn window() -> f32 {
+    WINDOW.lock(Cell::get).into()
+}
+
+/// Signed difference between the target, the active bearing or else north,
+/// and the heading, or `None` when there is no heading, e.g. in tilt-only
+/// mode
+pub static TARGET_ERROR: Signal<CriticalSectionRawMutex, Option<f32>> = Signal::new();
+
+/// Decides when the heading has come onto the target
+struct Trigger {
+    /// Left the window since the last pulse
+    armed: bool,
+    last_pulse: Option<Instant>,
+}
+
+impl Trigger {
+    /// Whether to pulse for `error`: once on coming within the window, then
+    /// not again until the heading has left it and the cooldown has passed
+    fn update(&mut self, error: f32, window: f32) -> bool {
+        if window == 0.0 || error.abs() > window + HYSTERESIS {
+            self.armed = true;
+            return false;
+        }
+        if error.abs() > window
+            || !self.armed
+            || self.last_pulse.is_some_and(|at| at.elapsed() < COOLDOWN)
+        {
+            return false;
+        }
+        self.armed = false;
+        self.last_pulse = Some(Instant::now());
+        true
+    }
+}
+
+/// Pulse a vibration motor or buzzer on the edge connector whenever the
+/// heading comes within the window of north, or of the active bearing, so the
+/// compass can be followed without looking at it
+#[embassy_executor::task]
+pub async fn haptic_task(mut pin: gpio::Output<'static>) {
+    let mut trigger = Trigger {
+        armed: true,
+        last_pulse: None,
+    };
+    loop {
+        let Some(error) = TARGET_ERROR.wait().await else {
+            trigger.armed = true;
+            continue;
+        };
+        if trigger.update(error, window()) {
+            pin.set_high();
+            Timer::after_millis(PULSE_MS).await;
+            pin.set_low();
+        }
+    }
+}
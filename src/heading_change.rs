@@ -0,0 +1,31 @@
+use crate::{angle_diff, get_cardinal_direction};
+
+/// Largest heading change threshold, degrees
+pub const MAX_THRESHOLD: u8 = 180;
+
+/// Lets a heading through only once it has moved far enough from the last
+/// one let through, or into another cardinal direction, so a board lying
+/// still doesn't repeat the same heading several times a second
+pub struct HeadingChange {
+    last: Option<f32>,
+}
+
+impl HeadingChange {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Whether `heading` is worth reporting, given the `threshold` in degrees
+    /// it has to move by. A threshold of zero reports every heading.
+    pub fn update(&mut self, heading: f32, threshold: u8) -> bool {
+        let changed = threshold == 0
+            || self.last.is_none_or(|last| {
+                angle_diff(heading, last).abs() >= threshold as f32
+                    || get_cardinal_direction(heading) != get_cardinal_direction(last)
+            });
+        if changed {
+            self.last = Some(heading);
+        }
+        changed
+    }
+}
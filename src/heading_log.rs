@@ -0,0 +1,48 @@
+use defmt::Format;
+use micromath::F32Ext;
+
+use crate::calibration::Calibration;
+
+/// Share of the expected field strength or of 1 g by which a reading may
+/// stray before the heading gets no confidence at all
+const MAX_ERROR: f32 = 0.5;
+
+/// Gravity, mg
+const GRAVITY_MG: f32 = 1000.0;
+
+/// A heading as logged over defmt, each value a field of its own so the host
+/// can pick them out rather than parse a sentence
+#[derive(Format)]
+pub struct HeadingRecord {
+    /// Degrees, against the display's north
+    pub heading: f32,
+    pub cardinal: &'static str,
+    /// Degrees
+    pub pitch: f32,
+    pub roll: f32,
+    /// 0 to 100, see [`confidence`]
+    pub confidence: u8,
+}
+
+/// How far a heading can be trusted, 0 to 100: full while the calibrated
+/// field is as strong as at calibration and the accelerometer reads gravity
+/// alone, less as either strays, e.g. near iron or while the board is
+/// shaken. Without a calibration the field can't be judged and it is at most
+/// 50.
+pub fn confidence(mag: [f32; 3], calibration: &Calibration, accel: [f32; 3]) -> u8 {
+    let field_error = match calibration.field_strength() {
+        strength if strength > 0.0 => relative_error(magnitude(mag), strength),
+        _ => MAX_ERROR / 2.0,
+    };
+    let accel_error = relative_error(magnitude(accel), GRAVITY_MG);
+    let error = field_error.max(accel_error).min(MAX_ERROR);
+    (100.0 * (1.0 - error / MAX_ERROR)).round() as u8
+}
+
+fn relative_error(value: f32, expected: f32) -> f32 {
+    (value - expected).abs() / expected
+}
+
+fn magnitude(v: [f32; 3]) -> f32 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
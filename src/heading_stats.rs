@@ -0,0 +1,71 @@
+use defmt::info;
+use micromath::F32Ext;
+
+use crate::{angle_diff, normalize_heading};
+
+/// Headings each report covers, about 10 seconds at the main loop's rate
+const WINDOW: usize = 50;
+
+/// Spread of the heading over a window of samples, for judging sensor noise
+/// and the effect of calibration or filtering changes with the board held
+/// still
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub struct Stats {
+    /// Circular mean, degrees
+    pub mean: f32,
+    /// Furthest readings either side of the mean, degrees
+    pub min: f32,
+    pub max: f32,
+    /// Mean squared deviation from the mean, degrees²
+    pub variance: f32,
+}
+
+/// Collects headings and summarizes each full window
+pub struct HeadingStats {
+    headings: [f32; WINDOW],
+    len: usize,
+}
+
+impl HeadingStats {
+    pub const fn new() -> Self {
+        Self {
+            headings: [0.0; WINDOW],
+            len: 0,
+        }
+    }
+
+    /// Feed a heading in degrees, returning the statistics once a window is
+    /// complete, then starting the next one
+    pub fn update(&mut self, heading: f32) -> Option<Stats> {
+        self.headings[self.len] = heading;
+        self.len += 1;
+        if self.len < WINDOW {
+            return None;
+        }
+        self.len = 0;
+
+        // Averaged as unit vectors, so readings either side of north don't
+        // average to south
+        let (sin, cos) = self.headings.iter().fold((0.0, 0.0), |(sin, cos), h| {
+            let h = h.to_radians();
+            (sin + h.sin(), cos + h.cos())
+        });
+        let mean = normalize_heading(sin.atan2(cos).to_degrees());
+
+        let (mut min, mut max, mut squares) = (0.0f32, 0.0f32, 0.0);
+        for &h in self.headings.iter() {
+            let deviation = angle_diff(h, mean);
+            min = min.min(deviation);
+            max = max.max(deviation);
+            squares += deviation * deviation;
+        }
+        let stats = Stats {
+            mean,
+            min: normalize_heading(mean + min),
+            max: normalize_heading(mean + max),
+            variance: squares / WINDOW as f32,
+        };
+        info!("heading stats: {}", stats);
+        Some(stats)
+    }
+}
@@ -0,0 +1,232 @@
+use core::{
+    cell::Cell,
+    future::Future,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use defmt::{info, warn, Format};
+use embassy_futures::select::{select, Either};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Ticker};
+
+/// The LED given over to the heartbeat, the top left corner as seen on the
+/// screen whichever way the board is mounted
+pub const LED: (usize, usize) = (0, 0);
+
+/// One heartbeat: the blinks, then a pause to the end of the cycle
+const CYCLE: Duration = Duration::from_secs(3);
+
+/// Length of each blink and of the gap after it
+const BLINK_MS: u64 = 200;
+
+/// Longest time without a good sensor reading before the sensor counts as
+/// failed, longer than the slowest loop period
+const SENSOR_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A part of the firmware whose health the heartbeat shows
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Subsystem {
+    /// Readings are coming from the accelerometer and magnetometer
+    Sensor,
+    /// The magnetometer has a completed calibration
+    Calibration,
+    /// The radio sends and receives without errors
+    Radio,
+}
+
+impl Subsystem {
+    const ALL: [Self; 3] = [Self::Sensor, Self::Calibration, Self::Radio];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Blinks per heartbeat while this is the first subsystem failing
+    fn blinks(self) -> u32 {
+        self as u32 + 2
+    }
+}
+
+/// Work that can hang, e.g. an I2C transfer that never finishes, and that
+/// the supervisor watches so it can be abandoned and started over
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Task {
+    /// Reading the sensor in the main loop
+    Sensor,
+    /// Drawing the needle in the main loop
+    Display,
+    /// Sending a frame to the OLED
+    #[cfg(feature = "oled")]
+    Oled,
+    /// Sending or receiving a radio packet
+    Radio,
+}
+
+impl Task {
+    #[cfg(not(feature = "oled"))]
+    const ALL: &[Self] = &[Self::Sensor, Self::Display, Self::Radio];
+    #[cfg(feature = "oled")]
+    const ALL: &[Self] = &[Self::Sensor, Self::Display, Self::Oled, Self::Radio];
+
+    /// Longest the work may take before it counts as stalled, well past how
+    /// long it takes when all is well
+    fn deadline(self) -> Duration {
+        match self {
+            Self::Sensor => Duration::from_secs(1),
+            Self::Display => Duration::from_secs(1),
+            #[cfg(feature = "oled")]
+            Self::Oled => Duration::from_secs(1),
+            // Receiving gives up after a second of silence by itself
+            Self::Radio => Duration::from_secs(3),
+        }
+    }
+}
+
+/// When each [`Task`] started the work it is doing, `None` while it waits
+/// for something else, e.g. a command, and can't stall
+static BUSY_SINCE: Mutex<CriticalSectionRawMutex, Cell<[Option<Instant>; Task::ALL.len()]>> =
+    Mutex::new(Cell::new([None; Task::ALL.len()]));
+
+/// Raised by the supervisor for a [`Task`] that has stalled
+static RESTART: [Signal<CriticalSectionRawMutex, ()>; Task::ALL.len()] =
+    [const { Signal::new() }; Task::ALL.len()];
+
+fn set_busy(task: Task, since: Option<Instant>) {
+    BUSY_SINCE.lock(|busy| {
+        let mut all = busy.get();
+        all[task as usize] = since;
+        busy.set(all);
+    });
+}
+
+/// Do `work` for `task` under the supervisor's eye. `None` if it ran past
+/// the task's deadline and was abandoned, for the caller to start the
+/// subsystem over; dropping the work also releases whatever it held, e.g.
+/// the I2C bus.
+pub async fn watch<F: Future>(task: Task, work: F) -> Option<F::Output> {
+    RESTART[task as usize].reset();
+    set_busy(task, Some(Instant::now()));
+    let result = match select(work, RESTART[task as usize].wait()).await {
+        Either::First(output) => Some(output),
+        Either::Second(()) => None,
+    };
+    set_busy(task, None);
+    result
+}
+
+/// One bit per [`Subsystem`], set while it reports a fault
+static FAULTS: AtomicU8 = AtomicU8::new(0);
+
+/// When the sensor last reported a good reading
+static LAST_READING: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> =
+    Mutex::new(Cell::new(None));
+
+/// Whether the heartbeat LED is lit right now, for the display to draw
+static LIT: AtomicBool = AtomicBool::new(false);
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the display should light [`LED`] over whatever else it shows
+pub fn led_lit() -> bool {
+    ENABLED.load(Ordering::Relaxed) && LIT.load(Ordering::Relaxed)
+}
+
+/// Called by the task looking after `subsystem` whenever it finds out how it
+/// is doing
+pub fn report(subsystem: Subsystem, ok: bool) {
+    if subsystem == Subsystem::Sensor && ok {
+        LAST_READING.lock(|last| last.set(Some(Instant::now())));
+    }
+    if ok {
+        FAULTS.fetch_and(!subsystem.bit(), Ordering::Relaxed);
+    } else {
+        FAULTS.fetch_or(subsystem.bit(), Ordering::Relaxed);
+    }
+}
+
+/// The reported faults, with the sensor counted as failed once it has gone
+/// quiet, e.g. because the main loop is stuck restarting it
+fn faults() -> u8 {
+    let quiet = LAST_READING
+        .lock(Cell::get)
+        .is_none_or(|at| at.elapsed() > SENSOR_TIMEOUT);
+    let faults = FAULTS.load(Ordering::Relaxed);
+    if quiet {
+        faults | Subsystem::Sensor.bit()
+    } else {
+        faults
+    }
+}
+
+/// Abandon the work of any [`Task`] past its deadline, so one hung transfer
+/// doesn't silently stop the compass
+fn check_stalls() {
+    let busy = BUSY_SINCE.lock(Cell::get);
+    for &task in Task::ALL {
+        if let Some(since) = busy[task as usize] {
+            let elapsed = since.elapsed();
+            if elapsed > task.deadline() {
+                warn!(
+                    "health: {} stalled for {} ms, restarting it",
+                    task,
+                    elapsed.as_millis()
+                );
+                set_busy(task, None);
+                RESTART[task as usize].signal(());
+            }
+        }
+    }
+}
+
+/// Supervisor watching the other tasks. Work that stalls, see [`watch`], is
+/// abandoned for its task to start over. The health they report goes into a
+/// heartbeat on [`LED`]: one blink every [`CYCLE`] while everything is fine,
+/// otherwise [`Subsystem::blinks`] for the first subsystem failing, 2 for
+/// the sensor, 3 for the calibration and 4 for the radio. Changes are logged
+/// as well.
+#[embassy_executor::task]
+pub async fn health_task() {
+    let mut ticker = Ticker::every(Duration::from_millis(BLINK_MS));
+    let mut logged = 0;
+    let mut blinks = 1;
+    let mut started = Instant::now();
+    loop {
+        check_stalls();
+        let faults = faults();
+        if faults != logged {
+            for subsystem in Subsystem::ALL {
+                match (faults & subsystem.bit() != 0, logged & subsystem.bit() != 0) {
+                    (true, false) => warn!("health: {} failing", subsystem),
+                    (false, true) => info!("health: {} ok", subsystem),
+                    _ => {}
+                }
+            }
+            logged = faults;
+        }
+
+        let elapsed = started.elapsed();
+        if elapsed >= CYCLE {
+            // Take a new reading of the faults at the start of each cycle, so
+            // the blinks count whole
+            blinks = Subsystem::ALL
+                .into_iter()
+                .find(|subsystem| faults & subsystem.bit() != 0)
+                .map_or(1, Subsystem::blinks);
+            started = Instant::now();
+        }
+        let step = (elapsed.as_millis() / BLINK_MS) as u32;
+        // Blinks on the even steps, gaps on the odd ones
+        LIT.store(
+            elapsed < CYCLE && step.is_multiple_of(2) && step / 2 < blinks,
+            Ordering::Relaxed,
+        );
+        ticker.next().await;
+    }
+}
@@ -0,0 +1,88 @@
+use defmt::debug;
+
+use crate::compass_module::{CompassModule, Reading, MAX_REPLY};
+
+/// An HMC6352's 7-bit address, 0x42 in its datasheet's 8-bit form
+pub const ADDRESS: u8 = 0x21;
+
+/// Commands, each an ASCII letter
+const GET_DATA: u8 = b'A';
+const READ_EEPROM: u8 = b'r';
+const WRITE_EEPROM: u8 = b'w';
+const READ_RAM: u8 = b'g';
+const WRITE_RAM: u8 = b'G';
+
+/// EEPROM and RAM contents a driver may check, as a fresh HMC6352 reads:
+/// its 8-bit address, the measurements summed, the software version and
+/// the operational mode, standby at 10 Hz
+const EEPROM: [(u8, u8); 4] = [(0x00, 0x42), (0x06, 0x04), (0x07, 0x01), (0x08, 0x50)];
+/// The output mode, heading, and the operational mode again
+const RAM: [(u8, u8); 2] = [(0x4E, 0x00), (0x74, 0x50)];
+
+/// What the next read returns
+#[derive(Clone, Copy)]
+enum Reply {
+    /// Two bytes of heading in tenths of a degree, 0 to 3599, high byte
+    /// first. Sent for a read without a command first too, as in the
+    /// HMC6352's continuous and query modes.
+    Heading,
+    /// One byte of EEPROM or RAM
+    Byte(u8),
+}
+
+/// An HMC6352: write `A` and read two bytes of heading, the way most
+/// sketches use it. Sleep, wake, calibration and offset commands are
+/// accepted and do nothing, and so are writes to its EEPROM and RAM.
+pub struct Hmc6352 {
+    reply: Reply,
+}
+
+impl Hmc6352 {
+    pub const fn new() -> Self {
+        Self {
+            reply: Reply::Heading,
+        }
+    }
+}
+
+impl CompassModule for Hmc6352 {
+    fn write(&mut self, bytes: &[u8]) {
+        let lookup = |table: &[(u8, u8)], address: Option<&u8>| {
+            table
+                .iter()
+                .find(|(at, _)| Some(at) == address)
+                .map_or(0, |&(_, value)| value)
+        };
+        self.reply = match bytes[0] {
+            GET_DATA => Reply::Heading,
+            READ_EEPROM => Reply::Byte(lookup(&EEPROM, bytes.get(1))),
+            READ_RAM => Reply::Byte(lookup(&RAM, bytes.get(1))),
+            command => {
+                if matches!(command, WRITE_EEPROM | WRITE_RAM) {
+                    debug!("hmc6352: ignored write {=[u8]:#x}", bytes);
+                }
+                Reply::Heading
+            }
+        };
+    }
+
+    fn read(&mut self, reading: Option<&Reading>, reply: &mut [u8; MAX_REPLY]) -> usize {
+        match self.reply {
+            Reply::Heading => {
+                let heading = reading.and_then(|reading| reading.heading);
+                let tenths = heading.map_or(0, |heading| (heading * 10.0) as u16 % 3600);
+                reply[..2].copy_from_slice(&tenths.to_be_bytes());
+                2
+            }
+            Reply::Byte(byte) => {
+                reply[0] = byte;
+                1
+            }
+        }
+    }
+
+    /// Back to the heading once a byte asked for has been read
+    fn sent(&mut self, _sent: usize) {
+        self.reply = Reply::Heading;
+    }
+}
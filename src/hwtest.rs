@@ -0,0 +1,184 @@
+//! On-target tests of the hardware-facing code, built with the `hw-test`
+//! feature and run in place of the application:
+//!
+//! ```text
+//! cargo run --release --features hw-test
+//! ```
+//!
+//! Each test logs its result over defmt. Once all have passed the board
+//! halts on a breakpoint, which `probe-rs run` takes as a successful exit; a
+//! failure panics, which it reports as an error.
+
+use defmt::{error, info, warn};
+use embassy_executor::Spawner;
+use embassy_nrf::nvmc::Nvmc;
+use embassy_time::{Duration, Instant, Timer};
+use micromath::F32Ext;
+
+use crate::{
+    animation,
+    board::Board,
+    bus,
+    calibration::Calibration,
+    display::{self, OnBoardLedMatrix},
+    led_matrix, matrix_scan,
+    profiles::{self, Profiles},
+    selftest,
+    sensor::{self, Accelerometer as _, BusPins, Magnetometer as _},
+    settings::Settings,
+    storage::{self, Storage},
+};
+
+type Outcome = Result<(), &'static str>;
+
+/// Longest wait for a first reading from each sensor
+const FIRST_READING: Duration = Duration::from_millis(500);
+const POLL_MS: u64 = 10;
+
+/// Gravity with the board at rest, mg
+const GRAVITY_MG: core::ops::RangeInclusive<f32> = 800.0..=1200.0;
+/// Earth's field is 25 to 65 µT, uncalibrated hard-iron offsets can add
+/// about as much again
+const FIELD_NT: core::ops::RangeInclusive<f32> = 10_000.0..=200_000.0;
+
+/// How long the matrix is driven in the timing tests, and how much longer
+/// than that it may take
+const SHOW_MS: u32 = 500;
+const SLACK_MS: u64 = 10;
+
+/// Run every test and halt. None of the application's tasks are started, so
+/// the spawner is only taken to keep it that way.
+pub async fn run(board: Board, _spawner: Spawner) -> ! {
+    matrix_scan::init(board.matrix_scan, &board.matrix);
+    let mut matrix = led_matrix::Builder::new(board.matrix).build();
+    let mut storage = Storage::new(Nvmc::new(board.nvmc));
+
+    let mut failed = 0;
+    for (name, outcome) in [
+        ("sensor bring-up", sensor_bring_up(board.sensor_bus).await),
+        (
+            "calibration storage round-trip",
+            calibration_round_trip(&mut storage),
+        ),
+        ("matrix timing", matrix_timing(&mut matrix).await),
+    ] {
+        match outcome {
+            Ok(()) => info!("test {} ... ok", name),
+            Err(reason) => {
+                error!("test {} ... FAILED: {}", name, reason);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        defmt::panic!("{} hardware tests failed", failed);
+    }
+    info!("all hardware tests passed");
+    loop {
+        cortex_m::asm::bkpt();
+    }
+}
+
+/// The power-on self-test passes, the sensor starts, and both halves report
+/// plausible readings with the board lying still
+async fn sensor_bring_up(mut pins: BusPins) -> Outcome {
+    if let Err(failure) = selftest::run(&mut pins, Ok(())).await {
+        warn!("{}", failure);
+        return Err("power-on self-test failed");
+    }
+    bus::start(pins).await;
+    let mut sensor = sensor::start(&Settings::DEFAULT).await.map_err(|e| {
+        warn!("{}", e);
+        "sensor didn't start"
+    })?;
+
+    let start = Instant::now();
+    let accel = loop {
+        match sensor.acceleration().await {
+            Ok(Some(accel)) => break accel,
+            Ok(None) if start.elapsed() < FIRST_READING => Timer::after_millis(POLL_MS).await,
+            Ok(None) => return Err("no acceleration reading"),
+            Err(e) => {
+                warn!("{}", e);
+                return Err("reading the accelerometer failed");
+            }
+        }
+    };
+    if !GRAVITY_MG.contains(&magnitude(accel)) {
+        warn!("acceleration {} mg", accel);
+        return Err("acceleration isn't 1 g at rest");
+    }
+
+    let start = Instant::now();
+    let field = loop {
+        match sensor.magnetic_field().await {
+            Ok(Some(field)) => break field,
+            Ok(None) if start.elapsed() < FIRST_READING => Timer::after_millis(POLL_MS).await,
+            Ok(None) => return Err("no magnetometer reading"),
+            Err(e) => {
+                warn!("{}", e);
+                return Err("reading the magnetometer failed");
+            }
+        }
+    };
+    if !FIELD_NT.contains(&magnitude(field)) {
+        warn!("field {} nT", field);
+        return Err("magnetic field out of range");
+    }
+    Ok(())
+}
+
+/// A calibration written to its flash page loads back unchanged, as the
+/// default profile's. Whatever was on the page before is put back afterwards.
+fn calibration_round_trip(storage: &mut Storage<'_>) -> Outcome {
+    let mut saved = [0u8; profiles::PAGE_LEN];
+    storage
+        .load(storage::CALIBRATION_PAGE, &mut saved)
+        .map_err(|_| "reading the calibration page failed")?;
+
+    let calibration = Calibration::new(
+        [1_250.0, -3_500.0, 12_000.0],
+        [[1.02, 0.01, -0.03], [0.01, 0.97, 0.02], [-0.03, 0.02, 1.01]],
+        48_000.0,
+    );
+    let result = storage
+        .store(storage::CALIBRATION_PAGE, &calibration.record())
+        .map_err(|_| "writing the calibration page failed")
+        .and_then(
+            |()| match Profiles::load(storage).calibration() == calibration {
+                true => Ok(()),
+                false => Err("calibration read back differs"),
+            },
+        );
+
+    storage
+        .store(storage::CALIBRATION_PAGE, &saved)
+        .map_err(|_| "restoring the calibration page failed")?;
+    result
+}
+
+/// Driving the matrix takes as long as asked, so the display neither
+/// flickers nor holds up the main loop
+async fn matrix_timing(matrix: &mut OnBoardLedMatrix) -> Outcome {
+    let start = Instant::now();
+    animation::show(matrix, &animation::TICK, SHOW_MS).await;
+    let elapsed = start.elapsed().as_millis();
+    if !(u64::from(SHOW_MS)..=u64::from(SHOW_MS) + SLACK_MS).contains(&elapsed) {
+        warn!("{} ms frame took {} ms", SHOW_MS, elapsed);
+        return Err("frame shown for the wrong time");
+    }
+
+    let start = Instant::now();
+    display::leds(matrix, &[(2, 2)]).await;
+    let elapsed = start.elapsed().as_millis();
+    if !(display::LEDS_MS..=display::LEDS_MS + SLACK_MS).contains(&elapsed) {
+        warn!("LEDs took {} ms", elapsed);
+        return Err("LEDs shown for the wrong time");
+    }
+    Ok(())
+}
+
+fn magnitude(v: [f32; 3]) -> f32 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
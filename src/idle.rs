@@ -0,0 +1,40 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embedded_hal_async::i2c::I2c;
+
+use crate::tap::ACCEL_ADDRESS;
+
+// Accelerometer interrupt generator 2 registers. The high-pass filter it
+// sees, so gravity doesn't count as motion, is set up with the tap filter in
+// `lsm303`.
+const INT2_CFG_A: u8 = 0x34;
+const INT2_THS_A: u8 = 0x36;
+const INT2_DURATION_A: u8 = 0x37;
+
+/// Any axis above the threshold (OR of the high events)
+const INT2_CFG_MOTION: u8 = 0b0010_1010;
+
+/// 16 mg per LSB at the default ±2 g full scale: a change of 128 mg, about
+/// what picking the board up gives
+const MOTION_THRESHOLD: u8 = 8;
+
+/// Heading change, in degrees, that counts as the board being used
+pub const HEADING_CHANGE: f32 = 5.0;
+
+/// Signalled by the sensor interrupt task on every interrupt, which while
+/// idle is the board being moved
+pub static ACTIVITY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Configure the accelerometer's interrupt generator 2 to detect motion, used
+/// to wake from idle. Like [`crate::tap::configure_click`] this must run
+/// before the driver configures the sensor. The interrupt is only routed to
+/// the sensor line while idle, as it would otherwise pass for taps.
+pub async fn configure_wake<I: I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    for (register, value) in [
+        (INT2_CFG_A, INT2_CFG_MOTION),
+        (INT2_THS_A, MOTION_THRESHOLD),
+        (INT2_DURATION_A, 0),
+    ] {
+        i2c.write(ACCEL_ADDRESS, &[register, value]).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use embassy_nrf::gpio::{AnyPin, Level, Output, OutputDrive};
+use embassy_time::Delay;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::animation;
+
+/// How long each row is lit per pass, µs, as on the 5x5 matrix
+const ROW_US: u32 = 2000;
+
+/// A picture for a `ROWS` x `COLS` matrix, one bit per column with the
+/// leftmost column in bit `COLS - 1`
+pub type Picture<const ROWS: usize> = [u16; ROWS];
+
+/// The level that lights an LED, on its row and on its column
+#[derive(Clone, Copy)]
+pub struct Polarity {
+    pub row_on: Level,
+    pub col_on: Level,
+}
+
+/// Rows active high and columns active low, as on the micro:bit and most
+/// common-cathode modules
+pub const ROWS_HIGH: Polarity = Polarity {
+    row_on: Level::High,
+    col_on: Level::Low,
+};
+
+fn off(on: Level) -> Level {
+    match on {
+        Level::High => Level::Low,
+        Level::Low => Level::High,
+    }
+}
+
+/// The pins of a matrix, one per row and one per column, from the board file
+pub struct Pins<const ROWS: usize, const COLS: usize> {
+    pub rows: [AnyPin; ROWS],
+    pub cols: [AnyPin; COLS],
+}
+
+/// Sets up a [`LedMatrix`] from its [`Pins`], standard drive with
+/// [`ROWS_HIGH`] unless told otherwise
+pub struct Builder<const ROWS: usize, const COLS: usize> {
+    pins: Pins<ROWS, COLS>,
+    polarity: Polarity,
+    drive: OutputDrive,
+}
+
+impl<const ROWS: usize, const COLS: usize> Builder<ROWS, COLS> {
+    /// No more than 16 columns fit a [`Picture`]'s rows
+    const FITS: () = assert!(COLS <= 16);
+
+    pub fn new(pins: Pins<ROWS, COLS>) -> Self {
+        Self {
+            pins,
+            polarity: ROWS_HIGH,
+            drive: OutputDrive::Standard,
+        }
+    }
+
+    pub fn polarity(mut self, polarity: Polarity) -> Self {
+        self.polarity = polarity;
+        self
+    }
+
+    /// High drive for a matrix whose rows light more LEDs at once than a
+    /// standard drive pin can feed
+    pub fn drive(mut self, drive: OutputDrive) -> Self {
+        self.drive = drive;
+        self
+    }
+
+    /// Every pin an output, with every LED off
+    pub fn build(self) -> LedMatrix<'static, ROWS, COLS> {
+        let () = Self::FITS;
+        let Polarity { row_on, col_on } = self.polarity;
+        LedMatrix {
+            rows: self
+                .pins
+                .rows
+                .map(|pin| Output::new(pin, off(row_on), self.drive)),
+            cols: self
+                .pins
+                .cols
+                .map(|pin| Output::new(pin, off(col_on), self.drive)),
+            polarity: self.polarity,
+        }
+    }
+}
+
+/// An LED matrix of any size driven straight from GPIOs, scanned a row at a
+/// time in software: the micro:bit's own 5x5 as well as one wired to the
+/// edge connector
+pub struct LedMatrix<'d, const ROWS: usize, const COLS: usize> {
+    rows: [Output<'d>; ROWS],
+    cols: [Output<'d>; COLS],
+    polarity: Polarity,
+}
+
+impl<'d, const ROWS: usize, const COLS: usize> LedMatrix<'d, ROWS, COLS> {
+    /// Show `picture` for `ms`, at least one pass over the rows, at the
+    /// display's [`animation::brightness`]
+    pub async fn show(&mut self, picture: &Picture<ROWS>, ms: u32) {
+        let lit_us =
+            ROW_US * u32::from(animation::brightness()) / u32::from(animation::GREY_LEVELS);
+        let pass_ms = (ROW_US * ROWS as u32 / 1000).max(1);
+        for _ in 0..(ms / pass_ms).max(1) {
+            self.scan(picture, ROW_US, lit_us).await;
+        }
+    }
+
+    /// One pass over the rows, each `row_us` long with its LEDs lit for the
+    /// first `lit_us` of it
+    pub async fn scan(&mut self, picture: &Picture<ROWS>, row_us: u32, lit_us: u32) {
+        let Polarity { row_on, col_on } = self.polarity;
+        for (row, bits) in self.rows.iter_mut().zip(picture) {
+            for (c, col) in self.cols.iter_mut().enumerate() {
+                let lit = bits & (1 << (COLS - 1 - c)) != 0;
+                col.set_level(if lit { col_on } else { off(col_on) });
+            }
+            row.set_level(row_on);
+            Delay.delay_us(lit_us).await;
+            row.set_level(off(row_on));
+            #[cfg(feature = "energy")]
+            crate::energy::record_leds(bits.count_ones(), lit_us);
+            Delay.delay_us(row_us - lit_us).await;
+        }
+    }
+
+    /// One pass over the rows with each row's time split into `steps`
+    /// steps of `step_us`, and each LED lit for as many of them as its
+    /// level, by row and column
+    pub async fn scan_levels(&mut self, levels: &[[u8; COLS]; ROWS], steps: u8, step_us: u32) {
+        let Polarity { row_on, col_on } = self.polarity;
+        for (row, levels) in self.rows.iter_mut().zip(levels) {
+            row.set_level(row_on);
+            for step in 0..steps {
+                for (col, &level) in self.cols.iter_mut().zip(levels) {
+                    col.set_level(if level > step { col_on } else { off(col_on) });
+                }
+                Delay.delay_us(step_us).await;
+                #[cfg(feature = "energy")]
+                crate::energy::record_leds(
+                    levels.iter().filter(|&&level| level > step).count() as u32,
+                    step_us,
+                );
+            }
+            row.set_level(off(row_on));
+        }
+    }
+
+    /// Every row off, so nothing is lit until the next scan
+    pub fn blank(&mut self) {
+        for row in &mut self.rows {
+            row.set_level(off(self.polarity.row_on));
+        }
+    }
+
+    /// A column's pin, for something else to drive between scans, such as
+    /// the light sensor
+    pub fn col(&mut self, col: usize) -> &mut Output<'d> {
+        &mut self.cols[col]
+    }
+}
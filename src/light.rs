@@ -0,0 +1,97 @@
+use defmt::debug;
+use embassy_nrf::{
+    gpio::{AnyPin, Pin as _, Port},
+    pac::{
+        self,
+        gpio::vals::{Dir, Input, Pull},
+    },
+};
+use embassy_time::{Duration, Instant, Timer};
+use micromath::F32Ext;
+
+use crate::{
+    animation::{self, GREY_LEVELS},
+    display::OnBoardLedMatrix,
+};
+
+/// How long the column is driven high to charge the LEDs' capacitance
+const CHARGE_US: u64 = 500;
+
+/// Longest the charge is waited on to leak away, taken as pitch dark. The
+/// display is blank meanwhile, so it is kept short enough not to flicker.
+const DARK: Duration = Duration::from_millis(20);
+
+/// Time for the charge to leak away in full daylight or under a lamp, and
+/// by dusk, between which the brightness is scaled
+const BRIGHT: Duration = Duration::from_micros(500);
+const DIM: Duration = Duration::from_millis(15);
+
+/// Dimmest level used, so the display never goes out altogether
+const MIN_LEVEL: u8 = 1;
+
+/// Measures ambient light with one column of the LED matrix, the way the
+/// micro:bit runtime does: with the rows low, driving the column high
+/// reverse-biases its LEDs and charges them up like small capacitors, and
+/// light falling on them leaks the charge away, faster the brighter it is.
+/// The column is read back as a digital input, so no ADC channel is needed.
+pub struct LightSensor {
+    port: pac::gpio::Gpio,
+    pin: usize,
+}
+
+impl LightSensor {
+    /// Sense through `col`, before it is taken for an output
+    pub fn new(col: &AnyPin) -> Self {
+        let port = match col.port() {
+            Port::Port0 => pac::P0,
+            Port::Port1 => pac::P1,
+        };
+        Self {
+            port,
+            pin: col.pin().into(),
+        }
+    }
+
+    /// How long the charge takes to leak away, up to [`DARK`], with the
+    /// display blank meanwhile. `col` is the column sensed through, driven
+    /// again afterwards.
+    pub async fn measure(&mut self, matrix: &mut OnBoardLedMatrix, col: usize) -> Duration {
+        animation::blank(matrix);
+        let col = matrix.col(col);
+        col.set_high();
+        Timer::after_micros(CHARGE_US).await;
+
+        let output = self.port.pin_cnf(self.pin).read();
+        self.port.pin_cnf(self.pin).write(|w| {
+            w.set_dir(Dir::INPUT);
+            w.set_input(Input::CONNECT);
+            w.set_pull(Pull::DISABLED);
+        });
+        let start = Instant::now();
+        while self.port.in_().read().pin(self.pin) && start.elapsed() < DARK {
+            Timer::after_micros(50).await;
+        }
+        let leak = start.elapsed();
+        self.port.pin_cnf(self.pin).write_value(output);
+        col.set_high();
+        leak
+    }
+}
+
+/// Display brightness for a leak time, from [`MIN_LEVEL`] in the dark to
+/// [`GREY_LEVELS`] in daylight, on a log scale like the eye's
+pub fn level(leak: Duration) -> u8 {
+    let leak = leak.as_micros().clamp(BRIGHT.as_micros(), DIM.as_micros()) as f32;
+    let (bright, dim) = (BRIGHT.as_micros() as f32, DIM.as_micros() as f32);
+    let darkness = (leak / bright).ln() / (dim / bright).ln();
+    let level = GREY_LEVELS as f32 - darkness * f32::from(GREY_LEVELS - MIN_LEVEL);
+    level.round() as u8
+}
+
+/// Measure the ambient light, as the display [`level`] for it
+pub async fn sense(sensor: &mut LightSensor, matrix: &mut OnBoardLedMatrix, col: usize) -> u8 {
+    let leak = sensor.measure(matrix, col).await;
+    let level = level(leak);
+    debug!("light: {} us, level {}", leak.as_micros(), level);
+    level
+}
@@ -0,0 +1,120 @@
+use embedded_hal_async::i2c::I2c;
+
+use crate::{
+    error::{Error, SensorOp},
+    sensor::{Bus, Magnetometer},
+};
+
+/// With SDO/SA1 low. High would clash with the LSM303AGR's magnetometer.
+const ADDRESS: u8 = 0x1C;
+
+const WHO_AM_I: u8 = 0x0F;
+const ID: u8 = 0x3D;
+
+const CTRL_REG1: u8 = 0x20;
+const CTRL_REG2: u8 = 0x21;
+const CTRL_REG3: u8 = 0x22;
+const CTRL_REG4: u8 = 0x23;
+const CTRL_REG5: u8 = 0x24;
+const STATUS_REG: u8 = 0x27;
+/// OUT_X_L with the top bit set, so a read goes on through all six output
+/// registers
+const OUT: u8 = 0x28 | 0x80;
+
+/// Ultra-high-performance X and Y in CTRL_REG1, Z in CTRL_REG4. Low-power
+/// is all zero.
+const OM_ULTRA_HIGH: u8 = 0b11 << 5;
+const OMZ_ULTRA_HIGH: u8 = 0b11 << 2;
+/// ±4 gauss
+const FS_4_GAUSS: u8 = 0x00;
+const MD_CONTINUOUS: u8 = 0x00;
+const MD_POWER_DOWN: u8 = 0x03;
+/// Block data update
+const BDU: u8 = 0x40;
+
+/// X, Y and Z all have new data
+const ZYXDA: u8 = 0b0000_1000;
+
+/// nT per LSB at ±4 gauss, 6842 LSB/gauss
+const NT_PER_LSB: f32 = 100_000.0 / 6842.0;
+
+/// Output data rates with their DO bits in CTRL_REG1, slowest first
+const RATES: [(u16, u8); 4] = [(10, 0b100), (20, 0b101), (40, 0b110), (80, 0b111)];
+
+/// An ST LIS3MDL magnetometer on the shared bus
+pub struct Lis3mdl {
+    i2c: Bus,
+}
+
+impl Lis3mdl {
+    /// `None` unless a LIS3MDL answers, otherwise configured to measure
+    /// continuously at `hz`
+    pub async fn probe(mut i2c: Bus, hz: u16) -> Result<Option<Self>, Error> {
+        let mut id = [0];
+        if i2c.write_read(ADDRESS, &[WHO_AM_I], &mut id).await.is_err() || id[0] != ID {
+            return Ok(None);
+        }
+        let mut mag = Self { i2c };
+        mag.write(CTRL_REG2, FS_4_GAUSS, SensorOp::ConfigureMagnetometer)
+            .await?;
+        mag.write(CTRL_REG5, BDU, SensorOp::ConfigureMagnetometer)
+            .await?;
+        mag.set_rate(hz, false).await?;
+        mag.write(CTRL_REG3, MD_CONTINUOUS, SensorOp::EnterContinuousMode)
+            .await?;
+        Ok(Some(mag))
+    }
+
+    async fn write(&mut self, register: u8, value: u8, op: SensorOp) -> Result<(), Error> {
+        self.i2c
+            .write(ADDRESS, &[register, value])
+            .await
+            .map_err(Error::bus(op))
+    }
+}
+
+impl Magnetometer for Lis3mdl {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        let op = SensorOp::ReadMagnetometer;
+        let mut status = [0];
+        self.i2c
+            .write_read(ADDRESS, &[STATUS_REG], &mut status)
+            .await
+            .map_err(Error::bus(op))?;
+        if status[0] & ZYXDA == 0 {
+            return Ok(None);
+        }
+        let mut buf = [0u8; 6];
+        self.i2c
+            .write_read(ADDRESS, &[OUT], &mut buf)
+            .await
+            .map_err(Error::bus(op))?;
+        Ok(Some([0, 1, 2].map(|axis| {
+            i16::from_le_bytes([buf[2 * axis], buf[2 * axis + 1]]) as f32 * NT_PER_LSB
+        })))
+    }
+
+    /// 10, 20, 40 or 80 Hz
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error> {
+        let (om, omz, rate) = if low_power {
+            (0, 0, RATES[0].1)
+        } else {
+            let (_, rate) = RATES
+                .into_iter()
+                .find(|&(rate_hz, _)| rate_hz >= hz)
+                .unwrap_or(RATES[RATES.len() - 1]);
+            (OM_ULTRA_HIGH, OMZ_ULTRA_HIGH, rate)
+        };
+        self.write(CTRL_REG1, om | rate << 2, SensorOp::SetRate)
+            .await?;
+        self.write(CTRL_REG4, omz, SensorOp::SetRate).await
+    }
+
+    async fn sleep(&mut self) -> Result<(), Error> {
+        self.write(CTRL_REG3, MD_POWER_DOWN, SensorOp::Idle).await
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        self.write(CTRL_REG3, MD_CONTINUOUS, SensorOp::Wake).await
+    }
+}
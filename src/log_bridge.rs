@@ -0,0 +1,143 @@
+//! defmt's global logger. Every frame goes out on RTT up channel 0 for a debug
+//! probe, and a copy waits in [`FRAMES`] for the telemetry task to send over
+//! the console UART once `log uart on` is given, so boards without a probe
+//! can be debugged from the USB serial port:
+//!
+//! ```text
+//! stty -F /dev/ttyACM0 115200 raw
+//! echo "log uart on" > /dev/ttyACM0
+//! defmt-print -e target/thumbv7em-none-eabihf/release/micro-compass < /dev/ttyACM0
+//! ```
+
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use critical_section::RestoreState;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    pipe::Pipe,
+};
+use heapless::Vec;
+use rtt_target::UpChannel;
+
+/// Longest frame copied for the UART, longer ones only go out on RTT
+const MAX_FRAME: usize = 128;
+
+/// Encoded frames waiting for the UART, the oldest kept when it fills up so
+/// the logs from boot survive until mirroring starts. Frames that don't fit
+/// are dropped whole, leaving the stream decodable.
+pub static FRAMES: Pipe<CriticalSectionRawMutex, 1024> = Pipe::new();
+
+/// Whether the UART carries defmt frames instead of telemetry
+static MIRRORING: AtomicBool = AtomicBool::new(false);
+
+static CHANNEL: Mutex<CriticalSectionRawMutex, RefCell<Option<UpChannel>>> =
+    Mutex::new(RefCell::new(None));
+
+/// The frame being logged, only touched between `acquire` and `release`
+struct State {
+    taken: bool,
+    restore: RestoreState,
+    encoder: defmt::Encoder,
+    frame: Vec<u8, MAX_FRAME>,
+    /// The frame outgrew [`MAX_FRAME`]
+    truncated: bool,
+}
+
+static STATE: Mutex<CriticalSectionRawMutex, RefCell<State>> = Mutex::new(RefCell::new(State {
+    taken: false,
+    restore: RestoreState::invalid(),
+    encoder: defmt::Encoder::new(),
+    frame: Vec::new(),
+    truncated: false,
+}));
+
+/// Log to `channel`, set up by `rtt_init!`
+pub fn init(channel: UpChannel) {
+    CHANNEL.lock(|current| *current.borrow_mut() = Some(channel));
+}
+
+pub fn mirroring() -> bool {
+    MIRRORING.load(Ordering::Relaxed)
+}
+
+pub fn set_mirroring(mirroring: bool) {
+    MIRRORING.store(mirroring, Ordering::Relaxed);
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        // Held until `release`, so a frame is never interleaved with another
+        // from an interrupt. Safety: defmt always calls `release` after this
+        let restore = unsafe { critical_section::acquire() };
+        STATE.lock(|state| {
+            let mut state = state.borrow_mut();
+            if state.taken {
+                panic!("defmt logger taken reentrantly");
+            }
+            state.taken = true;
+            state.restore = restore;
+            let State {
+                encoder,
+                frame,
+                truncated,
+                ..
+            } = &mut *state;
+            frame.clear();
+            *truncated = false;
+            encoder.start_frame(|bytes| emit(frame, truncated, bytes));
+        });
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        let restore = STATE.lock(|state| {
+            let mut state = state.borrow_mut();
+            let State {
+                encoder,
+                frame,
+                truncated,
+                ..
+            } = &mut *state;
+            encoder.end_frame(|bytes| emit(frame, truncated, bytes));
+            if !*truncated && FRAMES.free_capacity() >= frame.len() {
+                let _ = FRAMES.try_write(frame);
+            }
+            state.taken = false;
+            state.restore
+        });
+        // Safety: pairs with the acquire in `acquire`
+        unsafe { critical_section::release(restore) };
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        STATE.lock(|state| {
+            let mut state = state.borrow_mut();
+            let State {
+                encoder,
+                frame,
+                truncated,
+                ..
+            } = &mut *state;
+            encoder.write(bytes, |bytes| emit(frame, truncated, bytes));
+        });
+    }
+}
+
+/// Send encoded bytes out on RTT, and keep them for the UART
+fn emit(frame: &mut Vec<u8, MAX_FRAME>, truncated: &mut bool, bytes: &[u8]) {
+    CHANNEL.lock(|channel| {
+        if let Some(channel) = channel.borrow_mut().as_mut() {
+            channel.write(bytes);
+        }
+    });
+    if frame.extend_from_slice(bytes).is_err() {
+        *truncated = true;
+    }
+}
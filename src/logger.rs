@@ -0,0 +1,323 @@
+use core::{cell::RefCell, fmt::Write};
+
+use defmt::{info, warn, Format};
+use embassy_nrf::nvmc::PAGE_SIZE;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+use heapless::String;
+use micro_compass_core::log_format::{DeltaDecoder, DeltaEncoder, Record, MAX_FRAME_LEN};
+
+use crate::{
+    console,
+    storage::{self, Storage},
+    telemetry::{self, Sample, Timestamped},
+    time_sync,
+};
+
+/// Flash region reserved in `memory.x` for the sample log
+const LOG_START: u32 = 0x0005_0000;
+const LOG_END: u32 = 0x0007_8000;
+const LOG_PAGES: usize = (LOG_END - LOG_START) as usize / PAGE_SIZE;
+
+/// Which samples are written to the flash log
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum LogMode {
+    Off,
+    /// Every sample
+    Continuous,
+    /// Only samples taken while the board is moving, plus a few seconds
+    /// after, so long stationary breaks don't use up the log
+    Motion,
+}
+
+/// Encoded bytes held in RAM while flash is busy, e.g. erasing another page
+const PENDING_LEN: usize = 256;
+
+/// Start of a dump, with the address the logger writes next
+static DUMP: Signal<CriticalSectionRawMutex, u32> = Signal::new();
+
+/// The bytes the logger hasn't queued for flash yet and where they go, kept
+/// up to date for [`flush_padded`]
+static UNWRITTEN: Mutex<CriticalSectionRawMutex, RefCell<(u32, [u8; PENDING_LEN], usize)>> =
+    Mutex::new(RefCell::new((LOG_START, [0; PENDING_LEN], 0)));
+
+/// Appends [`Record`]s in the delta format to the log region, carrying on
+/// in the page after the last one written before a reset.
+///
+/// The region is a ring of flash pages. Each page starts with a keyframe, so
+/// it decodes on its own, and the page after the one being written is kept
+/// erased: it marks where the log ends after a reset, and is the next to be
+/// written once the current page fills up, taking the place of the oldest
+/// records.
+pub struct Logger {
+    encoder: DeltaEncoder,
+    /// Next flash address to write
+    next: u32,
+    /// Encoded bytes waiting to make up a whole flash word
+    pending: [u8; PENDING_LEN],
+    pending_len: usize,
+    /// The next frame doesn't fit in the current page. Once the pending bytes
+    /// are written logging moves on to the next page.
+    page_break: bool,
+    mode: LogMode,
+    /// Shortest time between logged samples, zero for every sample
+    interval: Duration,
+    last_logged: Option<Instant>,
+}
+
+impl Logger {
+    /// Find the end of the existing log, the erased page after the last one
+    /// written, and start writing there
+    pub fn resume(storage: &mut Storage<'_>) -> Self {
+        let erased: [bool; LOG_PAGES] =
+            core::array::from_fn(|i| page_erased(storage, page_start(i)));
+        let end = (0..LOG_PAGES).find(|&i| erased[i] && !erased[(i + LOG_PAGES - 1) % LOG_PAGES]);
+
+        let mut logger = Self {
+            encoder: DeltaEncoder::new(),
+            next: LOG_START,
+            pending: [0; PENDING_LEN],
+            pending_len: 0,
+            page_break: false,
+            mode: LogMode::Continuous,
+            interval: Duration::from_ticks(0),
+            last_logged: None,
+        };
+        match end {
+            // The last frame before a reset other than a panic almost always
+            // ends part way through a word, its last bytes lost with the
+            // RAM. A keyframe
+            // appended after it would be read as the rest of that frame, and
+            // garble the page from there on, so carry on in the erased page
+            // with a fresh stream instead.
+            Some(i) => logger.enter_page(page_start(i)),
+            // Empty
+            None if erased[0] => {}
+            // Filled straight through by firmware from before the ring
+            None => {
+                warn!("log has no free page, erasing");
+                storage::erase(LOG_START, LOG_START + 2 * PAGE_SIZE as u32);
+            }
+        }
+        info!(
+            "log: writing at {:x}, {} bytes",
+            logger.next,
+            LOG_END - LOG_START
+        );
+        logger
+    }
+
+    pub fn set_mode(&mut self, mode: LogMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Log `sample` if the [`LogMode`] and interval call for it, `moving`
+    /// as reported by the motion detector. Records are timed on the
+    /// [`time_sync`] timeline, so nothing is logged by a board in radio sync
+    /// mode before it has heard the clock board.
+    pub fn log(&mut self, sample: &Timestamped<Sample>, moving: bool) {
+        let record = match self.mode {
+            LogMode::Off => return,
+            LogMode::Continuous => true,
+            LogMode::Motion => moving,
+        };
+        let too_soon = self
+            .last_logged
+            .is_some_and(|last| last.elapsed() < self.interval);
+        if !record || too_soon {
+            return;
+        }
+        let Some(timestamp_ms) = time_sync::timeline_ms(sample.taken) else {
+            return;
+        };
+
+        // Start a new page rather than split a frame across two, so each page
+        // decodes on its own
+        let page_end = page_of(self.next) + PAGE_SIZE as u32;
+        if !self.page_break && self.next + (self.pending_len + MAX_FRAME_LEN) as u32 > page_end {
+            // Erased flash ends the page's stream
+            let padded = self.pending_len.next_multiple_of(4);
+            self.pending[self.pending_len..padded].fill(0xFF);
+            self.pending_len = padded;
+            self.page_break = true;
+        }
+        self.flush();
+        // While flash is busy words wait in RAM. Skip the sample rather than
+        // drop part of a frame, which would garble every delta after it.
+        if self.page_break || self.pending_len + MAX_FRAME_LEN > self.pending.len() {
+            warn!("flash busy, sample not logged");
+            return;
+        }
+
+        let sample = &sample.value;
+        let record = Record {
+            timestamp_ms,
+            heading_cdeg: (sample.heading * 100.0) as u16 % 36000,
+            pitch_cdeg: (sample.pitch * 100.0) as i16,
+            roll_cdeg: (sample.roll * 100.0) as i16,
+        };
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let len = self.encoder.encode(&record, &mut frame);
+        self.pending[self.pending_len..self.pending_len + len].copy_from_slice(&frame[..len]);
+        self.pending_len += len;
+        self.last_logged = Some(Instant::now());
+        self.flush();
+    }
+
+    /// Write out whole words of pending bytes, at most 3 bytes wait in RAM,
+    /// then move on to the next page if the current one is done
+    fn flush(&mut self) {
+        while self.pending_len >= 4 {
+            let word = [
+                self.pending[0],
+                self.pending[1],
+                self.pending[2],
+                self.pending[3],
+            ];
+            if !storage::append(self.next, word) {
+                break;
+            }
+            self.next += 4;
+            self.pending.copy_within(4..self.pending_len, 0);
+            self.pending_len -= 4;
+        }
+        if self.page_break && self.pending_len == 0 {
+            self.page_break = false;
+            self.enter_page(wrap(page_of(self.next - 4) + PAGE_SIZE as u32));
+        }
+        self.publish();
+    }
+
+    /// Keep [`UNWRITTEN`] up to date with the pending bytes
+    fn publish(&self) {
+        UNWRITTEN.lock(|unwritten| {
+            let (next, pending, len) = &mut *unwritten.borrow_mut();
+            *next = self.next;
+            pending[..self.pending_len].copy_from_slice(&self.pending[..self.pending_len]);
+            *len = self.pending_len;
+        });
+    }
+
+    /// Start writing at the already erased `page`, erasing the one after it
+    fn enter_page(&mut self, page: u32) {
+        self.next = page;
+        self.encoder = DeltaEncoder::new();
+        let ahead = wrap(page + PAGE_SIZE as u32);
+        storage::erase(ahead, ahead + PAGE_SIZE as u32);
+        trace!("log page {:x}", page);
+    }
+
+    /// Erase the whole log and start again from the beginning
+    pub fn erase(&mut self) {
+        storage::erase(LOG_START, LOG_END);
+        info!("log erased");
+        self.encoder = DeltaEncoder::new();
+        self.next = LOG_START;
+        self.pending_len = 0;
+        self.page_break = false;
+        self.publish();
+    }
+
+    /// Have [`dump_task`] send the log over the console, oldest record first
+    pub fn dump(&self) {
+        DUMP.signal(self.next);
+    }
+}
+
+/// Write the bytes the logger hasn't queued yet, the last word padded with
+/// the erased value, blocking. For the panic handler, after
+/// [`Storage::drain`] has written everything queued before them.
+pub fn flush_padded(storage: &mut Storage<'_>) {
+    UNWRITTEN.lock(|unwritten| {
+        // Left alone if the panic came while the logger was updating it
+        let Ok(unwritten) = unwritten.try_borrow() else {
+            return;
+        };
+        let (next, pending, len) = &*unwritten;
+        let mut padded = *pending;
+        let padded_len = len.next_multiple_of(4);
+        padded[*len..padded_len].fill(0xFF);
+        if let Err(e) = storage.write(*next, &padded[..padded_len]) {
+            defmt::error!("log tail lost: {}", e);
+        }
+    });
+}
+
+/// Decode the log and send it out as console replies, one line per record:
+///
+/// ```text
+/// LOG,<timestamp_ms>,<heading>,<pitch>,<roll>
+/// ```
+///
+/// followed by `LOG,end`. The heading is in the telemetry heading unit, pitch
+/// and roll in degrees. Replies are held back in the binary format, so it
+/// needs a text format. Logging carries on meanwhile, and on a long dump may
+/// overwrite the oldest page before it is read.
+#[embassy_executor::task]
+pub async fn dump_task() {
+    loop {
+        let next = DUMP.wait().await;
+        let newest = page_of(next);
+        info!("log dump started");
+        // The oldest records follow the erased page after the newest
+        let mut page = wrap(newest + 2 * PAGE_SIZE as u32);
+        loop {
+            // SAFETY: the log region is mapped flash, never used for code
+            let data = unsafe { core::slice::from_raw_parts(page as *const u8, PAGE_SIZE) };
+            for record in DeltaDecoder::new(data) {
+                let Ok(record) = record else {
+                    warn!("corrupt log page {:x}", page);
+                    break;
+                };
+                let mut line: String<{ console::MAX_REPLY }> = String::new();
+                let _ = write!(
+                    line,
+                    "LOG,{},{},{:.2},{:.2}",
+                    record.timestamp_ms,
+                    telemetry::heading_unit().format(record.heading_cdeg as f32 / 100.0),
+                    record.pitch_cdeg as f32 / 100.0,
+                    record.roll_cdeg as f32 / 100.0
+                );
+                // Wait for room rather than drop records
+                console::REPLIES.send(line).await;
+            }
+            if page == newest {
+                break;
+            }
+            page = wrap(page + PAGE_SIZE as u32);
+        }
+        console::REPLIES
+            .send(String::try_from("LOG,end").unwrap())
+            .await;
+        info!("log dump finished");
+    }
+}
+
+/// Start of the `i`th page of the region
+fn page_start(i: usize) -> u32 {
+    LOG_START + (i * PAGE_SIZE) as u32
+}
+
+/// Start of the page holding `address`
+fn page_of(address: u32) -> u32 {
+    address & !(PAGE_SIZE as u32 - 1)
+}
+
+/// Bring an address past the end of the region back round to the start
+fn wrap(address: u32) -> u32 {
+    LOG_START + (address - LOG_START) % (LOG_END - LOG_START)
+}
+
+fn page_erased(storage: &mut Storage<'_>, page: u32) -> bool {
+    let mut buf = [0u8; 64];
+    (page..page + PAGE_SIZE as u32)
+        .step_by(buf.len())
+        .all(|address| storage.load(address, &mut buf).is_ok() && buf.iter().all(|&b| b == 0xFF))
+}
@@ -0,0 +1,158 @@
+use core::cell::Cell;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Ticker};
+
+/// Loop iterations summarized in each report, about 10 s at full rate
+const REPORT_EVERY: u32 = 50;
+
+/// Count, mean and worst of a run of durations
+#[derive(Clone, Copy)]
+struct Stats {
+    count: u32,
+    total_us: u64,
+    max_us: u64,
+}
+
+impl Stats {
+    const EMPTY: Self = Self {
+        count: 0,
+        total_us: 0,
+        max_us: 0,
+    };
+
+    fn add(&mut self, duration: Duration) {
+        let us = duration.as_micros();
+        self.count += 1;
+        self.total_us += us;
+        self.max_us = self.max_us.max(us);
+    }
+
+    fn mean_us(&self) -> u64 {
+        self.total_us / u64::from(self.count.max(1))
+    }
+}
+
+/// Every I2C transfer on the shared bus since the last report, whichever
+/// task made it
+static I2C: Mutex<CriticalSectionRawMutex, Cell<Stats>> = Mutex::new(Cell::new(Stats::EMPTY));
+
+/// Count an I2C transfer that took `duration` once it had the bus
+pub fn record_i2c(duration: Duration) {
+    I2C.lock(|stats| {
+        let mut current = stats.get();
+        current.add(duration);
+        stats.set(current);
+    });
+}
+
+/// Paces the main loop at a fixed period with a [`Ticker`], and measures how
+/// well it keeps to it: how long each iteration works, how far the time
+/// between iterations strays from the period, and how often an iteration
+/// runs over. A summary goes out over defmt every [`REPORT_EVERY`]
+/// iterations, with the I2C transfer times over the same stretch.
+///
+/// Iterations that end early, e.g. while calibrating or after a sensor
+/// error, aren't paced and are left out.
+pub struct LoopTiming {
+    ticker: Ticker,
+    period: Duration,
+    /// When the current iteration started
+    started: Instant,
+    /// Iterations started since the last tick, more than one if some ended
+    /// early
+    iterations: u32,
+    /// When the last tick came, unless the iteration before ran over
+    last_tick: Option<Instant>,
+    busy: Stats,
+    /// Difference between the time from one tick to the next and the period
+    jitter: Stats,
+    overruns: u32,
+}
+
+impl LoopTiming {
+    pub fn new(period: Duration) -> Self {
+        Self {
+            ticker: Ticker::every(period),
+            period,
+            started: Instant::now(),
+            iterations: 0,
+            last_tick: None,
+            busy: Stats::EMPTY,
+            jitter: Stats::EMPTY,
+            overruns: 0,
+        }
+    }
+
+    /// At the top of every iteration
+    pub fn start(&mut self) {
+        self.started = Instant::now();
+        self.iterations += 1;
+    }
+
+    /// Wait for the next iteration, due `period` after the last one started.
+    /// One that ran over starts the next straight away and the period counts
+    /// from there, rather than several following in a burst to catch up.
+    pub async fn next(&mut self, period: Duration) {
+        if period != self.period {
+            self.period = period;
+            self.ticker = Ticker::every(period);
+            self.last_tick = None;
+        }
+
+        let busy = self.started.elapsed();
+        let paced = self.iterations == 1;
+        self.iterations = 0;
+        self.busy.add(busy);
+        if busy > self.period {
+            self.overruns += 1;
+            self.ticker.reset();
+            self.last_tick = None;
+        } else {
+            if !paced {
+                // Count the period from this iteration's start
+                self.ticker.reset_at(self.started);
+                self.last_tick = None;
+            }
+            self.ticker.next().await;
+            let now = Instant::now();
+            if let Some(last) = self.last_tick {
+                let actual = now - last;
+                self.jitter.add(if actual > self.period {
+                    actual - self.period
+                } else {
+                    self.period - actual
+                });
+            }
+            self.last_tick = Some(now);
+        }
+
+        if self.busy.count >= REPORT_EVERY {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        let i2c = I2C.lock(|stats| stats.replace(Stats::EMPTY));
+        info!(
+            "loop: {} ms period, busy mean {} max {} µs, jitter mean {} max {} µs, {} of {} overran",
+            self.period.as_millis(),
+            self.busy.mean_us(),
+            self.busy.max_us,
+            self.jitter.mean_us(),
+            self.jitter.max_us,
+            self.overruns,
+            self.busy.count
+        );
+        info!(
+            "i2c: {} transfers, mean {} max {} µs",
+            i2c.count,
+            i2c.mean_us(),
+            i2c.max_us
+        );
+        self.busy = Stats::EMPTY;
+        self.jitter = Stats::EMPTY;
+        self.overruns = 0;
+    }
+}
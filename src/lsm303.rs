@@ -0,0 +1,322 @@
+use defmt::debug;
+use embassy_time::Delay;
+use embedded_hal_async::i2c::I2c;
+use lsm303agr::{interface::I2cInterface, mode, Lsm303agr};
+
+use crate::{
+    bus,
+    error::{Error, SensorOp},
+    freefall, idle,
+    sensor::{
+        self, AccelHighPass, AccelPower, Accelerometer, Bus, Magnetometer, MAX_HIGH_PASS_CUTOFF,
+    },
+    settings::Settings,
+    tap,
+};
+
+const WHO_AM_I_A: u8 = 0x0F;
+const ACCEL_ID: u8 = 0x33;
+
+/// Accelerometer high-pass filter configuration
+const CTRL_REG2_A: u8 = 0x21;
+
+/// Cut-off frequency, 2 bits
+const CTRL_REG2_HPCF_SHIFT: u8 = 4;
+
+/// High-pass filter what the click engine sees
+const CTRL_REG2_HPCLICK: u8 = 0b0000_0100;
+
+/// High-pass filter what interrupt generator 2 sees, so gravity doesn't count
+/// as motion when waking from idle
+const CTRL_REG2_HPIS2: u8 = 0b0000_0010;
+
+type Driver<MODE> = Lsm303agr<I2cInterface<Bus>, MODE>;
+
+/// The board's own LSM303AGR. The driver tracks whether the magnetometer
+/// measures continuously in its type, so parking it swaps one for the other.
+pub struct Lsm303 {
+    driver: State,
+}
+
+enum State {
+    Continuous(Driver<mode::MagContinuous>),
+    Parked(Driver<mode::MagOneShot>),
+    /// Only while the magnetometer is being switched over
+    Switching,
+}
+
+/// Whether the LSM303AGR's accelerometer answers with its ID
+pub async fn answers<I: I2c>(i2c: &mut I) -> bool {
+    let mut id = [0];
+    i2c.write_read(tap::ACCEL_ADDRESS, &[WHO_AM_I_A], &mut id)
+        .await
+        .is_ok()
+        && id[0] == ACCEL_ID
+}
+
+impl Lsm303 {
+    /// Configure it from scratch over the shared [`bus`]: the click,
+    /// free-fall and wake interrupt generators directly, then both sensors at
+    /// their configured rates and power modes through the driver, the
+    /// magnetometer measuring continuously.
+    pub async fn start(settings: &Settings) -> Result<Self, Error> {
+        let mut device = bus::device();
+
+        // Tapping the board (reported on the sensor interrupt line, P0.25)
+        // cycles and locks stored bearings like buttons B and A
+        tap::configure_click(&mut device)
+            .await
+            .map_err(Error::bus(SensorOp::ConfigureTap))?;
+        // Dropping the board flashes the display and sends a telemetry event
+        freefall::configure_free_fall(&mut device)
+            .await
+            .map_err(Error::bus(SensorOp::ConfigureFreeFall))?;
+        // Moving the board wakes it from idle
+        idle::configure_wake(&mut device)
+            .await
+            .map_err(Error::bus(SensorOp::ConfigureWake))?;
+        configure_high_pass(&mut device, settings.accel_high_pass)
+            .await
+            .map_err(Error::bus(SensorOp::ConfigureHighPass))?;
+
+        let mut sensor = Lsm303agr::new_with_i2c(sensor::device());
+
+        // Read magnetometer ID
+        match sensor.magnetometer_id().await {
+            Ok(_id) => debug!("magnetometer id obtained"),
+            Err(_e) => debug!("error getting magnetometer id"),
+        }
+
+        sensor.init().await.map_err(Error::sensor(SensorOp::Init))?;
+        sensor
+            .acc_enable_interrupt(lsm303agr::Interrupt::Click)
+            .await
+            .map_err(Error::sensor(SensorOp::EnableInterrupt))?;
+        sensor
+            .acc_enable_interrupt(lsm303agr::Interrupt::Aoi1)
+            .await
+            .map_err(Error::sensor(SensorOp::EnableInterrupt))?;
+
+        // Configure accelerometer: mode and output data rate from the
+        // settings (high resolution at 50 Hz by default)
+        let (mode, odr) = accel_mode_and_odr(settings.accel_odr_hz, settings.accel_power);
+        sensor
+            .set_accel_mode_and_odr(&mut Delay, mode, odr)
+            .await
+            .map_err(Error::sensor(SensorOp::ConfigureAccelerometer))?;
+
+        // Configure magnetometer: mode and output data rate from the settings
+        // (high resolution at 10 Hz by default)
+        let (mode, odr) = mag_mode_and_odr(settings.mag_odr_hz, settings.mag_low_power);
+        sensor
+            .set_mag_mode_and_odr(&mut Delay, mode, odr)
+            .await
+            .map_err(Error::sensor(SensorOp::ConfigureMagnetometer))?;
+
+        // Enable continuous magnetometer mode
+        let sensor = sensor
+            .into_mag_continuous()
+            .await
+            .map_err(|e| Error::sensor(SensorOp::EnterContinuousMode)(e.error))?;
+        // Filtered unless turned off in the settings
+        let mut lsm303 = Self {
+            driver: State::Continuous(sensor),
+        };
+        lsm303.set_low_pass(settings.mag_low_pass).await?;
+        Ok(lsm303)
+    }
+}
+
+/// The high-pass filter in front of the tap and wake detectors, always on
+/// for waking. The driver doesn't expose it, so this talks to the bus
+/// directly.
+async fn configure_high_pass<I: I2c>(i2c: &mut I, filter: AccelHighPass) -> Result<(), I::Error> {
+    let mut value =
+        CTRL_REG2_HPIS2 | (filter.cutoff.min(MAX_HIGH_PASS_CUTOFF) << CTRL_REG2_HPCF_SHIFT);
+    if filter.tap {
+        value |= CTRL_REG2_HPCLICK;
+    }
+    i2c.write(tap::ACCEL_ADDRESS, &[CTRL_REG2_A, value]).await
+}
+
+/// Anything but the rates in [`sensor::ACCEL_RATES_HZ`] falls back to 50 Hz
+fn accel_mode_and_odr(
+    hz: u16,
+    power: AccelPower,
+) -> (lsm303agr::AccelMode, lsm303agr::AccelOutputDataRate) {
+    let odr = Some(hz)
+        .filter(|hz| sensor::ACCEL_RATES_HZ.contains(hz))
+        .and_then(lsm303agr::AccelOutputDataRate::from_hertz)
+        .unwrap_or(lsm303agr::AccelOutputDataRate::Hz50);
+    let mode = match power {
+        AccelPower::HighResolution => lsm303agr::AccelMode::HighResolution,
+        AccelPower::Normal => lsm303agr::AccelMode::Normal,
+        AccelPower::LowPower => lsm303agr::AccelMode::LowPower,
+    };
+    (mode, odr)
+}
+
+/// 10, 20, 50 or 100 Hz, anything else falls back to 10 Hz
+fn mag_mode_and_odr(
+    hz: u16,
+    low_power: bool,
+) -> (lsm303agr::MagMode, lsm303agr::MagOutputDataRate) {
+    let odr =
+        lsm303agr::MagOutputDataRate::from_hertz(hz).unwrap_or(lsm303agr::MagOutputDataRate::Hz10);
+    let mode = if low_power {
+        lsm303agr::MagMode::LowPower
+    } else {
+        lsm303agr::MagMode::HighResolution
+    };
+    (mode, odr)
+}
+
+impl Accelerometer for Lsm303 {
+    async fn acceleration(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        let op = SensorOp::ReadAccelerometer;
+        let accel = match &mut self.driver {
+            State::Continuous(sensor) => match sensor.accel_status().await {
+                Ok(status) if status.xyz_new_data() => Some(sensor.acceleration().await),
+                Ok(_) => None,
+                Err(e) => return Err(Error::sensor(op)(e)),
+            },
+            State::Parked(sensor) => match sensor.accel_status().await {
+                Ok(status) if status.xyz_new_data() => Some(sensor.acceleration().await),
+                Ok(_) => None,
+                Err(e) => return Err(Error::sensor(op)(e)),
+            },
+            State::Switching => None,
+        };
+        accel
+            .transpose()
+            .map(|accel| accel.map(|a| [a.x_mg() as f32, a.y_mg() as f32, a.z_mg() as f32]))
+            .map_err(Error::sensor(op))
+    }
+
+    async fn set_accel_rate(&mut self, hz: u16, power: AccelPower) -> Result<(), Error> {
+        let (mode, odr) = accel_mode_and_odr(hz, power);
+        let result = match &mut self.driver {
+            State::Continuous(sensor) => sensor.set_accel_mode_and_odr(&mut Delay, mode, odr).await,
+            State::Parked(sensor) => sensor.set_accel_mode_and_odr(&mut Delay, mode, odr).await,
+            State::Switching => Ok(()),
+        };
+        result.map_err(Error::sensor(SensorOp::SetRate))
+    }
+
+    async fn set_high_pass(&mut self, filter: AccelHighPass) -> Result<(), Error> {
+        configure_high_pass(&mut bus::device(), filter)
+            .await
+            .map_err(Error::bus(SensorOp::ConfigureHighPass))
+    }
+
+    async fn set_wake_on_motion(&mut self, enabled: bool) -> Result<(), Error> {
+        let interrupt = lsm303agr::Interrupt::Aoi2;
+        let result = match (&mut self.driver, enabled) {
+            (State::Continuous(sensor), true) => sensor.acc_enable_interrupt(interrupt).await,
+            (State::Continuous(sensor), false) => sensor.acc_disable_interrupt(interrupt).await,
+            (State::Parked(sensor), true) => sensor.acc_enable_interrupt(interrupt).await,
+            (State::Parked(sensor), false) => sensor.acc_disable_interrupt(interrupt).await,
+            (State::Switching, _) => Ok(()),
+        };
+        result.map_err(Error::sensor(SensorOp::EnableInterrupt))
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        let (mode, odr) = (
+            lsm303agr::AccelMode::PowerDown,
+            lsm303agr::AccelOutputDataRate::Hz50,
+        );
+        let result = match &mut self.driver {
+            State::Continuous(sensor) => sensor.set_accel_mode_and_odr(&mut Delay, mode, odr).await,
+            State::Parked(sensor) => sensor.set_accel_mode_and_odr(&mut Delay, mode, odr).await,
+            State::Switching => Ok(()),
+        };
+        result.map_err(Error::sensor(SensorOp::PowerDown))
+    }
+}
+
+impl Magnetometer for Lsm303 {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        let State::Continuous(sensor) = &mut self.driver else {
+            return Ok(None);
+        };
+        let op = SensorOp::ReadMagnetometer;
+        if !sensor
+            .mag_status()
+            .await
+            .map_err(Error::sensor(op))?
+            .xyz_new_data()
+        {
+            return Ok(None);
+        }
+        let field = sensor.magnetic_field().await.map_err(Error::sensor(op))?;
+        Ok(Some([
+            field.x_nt() as f32,
+            field.y_nt() as f32,
+            field.z_nt() as f32,
+        ]))
+    }
+
+    /// 10, 20, 50 or 100 Hz, anything else falls back to 10 Hz. Low power
+    /// keeps the rate.
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error> {
+        let (mode, odr) = mag_mode_and_odr(hz, low_power);
+        let result = match &mut self.driver {
+            State::Continuous(sensor) => sensor.set_mag_mode_and_odr(&mut Delay, mode, odr).await,
+            State::Parked(sensor) => sensor.set_mag_mode_and_odr(&mut Delay, mode, odr).await,
+            State::Switching => Ok(()),
+        };
+        result.map_err(Error::sensor(SensorOp::SetRate))
+    }
+
+    /// Halves the bandwidth from ODR/2 to ODR/4, so the readings lag by
+    /// about one sample more: 100 ms at the default 10 Hz
+    async fn set_low_pass(&mut self, enabled: bool) -> Result<(), Error> {
+        let result = match (&mut self.driver, enabled) {
+            (State::Continuous(sensor), true) => sensor.mag_enable_low_pass_filter().await,
+            (State::Continuous(sensor), false) => sensor.mag_disable_low_pass_filter().await,
+            (State::Parked(sensor), true) => sensor.mag_enable_low_pass_filter().await,
+            (State::Parked(sensor), false) => sensor.mag_disable_low_pass_filter().await,
+            (State::Switching, _) => Ok(()),
+        };
+        result.map_err(Error::sensor(SensorOp::SetLowPassFilter))
+    }
+
+    async fn sleep(&mut self) -> Result<(), Error> {
+        match core::mem::replace(&mut self.driver, State::Switching) {
+            State::Continuous(sensor) => match sensor.into_mag_one_shot().await {
+                Ok(parked) => {
+                    self.driver = State::Parked(parked);
+                    Ok(())
+                }
+                Err(e) => {
+                    self.driver = State::Continuous(e.dev);
+                    Err(Error::sensor(SensorOp::Idle)(e.error))
+                }
+            },
+            state => {
+                self.driver = state;
+                Ok(())
+            }
+        }
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        match core::mem::replace(&mut self.driver, State::Switching) {
+            State::Parked(sensor) => match sensor.into_mag_continuous().await {
+                Ok(sensor) => {
+                    self.driver = State::Continuous(sensor);
+                    Ok(())
+                }
+                Err(e) => {
+                    self.driver = State::Parked(e.dev);
+                    Err(Error::sensor(SensorOp::Wake)(e.error))
+                }
+            },
+            state => {
+                self.driver = state;
+                Ok(())
+            }
+        }
+    }
+}
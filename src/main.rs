@@ -1,201 +1,2107 @@
 #![no_std]
 #![no_main]
+// The on-target tests never hand the board back to the application
+#![cfg_attr(feature = "hw-test", allow(unreachable_code))]
 
-use defmt::{info, warn};
-use defmt_rtt as _;
+use defmt::{debug, info, warn};
 use embassy_executor::Spawner;
-use embassy_nrf::{self as hal, twim::Twim};
-use embassy_time::Delay;
+use embassy_futures::select::{select4, Either4};
+use embassy_nrf as hal;
+use embassy_time::{Delay, Duration, Instant, Timer};
 use embedded_hal_async::delay::DelayNs;
 use hal::{gpio, twim};
-use lsm303agr::Lsm303agr;
-use micromath::F32Ext;
-use panic_probe as _;
+use micro_compass_core::{
+    deadreckon,
+    deviation::DeviationTable,
+    heading::{angle_diff, compute_pitch_roll, get_cardinal_direction, normalize_heading},
+    protocol::{Orientation, Reference},
+    sun,
+};
+
+use animation::{Animation, Easing, Frame, Repeat};
+use display::CompassDisplay as _;
+use error::Error;
+#[cfg(feature = "gyro")]
+use sensor::Gyroscope as _;
+use sensor::{Accelerometer as _, Magnetometer as _};
+
+#[macro_use]
+mod trace;
+mod turn_rate;
+
+mod alarm;
+#[cfg(feature = "analog")]
+mod analog;
+mod animation;
+mod attitude;
+mod audio;
+mod battery;
+mod bearing;
+mod bezel;
+mod bias_check;
+mod board;
+mod bus;
+mod buttons;
+mod calibration;
+mod capabilities;
+mod clock;
+#[cfg(feature = "compass-module")]
+mod cmps12;
+#[cfg(feature = "compass-module")]
+mod compass_module;
+mod console;
+#[cfg(feature = "gps")]
+mod course_fusion;
+#[cfg(feature = "gps")]
+mod course_offset;
+mod display;
+#[cfg(feature = "energy")]
+mod energy;
+mod error;
+#[cfg(feature = "fault-injection")]
+mod fault;
+mod frame_buffer;
+mod freefall;
+mod fxos8700;
+#[cfg(feature = "gps")]
+mod geo;
+#[cfg(feature = "gps")]
+mod gps;
+#[cfg(feature = "gyro")]
+mod gyro_fusion;
+mod haptic;
+mod heading_change;
+mod heading_log;
+mod heading_stats;
+mod health;
+#[cfg(feature = "compass-module")]
+mod hmc6352;
+#[cfg(feature = "hw-test")]
+mod hwtest;
+mod idle;
+mod led_matrix;
+mod light;
+#[cfg(feature = "lis3mdl")]
+mod lis3mdl;
+mod log_bridge;
+mod logger;
+mod loop_timing;
+mod lsm303;
+mod matrix_scan;
+mod missed_data;
+#[cfg(feature = "mmc5603")]
+mod mmc5603;
+mod motion;
+mod motion_class;
+mod motioncal;
+mod mounting;
+#[cfg(feature = "gyro")]
+mod mpu6050;
+mod name;
+mod needle;
+mod night;
+mod nmea;
+#[cfg(feature = "oled")]
+mod oled;
+mod orienteering;
+mod panic;
+mod pedometer;
+mod pipeline;
+mod profiles;
+#[cfg(feature = "qmc5883l")]
+mod qmc5883l;
+mod race_timer;
+mod radio;
+mod reboot;
+#[cfg(feature = "redundant-mag")]
+mod redundancy;
+#[cfg(all(
+    feature = "redundant-mag",
+    not(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))
+))]
+compile_error!("redundant-mag needs one of the lis3mdl, qmc5883l and mmc5603 features");
+mod replay;
+#[cfg(feature = "rgb")]
+mod rgb;
+mod selftest;
+mod sensor;
+#[cfg(feature = "servo")]
+mod servo;
+mod settings;
+mod shake;
+mod sighting;
+mod sleep;
+mod snapshot;
+mod storage;
+mod strobe;
+mod sun_check;
+mod survey;
+mod tap;
+mod telemetry;
+mod tilt;
+mod time_sync;
+mod touch;
+mod trail;
+mod vibration;
+mod watchdog;
+#[cfg(feature = "gps")]
+mod waypoint;
+mod wizard;
 
 hal::bind_interrupts!(struct Irqs {
     TWISPI0 => twim::InterruptHandler<hal::peripherals::TWISPI0>;
+    SAADC => hal::saadc::InterruptHandler;
+    UARTE0 => hal::buffered_uarte::InterruptHandler<hal::peripherals::UARTE0>;
+    RADIO => hal::radio::InterruptHandler<hal::peripherals::RADIO>;
+    #[cfg(feature = "gps")]
+    UARTE1 => hal::buffered_uarte::InterruptHandler<hal::peripherals::UARTE1>;
+    #[cfg(feature = "rgb")]
+    SPI2 => hal::spim::InterruptHandler<hal::peripherals::SPI2>;
+    #[cfg(feature = "compass-module")]
+    TWISPI1 => hal::twis::InterruptHandler<hal::peripherals::TWISPI1>;
 });
 
+// Stamp every defmt line with the time since boot, in milliseconds like the
+// flash log's records
+defmt::timestamp!("{=u64:tms}", Instant::now().as_millis());
+
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
+    // defmt logs go out on RTT up channel 0, the inter-task trace on channel 1
+    #[cfg(not(feature = "trace"))]
+    {
+        let channels = rtt_target::rtt_init! {
+            up: {
+                0: { size: 1024, name: "defmt" }
+            }
+        };
+        log_bridge::init(channels.up.0);
+    }
+    #[cfg(feature = "trace")]
+    {
+        let channels = rtt_target::rtt_init! {
+            up: {
+                0: { size: 1024, name: "defmt" }
+                1: { size: 1024, name: "trace" }
+            }
+        };
+        log_bridge::init(channels.up.0);
+        trace::init(channels.up.1);
+    }
+
     info!("initializing micro-compass...");
     // Get a handle to the peripherals
-    let dp = hal::init(Default::default());
-
-    // Configure I2C using TWIM
-    let config = twim::Config::default();
-    let twim0 = Twim::new(dp.TWISPI0, Irqs, dp.P0_16, dp.P0_08, config);
-
-    // Initialize GPIO for LED Matrix (rows & cols)
-    let mut rows = [
-        gpio::Output::new(dp.P0_21, gpio::Level::Low, gpio::OutputDrive::Standard),
-        gpio::Output::new(dp.P0_22, gpio::Level::Low, gpio::OutputDrive::Standard),
-        gpio::Output::new(dp.P0_15, gpio::Level::Low, gpio::OutputDrive::Standard),
-        gpio::Output::new(dp.P0_24, gpio::Level::Low, gpio::OutputDrive::Standard),
-        gpio::Output::new(dp.P0_19, gpio::Level::Low, gpio::OutputDrive::Standard),
-    ];
-
-    let mut cols = [
-        gpio::Output::new(dp.P0_28, gpio::Level::Low, gpio::OutputDrive::Standard),
-        gpio::Output::new(dp.P0_11, gpio::Level::Low, gpio::OutputDrive::Standard),
-        gpio::Output::new(dp.P0_31, gpio::Level::Low, gpio::OutputDrive::Standard),
-        gpio::Output::new(dp.P1_05, gpio::Level::Low, gpio::OutputDrive::Standard),
-        gpio::Output::new(dp.P0_30, gpio::Level::Low, gpio::OutputDrive::Standard),
-    ];
-
-    // Initialize LSM303AGR
-    let mut sensor = Lsm303agr::new_with_i2c(twim0);
-
-    // Read magnetometer ID
-    match sensor.magnetometer_id().await {
-        Ok(_id) => info!("magnetometer id obtained"),
-        Err(_e) => info!("error getting magnetometer id"),
-    }
-
-    // Initialize sensor
-    sensor.init().await.unwrap();
-
-    // Configure accelerometer: High resolution mode, 50 Hz output data rate
-    sensor
-        .set_accel_mode_and_odr(
-            &mut Delay,
-            lsm303agr::AccelMode::HighResolution,
-            lsm303agr::AccelOutputDataRate::Hz50,
-        )
-        .await
-        .unwrap();
-
-    // Configure magnetometer: High resolution mode, 10 Hz output data rate
-    sensor
-        .set_mag_mode_and_odr(
-            &mut Delay,
-            lsm303agr::MagMode::HighResolution,
-            lsm303agr::MagOutputDataRate::Hz10,
-        )
-        .await
-        .unwrap();
+    // The radio needs the crystal oscillator
+    let mut config = hal::config::Config::default();
+    config.hfclk_source = hal::config::HfclkSource::ExternalXtal;
+    // Pins are assigned in src/board.rs
+    let board = board::Board::new(hal::init(config));
+    watchdog::log_reset_reason();
+    // The on-target tests take over the board instead, before the watchdog
+    // can't be stopped any more
+    #[cfg(feature = "hw-test")]
+    hwtest::run(board, spawner).await;
+    // From here on the main loop must come round regularly, or the board
+    // resets
+    let mut watchdog = watchdog::Watchdog::start(board.wdt);
+
+    // Frames held on the matrix are scanned in hardware
+    matrix_scan::init(board.matrix_scan, &board.matrix);
+    // The middle column of LEDs doubles as a light sensor
+    let mut light_sensor = light::LightSensor::new(&board.matrix.cols[LIGHT_COL]);
+    let mut light_measured: Option<Instant> = None;
+    // The same driver as the external matrix, when the scan isn't in
+    // hardware
+    let mut matrix = led_matrix::Builder::new(board.matrix).build();
+    // Another matrix, if the board file wires one up, kept scanned by its
+    // own task
+    if let Some(pins) = board.external_matrix {
+        let external = led_matrix::Builder::new(pins)
+            .polarity(board::EXTERNAL_MATRIX_POLARITY)
+            .drive(gpio::OutputDrive::HighDrive)
+            .build();
+        spawner.must_spawn(display::external_matrix_task(external));
+    }
+    // A needle sweeping round shows the whole matrix works before anything
+    // else starts
+    SPLASH.play(&mut matrix).await;
+    animation::show(&mut matrix, &SPLASH_ROSE, SPLASH_ROSE_MS).await;
+
+    let output = |pin| gpio::Output::new(pin, gpio::Level::Low, gpio::OutputDrive::Standard);
+    // Edge connector pin 0 pulses when the heading crosses the strobe bearing
+    let mut strobe = board
+        .strobe
+        .map(|pin| strobe::HeadingStrobe::new(output(pin), board::STROBE_BEARING));
+
+    // Buttons A and B lock and cycle stored bearings
+    let input = |pin| gpio::Input::new(pin, board::BUTTON_PULL);
+    spawner.must_spawn(buttons::buttons_task(
+        input(board.button_a),
+        input(board.button_b),
+    ));
+
+    // The UART to the interface MCU shows up as the micro:bit's USB serial
+    // port, carrying telemetry out and commands in
+    {
+        static RX_BUFFER: static_cell::StaticCell<[u8; 128]> = static_cell::StaticCell::new();
+        static TX_BUFFER: static_cell::StaticCell<[u8; 256]> = static_cell::StaticCell::new();
+        let console = board.console;
+        let uart = hal::buffered_uarte::BufferedUarte::new(
+            console.uarte,
+            console.timer,
+            console.ppi_ch1,
+            console.ppi_ch2,
+            console.ppi_group,
+            Irqs,
+            console.rx,
+            console.tx,
+            Default::default(),
+            RX_BUFFER.init([0; 128]),
+            TX_BUFFER.init([0; 256]),
+        );
+        let (rx, tx) = uart.split();
+        spawner.must_spawn(console::console_task(rx));
+        spawner.must_spawn(telemetry::telemetry_task(tx));
+    }
 
-    // Enable continuous magnetometer mode
-    let Ok(mut sensor) = sensor.into_mag_continuous().await else {
-        panic!("failed to enter continuous mode");
+    // Holding the touch logo toggles the display between magnetic and true
+    // north
+    if let Some(touch) = board.touch {
+        spawner.must_spawn(touch::touch_task(gpio::Flex::new(touch)));
+    }
+
+    // Speaker beeps faster as the heading approaches a stored bearing, or
+    // clicks out the quadrant
+    if let Some(speaker) = board.speaker {
+        let speaker = hal::pwm::SimplePwm::new_1ch(board.speaker_pwm, speaker);
+        spawner.must_spawn(audio::audio_task(speaker));
+    }
+
+    // A vibration motor on edge connector pin 16 pulses on coming onto north
+    // or the active bearing
+    if let Some(haptic) = board.haptic {
+        spawner.must_spawn(haptic::haptic_task(output(haptic)));
+    }
+
+    // Gathers the health of the sensor, calibration and radio into a
+    // heartbeat on a corner of the display
+    spawner.must_spawn(health::health_task());
+
+    // Breaks down the current drawn by the CPU, I2C and the display
+    #[cfg(feature = "energy")]
+    {
+        energy::init();
+        spawner.must_spawn(energy::energy_task());
+    }
+
+    // GPS receiver on edge connector pin 1, used to learn the heading
+    // offset, steady the heading while moving and find the way to a waypoint
+    #[cfg(feature = "gps")]
+    {
+        static RX_BUFFER: static_cell::StaticCell<[u8; 256]> = static_cell::StaticCell::new();
+        let mut config = hal::uarte::Config::default();
+        config.baudrate = hal::uarte::Baudrate::BAUD9600;
+        let gps = board.gps;
+        let rx = hal::buffered_uarte::BufferedUarteRx::new(
+            gps.uarte,
+            gps.timer,
+            gps.ppi_ch1,
+            gps.ppi_ch2,
+            gps.ppi_group,
+            Irqs,
+            gps.rx,
+            config,
+            RX_BUFFER.init([0; 256]),
+        );
+        spawner.must_spawn(gps::gps_task(rx));
+    }
+
+    // WS2812 LEDs on edge connector pin 2 show the heading as a color
+    #[cfg(feature = "rgb")]
+    {
+        let mut config = hal::spim::Config::default();
+        config.frequency = hal::spim::Frequency::M4;
+        let spim = hal::spim::Spim::new_txonly_nosck(board.rgb_spi, Irqs, board.rgb, config);
+        spawner.must_spawn(rgb::rgb_task(spim));
+    }
+
+    // Another microcontroller on the edge connector's I2C pins can read the
+    // heading as from a CMPS12 or an HMC6352
+    #[cfg(feature = "compass-module")]
+    {
+        let mut config = hal::twis::Config::default();
+        config.address0 = cmps12::ADDRESS;
+        config.address1 = Some(hmc6352::ADDRESS);
+        let twis = hal::twis::Twis::new(
+            board.module_twis,
+            Irqs,
+            board.module_sda,
+            board.module_scl,
+            config,
+        );
+        spawner.must_spawn(compass_module::compass_module_task(twis));
+    }
+
+    // An RC servo on edge connector pin 12 steers towards the active bearing
+    #[cfg(all(feature = "servo", not(feature = "analog")))]
+    {
+        let servo = hal::pwm::SimplePwm::new_2ch(board.servo_pwm, board.servo, board.servo_right);
+        spawner.must_spawn(servo::servo_task(servo));
+    }
+    #[cfg(all(feature = "servo", feature = "analog"))]
+    {
+        let servo = hal::pwm::SimplePwm::new_3ch(
+            board.servo_pwm,
+            board.servo,
+            board.servo_right,
+            board.analog,
+        );
+        spawner.must_spawn(servo::servo_task(servo));
+    }
+
+    // The heading as a voltage on edge connector pin 15, through an RC filter
+    #[cfg(all(feature = "analog", not(feature = "servo")))]
+    {
+        let analog = hal::pwm::SimplePwm::new_1ch(board.analog_pwm, board.analog);
+        spawner.must_spawn(analog::analog_task(analog));
+    }
+
+    // The supply voltage is measured every minute, reported in telemetry and
+    // shown as an icon now and then when low
+    {
+        let channel = hal::saadc::ChannelConfig::single_ended(hal::saadc::VddInput);
+        let saadc = hal::saadc::Saadc::new(board.saadc, Irqs, Default::default(), [channel]);
+        spawner.must_spawn(battery::battery_task(saadc));
+    }
+    let mut low_battery_shown: Option<Instant> = None;
+
+    let mut storage = storage::Storage::new(hal::nvmc::Nvmc::new(board.nvmc));
+    let mut bearings = bearing::BearingMemory::load(&mut storage);
+    // While navigating to a waypoint the display points to it, using the
+    // bearing from the latest GPS fix
+    #[cfg(feature = "gps")]
+    let mut waypoints = waypoint::WaypointMemory::load(&mut storage);
+    #[cfg(feature = "gps")]
+    let mut position: Option<(geo::Position, Instant)> = None;
+    let mut profiles = profiles::Profiles::load(&mut storage);
+    let mut calibration = profiles.calibration();
+    // Parts of a calibration arriving over the console
+    let mut calibration_import = calibration::Import::new();
+    telemetry::CALIBRATION.signal(telemetry::CalibrationStatus {
+        calibrating: false,
+        calibration,
+    });
+    name::load(&mut storage);
+    panic::report_last_crash(&mut storage);
+    // Checked by the self-test below
+    let (mut settings, settings_check) = match settings::Settings::load(&mut storage) {
+        Ok(settings) => (settings, Ok(())),
+        Err(e) => (settings::Settings::DEFAULT, Err(e)),
+    };
+    // The mode, the stored bearing being followed and the units are saved as
+    // they change, so the board starts as it was left unless told otherwise
+    if let Some(mode) = settings.startup {
+        settings.display = mode;
+        bearings.deactivate();
+    }
+    #[cfg(feature = "servo")]
+    let mut autopilot = servo::Autopilot::new(settings.gains);
+
+    // The micro:bit radio broadcasts the heading to nearby boards, or receives
+    // one to show instead
+    spawner.must_spawn(radio::radio_task(hal::radio::ble::Radio::new(
+        board.radio,
+        Irqs,
+    )));
+    let mut remote_heading: Option<(f32, Instant)> = None;
+    let mut logger = logger::Logger::resume(&mut storage);
+    spawner.must_spawn(logger::dump_task());
+    // From here on flash is written in the background
+    spawner.must_spawn(storage::flash_task(storage));
+
+    // While the board lies still the magnetometer and the display slow down
+    // to save power
+    let mut motion = motion::MotionDetector::new();
+    let mut low_rate = false;
+    // Raised while a bearing is taken or a calibration runs
+    let mut burst = snapshot::Burst::new();
+    // and after a while unused they go idle until it is moved
+    let mut last_active = Instant::now();
+    let mut active_heading: Option<f32> = None;
+
+    // Holds the heading through strong acceleration
+    let mut motion_classifier = motion_class::MotionClassifier::new();
+    // From the magnetometer reading to the heading shown
+    let mut pipeline = pipeline::Pipeline::new();
+    apply_settings(
+        &settings,
+        &mut logger,
+        #[cfg(feature = "servo")]
+        &mut autopilot,
+        &mut pipeline,
+    );
+
+    // Shaking the board starts a magnetometer calibration
+    let mut shake = shake::ShakeDetector::new();
+    let mut calibrating: Option<wizard::Wizard> = None;
+    // or with auto-calibration on, the offsets are refined in the background
+    let mut auto_calibrator = calibration::AutoCalibrator::new();
+    #[cfg(all(
+        feature = "redundant-mag",
+        any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+    ))]
+    let mut cross_check = redundancy::CrossCheck::new();
+    let mut mount_detector = mounting::Detector::new();
+
+    // Steps are counted from the accelerometer, long-pressing B shows them
+    let mut pedometer = pedometer::Pedometer::new();
+    // and advance the dead-reckoned position, holding A + B resets it
+    let mut dead_reckoning = deadreckon::DeadReckoning::new();
+
+    // Rate of turn, reported in telemetry
+    let mut turn_rate = turn_rate::TurnRate::new();
+    // and the heading's spread every few seconds
+    let mut heading_stats = heading_stats::HeadingStats::new();
+    // Paces the loop, and reports how well it keeps time
+    let mut loop_timing = loop_timing::LoopTiming::new(PERIOD);
+    // Swings towards the heading at the damping rate
+    let mut needle = needle::Needle::new();
+    // Polls that found no new data, counted rather than logged one by one
+    let mut missed_data = missed_data::MissedData::new();
+
+    // Headings are only logged once they change, when a threshold is set
+    let mut heading_change = heading_change::HeadingChange::new();
+    // Bearing and pace count, set from the serial console
+    let mut orienteering = orienteering::Orienteering::new();
+    let mut survey = survey::Survey::new();
+    let mut surveying = false;
+    let mut bezel: Option<bezel::Bezel> = None;
+    // Anchor or course alarm on leaving the allowed headings
+    let mut heading_alarm = alarm::HeadingAlarm::new();
+
+    // Turntable accuracy check, started from the serial console
+    let mut bias_check: Option<bias_check::BiasCheck> = None;
+    // `sun check`: the heading against the sun's azimuth
+    let mut sun_check: Option<sun_check::SunCheck> = None;
+    let mut sighting: Option<sighting::Sighting> = None;
+
+    // Taps, falls and motion are reported on the sensor interrupt line
+    spawner.must_spawn(tap::tap_task(gpio::Input::new(
+        board.sensor_int,
+        gpio::Pull::Up,
+    )));
+
+    // Initialize LSM303AGR, once it has passed the power-on self-test
+    let mut pins = board.sensor_bus;
+    match selftest::run(&mut pins, settings_check).await {
+        Ok(()) => animation::show(&mut matrix, &animation::TICK, SELF_TEST_PASSED_MS).await,
+        Err(failure) => self_test_failed(failure, &mut matrix, &mut watchdog).await,
+    }
+    bus::start(pins).await;
+    #[cfg(feature = "oled")]
+    spawner.must_spawn(oled::oled_task());
+    let mut sensor = match sensor::start(&settings).await {
+        Ok(sensor) => sensor,
+        Err(e) => {
+            warn!("{}", e);
+            restart_sensor(&settings, &mut matrix, &mut watchdog).await
+        }
     };
-    sensor.mag_enable_low_pass_filter().await.unwrap();
 
     loop {
+        loop_timing.start();
+        watchdog.feed();
+        if let Ok(command) = console::COMMANDS.try_receive() {
+            trace!("command received");
+            last_active = Instant::now();
+            let result = match command {
+                console::Command::SetName(new_name) => {
+                    name::set(&new_name).then_some(()).ok_or("invalid name")
+                }
+                console::Command::EraseLog => {
+                    logger.erase();
+                    Ok(())
+                }
+                console::Command::SetLogMode(mode) => {
+                    info!("log mode: {}", mode);
+                    logger.set_mode(mode);
+                    settings.log_mode = mode;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetLogInterval(seconds) => {
+                    logger.set_interval(Duration::from_secs(seconds.into()));
+                    settings.log_interval_s = seconds;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::DumpLog => {
+                    logger.dump();
+                    Ok(())
+                }
+                console::Command::Hello => {
+                    capabilities::hello();
+                    Ok(())
+                }
+                console::Command::ReportCapabilities => {
+                    capabilities::report().await;
+                    Ok(())
+                }
+                console::Command::ReportPipeline => {
+                    pipeline.report().await;
+                    Ok(())
+                }
+                #[cfg(all(
+                    feature = "redundant-mag",
+                    any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+                ))]
+                console::Command::ReportRedundancy => {
+                    redundancy::report(&cross_check, sensor.magnetometers(), &settings).await;
+                    Ok(())
+                }
+                console::Command::ReportSettings => {
+                    settings.report().await;
+                    Ok(())
+                }
+                console::Command::ResetSettings => {
+                    info!("settings reset");
+                    settings = settings::Settings::DEFAULT;
+                    settings.save();
+                    apply_settings(
+                        &settings,
+                        &mut logger,
+                        #[cfg(feature = "servo")]
+                        &mut autopilot,
+                        &mut pipeline,
+                    );
+                    low_rate = false;
+                    burst.reset();
+                    match sensor
+                        .set_accel_rate(settings.accel_odr_hz, settings.accel_power)
+                        .await
+                    {
+                        Ok(()) => match set_mag_rate(&mut sensor, &settings, false).await {
+                            Ok(()) => match sensor.set_low_pass(settings.mag_low_pass).await {
+                                Ok(()) => sensor
+                                    .set_high_pass(settings.accel_high_pass)
+                                    .await
+                                    .map_err(|_| "failed to set accelerometer high-pass filter"),
+                                Err(_) => Err("failed to set magnetometer low-pass filter"),
+                            },
+                            Err(_) => Err("failed to set magnetometer odr"),
+                        },
+                        Err(_) => Err("failed to set accelerometer odr"),
+                    }
+                }
+                console::Command::SetDeclination(declination) => {
+                    settings.declination = declination;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetMagOdr(hz) => {
+                    match sensor.set_rate(hz, settings.mag_low_power).await {
+                        Ok(()) => {
+                            settings.mag_odr_hz = hz;
+                            settings.save();
+                            // Lying still it drops back to the low rate
+                            low_rate = false;
+                            burst.reset();
+                            Ok(())
+                        }
+                        Err(_) => Err("failed to set magnetometer odr"),
+                    }
+                }
+                console::Command::SetMagLowPower(low_power) => {
+                    match sensor.set_rate(settings.mag_odr_hz, low_power).await {
+                        Ok(()) => {
+                            settings.mag_low_power = low_power;
+                            settings.save();
+                            low_rate = false;
+                            burst.reset();
+                            Ok(())
+                        }
+                        Err(_) => Err("failed to set magnetometer power mode"),
+                    }
+                }
+                console::Command::SetMagLowPass(enabled) => {
+                    match sensor.set_low_pass(enabled).await {
+                        Ok(()) => {
+                            settings.mag_low_pass = enabled;
+                            settings.save();
+                            Ok(())
+                        }
+                        Err(_) => Err("failed to set magnetometer low-pass filter"),
+                    }
+                }
+                console::Command::SetTapHighPass(tap) => {
+                    let filter = sensor::AccelHighPass {
+                        tap,
+                        ..settings.accel_high_pass
+                    };
+                    match sensor.set_high_pass(filter).await {
+                        Ok(()) => {
+                            settings.accel_high_pass = filter;
+                            settings.save();
+                            Ok(())
+                        }
+                        Err(_) => Err("failed to set accelerometer high-pass filter"),
+                    }
+                }
+                console::Command::SetHighPassCutoff(cutoff) => {
+                    let filter = sensor::AccelHighPass {
+                        cutoff,
+                        ..settings.accel_high_pass
+                    };
+                    match sensor.set_high_pass(filter).await {
+                        Ok(()) => {
+                            settings.accel_high_pass = filter;
+                            settings.save();
+                            Ok(())
+                        }
+                        Err(_) => Err("failed to set accelerometer high-pass filter"),
+                    }
+                }
+                console::Command::SetVibrationRejection(enabled) => {
+                    settings.vibration_rejection = enabled;
+                    settings.save();
+                    pipeline.gravity.reset();
+                    Ok(())
+                }
+                console::Command::SetAccelOdr(hz) => {
+                    match sensor.set_accel_rate(hz, settings.accel_power).await {
+                        Ok(()) => {
+                            settings.accel_odr_hz = hz;
+                            settings.save();
+                            Ok(())
+                        }
+                        Err(_) => Err("failed to set accelerometer odr"),
+                    }
+                }
+                console::Command::SetAccelPower(power) => {
+                    match sensor.set_accel_rate(settings.accel_odr_hz, power).await {
+                        Ok(()) => {
+                            settings.accel_power = power;
+                            settings.save();
+                            Ok(())
+                        }
+                        Err(_) => Err("failed to set accelerometer power mode"),
+                    }
+                }
+                console::Command::StartCalibration => match calibrating {
+                    Some(_) => Err("already calibrating"),
+                    None if settings.tilt_only => Err("magnetometer unused in tilt mode"),
+                    None => {
+                        calibrating = Some(start_calibration(calibration));
+                        Ok(())
+                    }
+                },
+                console::Command::StartBiasCheck => match bias_check {
+                    Some(_) => Err("bias check running"),
+                    None if settings.tilt_only => Err("magnetometer unused in tilt mode"),
+                    None => {
+                        bias_check = Some(bias_check::BiasCheck::new());
+                        Ok(())
+                    }
+                },
+                console::Command::SetDeviation(point, correction) => {
+                    settings.deviation.set(point, correction);
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetAlarm(sector) => {
+                    settings.alarm = sector;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::ClearDeviation => {
+                    settings.deviation = DeviationTable::EMPTY;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetLocation(location) => {
+                    settings.location = location;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetTime(unix_seconds) => {
+                    clock::set(unix_seconds);
+                    Ok(())
+                }
+                console::Command::StartSunCheck => {
+                    #[cfg(feature = "gps")]
+                    let location = position
+                        .filter(|(_, at)| at.elapsed() < FIX_TIMEOUT)
+                        .map(|(fix, _)| [fix.latitude as f32, fix.longitude as f32])
+                        .or(settings.location);
+                    #[cfg(not(feature = "gps"))]
+                    let location = settings.location;
+                    match (clock::now(), location) {
+                        (None, _) => Err("time not set"),
+                        (_, None) => Err("location not set"),
+                        _ if settings.tilt_only => Err("magnetometer unused in tilt mode"),
+                        (Some(now), Some([latitude, longitude])) => {
+                            let sun = sun::position(now, latitude, longitude);
+                            if sun.elevation < sun_check::MIN_ELEVATION {
+                                Err("sun too low")
+                            } else {
+                                sun_check = Some(sun_check::SunCheck::new(sun));
+                                Ok(())
+                            }
+                        }
+                    }
+                }
+                console::Command::SetLeg(Some((bearing, paces))) => {
+                    orienteering.start(bearing, paces);
+                    Ok(())
+                }
+                console::Command::SetLeg(None) => match orienteering.stop() {
+                    true => Ok(()),
+                    false => Err("no leg running"),
+                },
+                console::Command::Race(console::RaceCommand::Start) => {
+                    race_timer::start();
+                    Ok(())
+                }
+                console::Command::Race(console::RaceCommand::Stop) => match race_timer::stop() {
+                    true => Ok(()),
+                    false => Err("no countdown running"),
+                },
+                console::Command::Race(console::RaceCommand::Sync) => match race_timer::sync() {
+                    true => Ok(()),
+                    false => Err("no countdown running"),
+                },
+                console::Command::Survey(console::SurveyCommand::Start) => {
+                    info!("survey started");
+                    survey = survey::Survey::new();
+                    surveying = true;
+                    Ok(())
+                }
+                console::Command::Survey(console::SurveyCommand::Stop) => {
+                    match core::mem::replace(&mut surveying, false) {
+                        true => {
+                            info!("survey stopped");
+                            Ok(())
+                        }
+                        false => Err("no survey running"),
+                    }
+                }
+                console::Command::Survey(console::SurveyCommand::List) => {
+                    survey.list().await;
+                    Ok(())
+                }
+                console::Command::StopSunCheck => match sun_check.take() {
+                    Some(_) => {
+                        info!("sun check stopped");
+                        Ok(())
+                    }
+                    None => Err("no sun check running"),
+                },
+                console::Command::StopBiasCheck => match bias_check.take() {
+                    Some(_) => {
+                        info!("bias check stopped");
+                        Ok(())
+                    }
+                    None => Err("no bias check running"),
+                },
+                console::Command::SetReplay(on) => {
+                    info!("replay: {}", on);
+                    replay::set_active(on);
+                    // The filter shouldn't mix live and replayed readings
+                    pipeline.set_median(settings.mag_median.into());
+                    Ok(())
+                }
+                console::Command::Reboot(target) => {
+                    console::reply(format_args!("ok"));
+                    reboot(sensor, &mut matrix, target).await
+                }
+                console::Command::SetUartLog(on) => {
+                    info!("defmt over uart: {}", on);
+                    log_bridge::set_mirroring(on);
+                    Ok(())
+                }
+                console::Command::SetDisplayReference(reference) => {
+                    settings.display_reference = reference;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetTelemetryReference(reference) => {
+                    settings.telemetry_reference = reference;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetOutputFormat(format) => {
+                    info!("output format: {}", format);
+                    telemetry::set_output_format(format);
+                    settings.output_format = format;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetAttitudeOutput(output) => {
+                    info!("attitude output: {}", output);
+                    telemetry::set_attitude_output(output, settings.attitude_rate_hz);
+                    settings.attitude = output;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetAttitudeRate(hz) => {
+                    telemetry::set_attitude_output(settings.attitude, hz);
+                    settings.attitude_rate_hz = hz;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetHeadingUnit(unit) => {
+                    info!("heading unit: {}", unit.name());
+                    telemetry::set_heading_unit(unit);
+                    settings.heading_unit = unit;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::ExportCalibration => {
+                    calibration.export().await;
+                    Ok(())
+                }
+                console::Command::ListProfiles => {
+                    profiles.list().await;
+                    Ok(())
+                }
+                console::Command::SelectProfile(name) => profiles.select(&name).map(|selected| {
+                    calibration = selected;
+                    auto_calibrator = calibration::AutoCalibrator::new();
+                    telemetry::CALIBRATION.signal(telemetry::CalibrationStatus {
+                        calibrating: false,
+                        calibration,
+                    });
+                }),
+                console::Command::DeleteProfile(name) => profiles.delete(&name),
+                console::Command::ImportCalibration(part) => match calibration_import.add(part) {
+                    Ok(Some(new_calibration)) => {
+                        info!("calibration imported");
+                        profiles.save(new_calibration);
+                        calibration = new_calibration;
+                        telemetry::CALIBRATION.signal(telemetry::CalibrationStatus {
+                            calibrating: false,
+                            calibration,
+                        });
+                        Ok(())
+                    }
+                    Ok(None) => Ok(()),
+                    Err(reason) => Err(reason),
+                },
+                console::Command::SetCalibration(new_calibration) => {
+                    info!("calibration received");
+                    profiles.save(new_calibration);
+                    calibration = new_calibration;
+                    telemetry::CALIBRATION.signal(telemetry::CalibrationStatus {
+                        calibrating: false,
+                        calibration,
+                    });
+                    Ok(())
+                }
+                console::Command::SetTiltOnly(tilt_only) => {
+                    info!("tilt only: {}", tilt_only);
+                    settings.tilt_only = tilt_only;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetAudioMode(mode) => {
+                    info!("audio mode: {}", mode);
+                    audio::set_audio_mode(mode);
+                    settings.audio = mode;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetRadioMode(mode) => {
+                    settings.radio = mode;
+                    settings.save();
+                    radio::CONFIG.signal((settings.radio, settings.radio_group));
+                    Ok(())
+                }
+                console::Command::SetRadioGroup(group) => {
+                    settings.radio_group = group;
+                    settings.save();
+                    radio::CONFIG.signal((settings.radio, settings.radio_group));
+                    Ok(())
+                }
+                #[cfg(feature = "gps")]
+                console::Command::AddWaypoint(waypoint) => {
+                    waypoints.add(waypoint);
+                    waypoints.save();
+                    Ok(())
+                }
+                #[cfg(feature = "gps")]
+                console::Command::AddWaypointHere => match position {
+                    Some((here, _)) => {
+                        waypoints.add(here);
+                        waypoints.save();
+                        Ok(())
+                    }
+                    None => Err("no gps fix"),
+                },
+                #[cfg(feature = "gps")]
+                console::Command::NextWaypoint => {
+                    waypoints.next();
+                    waypoints.save();
+                    Ok(())
+                }
+                #[cfg(feature = "gps")]
+                console::Command::ClearWaypoints => {
+                    waypoints.clear();
+                    waypoints.save();
+                    Ok(())
+                }
+                console::Command::SetBezel(on) => {
+                    bezel = on.then(bezel::Bezel::new);
+                    Ok(())
+                }
+                console::Command::ReportBezel => match bezel.as_ref().and_then(|b| b.bearing()) {
+                    Some(bearing) => {
+                        console::reply(format_args!("bezel {}", bearing));
+                        Ok(())
+                    }
+                    None => Err("bezel off"),
+                },
+                console::Command::ReportSync => match time_sync::status() {
+                    Some(status) => {
+                        console::reply(format_args!(
+                            "offset {} us, last beacon {} ms ago",
+                            status.offset_us,
+                            status.since_beacon.as_millis()
+                        ));
+                        Ok(())
+                    }
+                    None if time_sync::following() => Err("no beacon heard yet"),
+                    None => Err("not in radio sync mode"),
+                },
+                console::Command::ReportSteps => {
+                    console::reply(format_args!("steps {}", pedometer.steps()));
+                    Ok(())
+                }
+                console::Command::SetMagMedian(window) => {
+                    pipeline.set_median(window.into());
+                    settings.mag_median = window;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetMagAverage(samples) => {
+                    settings.mag_average = samples;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetAdaptiveRate(adaptive) => {
+                    settings.adaptive_rate = adaptive;
+                    settings.save();
+                    if low_rate && !adaptive {
+                        low_rate = false;
+                        burst.reset();
+                        set_mag_rate(&mut sensor, &settings, false)
+                            .await
+                            .map_err(|_| "failed to set magnetometer odr")
+                    } else {
+                        Ok(())
+                    }
+                }
+                console::Command::SetIdleTimeout(seconds) => {
+                    settings.idle_timeout_s = seconds;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetHeadingThreshold(degrees) => {
+                    settings.heading_threshold = degrees;
+                    settings.save();
+                    telemetry::set_heading_threshold(degrees);
+                    Ok(())
+                }
+                console::Command::SetHapticWindow(degrees) => {
+                    settings.haptic_window = degrees;
+                    settings.save();
+                    haptic::set_window(degrees);
+                    Ok(())
+                }
+                console::Command::SetRedundancyLimit(degrees) => {
+                    settings.redundancy_limit = degrees;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetBrightness(level) => {
+                    settings.brightness = level;
+                    settings.save();
+                    match level {
+                        Some(level) => animation::set_brightness(level),
+                        // Measured again on the next loop
+                        None => light_measured = None,
+                    }
+                    Ok(())
+                }
+                console::Command::SetRefreshRate(hz) => {
+                    settings.refresh_hz = hz;
+                    settings.save();
+                    display::set_refresh(hz);
+                    Ok(())
+                }
+                console::Command::SetDamping(rate) => {
+                    settings.damping = rate;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetHeartbeat(enabled) => {
+                    settings.heartbeat = enabled;
+                    settings.save();
+                    health::set_enabled(enabled);
+                    Ok(())
+                }
+                console::Command::SetAutoCalibration(enabled) => {
+                    settings.auto_calibration = enabled;
+                    settings.save();
+                    auto_calibrator = calibration::AutoCalibrator::new();
+                    Ok(())
+                }
+                console::Command::SetMounting(mounting) => {
+                    info!("mounting: {}", defmt::Display2Format(&mounting));
+                    settings.mounting = mounting;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetStride(stride) => {
+                    settings.stride_m = stride;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::ResetSteps => {
+                    pedometer.reset();
+                    Ok(())
+                }
+                console::Command::SetDisplayMode(mode) => {
+                    info!("display mode: {}", mode);
+                    settings.display = mode;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetStartup(mode) => {
+                    settings.startup = mode;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetGains(gains) => {
+                    settings.gains = gains;
+                    settings.save();
+                    #[cfg(feature = "servo")]
+                    autopilot.set_gains(gains);
+                    Ok(())
+                }
+                console::Command::SetSteering(steering) => {
+                    info!("steering: {}", steering);
+                    settings.steering = steering;
+                    settings.save();
+                    #[cfg(feature = "servo")]
+                    servo::set_steering(steering);
+                    Ok(())
+                }
+                console::Command::SetPalette(palette) => {
+                    info!("palette: {}", palette);
+                    settings.palette = palette;
+                    settings.save();
+                    Ok(())
+                }
+                console::Command::SetNightMode(mode) => {
+                    settings.night = mode;
+                    settings.save();
+                    // Automatic starts out of it until the light is measured,
+                    // on the next loop
+                    night::set(mode == settings::NightMode::On);
+                    light_measured = None;
+                    Ok(())
+                }
+            };
+            match result {
+                Ok(()) => console::reply(format_args!("ok")),
+                Err(reason) => console::reply(format_args!("error: {reason}")),
+            }
+        }
+
         // Read accelerometer data
-        let (accel_x, accel_y, accel_z) = if sensor.accel_status().await.unwrap().xyz_new_data() {
-            let accel = sensor.acceleration().await.unwrap();
-            (
-                accel.x_mg() as f32,
-                accel.y_mg() as f32,
-                accel.z_mg() as f32,
-            )
-        } else {
-            warn!("No new accelerometer data available");
-            continue;
+        let raw_accel = match health::watch(health::Task::Sensor, sensor.acceleration())
+            .await
+            .unwrap_or(Err(Error::Sensor(
+                error::SensorOp::ReadAccelerometer,
+                error::BusError::Stalled,
+            ))) {
+            Ok(Some(accel)) => {
+                missed_data.record(missed_data::Source::Accelerometer, true);
+                accel
+            }
+            Ok(None) => {
+                missed_data.record(missed_data::Source::Accelerometer, false);
+                continue;
+            }
+            Err(e) => {
+                warn!("{}, restarting the sensor", e);
+                health::report(health::Subsystem::Sensor, false);
+                sensor = restart_sensor(&settings, &mut matrix, &mut watchdog).await;
+                low_rate = false;
+                burst.reset();
+                continue;
+            }
+        };
+        // Everything worked out from this reading carries its time
+        let taken = Instant::now();
+        let mounting = match settings.mounting {
+            mounting::MountMode::Fixed(mounting) => mounting,
+            mounting::MountMode::Auto => mount_detector.update(raw_accel),
         };
+        // In the flat board frame, whichever way up it is mounted
+        let [accel_x, accel_y, accel_z] = mounting.apply(raw_accel);
+
+        if freefall::FREE_FALL.try_take().is_some() {
+            warn!("free fall detected");
+            // Dropped if the telemetry task has fallen behind
+            let _ = telemetry::EVENTS.try_send(telemetry::Event::FreeFall);
+            // Not at night, the whole matrix blinking would dazzle
+            if !night::active() {
+                ALERT.play(&mut matrix).await;
+            }
+        }
+
+        let stepped = pedometer.update([accel_x, accel_y, accel_z]);
+        if stepped {
+            trace!("step {}", pedometer.steps());
+        }
+        let moving = motion.update([accel_x, accel_y, accel_z]);
+        if settings.adaptive_rate && moving == low_rate {
+            low_rate = !moving;
+            info!("{} rate", if low_rate { "low" } else { "full" });
+            // A burst goes back to it when it ends
+            if !burst.active() {
+                if let Err(e) = set_mag_rate(&mut sensor, &settings, low_rate).await {
+                    warn!("{}", e);
+                }
+            }
+        }
 
-        // Read magnetometer data
-        let (mag_x, mag_y, mag_z) = if sensor.mag_status().await.unwrap().xyz_new_data() {
-            let data = sensor.magnetic_field().await.unwrap();
-            (data.x_nt() as f32, data.y_nt() as f32, data.z_nt() as f32)
-        } else {
-            warn!("No new magnetometer data available");
+        // Away from the magnetometer, show which side is up instead of a heading
+        if settings.tilt_only {
+            let tilt = tilt::Tilt::new([accel_x, accel_y, accel_z]);
+            telemetry::TILT.signal(telemetry::Timestamped { taken, value: tilt });
+            trace!("tilt published");
+            audio::TARGET_ERROR.signal(None);
+            audio::HEADING.signal(None);
+            haptic::TARGET_ERROR.signal(None);
+            #[cfg(feature = "servo")]
+            {
+                servo::COMMAND.signal(None);
+                autopilot.reset();
+            }
+            #[cfg(feature = "analog")]
+            analog::HEADING.signal(None);
+            #[cfg(feature = "compass-module")]
+            compass_module::update(compass_module::Reading {
+                heading: None,
+                pitch: tilt.pitch,
+                roll: tilt.roll,
+                calibrated: calibration.is_calibrated(),
+            });
+            heading_alarm.silence();
+            match tilt.orientation {
+                Orientation::LogoUp => display::arrow(&mut matrix, "N", &mounting).await,
+                Orientation::LogoDown => display::arrow(&mut matrix, "S", &mounting).await,
+                Orientation::LeftUp => display::arrow(&mut matrix, "W", &mounting).await,
+                Orientation::RightUp => display::arrow(&mut matrix, "E", &mounting).await,
+                Orientation::FaceUp | Orientation::FaceDown => {
+                    display::leds(&mut matrix, &[tilt.bubble()]).await
+                }
+            }
+            // Paced like a heading, at the low rate too while lying still
+            let period = if low_rate { LOW_RATE_PERIOD } else { PERIOD };
+            loop_timing.next(period).await;
             continue;
-        };
+        }
+
+        let wanted = sighting.is_some() || calibrating.is_some();
+        if let Err(e) = burst.hold(&mut sensor, wanted, &settings, low_rate).await {
+            warn!("{}", e);
+        }
 
-        // Compute tilt compensation
-        let heading = compute_heading(accel_x, accel_y, accel_z, mag_x, mag_y, mag_z);
-        let cardinal_direction = get_cardinal_direction(heading);
-        info!(
-            "Heading: {}.{:02}° ({})",
-            heading as i32,
-            (heading.fract() * 100.0) as i32,
-            cardinal_direction
+        // Strong linear acceleration throws the tilt off, and the heading
+        // with it. The gyroscope or the GPS course carry it through where
+        // there is one, otherwise the last trusted heading is held.
+        let motion_class = motion_classifier.update([accel_x, accel_y, accel_z]);
+        let mut frame = pipeline::Frame::new(
+            [accel_x, accel_y, accel_z],
+            pipeline::Inputs::new(motion_class),
         );
-        display_direction_on_led(&mut rows, &mut cols, cardinal_direction).await;
 
-        // Delay before next read
-        Delay.delay_ms(100).await;
+        // Read magnetometer data, several consecutive samples to average when
+        // configured, or while taking a bearing. At a high ODR they only take
+        // a few ms each.
+        let samples = usize::from(match sighting {
+            Some(_) if burst.active() => snapshot::BEARING_SAMPLES,
+            _ => settings.mag_average,
+        });
+        let mut failed = None;
+        while frame.samples().len() < samples {
+            let field = health::watch(health::Task::Sensor, sensor.magnetic_field())
+                .await
+                .unwrap_or(Err(Error::Sensor(
+                    error::SensorOp::ReadMagnetometer,
+                    error::BusError::Stalled,
+                )));
+            match field {
+                Ok(Some(field)) => {
+                    frame.push(field);
+                }
+                Ok(None) if frame.samples().is_empty() => break,
+                Ok(None) => Delay.delay_ms(MAG_POLL_MS).await,
+                Err(e) => {
+                    failed = Some(e);
+                    break;
+                }
+            }
+        }
+        if let Some(e) = failed {
+            warn!("{}, restarting the sensor", e);
+            health::report(health::Subsystem::Sensor, false);
+            sensor = restart_sensor(&settings, &mut matrix, &mut watchdog).await;
+            low_rate = false;
+            burst.reset();
+            continue;
+        }
+        missed_data.record(
+            missed_data::Source::Magnetometer,
+            !frame.samples().is_empty(),
+        );
+        if frame.samples().is_empty() {
+            continue;
+        }
+        // Calibrated in the sensor's own frame, so a calibration holds
+        // whichever way up the board is mounted
+        pipeline.filter(&mut frame);
+        let [mag_x, mag_y, mag_z] = frame.mag;
+        health::report(health::Subsystem::Sensor, true);
+        health::report(health::Subsystem::Calibration, calibration.is_calibrated());
+
+        if calibrating.is_none() && shake.update(accel_x, accel_y, accel_z) {
+            info!("shake detected, starting calibration");
+            calibrating = Some(start_calibration(calibration));
+        }
+
+        // While calibrating, the wizard takes the raw samples and the buttons
+        if let Some(wizard) = &mut calibrating {
+            heading_alarm.silence();
+            let button = buttons::BUTTONS.try_receive().ok();
+            if button.is_some() {
+                last_active = Instant::now();
+            }
+            let (pitch, roll) = compute_pitch_roll(accel_x, accel_y, accel_z);
+            displays(&mut matrix, &settings, mounting)
+                .show_status(&display::Status {
+                    pitch: pitch.to_degrees(),
+                    roll: roll.to_degrees(),
+                    calibrating: true,
+                    calibrated: calibration.is_calibrated(),
+                })
+                .await;
+            let result = match wizard.update([mag_x, mag_y, mag_z], button) {
+                wizard::Outcome::Continue => {
+                    wizard.show(&mut matrix).await;
+                    continue;
+                }
+                wizard::Outcome::Cancelled => {
+                    info!("calibration cancelled");
+                    None
+                }
+                wizard::Outcome::Finished(Some((new_calibration, quality))) => {
+                    // Dropped if the telemetry task has fallen behind
+                    let _ =
+                        telemetry::EVENTS.try_send(telemetry::Event::CalibrationQuality(quality));
+                    if quality.is_acceptable() {
+                        info!("calibration complete: {}", quality);
+                        profiles.save(new_calibration);
+                        calibration = new_calibration;
+                        Some((animation::TICK, Some(quality.score)))
+                    } else {
+                        warn!("calibration not kept: {}", quality);
+                        Some((animation::CROSS, Some(quality.score)))
+                    }
+                }
+                wizard::Outcome::Finished(None) => {
+                    warn!("calibration failed, rotate through all orientations");
+                    Some((animation::CROSS, None))
+                }
+            };
+            if let Some((frame, score)) = result {
+                animation::show(&mut matrix, &frame, wizard::RESULT_MS).await;
+                if let Some(score) = score {
+                    let mut displays = displays(&mut matrix, &settings, mounting);
+                    display::show_number(&mut displays, score.into()).await;
+                }
+            }
+            calibrating = None;
+            trace!("mode switched: heading");
+            telemetry::CALIBRATION.signal(telemetry::CalibrationStatus {
+                calibrating: false,
+                calibration,
+            });
+            continue;
+        }
+
+        let raw_mag = [mag_x, mag_y, mag_z];
+        if settings.auto_calibration && auto_calibrator.update(raw_mag, &mut calibration) {
+            profiles.save(calibration);
+            telemetry::CALIBRATION.signal(telemetry::CalibrationStatus {
+                calibrating: false,
+                calibration,
+            });
+        }
+
+        #[cfg(feature = "gyro")]
+        {
+            frame.input.rate = sensor.angular_rate().await.unwrap_or_else(|e| {
+                warn!("{}", e);
+                None
+            });
+            frame.input.still = !moving;
+        }
+        #[cfg(feature = "gps")]
+        {
+            frame.input.fix = gps::COURSE.try_take();
+            if let Some(fix) = frame.input.fix {
+                position = Some((fix.position, Instant::now()));
+            }
+        }
+        // A fault on the magnetometer in use hands the heading to the backup,
+        // with the calibration learned for it
+        #[cfg(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        ))]
+        let backup = cross_check.update(
+            raw_mag,
+            sensor.backup_field().await,
+            &calibration,
+            frame.accel,
+            mounting,
+            settings.redundancy_limit,
+        );
+        #[cfg(not(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        )))]
+        let backup: Option<([f32; 3], calibration::Calibration)> = None;
+        if let Some((mag, _)) = backup {
+            frame.mag = mag;
+        }
+        pipeline.run(
+            &mut frame,
+            &pipeline::Context {
+                settings: &settings,
+                calibration: backup
+                    .as_ref()
+                    .map_or(&calibration, |(_, calibration)| calibration),
+                mounting,
+            },
+        );
+        let heading = frame.heading;
+        let [grav_x, grav_y, grav_z] = frame.gravity;
+
+        if touch::LOGO_HELD.try_take().is_some() {
+            trace!("logo held received");
+            toggle_reference(&mut settings);
+        }
+        let magnetic_heading = heading;
+        if moving
+            || active_heading.is_none_or(|active| {
+                angle_diff(magnetic_heading, active).abs() > idle::HEADING_CHANGE
+            })
+        {
+            active_heading = Some(magnetic_heading);
+            last_active = Instant::now();
+        }
+        if let Some(check) = &mut bias_check {
+            if check.update(magnetic_heading) {
+                bias_check = None;
+            }
+        }
+        let true_heading = frame.true_heading;
+        if let Some(check) = &mut sun_check {
+            if check.update(true_heading) {
+                sun_check = None;
+            }
+        }
+        let referenced = |reference| match reference {
+            Reference::Magnetic => magnetic_heading,
+            Reference::True => true_heading,
+        };
+        let heading = referenced(settings.display_reference);
+        let alarm_active = heading_alarm.update(heading, settings.alarm);
+        if stepped {
+            dead_reckoning.step(true_heading, settings.stride_m);
+        }
+        let (pitch, roll) = compute_pitch_roll(grav_x, grav_y, grav_z);
+        let sample = telemetry::Sample {
+            heading: referenced(settings.telemetry_reference),
+            reference: settings.telemetry_reference,
+            display_reference: settings.display_reference,
+            magnetic_heading,
+            true_heading: (settings.declination != 0.0).then_some(true_heading),
+            pitch: pitch.to_degrees(),
+            roll: roll.to_degrees(),
+            accel: [accel_x, accel_y, accel_z],
+            mag: raw_mag,
+            steps: dead_reckoning.steps(),
+            position: dead_reckoning.position(),
+            rate_of_turn: turn_rate.update(magnetic_heading),
+            alarm: settings.alarm.map(|_| alarm_active),
+        };
+        telemetry::SAMPLE.signal(telemetry::Timestamped {
+            taken,
+            value: sample,
+        });
+        trace!("sample published");
+        #[cfg(feature = "compass-module")]
+        compass_module::update(compass_module::Reading {
+            heading: Some(sample.heading),
+            pitch: sample.pitch,
+            roll: sample.roll,
+            calibrated: calibration.is_calibrated(),
+        });
+        if let Some(stats) = heading_stats.update(sample.heading) {
+            // Dropped if the telemetry task has fallen behind
+            let _ = telemetry::EVENTS.try_send(telemetry::Event::HeadingStats(stats));
+        }
+        logger.log(
+            &telemetry::Timestamped {
+                taken,
+                value: sample,
+            },
+            moving,
+        );
+
+        if heading_change.update(heading, settings.heading_threshold) {
+            debug!(
+                "{}",
+                heading_log::HeadingRecord {
+                    heading,
+                    cardinal: get_cardinal_direction(heading),
+                    pitch: sample.pitch,
+                    roll: sample.roll,
+                    confidence: heading_log::confidence(
+                        [mag_x, mag_y, mag_z],
+                        &calibration,
+                        [grav_x, grav_y, grav_z]
+                    ),
+                }
+            );
+        }
+
+        if let Ok(event) = buttons::BUTTONS.try_receive() {
+            trace!("button {:?} received", event);
+            last_active = Instant::now();
+            use buttons::{Button, Press};
+            match (event.button, event.press) {
+                // During a bias check button A records a turntable position,
+                // during a sun check the heading towards the sun
+                (Button::A, Press::Click) => match (&mut bias_check, &mut sun_check) {
+                    (Some(check), _) => check.record(),
+                    (None, Some(check)) => check.record(),
+                    (None, None) => bearings.lock(heading),
+                },
+                (Button::B, Press::Click) => bearings.next(),
+                (Button::AB, Press::Click | Press::DoubleClick) => bearings.clear(),
+                (Button::A, Press::DoubleClick) => toggle_reference(&mut settings),
+                (Button::B, Press::DoubleClick) => {
+                    let mut displays = displays(&mut matrix, &settings, mounting);
+                    display::show_number(&mut displays, (heading + 0.5) as u32 % 360).await
+                }
+                (Button::AB, Press::Long) => {
+                    info!("dead reckoning reset");
+                    dead_reckoning.reset();
+                }
+                // Either way until changed again, overriding automatic
+                (Button::AB, Press::VeryLong) => {
+                    settings.night = if night::active() {
+                        settings::NightMode::Off
+                    } else {
+                        settings::NightMode::On
+                    };
+                    settings.save();
+                    night::set(settings.night == settings::NightMode::On);
+                }
+                (Button::B, Press::Long | Press::VeryLong) => {
+                    let mut displays = displays(&mut matrix, &settings, mounting);
+                    display::show_number(&mut displays, pedometer.steps()).await
+                }
+                (Button::A, Press::VeryLong) => power_off(sensor, &mut matrix).await,
+                // Unless it applies a learned heading offset, holding A takes
+                // a bearing
+                (Button::A, Press::Long) => {
+                    #[cfg(feature = "gps")]
+                    let confirmed = match pipeline.course.offset.confirm() {
+                        Some(offset) => {
+                            settings.course_offset = offset;
+                            settings.save();
+                            true
+                        }
+                        None => false,
+                    };
+                    #[cfg(not(feature = "gps"))]
+                    let confirmed = false;
+                    if !confirmed {
+                        sighting = Some(sighting::Sighting::new());
+                    }
+                }
+            }
+            let recorded =
+                event.button == Button::A && (bias_check.is_some() || sun_check.is_some());
+            if !recorded && event.press == Press::Click {
+                bearings.save();
+            }
+        }
+
+        if let Ok(tap) = tap::TAPS.try_receive() {
+            trace!("tap {:?} received", tap);
+            match tap {
+                tap::Tap::Single => bearings.next(),
+                tap::Tap::Double => bearings.lock(heading),
+            }
+            bearings.save();
+        }
+
+        if let Some(bezel) = &mut bezel {
+            bezel.update([accel_x, accel_y, accel_z], heading);
+        }
+
+        let sighted = sighting
+            .as_mut()
+            .and_then(|sighting| sighting.update(heading, moving));
+        if sighted.is_some() {
+            sighting = None;
+        }
+
+        // While following a stored bearing, point towards it rather than north
+        let target = bearings.active();
+        // A waypoint takes over from a stored bearing while the fix is fresh
+        #[cfg(feature = "gps")]
+        let target = match (waypoints.active(), position) {
+            (Some(waypoint), Some((here, at))) if at.elapsed() < FIX_TIMEOUT => {
+                let (bearing, distance) = here.bearing_and_distance(&waypoint);
+                debug!("waypoint {}m away, bearing {}°", distance, bearing);
+                // The bearing is from true north, like the display may not be
+                Some(match settings.display_reference {
+                    Reference::Magnetic => normalize_heading(bearing - settings.declination),
+                    Reference::True => bearing,
+                })
+            }
+            _ => target,
+        };
+        // and an orienteering leg from both
+        let leg = orienteering.update(heading, stepped);
+        let surveyed = surveying.then(|| survey.update([mag_x, mag_y, mag_z], heading, stepped));
+        let target = match leg {
+            Some(orienteering::Leg::Following { bearing, .. }) => Some(bearing),
+            _ => target,
+        };
+        radio::HEADING.signal(heading);
+        if let Some(remote) = radio::REMOTE_HEADING.try_take() {
+            remote_heading = Some((remote, Instant::now()));
+        }
+        let remote = remote_heading
+            .filter(|(_, at)| at.elapsed() < REMOTE_TIMEOUT)
+            .map(|(remote, _)| remote);
+        // Comparing headings with another board, point the way to turn to
+        // match it
+        let comparing = settings.radio == radio::RadioMode::Compare;
+        let target = match remote {
+            Some(remote) if comparing => Some(remote),
+            _ => target,
+        };
+        let matched =
+            comparing && remote.is_some_and(|remote| angle_diff(remote, heading).abs() <= MATCHED);
+        haptic::TARGET_ERROR.signal(Some(angle_diff(target.unwrap_or(0.0), heading)));
+        #[cfg(feature = "analog")]
+        analog::HEADING.signal(Some(heading));
+        #[cfg(feature = "servo")]
+        servo::COMMAND.signal(Some(autopilot.command(target, heading)));
+        let shown = match target {
+            Some(target) => {
+                let error = angle_diff(target, heading);
+                audio::TARGET_ERROR.signal(Some(error));
+                #[cfg(feature = "rgb")]
+                rgb::COLOR.signal(rgb::course_color(error));
+                normalize_heading(error)
+            }
+            None => {
+                audio::TARGET_ERROR.signal(None);
+                #[cfg(feature = "rgb")]
+                rgb::COLOR.signal(rgb::heading_color(settings.palette, heading));
+                heading
+            }
+        };
+        // In radio receive mode the display follows the other board instead
+        let shown = match remote {
+            Some(remote) if settings.radio == radio::RadioMode::Receive => remote,
+            _ => shown,
+        };
+        let night_auto = settings.night == settings::NightMode::Auto;
+        if (settings.brightness.is_none() || night_auto)
+            && light_measured.is_none_or(|at| at.elapsed() >= LIGHT_INTERVAL)
+        {
+            let level = light::sense(&mut light_sensor, &mut matrix, LIGHT_COL).await;
+            if settings.brightness.is_none() {
+                animation::set_brightness(level);
+            }
+            if night_auto {
+                night::follow(level);
+            }
+            light_measured = Some(Instant::now());
+        }
+        {
+            let mut displays = displays(&mut matrix, &settings, mounting);
+            let marker = bezel.as_ref().and_then(|bezel| bezel.marker(heading));
+            let drawn = health::watch(health::Task::Display, async {
+                displays.show_bezel(marker).await;
+                for _ in 0..display::frames_per_draw() {
+                    displays
+                        .draw_needle(needle.step(shown, settings.damping))
+                        .await;
+                }
+                displays
+                    .show_status(&display::Status {
+                        pitch: pitch.to_degrees(),
+                        roll: roll.to_degrees(),
+                        calibrating: false,
+                        calibrated: calibration.is_calibrated(),
+                    })
+                    .await;
+            })
+            .await;
+            // Nothing to set up again, the next loop draws from scratch
+            if drawn.is_none() {
+                warn!("needle not drawn");
+            }
+        }
+        // Flash a warning over the needle while the heading alarm goes off
+        if alarm_active {
+            animation::show(&mut matrix, &ALARM, ALARM_MS).await;
+        }
+        match leg {
+            Some(orienteering::Leg::Following {
+                remaining,
+                show_remaining: true,
+                ..
+            }) => {
+                let mut displays = displays(&mut matrix, &settings, mounting);
+                display::show_number(&mut displays, remaining).await;
+            }
+            Some(orienteering::Leg::Arrived) => {
+                audio::ARRIVED.signal(());
+                if !night::active() {
+                    animation::show(&mut matrix, &animation::TICK, ARRIVED_MS).await;
+                }
+            }
+            _ => {}
+        }
+        // A tick once lined up with the other board
+        if matched && !night::active() {
+            animation::show(&mut matrix, &animation::TICK, MATCHED_MS).await;
+        }
+        // A bearing once taken, or a cross if the board wouldn't keep still
+        match sighted {
+            Some(Ok(bearing)) => {
+                let mut displays = displays(&mut matrix, &settings, mounting);
+                display::show_number(&mut displays, bearing.degrees()).await;
+            }
+            Some(Err(_)) => {
+                animation::show(&mut matrix, &animation::CROSS, SIGHTING_FAILED_MS).await;
+            }
+            None => {}
+        }
+        // The field against its surroundings while surveying
+        match surveyed {
+            Some(survey::Reading { anomaly: true, .. }) if !night::active() => {
+                animation::show(&mut matrix, &survey::ANOMALY, ANOMALY_MS).await;
+            }
+            Some(survey::Reading { level, .. }) => {
+                animation::show(&mut matrix, &survey::bar(level), SURVEY_BAR_MS).await;
+            }
+            None => {}
+        }
+        // and the race countdown alongside it
+        if let Some(remaining) = race_timer::remaining() {
+            let digit = display::digit(race_timer::digit(remaining));
+            animation::show(&mut matrix, &digit, RACE_DIGIT_MS).await;
+        }
+        audio::HEADING.signal(Some(heading));
+        if let Some(strobe) = &mut strobe {
+            strobe.update(heading).await;
+        }
+
+        if battery::level().is_some_and(|battery| battery.is_low())
+            && !night::active()
+            && low_battery_shown.is_none_or(|at| at.elapsed() >= LOW_BATTERY_INTERVAL)
+        {
+            animation::show(&mut matrix, &LOW_BATTERY, LOW_BATTERY_MS).await;
+            low_battery_shown = Some(Instant::now());
+        }
+
+        let idle_timeout = Duration::from_secs(settings.idle_timeout_s.into());
+        if settings.idle_timeout_s != 0
+            && calibrating.is_none()
+            && bias_check.is_none()
+            && sun_check.is_none()
+            && !alarm_active
+            && race_timer::gun().is_none()
+            && !surveying
+            && last_active.elapsed() >= idle_timeout
+        {
+            go_idle(&mut sensor, &mut matrix, &mut watchdog).await;
+            last_active = Instant::now();
+            continue;
+        }
+
+        // Wait for the next read
+        let period = if low_rate { LOW_RATE_PERIOD } else { PERIOD };
+        loop_timing.next(period).await;
+    }
+}
+
+/// Everything the compass is shown on: the LED matrix, the board file's
+/// external matrix, and the OLED as well when it's built in
+fn displays<'a>(
+    matrix: &'a mut display::OnBoardLedMatrix,
+    settings: &settings::Settings,
+    mounting: mounting::Mounting,
+) -> impl display::CompassDisplay + use<'a> {
+    let matrix = display::Matrix::new(matrix, settings.display, mounting);
+    // First, so it starts on text while the 5x5 matrix shows it a digit at a
+    // time
+    let matrix = (display::ExternalMatrix, matrix);
+    #[cfg(feature = "oled")]
+    let matrix = (matrix, oled::Oled);
+    matrix
+}
+
+/// Time from the start of one loop to the next, about 5 Hz, the display
+/// taking half of it
+const PERIOD: Duration = Duration::from_millis(200);
+
+/// Loop period while the board lies still, about 2 Hz
+const LOW_RATE_PERIOD: Duration = Duration::from_millis(500);
+
+/// Magnetometer ODR while the board lies still, Hz
+const LOW_RATE_ODR_HZ: u16 = 10;
+
+/// How often the magnetometer is polled for the next sample to average
+const MAG_POLL_MS: u32 = 2;
+
+/// Waypoint navigation stops this long after the last GPS fix
+#[cfg(feature = "gps")]
+const FIX_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Column of the LED matrix sensing the ambient light, and how often it does
+const LIGHT_COL: usize = 2;
+const LIGHT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A received heading is shown until this long after the last one arrived
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Degrees from the other board's heading that count as lined up with it in
+/// radio compare mode, and how long the tick saying so is shown each loop
+const MATCHED: f32 = 3.0;
+const MATCHED_MS: u32 = 100;
+
+/// Hand the settings to the tasks and state they configure, at startup and
+/// when they are reset. The sensor's rates and filters are set apart, as
+/// setting them can fail.
+fn apply_settings(
+    settings: &settings::Settings,
+    logger: &mut logger::Logger,
+    #[cfg(feature = "servo")] autopilot: &mut servo::Autopilot,
+    pipeline: &mut pipeline::Pipeline,
+) {
+    telemetry::set_output_format(settings.output_format);
+    telemetry::set_heading_unit(settings.heading_unit);
+    telemetry::set_attitude_output(settings.attitude, settings.attitude_rate_hz);
+    telemetry::set_heading_threshold(settings.heading_threshold);
+    haptic::set_window(settings.haptic_window);
+    health::set_enabled(settings.heartbeat);
+    audio::set_audio_mode(settings.audio);
+    // Full until the first light reading when automatic
+    animation::set_brightness(settings.brightness.unwrap_or(animation::GREY_LEVELS));
+    night::set(settings.night == settings::NightMode::On);
+    display::set_refresh(settings.refresh_hz);
+    #[cfg(feature = "servo")]
+    {
+        servo::set_steering(settings.steering);
+        autopilot.set_gains(settings.gains);
+    }
+    #[cfg(feature = "gps")]
+    pipeline.course.offset.set_offset(settings.course_offset);
+    radio::CONFIG.signal((settings.radio, settings.radio_group));
+    logger.set_mode(settings.log_mode);
+    logger.set_interval(Duration::from_secs(settings.log_interval_s.into()));
+    pipeline.set_median(settings.mag_median.into());
+}
+
+/// Drop the magnetometer to its lowest rate and power while the board lies
+/// still, or restore the configured ODR and power mode. The accelerometer
+/// stays at its configured rate, which tap and free-fall detection depend on.
+async fn set_mag_rate(
+    sensor: &mut sensor::Sensor,
+    settings: &settings::Settings,
+    low_rate: bool,
+) -> Result<(), Error> {
+    if low_rate {
+        sensor.set_rate(LOW_RATE_ODR_HZ, true).await
+    } else {
+        sensor
+            .set_rate(settings.mag_odr_hz, settings.mag_low_power)
+            .await
     }
 }
 
-fn compute_heading(
-    accel_x: f32,
-    accel_y: f32,
-    accel_z: f32,
-    mag_x: f32,
-    mag_y: f32,
-    mag_z: f32,
-) -> f32 {
-    // Normalize accelerometer values
-    let accel_norm = (accel_x * accel_x + accel_y * accel_y + accel_z * accel_z).sqrt();
-    let ax = accel_x / accel_norm;
-    let ay = accel_y / accel_norm;
-    let az = accel_z / accel_norm;
-
-    // Compute pitch and roll angles
-    let pitch = (-ax).asin();
-    let roll = ay.atan2(az);
-
-    // Tilt compensation
-    let mag_xh = mag_x * roll.cos() + mag_y * roll.sin() * pitch.sin() - mag_z * pitch.cos();
-    let mag_yh = mag_y * pitch.cos() + mag_z * pitch.sin();
-
-    // Comput heading using atan2
-    let mut heading = mag_yh.atan2(mag_xh).to_degrees();
-
-    // Convert range from -180 to 180 into 0 to 360
-    if heading < 0.0 {
-        heading += 360.0;
-    }
-
-    heading
+/// How long the cross is shown while the sensor is being restarted
+const SENSOR_ERROR_MS: u32 = 500;
+
+/// How long the tick is shown once the power-on self-test has passed
+const SELF_TEST_PASSED_MS: u32 = 500;
+
+/// Stop after a failed power-on self-test, showing a cross then the
+/// number of the failed check, and for a self-test the axes that failed
+/// it, over and over until the board is reset.
+/// Corrupt settings are replaced by the defaults, so the next boot gets past
+/// them.
+async fn self_test_failed(
+    failure: selftest::Failure,
+    matrix: &mut display::OnBoardLedMatrix,
+    watchdog: &mut watchdog::Watchdog,
+) -> ! {
+    warn!("self-test failed: {}", failure);
+    if failure.check == selftest::Check::Settings {
+        settings::Settings::DEFAULT.save();
+    }
+    loop {
+        animation::show(matrix, &animation::CROSS, SENSOR_ERROR_MS).await;
+        let mut display = display::Matrix::new(
+            matrix,
+            settings::DisplayMode::Arrow,
+            mounting::Mounting::FLAT,
+        );
+        display::show_number(&mut display, failure.check as u32).await;
+        if let Some(axes) = failure.axes_frame() {
+            animation::show(matrix, &axes, SENSOR_ERROR_MS).await;
+        }
+        watchdog.feed();
+    }
 }
 
-/// Map heading to the four main cardinal directions (N, E, S, W)
-fn get_cardinal_direction(heading: f32) -> &'static str {
-    match heading {
-        h if h >= 315.0 || h < 45.0 => "N",
-        h if h >= 45.0 && h < 135.0 => "E",
-        h if h >= 135.0 && h < 225.0 => "S",
-        h if h >= 225.0 && h < 315.0 => "W",
-        _ => "?", // Fallback (should never happen)
+/// Recover the I2C bus and configure the sensor again, showing a cross before
+/// every attempt, until it responds
+async fn restart_sensor(
+    settings: &settings::Settings,
+    matrix: &mut display::OnBoardLedMatrix,
+    watchdog: &mut watchdog::Watchdog,
+) -> sensor::Sensor {
+    loop {
+        animation::show(matrix, &animation::CROSS, SENSOR_ERROR_MS).await;
+        watchdog.feed();
+        bus::recover().await;
+        match sensor::start(settings).await {
+            Ok(sensor) => {
+                info!("sensor restarted");
+                return sensor;
+            }
+            Err(e) => warn!("{}", e),
+        }
     }
 }
 
-/// Display an arrow on the LED matrix for N, E, S, W
-async fn display_direction_on_led(
-    rows: &mut [gpio::Output<'_>; 5],
-    cols: &mut [gpio::Output<'_>; 5],
-    direction: &str,
+/// Wait after waking for the interrupt line to settle, so the motion that
+/// woke the board isn't taken for a tap or a fall
+const WAKE_SETTLE: Duration = Duration::from_millis(500);
+
+/// Blank the display and put the magnetometer in idle mode until the board is
+/// moved, a button is pressed or a console command arrives. The button press
+/// only wakes the board, commands are carried out afterwards.
+async fn go_idle(
+    sensor: &mut sensor::Sensor,
+    matrix: &mut display::OnBoardLedMatrix,
+    watchdog: &mut watchdog::Watchdog,
 ) {
-    let arrow = match direction {
-        // North: Arrow pointing up
-        "N" => [(0, 2), (1, 1), (1, 2), (1, 3), (2, 2), (3, 2), (4, 2)],
-        // South: Arrow pointing down
-        "S" => [(0, 2), (1, 2), (2, 2), (3, 1), (3, 2), (3, 3), (4, 2)],
-        // East: Arrow pointing right
-        "E" => [(2, 0), (2, 1), (2, 2), (2, 3), (2, 4), (1, 3), (3, 3)],
-        // West: Arrow pointing left
-        "W" => [(2, 0), (1, 1), (3, 1), (2, 1), (2, 2), (2, 3), (2, 4)],
-        _ => [(2, 2), (2, 2), (2, 2), (2, 2), (2, 2), (2, 2), (2, 2)], // Default center dot
-    };
+    info!("going idle");
+    animation::blank(matrix);
+    if let Err(e) = sensor.set_wake_on_motion(true).await {
+        warn!("no wake on motion: {}", e);
+    }
+    #[cfg(feature = "gyro")]
+    if let Err(e) = sensor.set_asleep(true).await {
+        warn!("{}", e);
+    }
+    idle::ACTIVITY.reset();
 
-    // Turn off all LEDs before updating
-    for row in rows.iter_mut() {
-        row.set_low();
+    match sensor.sleep().await {
+        Ok(()) => {
+            wait_for_wake(watchdog).await;
+            while let Err(e) = sensor.wake().await {
+                warn!("{}, retrying", e);
+                watchdog.feed();
+                Timer::after(WAKE_SETTLE).await;
+            }
+        }
+        Err(e) => {
+            warn!("{}", e);
+            wait_for_wake(watchdog).await;
+        }
+    }
+
+    if let Err(e) = sensor.set_wake_on_motion(false).await {
+        warn!("wake on motion left on: {}", e);
+    }
+    #[cfg(feature = "gyro")]
+    if let Err(e) = sensor.set_asleep(false).await {
+        warn!("{}", e);
+    }
+    Timer::after(WAKE_SETTLE).await;
+    tap::TAPS.clear();
+    freefall::FREE_FALL.reset();
+    info!("awake");
+}
+
+/// Power the sensor down, let queued flash writes finish and enter SYSTEM
+/// OFF until button A is pressed
+async fn power_off(sensor: sensor::Sensor, matrix: &mut display::OnBoardLedMatrix) -> ! {
+    info!("powering off");
+    shut_down(sensor, matrix).await;
+    sleep::system_off()
+}
+
+/// Reset into the firmware again or the bootloader, with the sensors and
+/// display shut down as for [`power_off`], and the radio too
+async fn reboot(
+    sensor: sensor::Sensor,
+    matrix: &mut display::OnBoardLedMatrix,
+    target: reboot::Target,
+) -> ! {
+    // Let the reply go out first
+    Timer::after(REBOOT_REPLY_DELAY).await;
+    radio::CONFIG.signal((radio::RadioMode::Off, 0));
+    shut_down(sensor, matrix).await;
+    reboot::reset(target)
+}
+
+/// Time for the telemetry task to send the reply to `reboot`
+const REBOOT_REPLY_DELAY: Duration = Duration::from_millis(100);
+
+/// Blank the display, put the sensors in their lowest power state, and let
+/// flash writes finish, before the board is powered off or reset
+async fn shut_down(mut sensor: sensor::Sensor, matrix: &mut display::OnBoardLedMatrix) {
+    animation::blank(matrix);
+    if let Err(e) = sensor.power_down().await {
+        warn!("{}", e);
+    }
+    if let Err(e) = sensor.sleep().await {
+        warn!("{}", e);
     }
-    for col in cols.iter_mut() {
-        col.set_low();
+    #[cfg(feature = "gyro")]
+    if let Err(e) = sensor.set_asleep(true).await {
+        warn!("{}", e);
     }
+    storage::sync().await;
+}
+
+/// How often the watchdog is fed while idle
+const IDLE_FEED_INTERVAL: Duration = Duration::from_secs(1);
 
-    // Light up the LEDs based on the selected pattern
-    for &(row, col) in arrow.iter() {
-        rows[row].set_high();
-        cols[col].set_high();
+async fn wait_for_wake(watchdog: &mut watchdog::Watchdog) {
+    loop {
+        match select4(
+            idle::ACTIVITY.wait(),
+            buttons::BUTTONS.ready_to_receive(),
+            console::COMMANDS.ready_to_receive(),
+            Timer::after(IDLE_FEED_INTERVAL),
+        )
+        .await
+        {
+            Either4::First(_) | Either4::Third(_) => return,
+            Either4::Second(_) => {
+                let _ = buttons::BUTTONS.try_receive();
+                return;
+            }
+            Either4::Fourth(_) => watchdog.feed(),
+        }
     }
+}
 
-    // Small delay for visibility
-    Delay.delay_ms(100).await;
+/// Switch the display between magnetic and true north
+fn toggle_reference(settings: &mut settings::Settings) {
+    settings.display_reference = match settings.display_reference {
+        Reference::Magnetic => Reference::True,
+        Reference::True => Reference::Magnetic,
+    };
+    settings.save();
+    info!(
+        "display reference: {}",
+        settings::reference_name(settings.display_reference)
+    );
 }
+
+/// Begin collecting samples for a magnetometer calibration
+fn start_calibration(calibration: calibration::Calibration) -> wizard::Wizard {
+    trace!("mode switched: calibrating");
+    telemetry::CALIBRATION.signal(telemetry::CalibrationStatus {
+        calibrating: true,
+        calibration,
+    });
+    wizard::Wizard::new()
+}
+
+/// The whole matrix blinks three times after a fall
+const ALERT: Animation = Animation {
+    frames: &[([0b11111; 5], 150), ([0; 5], 150)],
+    easing: Easing::Linear,
+    repeat: Repeat::Times(3),
+};
+
+/// Boot splash: a needle sweeping clockwise from north, slowing down as it
+/// comes round, then the compass rose
+const SPLASH: Animation = Animation {
+    frames: &[
+        ([0b00100, 0b00100, 0b00100, 0b00000, 0b00000], 60),
+        ([0b00001, 0b00010, 0b00100, 0b00000, 0b00000], 60),
+        ([0b00000, 0b00000, 0b00111, 0b00000, 0b00000], 60),
+        ([0b00000, 0b00000, 0b00100, 0b00010, 0b00001], 60),
+        ([0b00000, 0b00000, 0b00100, 0b00100, 0b00100], 60),
+        ([0b00000, 0b00000, 0b00100, 0b01000, 0b10000], 60),
+        ([0b00000, 0b00000, 0b11100, 0b00000, 0b00000], 60),
+        ([0b10000, 0b01000, 0b00100, 0b00000, 0b00000], 60),
+    ],
+    easing: Easing::EaseOut,
+    repeat: Repeat::Once,
+};
+const SPLASH_ROSE: Frame = [0b10101, 0b01110, 0b11111, 0b01110, 0b10101];
+const SPLASH_ROSE_MS: u32 = 400;
+
+/// Battery outline, empty
+const LOW_BATTERY: Frame = [0b00100, 0b01110, 0b01010, 0b01010, 0b01110];
+
+/// How long the low battery icon is shown, and how often
+const LOW_BATTERY_MS: u32 = 1000;
+
+/// An exclamation mark, flashed after the needle each loop while the heading
+/// alarm goes off
+const ALARM: Frame = [0b00100, 0b00100, 0b00100, 0b00000, 0b00100];
+const ALARM_MS: u32 = 100;
+
+/// How long the tick is shown on reaching the end of an orienteering leg
+const ARRIVED_MS: u32 = 1000;
+
+/// How long the race countdown's digit is shown after the needle each loop
+const RACE_DIGIT_MS: u32 = 100;
+
+/// How long the cross is shown when a bearing couldn't be taken
+const SIGHTING_FAILED_MS: u32 = 1000;
+
+/// How long the survey's bar is shown after the needle each loop
+const SURVEY_BAR_MS: u32 = 60;
+
+/// How long a survey anomaly is shown in place of the bar
+const ANOMALY_MS: u32 = 400;
+const LOW_BATTERY_INTERVAL: Duration = Duration::from_secs(30);
@@ -0,0 +1,270 @@
+use core::cell::RefCell;
+
+use embassy_nrf::{
+    gpio::{Pin as _, Port},
+    gpiote::Channel as _,
+    pac::{
+        self,
+        gpiote::vals as gpiote_vals,
+        pwm::vals as pwm_vals,
+        shared::{regs::Psel, vals::Connect},
+        timer::vals as timer_vals,
+    },
+    peripherals::{PWM2, PWM3, TIMER3},
+    ppi::Channel as _,
+};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+use crate::{
+    board::{MatrixScan, MATRIX_COLS, MATRIX_ROWS},
+    led_matrix::Pins,
+};
+
+/// How long each row is lit per pass, µs, and the PWM period, at 1 MHz
+pub const ROW_US: u16 = 2000;
+
+/// Time at the end of each row with every column off, µs, so the next row
+/// never comes on with the last row's columns
+const BLANK_US: u16 = 20;
+
+/// How long each LED is lit in each pass, µs, by row and column
+pub type Duties = [[u16; MATRIX_COLS]; MATRIX_ROWS];
+
+/// The columns' PWM values for a pass, one step per row, the four channels
+/// of a PWM each
+type Sequence = [[u16; 4]; MATRIX_ROWS];
+
+/// A compare register of TIMER3 for each row, and a channel of one of the
+/// two PWMs for each column
+const _: () = assert!(MATRIX_ROWS <= 6 && MATRIX_COLS <= 8);
+
+static SCANNER: Mutex<CriticalSectionRawMutex, RefCell<Option<Scanner>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Scans the LED matrix with no help from the CPU once started, so a frame
+/// held on the display costs no wakeups and shows without jitter, however
+/// busy the executor is.
+///
+/// The columns are driven by PWM: two PWMs, for the first four columns and
+/// the rest, play a sequence of a step per row on a loop, each column low,
+/// and its LED lit, for the LED's duty at the start of the step. At the end of each step PPI has a timer count it, and the timer's
+/// compare events, through PPI again, have GPIOTE switch the rows over.
+///
+/// The pins stay with their GPIO drivers: the scan takes them over only
+/// while it runs, from the first [`start`] until [`stop`]. Starting it again
+/// meanwhile only switches it over to the new duties.
+struct Scanner {
+    /// Held so nothing else can have them, only used through their registers
+    _peripherals: (TIMER3, PWM2, PWM3),
+    /// Pin and port of each row
+    rows: [(u8, bool); MATRIX_ROWS],
+    cols: [Psel; MATRIX_COLS],
+    gpiote: [usize; MATRIX_ROWS],
+    ppi: [usize; MATRIX_ROWS + 1],
+    /// Read by the PWMs' EasyDMA, so kept in RAM
+    first_cols: Sequence,
+    last_cols: Sequence,
+    running: bool,
+}
+
+/// Set up the PWMs, the timer and PPI, ready for [`start`]. Takes the pins
+/// before they are made outputs.
+pub fn init(peripherals: MatrixScan, pins: &Pins<MATRIX_ROWS, MATRIX_COLS>) {
+    let MatrixScan {
+        timer,
+        pwm,
+        pwm_last,
+        gpiote,
+        ppi,
+    } = peripherals;
+    let gpiote = gpiote.map(|channel| channel.number());
+    let ppi = ppi.map(|channel| channel.number());
+    let peripherals = (timer, pwm, pwm_last);
+
+    let timer = pac::TIMER3;
+    timer
+        .mode()
+        .write(|w| w.set_mode(timer_vals::Mode::COUNTER));
+    timer
+        .bitmode()
+        .write(|w| w.set_bitmode(timer_vals::Bitmode::_16BIT));
+    // Row n comes on after n steps, row 0 again after a step per row
+    for n in 0..MATRIX_ROWS {
+        timer.cc(n).write_value(n as u32 + 1);
+    }
+    timer
+        .shorts()
+        .write(|w| w.set_compare_clear(MATRIX_ROWS - 1, true));
+
+    let address = |ptr: *mut u32| ptr as u32;
+    pac::PPI
+        .ch(ppi[0])
+        .eep()
+        .write_value(address(pac::PWM2.events_pwmperiodend().as_ptr()));
+    pac::PPI
+        .ch(ppi[0])
+        .tep()
+        .write_value(address(timer.tasks_count().as_ptr()));
+    for n in 0..MATRIX_ROWS {
+        let channel = ppi[n + 1];
+        pac::PPI
+            .ch(channel)
+            .eep()
+            .write_value(address(timer.events_compare(n).as_ptr()));
+        pac::PPI.ch(channel).tep().write_value(address(
+            pac::GPIOTE
+                .tasks_set(gpiote[(n + 1) % MATRIX_ROWS])
+                .as_ptr(),
+        ));
+        pac::PPI
+            .fork(channel)
+            .tep()
+            .write_value(address(pac::GPIOTE.tasks_clr(gpiote[n]).as_ptr()));
+    }
+
+    let scanner = Scanner {
+        _peripherals: peripherals,
+        rows: pins
+            .rows
+            .each_ref()
+            .map(|pin| (pin.pin(), pin.port() == Port::Port1)),
+        cols: pins.cols.each_ref().map(|pin| pin.psel_bits()),
+        gpiote,
+        ppi,
+        first_cols: [[0; 4]; MATRIX_ROWS],
+        last_cols: [[0; 4]; MATRIX_ROWS],
+        running: false,
+    };
+    SCANNER.lock(|current| {
+        let mut current = current.borrow_mut();
+        let scanner = current.insert(scanner);
+        for (pwm, sequence) in [
+            (pac::PWM2, &scanner.first_cols),
+            (pac::PWM3, &scanner.last_cols),
+        ] {
+            configure(pwm, sequence);
+        }
+    });
+}
+
+/// The PWM settings that stay the same from one frame to the next
+fn configure(pwm: pac::pwm::Pwm, sequence: &Sequence) {
+    pwm.enable().write(|w| w.set_enable(true));
+    pwm.mode().write(|w| w.set_updown(pwm_vals::Updown::UP));
+    pwm.prescaler()
+        .write(|w| w.set_prescaler(pwm_vals::Prescaler::DIV_16));
+    pwm.countertop().write(|w| w.set_countertop(ROW_US));
+    pwm.decoder().write(|w| {
+        w.set_load(pwm_vals::Load::INDIVIDUAL);
+        w.set_mode(pwm_vals::Mode::REFRESH_COUNT);
+    });
+    // Both sequences are the same one, played back to back for good
+    for n in 0..2 {
+        let seq = pwm.seq(n);
+        seq.ptr().write_value(sequence.as_ptr() as u32);
+        seq.cnt()
+            .write(|w| w.set_cnt(pwm_vals::CntCnt::from_bits((sequence.len() * 4) as u16)));
+        seq.refresh()
+            .write(|w| w.set_cnt(pwm_vals::RefreshCnt::from_bits(0)));
+        seq.enddelay().write(|w| w.set_cnt(0));
+    }
+    pwm.loop_()
+        .write(|w| w.set_cnt(pwm_vals::LoopCnt::from_bits(1)));
+    pwm.shorts().write(|w| w.set_loopsdone_seqstart0(true));
+    for channel in 0..4 {
+        pwm.psel().out(channel).write_value(disconnected());
+    }
+}
+
+/// Start scanning `duties` out, `false` without a [`init`]ed scanner. A scan
+/// already running carries on, from its next pass with `duties` in place of
+/// the last ones, so a run of frames shows without a gap between them.
+pub fn start(duties: &Duties) -> bool {
+    SCANNER.lock(|scanner| {
+        let mut scanner = scanner.borrow_mut();
+        let Some(scanner) = scanner.as_mut() else {
+            return false;
+        };
+        for (row, duties) in duties.iter().enumerate() {
+            // Low for the duty, then high. Columns are active low.
+            // Past the last column the channel is disconnected
+            let duty = |col: usize| {
+                duties
+                    .get(col)
+                    .map_or(0, |&duty| duty.min(ROW_US - BLANK_US))
+            };
+            scanner.first_cols[row] = core::array::from_fn(duty);
+            scanner.last_cols[row] = core::array::from_fn(|col| duty(col + 4));
+        }
+        if scanner.running {
+            return true;
+        }
+        scanner.running = true;
+
+        let timer = pac::TIMER3;
+        timer.tasks_clear().write_value(1);
+        timer.tasks_start().write_value(1);
+        for (row, (&channel, &(pin, port))) in scanner.gpiote.iter().zip(&scanner.rows).enumerate()
+        {
+            pac::GPIOTE.config(channel).write(|w| {
+                w.set_mode(gpiote_vals::Mode::TASK);
+                w.set_psel(pin);
+                w.set_port(port);
+                w.set_polarity(gpiote_vals::Polarity::NONE);
+                w.set_outinit(if row == 0 {
+                    gpiote_vals::Outinit::HIGH
+                } else {
+                    gpiote_vals::Outinit::LOW
+                });
+            });
+        }
+        for (n, &col) in scanner.cols.iter().enumerate() {
+            let pwm = if n < 4 { pac::PWM2 } else { pac::PWM3 };
+            pwm.psel().out(n % 4).write_value(col);
+        }
+        for &channel in &scanner.ppi {
+            pac::PPI.chenset().write(|w| w.set_ch(channel, true));
+        }
+        // Back to back, so they run a few cycles apart at most
+        pac::PWM2.tasks_seqstart(0).write_value(1);
+        pac::PWM3.tasks_seqstart(0).write_value(1);
+        true
+    })
+}
+
+/// Stop scanning, if it is, and hand the pins back to their GPIO drivers.
+/// Safe from the panic handler too, even if the panic came while starting.
+pub fn stop() {
+    SCANNER.lock(|scanner| {
+        if let Ok(Some(scanner)) = scanner.try_borrow_mut().as_deref_mut() {
+            if scanner.running {
+                release(&scanner.gpiote, &scanner.ppi);
+                scanner.running = false;
+            }
+        }
+    });
+}
+
+fn release(gpiote: &[usize; MATRIX_ROWS], ppi: &[usize; MATRIX_ROWS + 1]) {
+    for &channel in ppi {
+        pac::PPI.chenclr().write(|w| w.set_ch(channel, true));
+    }
+    for pwm in [pac::PWM2, pac::PWM3] {
+        pwm.tasks_stop().write_value(1);
+        for channel in 0..4 {
+            pwm.psel().out(channel).write_value(disconnected());
+        }
+    }
+    pac::TIMER3.tasks_stop().write_value(1);
+    for &channel in gpiote {
+        pac::GPIOTE
+            .config(channel)
+            .write(|w| w.set_mode(gpiote_vals::Mode::DISABLED));
+    }
+}
+
+fn disconnected() -> Psel {
+    let mut psel = Psel(0);
+    psel.set_connect(Connect::DISCONNECTED);
+    psel
+}
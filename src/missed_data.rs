@@ -0,0 +1,108 @@
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Instant};
+
+use crate::health;
+
+/// How often the counts are summarized
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Longest a sensor may go with no new data before it counts as starved,
+/// well past its slowest data rate
+const STARVATION: Duration = Duration::from_secs(1);
+
+/// A sensor whose data-ready status the main loop polls
+#[derive(Clone, Copy, Format)]
+pub enum Source {
+    Accelerometer,
+    Magnetometer,
+}
+
+impl Source {
+    const ALL: [Self; 2] = [Self::Accelerometer, Self::Magnetometer];
+}
+
+/// Polls of one [`Source`] since the last report
+#[derive(Clone, Copy)]
+struct Counts {
+    polls: u32,
+    missed: u32,
+    /// Since when every poll has come up empty
+    empty_since: Option<Instant>,
+    /// The starvation has been reported
+    starved: bool,
+}
+
+impl Counts {
+    const EMPTY: Self = Self {
+        polls: 0,
+        missed: 0,
+        empty_since: None,
+        starved: false,
+    };
+}
+
+/// Counts the polls that find no new data, which happen now and then when
+/// the loop runs a little ahead of a sensor, instead of warning about each
+/// one. A summary goes out over defmt every [`REPORT_INTERVAL`] when any
+/// were missed, and a sensor with no new data for [`STARVATION`] is reported
+/// to [`health`] as failing, until the loop gets a whole reading again.
+pub struct MissedData {
+    counts: [Counts; Source::ALL.len()],
+    since: Instant,
+}
+
+impl MissedData {
+    pub fn new() -> Self {
+        Self {
+            counts: [Counts::EMPTY; Source::ALL.len()],
+            since: Instant::now(),
+        }
+    }
+
+    /// A poll of `source`, which had new data or not
+    pub fn record(&mut self, source: Source, new_data: bool) {
+        let counts = &mut self.counts[source as usize];
+        counts.polls += 1;
+        if new_data {
+            if counts.starved {
+                info!("missed data: {} back", source);
+            }
+            counts.empty_since = None;
+            counts.starved = false;
+        } else {
+            counts.missed += 1;
+            let since = *counts.empty_since.get_or_insert_with(Instant::now);
+            if !counts.starved && since.elapsed() >= STARVATION {
+                warn!(
+                    "missed data: no new {} data for {} ms",
+                    source,
+                    since.elapsed().as_millis()
+                );
+                counts.starved = true;
+                health::report(health::Subsystem::Sensor, false);
+            }
+        }
+
+        if self.since.elapsed() >= REPORT_INTERVAL {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        for source in Source::ALL {
+            let counts = &mut self.counts[source as usize];
+            if counts.missed > 0 {
+                warn!(
+                    "missed data: {} of {} {} polls in {} s had no new data",
+                    counts.missed,
+                    counts.polls,
+                    source,
+                    self.since.elapsed().as_secs()
+                );
+            }
+            counts.polls = 0;
+            counts.missed = 0;
+        }
+        self.since = Instant::now();
+    }
+}
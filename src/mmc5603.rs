@@ -0,0 +1,123 @@
+use embedded_hal_async::i2c::I2c;
+
+use crate::{
+    error::{Error, SensorOp},
+    sensor::{Bus, Magnetometer},
+};
+
+const ADDRESS: u8 = 0x30;
+
+const PRODUCT_ID: u8 = 0x39;
+const ID: u8 = 0x10;
+
+/// Bits 19 to 4 of X, Y and Z big-endian, then bits 3 to 0 of each in the
+/// top of one more register
+const OUT: u8 = 0x00;
+const STATUS_1: u8 = 0x18;
+const ODR: u8 = 0x1A;
+const INTERNAL_CONTROL_0: u8 = 0x1B;
+const INTERNAL_CONTROL_1: u8 = 0x1C;
+const INTERNAL_CONTROL_2: u8 = 0x1D;
+
+/// A measurement has finished, set again by each one in continuous mode
+const MEAS_M_DONE: u8 = 0b0100_0000;
+
+/// Rate from the ODR register, with the sensor set and reset automatically
+const CMM_FREQ_EN: u8 = 0x80;
+const AUTO_SR_EN: u8 = 0x20;
+/// 6.6 ms measurements, fast enough for up to 75 Hz
+const BW_6_6_MS: u8 = 0x00;
+const CMM_EN: u8 = 0x10;
+
+/// Offset of the unsigned 20-bit output
+const ZERO: i32 = 1 << 19;
+/// nT per LSB, 16384 LSB/gauss
+const NT_PER_LSB: f32 = 100_000.0 / 16_384.0;
+
+/// Fastest rate at [`BW_6_6_MS`], and the rate while the board lies still
+const MAX_HZ: u16 = 75;
+const LOW_POWER_HZ: u16 = 10;
+
+/// A MEMSIC MMC5603 magnetometer on the shared bus
+pub struct Mmc5603 {
+    i2c: Bus,
+}
+
+impl Mmc5603 {
+    /// `None` unless an MMC5603 answers, otherwise configured to measure
+    /// continuously at `hz`
+    pub async fn probe(mut i2c: Bus, hz: u16) -> Result<Option<Self>, Error> {
+        let mut id = [0];
+        if i2c
+            .write_read(ADDRESS, &[PRODUCT_ID], &mut id)
+            .await
+            .is_err()
+            || id[0] != ID
+        {
+            return Ok(None);
+        }
+        let mut mag = Self { i2c };
+        mag.write(
+            INTERNAL_CONTROL_1,
+            BW_6_6_MS,
+            SensorOp::ConfigureMagnetometer,
+        )
+        .await?;
+        mag.set_rate(hz, false).await?;
+        Ok(Some(mag))
+    }
+
+    async fn write(&mut self, register: u8, value: u8, op: SensorOp) -> Result<(), Error> {
+        self.i2c
+            .write(ADDRESS, &[register, value])
+            .await
+            .map_err(Error::bus(op))
+    }
+}
+
+impl Magnetometer for Mmc5603 {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        let op = SensorOp::ReadMagnetometer;
+        let mut status = [0];
+        self.i2c
+            .write_read(ADDRESS, &[STATUS_1], &mut status)
+            .await
+            .map_err(Error::bus(op))?;
+        if status[0] & MEAS_M_DONE == 0 {
+            return Ok(None);
+        }
+        let mut buf = [0u8; 9];
+        self.i2c
+            .write_read(ADDRESS, &[OUT], &mut buf)
+            .await
+            .map_err(Error::bus(op))?;
+        Ok(Some([0, 1, 2].map(|axis| {
+            let raw = (buf[2 * axis] as i32) << 12
+                | (buf[2 * axis + 1] as i32) << 4
+                | (buf[6 + axis] as i32) >> 4;
+            (raw - ZERO) as f32 * NT_PER_LSB
+        })))
+    }
+
+    /// 1 to 75 Hz
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error> {
+        let hz = if low_power {
+            LOW_POWER_HZ
+        } else {
+            hz.clamp(1, MAX_HZ)
+        };
+        let op = SensorOp::SetRate;
+        self.write(ODR, hz as u8, op).await?;
+        self.write(INTERNAL_CONTROL_0, CMM_FREQ_EN | AUTO_SR_EN, op)
+            .await?;
+        self.write(INTERNAL_CONTROL_2, CMM_EN, op).await
+    }
+
+    async fn sleep(&mut self) -> Result<(), Error> {
+        self.write(INTERNAL_CONTROL_2, 0, SensorOp::Idle).await
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        self.write(INTERNAL_CONTROL_2, CMM_EN, SensorOp::Wake).await
+    }
+}
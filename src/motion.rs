@@ -0,0 +1,41 @@
+use embassy_time::{Duration, Instant};
+
+/// Change in acceleration between samples, on any axis, that counts as
+/// moving, mg
+const MOTION_THRESHOLD_MG: f32 = 80.0;
+
+/// Still counts as moving this long after the last jolt, so brief pauses
+/// don't register as stopping
+const MOTION_HOLD: Duration = Duration::from_secs(10);
+
+/// Tells whether the board is being moved from changes between accelerometer
+/// samples
+pub struct MotionDetector {
+    last_accel: Option<[f32; 3]>,
+    last_motion: Option<Instant>,
+}
+
+impl MotionDetector {
+    pub const fn new() -> Self {
+        Self {
+            last_accel: None,
+            last_motion: None,
+        }
+    }
+
+    /// Feed raw acceleration in mg, returning whether the board has moved
+    /// within the last [`MOTION_HOLD`]
+    pub fn update(&mut self, accel: [f32; 3]) -> bool {
+        let jolted = self.last_accel.is_some_and(|last| {
+            last.iter()
+                .zip(accel)
+                .any(|(last, now)| (now - last).abs() > MOTION_THRESHOLD_MG)
+        });
+        self.last_accel = Some(accel);
+        if jolted {
+            self.last_motion = Some(Instant::now());
+        }
+        self.last_motion
+            .is_some_and(|since| since.elapsed() < MOTION_HOLD)
+    }
+}
@@ -0,0 +1,109 @@
+use defmt::{debug, Format};
+use embassy_time::{Duration, Instant};
+use micromath::F32Ext;
+
+const GRAVITY_MG: f32 = 1000.0;
+
+/// Share of the linear acceleration level kept from one reading to the
+/// next, so a single jolt keeps the class up for a few readings
+const DECAY: f32 = 0.7;
+
+/// Linear acceleration level below which the board counts as still, mg
+const STILL_MG: f32 = 30.0;
+
+/// Level above which the accelerometer can no longer be trusted for the
+/// tilt, and below which it can again, mg
+const DYNAMIC_MG: f32 = 200.0;
+const CALM_MG: f32 = 120.0;
+
+/// Longest a heading is held for. Past this the motion is taken to be how
+/// the board is carried, e.g. running, and the live heading is shown again.
+const MAX_HOLD: Duration = Duration::from_secs(3);
+
+/// How the board is moving, from how far the acceleration's magnitude strays
+/// from 1 g
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum MotionClass {
+    Still,
+    /// Handled or carried, the accelerometer still gives the tilt
+    Moving,
+    /// Accelerating hard, e.g. running or a vehicle turning, so the tilt and
+    /// the heading worked out from it are off
+    Dynamic,
+}
+
+pub struct MotionClassifier {
+    /// Linear acceleration, the largest recent one decaying away, mg
+    level: f32,
+    class: MotionClass,
+}
+
+impl MotionClassifier {
+    pub const fn new() -> Self {
+        Self {
+            level: 0.0,
+            class: MotionClass::Still,
+        }
+    }
+
+    /// Feed acceleration in mg
+    pub fn update(&mut self, accel: [f32; 3]) -> MotionClass {
+        let magnitude = (accel[0] * accel[0] + accel[1] * accel[1] + accel[2] * accel[2]).sqrt();
+        self.level = (magnitude - GRAVITY_MG).abs().max(self.level * DECAY);
+        let dynamic = match self.class {
+            MotionClass::Dynamic => self.level > CALM_MG,
+            _ => self.level > DYNAMIC_MG,
+        };
+        let class = if dynamic {
+            MotionClass::Dynamic
+        } else if self.level < STILL_MG {
+            MotionClass::Still
+        } else {
+            MotionClass::Moving
+        };
+        if class != self.class {
+            debug!("motion: {}", class);
+            self.class = class;
+        }
+        class
+    }
+}
+
+/// Holds the last trusted heading while the board is [`MotionClass::Dynamic`]
+/// and nothing else, a gyroscope or the GPS course, carries it through
+pub struct HeadingHold {
+    trusted: Option<f32>,
+    /// When the current hold started
+    since: Option<Instant>,
+}
+
+impl HeadingHold {
+    pub const fn new() -> Self {
+        Self {
+            trusted: None,
+            since: None,
+        }
+    }
+
+    /// The heading to use in place of `heading`. `covered` says another
+    /// source has already taken over from the tilt-compensated magnetometer,
+    /// so the heading can be trusted whatever the motion.
+    pub fn update(&mut self, heading: f32, class: MotionClass, covered: bool) -> f32 {
+        if class != MotionClass::Dynamic || covered {
+            self.trusted = Some(heading);
+            self.since = None;
+            return heading;
+        }
+        let Some(trusted) = self.trusted else {
+            return heading;
+        };
+        let since = *self.since.get_or_insert_with(|| {
+            debug!("holding the heading at {}", trusted);
+            Instant::now()
+        });
+        if since.elapsed() > MAX_HOLD {
+            return heading;
+        }
+        trusted
+    }
+}
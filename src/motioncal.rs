@@ -0,0 +1,83 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::calibration::Calibration;
+
+/// Length of the calibration packet MotionCal sends: 2 header bytes, 16
+/// floats, CRC-16
+pub const PACKET_LEN: usize = 68;
+
+/// First two bytes of a calibration packet
+pub const PACKET_HEADER: [u8; 2] = [117, 84];
+
+/// MotionCal expects raw accelerometer counts at 8192 per g
+const ACCEL_COUNTS_PER_MG: f32 = 8.192;
+
+/// and raw magnetometer counts at 0.1 µT
+const NT_PER_MAG_COUNT: f32 = 100.0;
+
+/// One `Raw:` line as read by MotionCal, from raw acceleration (mg) and
+/// magnetic field (nT). There is no gyroscope, its fields are zero.
+pub fn raw_line(accel: [f32; 3], mag: [f32; 3]) -> String<96> {
+    let [ax, ay, az] = accel.map(|a| (a * ACCEL_COUNTS_PER_MG) as i32);
+    let [mx, my, mz] = mag.map(|m| (m / NT_PER_MAG_COUNT) as i32);
+    let mut line = String::new();
+    // Fits comfortably, a failed write would only truncate the line
+    let _ = write!(line, "Raw:{ax},{ay},{az},0,0,0,{mx},{my},{mz}\r\n");
+    line
+}
+
+/// The `Cal1:` and `Cal2:` lines MotionCal reads back to confirm a calibration
+/// it sent was received
+pub fn calibration_lines(calibration: &Calibration) -> String<192> {
+    let [ox, oy, oz] = calibration.offset().map(|o| o / 1000.0);
+    let mut lines = String::new();
+    let _ = write!(
+        lines,
+        "Cal1:0.000,0.000,0.000,0.000,0.000,0.000,{ox:.3},{oy:.3},{oz:.3},{:.3}\r\nCal2:",
+        calibration.field_strength() / 1000.0
+    );
+    for (i, value) in calibration.soft_iron().iter().flatten().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let _ = write!(lines, "{separator}{value:.4}");
+    }
+    let _ = write!(lines, "\r\n");
+    lines
+}
+
+/// Decode a calibration packet: accelerometer offsets (3), gyroscope offsets
+/// (3), magnetometer offsets in µT (3), field strength in µT, then the
+/// symmetric soft-iron matrix as its diagonal (3) and the `xy`, `xz`, `yz`
+/// terms, all little-endian `f32`. Returns `None` if the CRC does not match.
+pub fn parse_packet(packet: &[u8; PACKET_LEN]) -> Option<Calibration> {
+    if packet[..2] != PACKET_HEADER || crc16(packet) != 0 {
+        return None;
+    }
+    let float = |i: usize| {
+        let at = 2 + i * 4;
+        f32::from_le_bytes([packet[at], packet[at + 1], packet[at + 2], packet[at + 3]])
+    };
+
+    let offset = [float(6), float(7), float(8)].map(|o| o * 1000.0);
+    let field_strength = float(9) * 1000.0;
+    let [xx, yy, zz, xy, xz, yz]: [f32; 6] = core::array::from_fn(|i| float(10 + i));
+    let soft_iron = [[xx, xy, xz], [xy, yy, yz], [xz, yz, zz]];
+    Some(Calibration::new(offset, soft_iron, field_strength))
+}
+
+/// CRC-16 (polynomial 0xA001, initial value 0xFFFF) as used by MotionCal.
+/// Over a whole packet, CRC included, it comes out as zero.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |mut crc, &byte| {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
@@ -0,0 +1,280 @@
+use core::fmt;
+
+use defmt::info;
+use embassy_time::{Duration, Instant};
+use micro_compass_core::protocol::Orientation;
+
+/// How long a new side has to stay up before the automatic mounting follows
+/// it, so a wobble through 45° doesn't flip the display back and forth
+const SETTLE: Duration = Duration::from_secs(1);
+
+/// Share of the acceleration along the side facing up needed to switch to
+/// it, about 35° of tilt
+const MIN_UP_SHARE: f32 = 0.8;
+
+/// A fixed mounting, or one picked from which side faces up
+#[derive(Clone, Copy, PartialEq)]
+pub enum MountMode {
+    Fixed(Mounting),
+    Auto,
+}
+
+/// Set in a settings word for [`MountMode::Auto`], past the 9 bits of a
+/// [`Mounting`]
+const AUTO_BIT: u32 = 1 << 9;
+
+/// How the board is mounted, as the sensor axis (and its sign) that each axis
+/// of the flat board frame reads from: X to the right, Y towards the logo and
+/// Z out of the display. Only 90° rotations, so each sensor axis is used once.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Mounting {
+    axes: [Axis; 3],
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Axis {
+    /// 0 to 2 for X, Y, Z
+    index: u8,
+    negate: bool,
+}
+
+impl Axis {
+    const fn new(index: u8, negate: bool) -> Self {
+        Self { index, negate }
+    }
+}
+
+impl Mounting {
+    /// Lying flat, display up
+    pub const FLAT: Self = Self {
+        axes: [
+            Axis::new(0, false),
+            Axis::new(1, false),
+            Axis::new(2, false),
+        ],
+    };
+
+    /// Lying flat, display down, logo still ahead
+    pub const UPSIDE_DOWN: Self = Self {
+        axes: [Axis::new(0, true), Axis::new(1, false), Axis::new(2, true)],
+    };
+
+    /// Standing on the edge connector with the display towards the user,
+    /// heading where the back of the board faces
+    pub const VERTICAL: Self = Self {
+        axes: [Axis::new(0, false), Axis::new(2, true), Axis::new(1, false)],
+    };
+
+    /// Standing on the logo edge, display towards the user
+    const VERTICAL_LOGO_DOWN: Self = Self {
+        axes: [Axis::new(0, true), Axis::new(2, true), Axis::new(1, true)],
+    };
+
+    /// Standing on the left edge, display towards the user
+    const VERTICAL_LEFT_DOWN: Self = Self {
+        axes: [Axis::new(1, true), Axis::new(2, true), Axis::new(0, false)],
+    };
+
+    /// Standing on the right edge, display towards the user
+    const VERTICAL_RIGHT_DOWN: Self = Self {
+        axes: [Axis::new(1, false), Axis::new(2, true), Axis::new(0, true)],
+    };
+
+    /// Parse a preset name, or three axes such as `x -z y`
+    pub fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let first = words.next()?;
+        let mounting = match first {
+            "flat" => Self::FLAT,
+            "upside-down" => Self::UPSIDE_DOWN,
+            "vertical" => Self::VERTICAL,
+            _ => Self {
+                axes: [
+                    parse_axis(first)?,
+                    parse_axis(words.next()?)?,
+                    parse_axis(words.next()?)?,
+                ],
+            },
+        };
+        if words.next().is_some() {
+            return None;
+        }
+        let [a, b, c] = mounting.axes.map(|axis| axis.index);
+        (a != b && b != c && a != c).then_some(mounting)
+    }
+
+    /// Rotate a sensor reading into the flat board frame
+    pub fn apply(&self, reading: [f32; 3]) -> [f32; 3] {
+        self.axes.map(|axis| {
+            let value = reading[axis.index as usize];
+            if axis.negate {
+                -value
+            } else {
+                value
+            }
+        })
+    }
+
+    /// Where to light a matrix LED so a picture drawn for a flat board looks
+    /// the same to someone facing the display: its right is the board's X
+    /// axis, its top the Y axis if the display lies flat or the Z axis if it
+    /// stands upright. Unchanged if neither lies in the display.
+    pub fn screen(&self, (row, col): (usize, usize)) -> (usize, usize) {
+        let right = self.axes[0];
+        let up = if self.axes[2].index == 2 {
+            self.axes[1]
+        } else {
+            self.axes[2]
+        };
+        if right.index == 2 || up.index == 2 {
+            return (row, col);
+        }
+        let sign = |axis: Axis| if axis.negate { -1 } else { 1 };
+        // In LEDs from the center, sensor X towards the right edge and Y
+        // towards the logo
+        let mut sensor = [0i32; 2];
+        sensor[right.index as usize] += sign(right) * (col as i32 - 2);
+        sensor[up.index as usize] += sign(up) * (2 - row as i32);
+        let [x, y] = sensor;
+        ((2 - y) as usize, (x + 2) as usize)
+    }
+
+    /// Packed into a settings word, 3 bits per axis
+    pub fn to_bits(self) -> u32 {
+        self.axes.iter().enumerate().fold(0, |bits, (i, axis)| {
+            bits | ((axis.index as u32) | (axis.negate as u32) << 2) << (3 * i)
+        })
+    }
+
+    /// `None` for bits that aren't a valid mounting
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        let axis = |i: u32| {
+            let bits = bits >> (3 * i);
+            Axis::new((bits & 0b11) as u8, bits & 0b100 != 0)
+        };
+        let mounting = Self {
+            axes: [axis(0), axis(1), axis(2)],
+        };
+        let [a, b, c] = mounting.axes.map(|axis| axis.index);
+        let valid = a < 3 && b < 3 && c < 3 && a != b && b != c && a != c;
+        (valid && bits >> 9 == 0).then_some(mounting)
+    }
+}
+
+impl MountMode {
+    /// Parse `auto`, or a fixed mounting as [`Mounting::parse`] does
+    pub fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let first = words.next()?;
+        if first == "auto" {
+            return words.next().is_none().then_some(Self::Auto);
+        }
+        Mounting::parse(core::iter::once(first).chain(words)).map(Self::Fixed)
+    }
+
+    pub fn to_bits(self) -> u32 {
+        match self {
+            Self::Fixed(mounting) => mounting.to_bits(),
+            Self::Auto => AUTO_BIT,
+        }
+    }
+
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        if bits == AUTO_BIT {
+            Some(Self::Auto)
+        } else {
+            Mounting::from_bits(bits).map(Self::Fixed)
+        }
+    }
+}
+
+impl fmt::Display for MountMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(mounting) => mounting.fmt(f),
+            Self::Auto => f.write_str("auto"),
+        }
+    }
+}
+
+/// Follows which side of the board faces up for [`MountMode::Auto`]: flat
+/// with the display up or down, or upright with the display towards the user
+/// on any of its edges, e.g. worn on a lanyard
+pub struct Detector {
+    current: Mounting,
+    /// A different mounting the board has been in since the given time
+    candidate: Option<(Mounting, Instant)>,
+}
+
+impl Detector {
+    pub const fn new() -> Self {
+        Self {
+            current: Mounting::FLAT,
+            candidate: None,
+        }
+    }
+
+    /// From raw acceleration in mg, the mounting to apply
+    pub fn update(&mut self, accel: [f32; 3]) -> Mounting {
+        // Squared, along all axes and along the one gravity pulls on most
+        let total = accel.iter().map(|a| a * a).sum::<f32>();
+        let up = accel.iter().map(|a| a * a).fold(0.0, f32::max);
+        // Too close to between two sides, or in free fall
+        if total == 0.0 || up < MIN_UP_SHARE * MIN_UP_SHARE * total {
+            self.candidate = None;
+            return self.current;
+        }
+
+        let mounting = match crate::tilt::orientation(accel) {
+            Orientation::FaceUp => Mounting::FLAT,
+            Orientation::FaceDown => Mounting::UPSIDE_DOWN,
+            Orientation::LogoUp => Mounting::VERTICAL,
+            Orientation::LogoDown => Mounting::VERTICAL_LOGO_DOWN,
+            Orientation::LeftUp => Mounting::VERTICAL_RIGHT_DOWN,
+            Orientation::RightUp => Mounting::VERTICAL_LEFT_DOWN,
+        };
+        if mounting == self.current {
+            self.candidate = None;
+            return self.current;
+        }
+        match self.candidate {
+            Some((candidate, since)) if candidate == mounting => {
+                if since.elapsed() >= SETTLE {
+                    info!("mounting now {}", defmt::Display2Format(&mounting));
+                    self.current = mounting;
+                    self.candidate = None;
+                }
+            }
+            _ => self.candidate = Some((mounting, Instant::now())),
+        }
+        self.current
+    }
+}
+
+fn parse_axis(word: &str) -> Option<Axis> {
+    let (negate, name) = match word.strip_prefix('-') {
+        Some(name) => (true, name),
+        None => (false, word),
+    };
+    let index = match name {
+        "x" => 0,
+        "y" => 1,
+        "z" => 2,
+        _ => return None,
+    };
+    Some(Axis::new(index, negate))
+}
+
+/// As the axes `mount` takes, e.g. `x -z y`
+impl fmt::Display for Mounting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, axis) in self.axes.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            if axis.negate {
+                f.write_str("-")?;
+            }
+            f.write_str(["x", "y", "z"][axis.index as usize])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use embedded_hal_async::i2c::I2c;
+
+use crate::{
+    error::{Error, SensorOp},
+    sensor::{Bus, Gyroscope},
+};
+
+/// With AD0 low
+const ADDRESS: u8 = 0x68;
+
+const WHO_AM_I: u8 = 0x75;
+const ID: u8 = 0x68;
+
+const SMPLRT_DIV: u8 = 0x19;
+const CONFIG: u8 = 0x1A;
+const GYRO_CONFIG: u8 = 0x1B;
+const INT_STATUS: u8 = 0x3A;
+/// X, Y and Z, big-endian
+const GYRO_XOUT_H: u8 = 0x43;
+const PWR_MGMT_1: u8 = 0x6B;
+/// The accelerometer is never read
+const PWR_MGMT_2: u8 = 0x6C;
+
+/// New data is ready
+const DATA_RDY_INT: u8 = 0b0000_0001;
+
+/// Clocked from the X gyro's PLL, and asleep if `SLEEP` is added
+const CLKSEL_PLL_X: u8 = 0x01;
+const SLEEP: u8 = 0x40;
+/// Accelerometer X, Y and Z on standby
+const STBY_ACCEL: u8 = 0b0011_1000;
+/// 5 Hz low-pass filter, so a reading averages the time since the main
+/// loop's last one, at a 1 kHz sample rate divided down to 50 Hz
+const DLPF_5HZ: u8 = 6;
+const SAMPLE_RATE_DIVIDER: u8 = 19;
+/// ±250 °/s
+const FS_250_DPS: u8 = 0x00;
+
+/// °/s per LSB at ±250 °/s
+const DPS_PER_LSB: f32 = 1.0 / 131.0;
+
+/// An InvenSense MPU-6050 gyroscope on the shared bus, e.g. on a GY-521
+/// breakout
+pub struct Mpu6050 {
+    i2c: Bus,
+}
+
+impl Mpu6050 {
+    /// `None` unless an MPU-6050 answers, otherwise configured to measure
+    pub async fn probe(mut i2c: Bus) -> Result<Option<Self>, Error> {
+        let mut id = [0];
+        if i2c.write_read(ADDRESS, &[WHO_AM_I], &mut id).await.is_err() || id[0] != ID {
+            return Ok(None);
+        }
+        let mut gyro = Self { i2c };
+        for (register, value) in [
+            (PWR_MGMT_1, CLKSEL_PLL_X),
+            (PWR_MGMT_2, STBY_ACCEL),
+            (CONFIG, DLPF_5HZ),
+            (SMPLRT_DIV, SAMPLE_RATE_DIVIDER),
+            (GYRO_CONFIG, FS_250_DPS),
+        ] {
+            gyro.write(register, value, SensorOp::ConfigureGyroscope)
+                .await?;
+        }
+        Ok(Some(gyro))
+    }
+
+    async fn write(&mut self, register: u8, value: u8, op: SensorOp) -> Result<(), Error> {
+        self.i2c
+            .write(ADDRESS, &[register, value])
+            .await
+            .map_err(Error::bus(op))
+    }
+}
+
+impl Gyroscope for Mpu6050 {
+    async fn angular_rate(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        let op = SensorOp::ReadGyroscope;
+        let mut status = [0];
+        self.i2c
+            .write_read(ADDRESS, &[INT_STATUS], &mut status)
+            .await
+            .map_err(Error::bus(op))?;
+        if status[0] & DATA_RDY_INT == 0 {
+            return Ok(None);
+        }
+        let mut buf = [0u8; 6];
+        self.i2c
+            .write_read(ADDRESS, &[GYRO_XOUT_H], &mut buf)
+            .await
+            .map_err(Error::bus(op))?;
+        Ok(Some([0, 1, 2].map(|axis| {
+            i16::from_be_bytes([buf[2 * axis], buf[2 * axis + 1]]) as f32 * DPS_PER_LSB
+        })))
+    }
+
+    async fn set_asleep(&mut self, asleep: bool) -> Result<(), Error> {
+        let (value, op) = match asleep {
+            true => (CLKSEL_PLL_X | SLEEP, SensorOp::Idle),
+            false => (CLKSEL_PLL_X, SensorOp::Wake),
+        };
+        self.write(PWR_MGMT_1, value, op).await
+    }
+}
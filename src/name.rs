@@ -0,0 +1,85 @@
+use core::{cell::RefCell, fmt::Write};
+
+use defmt::info;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use heapless::String;
+
+use crate::storage::{self, Storage};
+
+/// Longest accepted device name
+pub const MAX_NAME_LEN: usize = 20;
+
+pub type DeviceName = String<MAX_NAME_LEN>;
+
+/// Marks a valid record, erased flash reads back as `0xFFFF_FFFF`
+const MAGIC: u32 = 0x4E41_0001;
+
+/// magic (4) + length (4) + name
+const RECORD_LEN: usize = 8 + MAX_NAME_LEN;
+
+const _: () = assert!(RECORD_LEN <= storage::MAX_RECORD_LEN);
+
+/// FICR DEVICEID[0], the low word of the chip's unique ID
+const FICR_DEVICEID0: *const u32 = 0x1000_0060 as _;
+
+/// The device name currently in use
+static NAME: Mutex<CriticalSectionRawMutex, RefCell<DeviceName>> =
+    Mutex::new(RefCell::new(String::new()));
+
+/// The device name, used in telemetry headers and anywhere else units need
+/// telling apart
+pub fn current() -> DeviceName {
+    NAME.lock(|name| name.borrow().clone())
+}
+
+/// Restore the stored name, or derive a default from the chip's unique ID
+pub fn load(storage: &mut Storage<'_>) {
+    let mut buf = [0u8; RECORD_LEN];
+    let stored = storage
+        .load(storage::NAME_PAGE, &mut buf)
+        .ok()
+        .filter(|_| u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) == MAGIC)
+        .and_then(|_| {
+            let len = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as usize;
+            let name = core::str::from_utf8(buf[8..].get(..len)?).ok()?;
+            validate(name)
+        });
+
+    let name = stored.unwrap_or_else(default_name);
+    info!("device name: {}", name.as_str());
+    NAME.lock(|current| *current.borrow_mut() = name);
+}
+
+/// Rename the device and persist the new name. Returns `false` if `name` is
+/// empty, too long or not printable ASCII.
+pub fn set(name: &str) -> bool {
+    let Some(name) = validate(name) else {
+        return false;
+    };
+
+    let mut buf = [0u8; RECORD_LEN];
+    buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    buf[4..8].copy_from_slice(&(name.len() as u32).to_le_bytes());
+    buf[8..8 + name.len()].copy_from_slice(name.as_bytes());
+    storage::save(storage::NAME_PAGE, &buf);
+
+    info!("device name: {}", name.as_str());
+    NAME.lock(|current| *current.borrow_mut() = name);
+    true
+}
+
+fn validate(name: &str) -> Option<DeviceName> {
+    let printable = name.bytes().all(|b| b.is_ascii_graphic() || b == b' ');
+    if name.is_empty() || !printable {
+        return None;
+    }
+    String::try_from(name).ok()
+}
+
+/// `compass-` followed by the low 16 bits of the chip's unique ID
+fn default_name() -> DeviceName {
+    let id = unsafe { core::ptr::read_volatile(FICR_DEVICEID0) };
+    let mut name = String::new();
+    let _ = write!(name, "compass-{:04x}", id & 0xFFFF);
+    name
+}
@@ -0,0 +1,42 @@
+use embassy_time::Instant;
+
+use crate::{angle_diff, normalize_heading};
+
+/// Slowest and fastest needle damping, degrees per second
+pub const DAMPING_RANGE: core::ops::RangeInclusive<u16> = 10..=720;
+
+/// The needle as drawn. With damping it swings towards the heading no faster
+/// than a set rate, the short way round, like a compass card settling in its
+/// fluid, rather than jumping from one reading to the next.
+pub struct Needle {
+    /// Where it was last drawn, `None` until the first time
+    angle: Option<f32>,
+    moved: Instant,
+}
+
+impl Needle {
+    pub fn new() -> Self {
+        Self {
+            angle: None,
+            moved: Instant::now(),
+        }
+    }
+
+    /// Where to draw the needle on its way to `target`, moving at most
+    /// `damping` degrees per second since it was last drawn. Without damping
+    /// it goes straight there.
+    pub fn step(&mut self, target: f32, damping: Option<u16>) -> f32 {
+        let now = Instant::now();
+        let angle = match (self.angle, damping) {
+            (Some(angle), Some(rate)) => {
+                let seconds = (now - self.moved).as_micros() as f32 / 1e6;
+                let max = f32::from(rate) * seconds;
+                normalize_heading(angle + angle_diff(target, angle).clamp(-max, max))
+            }
+            _ => target,
+        };
+        self.angle = Some(angle);
+        self.moved = now;
+        angle
+    }
+}
@@ -0,0 +1,40 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::info;
+
+use crate::animation::GREY_LEVELS;
+
+/// Brightness the display drops to at night
+pub const BRIGHTNESS: u8 = 1;
+
+/// Measured light levels, from 1 to [`GREY_LEVELS`], at or below which
+/// automatic night mode comes on, and at or above which it goes off again,
+/// apart so a passing torch beam doesn't toggle it
+const DUSK: u8 = 1;
+const DAWN: u8 = 3;
+
+/// Set while the display is in night mode
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Night mode: the display at its dimmest, a single LED for the needle and
+/// none of the ticks and flashes, so it can be read with dark-adapted eyes
+/// without spoiling them
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+pub fn set(active: bool) {
+    if ACTIVE.swap(active, Ordering::Relaxed) != active {
+        info!("night mode {}", if active { "on" } else { "off" });
+    }
+}
+
+/// Follow the ambient light, from a [`crate::light::level`]
+pub fn follow(level: u8) {
+    debug_assert!(level <= GREY_LEVELS);
+    if level <= DUSK {
+        set(true);
+    } else if level >= DAWN {
+        set(false);
+    }
+}
@@ -0,0 +1,62 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+/// Longest NMEA sentence, including the `$` and trailing CR LF
+pub const MAX_SENTENCE: usize = 82;
+
+/// XOR of every character between the `$` and the `*`
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |acc, b| acc ^ b)
+}
+
+/// Frame `body` as a complete sentence, `$<body>*<checksum>\r\n`
+fn sentence(body: &str) -> String<MAX_SENTENCE> {
+    let mut sentence = String::new();
+    // Bodies built here are all well under the length limit
+    let _ = write!(sentence, "${}*{:02X}\r\n", body, checksum(body));
+    sentence
+}
+
+/// `$HCHDM`: heading relative to magnetic north
+pub fn hdm(heading: f32) -> String<MAX_SENTENCE> {
+    let mut body: String<MAX_SENTENCE> = String::new();
+    let _ = write!(body, "HCHDM,{:.1},M", heading);
+    sentence(&body)
+}
+
+/// `$HCROT`: rate of turn, from degrees per second to the sentence's degrees
+/// per minute, negative turning to port
+pub fn rot(rate: f32) -> String<MAX_SENTENCE> {
+    let mut body: String<MAX_SENTENCE> = String::new();
+    let _ = write!(body, "HCROT,{:.1},A", rate * 60.0);
+    sentence(&body)
+}
+
+/// `$HCHDT`: heading relative to true north
+pub fn hdt(heading: f32) -> String<MAX_SENTENCE> {
+    let mut body: String<MAX_SENTENCE> = String::new();
+    let _ = write!(body, "HCHDT,{:.1},T", heading);
+    sentence(&body)
+}
+
+/// `$HCALR`: the state of the heading alarm, `A` while the heading is outside
+/// the sector and `V` otherwise, stamped with UTC when it is known
+pub fn alr(active: bool, utc: Option<i64>) -> String<MAX_SENTENCE> {
+    let mut body: String<MAX_SENTENCE> = String::new();
+    let _ = write!(body, "HCALR,");
+    if let Some(utc) = utc {
+        let seconds = utc.rem_euclid(86_400);
+        let _ = write!(
+            body,
+            "{:02}{:02}{:02}.00",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+    }
+    let condition = if active { 'A' } else { 'V' };
+    // Alarm 1, never acknowledged: it stops when the heading comes back
+    let _ = write!(body, ",001,{condition},V,HEADING OUTSIDE SECTOR");
+    sentence(&body)
+}
@@ -0,0 +1,372 @@
+use core::{cell::RefCell, fmt::Write as _};
+
+use defmt::{info, warn};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c;
+use heapless::String;
+use micromath::F32Ext;
+use static_cell::StaticCell;
+
+use crate::{
+    bus,
+    display::{CompassDisplay, Status},
+    error::Error,
+    health::{self, Task},
+};
+
+/// I2C address of an SSD1306 with its D/C pin low, as on most modules
+const ADDRESS: u8 = 0x3C;
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 64;
+
+/// The SSD1306 maps each byte to a column of 8 pixels in a "page" of rows
+const PAGES: usize = HEIGHT / 8;
+
+/// Control bytes starting a run of commands or of display data
+const COMMANDS: u8 = 0x00;
+const DATA: u8 = 0x40;
+
+/// Display off, clock, 64 line multiplex, no offset, start line 0, charge
+/// pump on, horizontal addressing, columns and rows scanned the way most
+/// modules are wired, COM pin layout for 128x64, contrast, precharge, VCOMH
+/// level, show RAM, not inverted, display on
+const INIT: [u8; 26] = [
+    COMMANDS, 0xAE, 0xD5, 0x80, 0xA8, 0x3F, 0xD3, 0x00, 0x40, 0x8D, 0x14, 0x20, 0x00, 0xA1, 0xC8,
+    0xDA, 0x12, 0x81, 0xCF, 0xD9, 0xF1, 0xDB, 0x40, 0xA4, 0xA6, 0xAF,
+];
+
+/// Column and page range covering the whole display, set before each frame
+const WHOLE_SCREEN: [u8; 7] = [COMMANDS, 0x21, 0, WIDTH as u8 - 1, 0x22, 0, PAGES as u8 - 1];
+
+/// Shortest time between frames. A frame is 1 KiB over the shared bus, sent
+/// a page at a time so the sensor's transfers can go in between.
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Wait before trying a display that didn't answer again
+const RETRY: Duration = Duration::from_secs(5);
+
+/// The compass rose on the left half of the screen
+const ROSE_CENTER: (i32, i32) = (31, 31);
+const ROSE_RADIUS: f32 = 30.0;
+/// Distance of the cardinal letters from the center
+const LETTER_RADIUS: f32 = 20.0;
+
+/// How far the bezel marker reaches in from the ring, pixels, and half its
+/// width at the ring, degrees
+const BEZEL_DEPTH: f32 = 7.0;
+const BEZEL_HALF_WIDTH: f32 = 8.0;
+
+/// Left edge of the readouts on the right half of the screen
+const READOUT_X: i32 = 70;
+
+/// Longest message shown in place of the heading, the first
+/// [`LARGE_TEXT`] characters of it in large ones
+const MAX_TEXT: usize = 9;
+const LARGE_TEXT: usize = 4;
+
+/// What the OLED shows
+#[derive(Clone)]
+struct View {
+    /// Degrees, 0 to 360, as on the LED matrix. `None` while calibrating
+    heading: Option<f32>,
+    /// Shown instead of the heading's digits until the next needle
+    text: Option<String<MAX_TEXT>>,
+    status: Status,
+    /// Degrees clockwise from the top
+    bezel: Option<f32>,
+}
+
+/// Built up by [`Oled`] as the main loop draws
+static CURRENT: Mutex<CriticalSectionRawMutex, RefCell<View>> = Mutex::new(RefCell::new(View {
+    heading: None,
+    text: None,
+    status: Status {
+        pitch: 0.0,
+        roll: 0.0,
+        calibrating: false,
+        calibrated: false,
+    },
+    bezel: None,
+}));
+
+/// Latest view, drawn by the OLED task at its own rate
+static VIEW: Signal<CriticalSectionRawMutex, View> = Signal::new();
+
+/// The OLED as a [`CompassDisplay`], handing what is drawn to [`oled_task`]
+pub struct Oled;
+
+impl Oled {
+    fn update(&mut self, change: impl FnOnce(&mut View)) {
+        let view = CURRENT.lock(|view| {
+            let mut view = view.borrow_mut();
+            change(&mut view);
+            view.clone()
+        });
+        VIEW.signal(view);
+    }
+}
+
+impl CompassDisplay for Oled {
+    async fn draw_needle(&mut self, angle: f32) {
+        self.update(|view| {
+            view.heading = Some(angle);
+            view.text = None;
+        });
+    }
+
+    /// Cut short to what fits beside the rose
+    async fn draw_text(&mut self, text: &str) {
+        let text = text.get(..MAX_TEXT).unwrap_or(text);
+        self.update(|view| view.text = String::try_from(text).ok());
+    }
+
+    async fn show_status(&mut self, status: &Status) {
+        self.update(|view| {
+            view.status = *status;
+            if status.calibrating {
+                view.heading = None;
+            }
+        });
+    }
+
+    async fn show_bezel(&mut self, marker: Option<f32>) {
+        self.update(|view| view.bezel = marker);
+    }
+}
+
+/// One bit per pixel, in the SSD1306's page layout
+struct Frame {
+    pixels: [u8; WIDTH * PAGES],
+}
+
+static FRAME: StaticCell<Frame> = StaticCell::new();
+
+/// Draw each [`View`] on an SSD1306 128x64 OLED on the shared I2C bus: a
+/// compass rose turning under a fixed needle, the heading in large digits,
+/// pitch, roll and the calibration status. Without a display answering it
+/// tries again every few seconds.
+#[embassy_executor::task]
+pub async fn oled_task() {
+    let mut i2c = bus::device();
+    let frame = FRAME.init(Frame {
+        pixels: [0; WIDTH * PAGES],
+    });
+    loop {
+        if i2c.write(ADDRESS, &INIT).await.is_err() {
+            warn!("no oled: {}", Error::Display);
+            Timer::after(RETRY).await;
+            continue;
+        }
+        info!("oled started");
+        loop {
+            let view = VIEW.wait().await;
+            frame.draw(&view);
+            // A stalled flush is dropped, freeing the bus, and the display
+            // set up again
+            let flushed = health::watch(Task::Oled, frame.flush(&mut i2c)).await;
+            if !matches!(flushed, Some(Ok(()))) {
+                warn!("failed to update oled: {}", Error::Display);
+                break;
+            }
+            Timer::after(REFRESH).await;
+        }
+    }
+}
+
+impl Frame {
+    fn draw(&mut self, view: &View) {
+        self.pixels.fill(0);
+        let (cx, cy) = ROSE_CENTER;
+
+        // The rose, ticks every 45°, turned so the heading is at the top
+        self.circle(cx, cy, ROSE_RADIUS as i32);
+        let heading = view.heading.unwrap_or(0.0);
+        for tick in 0..8 {
+            let angle = tick as f32 * 45.0 - heading;
+            let inner = if tick % 2 == 0 { 24.0 } else { 27.0 };
+            let (x0, y0) = polar(angle, inner);
+            let (x1, y1) = polar(angle, ROSE_RADIUS);
+            self.line(x0, y0, x1, y1);
+        }
+        if view.heading.is_some() {
+            for (bearing, letter) in [(0.0, "N"), (90.0, "E"), (180.0, "S"), (270.0, "W")] {
+                let (x, y) = polar(bearing - heading, LETTER_RADIUS);
+                // Centered on the point
+                self.text(x - 2, y - 3, letter, 1);
+            }
+            // The bezel's marker, a wedge pointing in from the ring
+            if let Some(marker) = view.bezel {
+                let (x, y) = polar(marker, ROSE_RADIUS - BEZEL_DEPTH);
+                for side in [-BEZEL_HALF_WIDTH, BEZEL_HALF_WIDTH] {
+                    let (x1, y1) = polar(marker + side, ROSE_RADIUS);
+                    self.line(x, y, x1, y1);
+                }
+            }
+            // The needle, pointing the way the board faces
+            self.line(cx, cy, cx, cy - 14);
+            self.line(cx - 2, cy - 11, cx, cy - 14);
+            self.line(cx + 2, cy - 11, cx, cy - 14);
+        }
+
+        let mut line: String<12> = String::new();
+        let mut scale = 2;
+        match (&view.text, view.heading) {
+            (Some(text), _) => {
+                let _ = line.push_str(text);
+                if text.len() > LARGE_TEXT {
+                    scale = 1;
+                }
+            }
+            (None, Some(heading)) => {
+                let _ = write!(line, "{:03}", heading.round() as u32 % 360);
+                self.text(READOUT_X + 36, 2, "°", 1);
+            }
+            (None, None) => {
+                let _ = line.push_str("---");
+            }
+        }
+        self.text(READOUT_X, 2, &line, scale);
+
+        let status = &view.status;
+        line.clear();
+        let _ = write!(line, "P {:.0}", status.pitch);
+        self.text(READOUT_X, 26, &line, 1);
+        line.clear();
+        let _ = write!(line, "R {:.0}", status.roll);
+        self.text(READOUT_X, 36, &line, 1);
+
+        let status = if status.calibrating {
+            "CAL..."
+        } else if status.calibrated {
+            "CAL OK"
+        } else {
+            "NO CAL"
+        };
+        self.text(READOUT_X, 54, status, 1);
+    }
+
+    fn set(&mut self, x: i32, y: i32) {
+        if (0..WIDTH as i32).contains(&x) && (0..HEIGHT as i32).contains(&y) {
+            let (x, y) = (x as usize, y as usize);
+            self.pixels[y / 8 * WIDTH + x] |= 1 << (y % 8);
+        }
+    }
+
+    /// Bresenham's line, both ends included
+    fn line(&mut self, mut x0: i32, mut y0: i32, x1: i32, y1: i32) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let mut error = dx + dy;
+        loop {
+            self.set(x0, y0);
+            if x0 == x1 && y0 == y1 {
+                return;
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x0 += sx;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y0 += sy;
+            }
+        }
+    }
+
+    /// Midpoint circle, one octant mirrored eight ways
+    fn circle(&mut self, cx: i32, cy: i32, radius: i32) {
+        let (mut x, mut y) = (radius, 0);
+        let mut error = 1 - radius;
+        while x >= y {
+            for (px, py) in [(x, y), (y, x), (-y, x), (-x, y)] {
+                self.set(cx + px, cy + py);
+                self.set(cx - px, cy - py);
+            }
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    /// Text with its top left corner at `x`, `y`, each pixel of the 5x7 font
+    /// drawn `scale` pixels square. Characters missing from the font are
+    /// left blank.
+    fn text(&mut self, x: i32, y: i32, text: &str, scale: i32) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as i32 * 6 * scale;
+            for (col, bits) in glyph(c).iter().enumerate() {
+                for row in 0..7 {
+                    if bits & (1 << row) == 0 {
+                        continue;
+                    }
+                    for (dx, dy) in (0..scale).flat_map(|dx| (0..scale).map(move |dy| (dx, dy))) {
+                        self.set(left + col as i32 * scale + dx, y + row * scale + dy);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send the frame a page at a time
+    async fn flush<I: I2c>(&self, i2c: &mut I) -> Result<(), I::Error> {
+        i2c.write(ADDRESS, &WHOLE_SCREEN).await?;
+        let mut chunk = [0u8; WIDTH + 1];
+        chunk[0] = DATA;
+        for page in self.pixels.chunks_exact(WIDTH) {
+            chunk[1..].copy_from_slice(page);
+            i2c.write(ADDRESS, &chunk).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Screen position `radius` pixels from the rose's center, at `degrees`
+/// clockwise from the top
+fn polar(degrees: f32, radius: f32) -> (i32, i32) {
+    let (cx, cy) = ROSE_CENTER;
+    let radians = degrees.to_radians();
+    (
+        cx + (radius * radians.sin()).round() as i32,
+        cy - (radius * radians.cos()).round() as i32,
+    )
+}
+
+/// Columns of a 5x7 character, least significant bit at the top
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0x3E, 0x51, 0x49, 0x45, 0x3E],
+        '1' => [0x00, 0x42, 0x7F, 0x40, 0x00],
+        '2' => [0x42, 0x61, 0x51, 0x49, 0x46],
+        '3' => [0x21, 0x41, 0x45, 0x4B, 0x31],
+        '4' => [0x18, 0x14, 0x12, 0x7F, 0x10],
+        '5' => [0x27, 0x45, 0x45, 0x45, 0x39],
+        '6' => [0x3C, 0x4A, 0x49, 0x49, 0x30],
+        '7' => [0x01, 0x71, 0x09, 0x05, 0x03],
+        '8' => [0x36, 0x49, 0x49, 0x49, 0x36],
+        '9' => [0x06, 0x49, 0x49, 0x29, 0x1E],
+        'A' => [0x7E, 0x11, 0x11, 0x11, 0x7E],
+        'C' => [0x3E, 0x41, 0x41, 0x41, 0x22],
+        'E' => [0x7F, 0x49, 0x49, 0x49, 0x41],
+        'K' => [0x7F, 0x08, 0x14, 0x22, 0x41],
+        'L' => [0x7F, 0x40, 0x40, 0x40, 0x40],
+        'N' => [0x7F, 0x04, 0x08, 0x10, 0x7F],
+        'O' => [0x3E, 0x41, 0x41, 0x41, 0x3E],
+        'P' => [0x7F, 0x09, 0x09, 0x09, 0x06],
+        'R' => [0x7F, 0x09, 0x19, 0x29, 0x46],
+        'S' => [0x46, 0x49, 0x49, 0x49, 0x31],
+        'W' => [0x3F, 0x40, 0x38, 0x40, 0x3F],
+        '-' => [0x08, 0x08, 0x08, 0x08, 0x08],
+        '.' => [0x00, 0x60, 0x60, 0x00, 0x00],
+        '°' => [0x00, 0x06, 0x09, 0x09, 0x06],
+        _ => [0; 5],
+    }
+}
@@ -0,0 +1,123 @@
+use defmt::info;
+use embassy_time::{Duration, Instant};
+
+use crate::{angle_diff, normalize_heading};
+
+/// Steps to a pace, which counts each time the same foot lands
+const STEPS_PER_PACE: u32 = 2;
+
+/// Remaining paces are shown every this many paces along the leg
+pub const SHOW_EVERY_PACES: u32 = 20;
+
+/// Degrees either side of the bearing that count as on course
+const ON_COURSE: f32 = 10.0;
+
+/// How long the arrival is shown before going back to the compass
+const ARRIVED_FOR: Duration = Duration::from_secs(3);
+
+/// Longest leg that can be set, paces
+pub const MAX_PACES: u32 = 9999;
+
+enum State {
+    /// Walking a leg on `bearing`, with the steps taken so far
+    Following {
+        bearing: f32,
+        paces: u32,
+        steps: u32,
+        off_course: bool,
+    },
+    Arrived {
+        at: Instant,
+    },
+}
+
+/// What the display and speaker should show while a leg is walked
+pub enum Leg {
+    /// Still on the way: the bearing to follow, the paces left, and whether
+    /// they are due to be shown
+    Following {
+        bearing: f32,
+        remaining: u32,
+        show_remaining: bool,
+    },
+    /// Reached the end of the leg on this update
+    Arrived,
+    /// Recently arrived
+    Done,
+}
+
+/// Orienteering by bearing and pace count: walk a set bearing for a set
+/// number of paces, counted by the pedometer, with the needle pointing along
+/// the bearing until the end of the leg
+pub struct Orienteering {
+    state: Option<State>,
+}
+
+impl Orienteering {
+    pub const fn new() -> Self {
+        Self { state: None }
+    }
+
+    /// Start a leg of `paces` on `bearing`, in degrees from the display's north
+    pub fn start(&mut self, bearing: f32, paces: u32) {
+        info!("leg: {} paces on {}", paces, bearing);
+        self.state = Some(State::Following {
+            bearing: normalize_heading(bearing),
+            paces,
+            steps: 0,
+            off_course: false,
+        });
+    }
+
+    pub fn stop(&mut self) -> bool {
+        self.state.take().is_some()
+    }
+
+    /// Feed the heading, and whether a step was just taken
+    pub fn update(&mut self, heading: f32, stepped: bool) -> Option<Leg> {
+        match self.state.as_mut()? {
+            State::Following {
+                bearing,
+                paces,
+                steps,
+                off_course,
+            } => {
+                if stepped {
+                    *steps += 1;
+                }
+                let done = *steps / STEPS_PER_PACE;
+                if done >= *paces {
+                    info!("leg: arrived");
+                    self.state = Some(State::Arrived { at: Instant::now() });
+                    return Some(Leg::Arrived);
+                }
+                let off = angle_diff(*bearing, heading).abs() > ON_COURSE;
+                if off != *off_course {
+                    info!(
+                        "leg: {} course, heading {}",
+                        if off { "off" } else { "on" },
+                        heading
+                    );
+                    *off_course = off;
+                }
+                // On the step completing a multiple of the interval
+                let show_remaining = stepped
+                    && steps.is_multiple_of(STEPS_PER_PACE)
+                    && done.is_multiple_of(SHOW_EVERY_PACES)
+                    && done > 0;
+                Some(Leg::Following {
+                    bearing: *bearing,
+                    remaining: *paces - done,
+                    show_remaining,
+                })
+            }
+            State::Arrived { at } => {
+                if at.elapsed() >= ARRIVED_FOR {
+                    self.state = None;
+                    return None;
+                }
+                Some(Leg::Done)
+            }
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::peripheral::{DCB, SCB};
+use cortex_m_rt::{exception, ExceptionFrame};
+use defmt::{info, warn};
+use embassy_nrf::{
+    nvmc::Nvmc,
+    pac::{
+        self,
+        gpio::vals::{Dir, Input},
+    },
+    peripherals::NVMC,
+};
+use embassy_time::Instant;
+
+use crate::{
+    board, console, logger,
+    storage::{self, Storage},
+};
+
+/// Marks a valid crash record, erased flash reads back as `0xFFFF_FFFF`
+const CRASH_MAGIC: u32 = 0xDEAD_0003;
+
+/// Bytes of the panicking file's path kept in the record (the tail end, which
+/// holds the file name)
+const FILE_LEN: usize = 44;
+
+/// Bytes of the panic message kept in the record, the rest is cut off
+const MESSAGE_LEN: usize = 64;
+
+/// Offsets of the fields in a crash record
+const COUNT_AT: usize = 4;
+const UPTIME_AT: usize = 8;
+const LINE_AT: usize = 12;
+const COLUMN_AT: usize = 16;
+const FILE_AT: usize = 20;
+const MESSAGE_AT: usize = FILE_AT + 4 + FILE_LEN;
+/// Left erased when the record is written, cleared once the next boot has
+/// reported it
+const REPORTED_AT: usize = MESSAGE_AT + 4 + MESSAGE_LEN;
+
+/// magic (4) + crash count (4) + uptime ms (4) + line (4) + column (4) + file
+/// length (4) + file + message length (4) + message + reported (4)
+const CRASH_RECORD_LEN: usize = REPORTED_AT + 4;
+
+/// Set once the first panic or hard fault is being handled
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+/// Shown on the LED matrix after a panic, one bit per column with the leftmost
+/// column in bit 4
+const SAD_FACE: [u8; 5] = [0b00000, 0b01010, 0b00000, 0b01110, 0b10001];
+
+/// How long [`SAD_FACE`] is shown before the board resets, well inside the
+/// watchdog timeout
+const SAD_FACE_MS: u32 = 3000;
+
+/// Each row of the matrix is lit this long in turn, in CPU cycles (2 ms at
+/// 64 MHz)
+const ROW_CYCLES: u32 = 128_000;
+
+/// Replaces `panic-probe`'s handler so that the panic location and message
+/// are written to flash as a final event record, giving post-mortem analysis
+/// of field crashes the freshest possible data.
+///
+/// Without a debugger the display shows a sad face for a few seconds and the
+/// board resets, rather than freezing on the last arrow. With one attached it
+/// halts like `panic-probe` so `probe-rs` prints a backtrace.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    // Guard against panicking again while recording the first panic
+    if !CRASHED.swap(true, Ordering::Relaxed) {
+        defmt::error!("{}", defmt::Display2Format(info));
+        let (file, line, column) = match info.location() {
+            Some(location) => (location.file(), location.line(), location.column()),
+            None => ("", 0, 0),
+        };
+        let mut message = Truncated::<MESSAGE_LEN>::default();
+        // Only fails once the message is cut off
+        let _ = write!(message, "{}", info.message());
+        write_record(file, line, column, &message);
+    }
+
+    if DCB::is_debugger_attached() {
+        hard_fault();
+    }
+    show_sad_face();
+    SCB::sys_reset();
+}
+
+/// Record faults that panics don't catch, e.g. a stack overflow or a bad
+/// pointer, with the faulting address as the message, then reset like a
+/// panic. A panic halting under a debugger ends up here too, already recorded.
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    cortex_m::interrupt::disable();
+
+    if !CRASHED.swap(true, Ordering::Relaxed) {
+        defmt::error!("hard fault at pc {=u32:#010x}", frame.pc());
+        let mut message = Truncated::<MESSAGE_LEN>::default();
+        let _ = write!(
+            message,
+            "hard fault at pc {:#010x}, lr {:#010x}",
+            frame.pc(),
+            frame.lr()
+        );
+        write_record("", 0, 0, &message);
+    }
+
+    if DCB::is_debugger_attached() {
+        loop {
+            cortex_m::asm::bkpt();
+        }
+    }
+    show_sad_face();
+    SCB::sys_reset();
+}
+
+/// Write the final event record, counting it on from the one it replaces,
+/// after whatever was still queued for flash and the log's unwritten tail.
+/// The NVMC is owned by the flash
+/// task, which will never run again, so it is safe to take it over here.
+fn write_record(file: &str, line: u32, column: u32, message: &Truncated<MESSAGE_LEN>) {
+    let mut storage = Storage::new(Nvmc::new(unsafe { NVMC::steal() }));
+    storage.drain();
+    logger::flush_padded(&mut storage);
+    let mut buf = [0u8; CRASH_RECORD_LEN];
+    let count = match storage.load(storage::CRASH_PAGE, &mut buf) {
+        Ok(()) if word(&buf, 0) == CRASH_MAGIC => word(&buf, COUNT_AT).saturating_add(1),
+        _ => 1,
+    };
+
+    let file = &file.as_bytes()[file.len().saturating_sub(FILE_LEN)..];
+    let message = &message.buf[..message.len];
+    buf.fill(0);
+    buf[0..4].copy_from_slice(&CRASH_MAGIC.to_le_bytes());
+    buf[COUNT_AT..COUNT_AT + 4].copy_from_slice(&count.to_le_bytes());
+    buf[UPTIME_AT..UPTIME_AT + 4]
+        .copy_from_slice(&(Instant::now().as_millis() as u32).to_le_bytes());
+    buf[LINE_AT..LINE_AT + 4].copy_from_slice(&line.to_le_bytes());
+    buf[COLUMN_AT..COLUMN_AT + 4].copy_from_slice(&column.to_le_bytes());
+    buf[FILE_AT..FILE_AT + 4].copy_from_slice(&(file.len() as u32).to_le_bytes());
+    buf[FILE_AT + 4..FILE_AT + 4 + file.len()].copy_from_slice(file);
+    buf[MESSAGE_AT..MESSAGE_AT + 4].copy_from_slice(&(message.len() as u32).to_le_bytes());
+    buf[MESSAGE_AT + 4..MESSAGE_AT + 4 + message.len()].copy_from_slice(message);
+
+    // The reported word is left erased
+    if let Err(e) = storage.store(storage::CRASH_PAGE, &buf[..REPORTED_AT]) {
+        defmt::error!("crash not recorded: {}", e);
+    }
+}
+
+/// Report the last crash over defmt and the serial console if it hasn't been
+/// yet, along with how many there have been, then mark it reported
+pub fn report_last_crash(storage: &mut Storage) {
+    let mut buf = [0u8; CRASH_RECORD_LEN];
+    if let Err(e) = storage.load(storage::CRASH_PAGE, &mut buf) {
+        warn!("failed to read the crash record: {}", e);
+        return;
+    }
+    if word(&buf, 0) != CRASH_MAGIC {
+        return;
+    }
+    let count = word(&buf, COUNT_AT);
+    if word(&buf, REPORTED_AT) != u32::MAX {
+        info!("{} crashes so far", count);
+        return;
+    }
+
+    let text = |at: usize, max: usize| {
+        let len = (word(&buf, at) as usize).min(max);
+        let bytes = &buf[at + 4..at + 4 + len];
+        // A message cut off mid character keeps what came before it
+        core::str::from_utf8(bytes)
+            .or_else(|e| core::str::from_utf8(&bytes[..e.valid_up_to()]))
+            .unwrap_or_default()
+    };
+    let file = text(FILE_AT, FILE_LEN);
+    let message = text(MESSAGE_AT, MESSAGE_LEN);
+    let line = word(&buf, LINE_AT);
+    let uptime_ms = word(&buf, UPTIME_AT);
+    warn!(
+        "crash {} after {} ms at {}:{}:{}: {}",
+        count,
+        uptime_ms,
+        file,
+        line,
+        word(&buf, COLUMN_AT),
+        message
+    );
+    // Sent once the telemetry task is running
+    console::reply(format_args!("crash {} at {}:{}", count, file, line));
+    console::reply(format_args!("crash message: {}", message));
+
+    if let Err(e) = storage.write(storage::CRASH_PAGE + REPORTED_AT as u32, &[0; 4]) {
+        warn!("crash left unreported: {}", e);
+    }
+}
+
+fn word(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+/// Collects formatted text, dropping whatever doesn't fit
+struct Truncated<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Default for Truncated<N> {
+    fn default() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+}
+
+impl<const N: usize> Write for Truncated<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(N - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            Err(core::fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Scan [`SAD_FACE`] onto the LED matrix for [`SAD_FACE_MS`], busy waiting
+/// since the executor is no longer running. The pins are taken over from
+/// `main` like the NVMC.
+fn show_sad_face() {
+    crate::matrix_scan::stop();
+    // Rows active high and columns active low
+    let rows = board::MATRIX_ROW_PINS.map(board::registers);
+    let cols = board::MATRIX_COL_PINS.map(board::registers);
+    for (port, pin) in rows.into_iter().chain(cols) {
+        port.pin_cnf(pin).write(|w| {
+            w.set_dir(Dir::OUTPUT);
+            w.set_input(Input::DISCONNECT);
+        });
+    }
+    let set = |(port, pin): (pac::gpio::Gpio, usize), high: bool| {
+        if high {
+            port.outset().write(|w| w.set_pin(pin, true));
+        } else {
+            port.outclr().write(|w| w.set_pin(pin, true));
+        }
+    };
+    for row in rows {
+        set(row, false);
+    }
+
+    for _ in 0..SAD_FACE_MS / 10 {
+        for (row, bits) in rows.into_iter().zip(SAD_FACE) {
+            for (c, col) in cols.into_iter().enumerate() {
+                // Columns are active low
+                set(col, bits & (0b10000 >> c) == 0);
+            }
+            set(row, true);
+            cortex_m::asm::delay(ROW_CYCLES);
+            set(row, false);
+        }
+    }
+}
+
+/// Trigger a `HardFault` via `udf`, which makes `probe-rs` print a backtrace
+/// and exit with a non-zero status code.
+fn hard_fault() -> ! {
+    // If `UsageFault` is enabled `udf` raises that instead of `HardFault`
+    const SHCSR: *mut u32 = 0xE000_ED24usize as _;
+    const USGFAULTENA: u32 = 18;
+    unsafe {
+        let shcsr = core::ptr::read_volatile(SHCSR);
+        core::ptr::write_volatile(SHCSR, shcsr & !(1 << USGFAULTENA));
+    }
+
+    cortex_m::asm::udf();
+}
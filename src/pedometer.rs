@@ -0,0 +1,70 @@
+use embassy_time::{Duration, Instant};
+use micromath::F32Ext;
+
+/// Rise of the acceleration magnitude above its running average that counts
+/// as a footfall, mg
+const STEP_THRESHOLD_MG: f32 = 150.0;
+
+/// Fastest cadence counted, about 4 steps a second when running
+const MIN_STEP_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How quickly the running average follows the magnitude. Slow enough to
+/// stay near 1 g while walking.
+const BASELINE_GAIN: f32 = 0.1;
+
+/// Counts steps from peaks in the acceleration magnitude, which jumps as each
+/// foot lands. The magnitude doesn't depend on how the board is held.
+///
+/// Samples arrive at the main loop's rate, around 5 Hz, so a fast run may
+/// miss some steps.
+pub struct Pedometer {
+    /// Running average of the magnitude, mg
+    baseline: f32,
+    /// The magnitude has dropped back below the average since the last step
+    armed: bool,
+    last_step: Option<Instant>,
+    steps: u32,
+}
+
+impl Pedometer {
+    pub const fn new() -> Self {
+        Self {
+            baseline: 1000.0,
+            armed: false,
+            last_step: None,
+            steps: 0,
+        }
+    }
+
+    /// Feed raw acceleration in mg, returning `true` if it completes a step
+    pub fn update(&mut self, accel: [f32; 3]) -> bool {
+        let [x, y, z] = accel;
+        let magnitude = (x * x + y * y + z * z).sqrt();
+        let rise = magnitude - self.baseline;
+        self.baseline += BASELINE_GAIN * rise;
+
+        if rise < 0.0 {
+            self.armed = true;
+            return false;
+        }
+        let too_soon = self
+            .last_step
+            .is_some_and(|last| last.elapsed() < MIN_STEP_INTERVAL);
+        if !self.armed || rise < STEP_THRESHOLD_MG || too_soon {
+            return false;
+        }
+
+        self.armed = false;
+        self.last_step = Some(Instant::now());
+        self.steps += 1;
+        true
+    }
+
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    pub fn reset(&mut self) {
+        self.steps = 0;
+    }
+}
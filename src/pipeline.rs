@@ -0,0 +1,331 @@
+//! The firmware's heading pipeline: the stages in `core` that need nothing
+//! from the board, with the firmware's own in between, each working on the
+//! same [`Frame`]:
+//!
+//! median → average → calibrate → remap → gravity → tilt → deviation → gyro
+//! → course → hold → declination → units
+//!
+//! The median and averaging filters run first, on their own, as the
+//! calibration wizard and the cross-check take the filtered field before
+//! the heading is worked out. The calibration comes before the remap, as it
+//! was fitted in the sensor's own frame and so holds whichever way up the
+//! board is mounted. The gyroscope and GPS course stages are only built in
+//! with the `gyro` and `gps` features. A new filter or correction is a new
+//! [`Stage`], put in its place in [`Pipeline::stages`], with no change to
+//! the main loop. What each stage made of the last reading is kept for the
+//! `pipeline` console command.
+
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+use micro_compass_core::{
+    deviation::DeviationTable,
+    median::MedianFilter,
+    pipeline::{
+        self, Average, Config, Declination, Deviation, Median, Probe, Stage, Tilt, Trace, Units,
+        MAX_STAGES,
+    },
+    protocol::Reference,
+    units::HeadingUnit,
+};
+
+#[cfg(feature = "gyro")]
+use crate::gyro_fusion::GyroFusion;
+use crate::{
+    calibration::Calibration,
+    console,
+    motion_class::{HeadingHold, MotionClass},
+    mounting::Mounting,
+    settings::Settings,
+    vibration::GravityEstimate,
+};
+#[cfg(feature = "gps")]
+use crate::{course_fusion::CourseFusion, course_offset::OffsetLearner, gps::CourseFix};
+
+/// Readings on their way through the [`Pipeline`]
+pub type Frame = pipeline::Frame<Inputs>;
+
+/// What the firmware's own stages go by, besides the readings
+pub struct Inputs {
+    pub motion: MotionClass,
+    /// Whether a gyroscope or the GPS course carries the heading through
+    /// hard acceleration
+    pub covered: bool,
+    /// Rate of turn in the sensor's frame, °/s, if one was read
+    #[cfg(feature = "gyro")]
+    pub rate: Option<[f32; 3]>,
+    /// Lying still, so the gyroscope's bias can be learned
+    #[cfg(feature = "gyro")]
+    pub still: bool,
+    /// A GPS fix that came in since the last reading
+    #[cfg(feature = "gps")]
+    pub fix: Option<CourseFix>,
+}
+
+impl Inputs {
+    pub fn new(motion: MotionClass) -> Self {
+        Self {
+            motion,
+            covered: false,
+            #[cfg(feature = "gyro")]
+            rate: None,
+            #[cfg(feature = "gyro")]
+            still: false,
+            #[cfg(feature = "gps")]
+            fix: None,
+        }
+    }
+}
+
+/// What the stages go by that the main loop owns
+pub struct Context<'a> {
+    pub settings: &'a Settings,
+    pub calibration: &'a Calibration,
+    pub mounting: Mounting,
+}
+
+impl Config for Context<'_> {
+    fn deviation(&self) -> &DeviationTable {
+        &self.settings.deviation
+    }
+
+    fn declination(&self) -> f32 {
+        self.settings.declination
+    }
+
+    fn reference(&self) -> Reference {
+        self.settings.display_reference
+    }
+
+    fn unit(&self) -> HeadingUnit {
+        self.settings.heading_unit
+    }
+}
+
+/// Hard and soft iron, in the sensor's frame
+struct Calibrate;
+
+impl Stage<Inputs, Context<'_>> for Calibrate {
+    fn name(&self) -> &'static str {
+        "calibrate"
+    }
+
+    fn apply(&mut self, frame: &mut Frame, context: &Context<'_>) {
+        frame.mag = context.calibration.apply(frame.mag);
+    }
+
+    fn probe(&self, frame: &Frame) -> Probe {
+        Probe::Field(frame.mag)
+    }
+}
+
+/// Into the flat board frame, whichever way up it is mounted
+struct Remap;
+
+impl Stage<Inputs, Context<'_>> for Remap {
+    fn name(&self) -> &'static str {
+        "remap"
+    }
+
+    fn apply(&mut self, frame: &mut Frame, context: &Context<'_>) {
+        frame.mag = context.mounting.apply(frame.mag);
+    }
+
+    fn probe(&self, frame: &Frame) -> Probe {
+        Probe::Field(frame.mag)
+    }
+}
+
+/// Down, with vibration and bumps filtered out under `set vibration on`
+pub struct Gravity(GravityEstimate);
+
+impl Gravity {
+    /// Start the estimate over
+    pub fn reset(&mut self) {
+        self.0 = GravityEstimate::new();
+    }
+}
+
+impl Stage<Inputs, Context<'_>> for Gravity {
+    fn name(&self) -> &'static str {
+        "gravity"
+    }
+
+    fn apply(&mut self, frame: &mut Frame, context: &Context<'_>) {
+        frame.gravity = match context.settings.vibration_rejection {
+            true => self.0.update(frame.accel),
+            false => frame.accel,
+        };
+    }
+
+    fn probe(&self, frame: &Frame) -> Probe {
+        Probe::Gravity(frame.gravity)
+    }
+}
+
+/// The gyroscope's rate of turn, carrying the heading through acceleration
+#[cfg(feature = "gyro")]
+struct Gyro(GyroFusion);
+
+#[cfg(feature = "gyro")]
+impl Stage<Inputs, Context<'_>> for Gyro {
+    fn name(&self) -> &'static str {
+        "gyro"
+    }
+
+    fn apply(&mut self, frame: &mut Frame, context: &Context<'_>) {
+        let rate = frame.input.rate.map(|rate| context.mounting.apply(rate));
+        frame.input.covered |= rate.is_some();
+        frame.heading = self.0.fuse(
+            frame.heading,
+            rate,
+            frame.gravity,
+            frame.input.still,
+            frame.input.motion == MotionClass::Dynamic,
+        );
+    }
+}
+
+/// The GPS course over ground: a learned heading offset, and the course
+/// itself while moving
+#[cfg(feature = "gps")]
+pub struct Course {
+    pub offset: OffsetLearner,
+    fusion: CourseFusion,
+}
+
+#[cfg(feature = "gps")]
+impl Stage<Inputs, Context<'_>> for Course {
+    fn name(&self) -> &'static str {
+        "course"
+    }
+
+    fn apply(&mut self, frame: &mut Frame, context: &Context<'_>) {
+        let dynamic = frame.input.motion == MotionClass::Dynamic;
+        if let Some(fix) = frame.input.fix {
+            // The offset is only learned from a heading that can be trusted
+            if !dynamic {
+                self.offset
+                    .observe(frame.heading, fix, context.settings.declination);
+            }
+            self.fusion.observe(fix);
+        }
+        frame.input.covered |= self.fusion.has_course();
+        frame.heading = self.fusion.fuse(
+            self.offset.apply(frame.heading),
+            context.settings.declination,
+            dynamic,
+        );
+    }
+}
+
+/// The last trusted heading, held through acceleration nothing else covers
+struct Hold(HeadingHold);
+
+impl Stage<Inputs, Context<'_>> for Hold {
+    fn name(&self) -> &'static str {
+        "hold"
+    }
+
+    fn apply(&mut self, frame: &mut Frame, _context: &Context<'_>) {
+        frame.heading = self
+            .0
+            .update(frame.heading, frame.input.motion, frame.input.covered);
+    }
+}
+
+/// The stages in order, and what each made of the last reading
+pub struct Pipeline {
+    median: Median,
+    average: Average,
+    calibrate: Calibrate,
+    remap: Remap,
+    pub gravity: Gravity,
+    tilt: Tilt,
+    deviation: Deviation,
+    #[cfg(feature = "gyro")]
+    gyro: Gyro,
+    #[cfg(feature = "gps")]
+    pub course: Course,
+    hold: Hold,
+    declination: Declination,
+    units: Units,
+    trace: Trace,
+}
+
+impl Pipeline {
+    pub const fn new() -> Self {
+        Self {
+            median: Median(MedianFilter::new(1)),
+            average: Average,
+            calibrate: Calibrate,
+            remap: Remap,
+            gravity: Gravity(GravityEstimate::new()),
+            tilt: Tilt,
+            deviation: Deviation,
+            #[cfg(feature = "gyro")]
+            gyro: Gyro(GyroFusion::new()),
+            #[cfg(feature = "gps")]
+            course: Course {
+                offset: OffsetLearner::new(),
+                fusion: CourseFusion::new(),
+            },
+            hold: Hold(HeadingHold::new()),
+            declination: Declination,
+            units: Units(HeadingUnit::Degrees),
+            trace: Trace::new(),
+        }
+    }
+
+    /// Median filter over `window` readings from now on, starting afresh
+    pub fn set_median(&mut self, window: usize) {
+        self.median.0.set_window(window);
+    }
+
+    /// Filter the frame's readings into its field, the raw one the
+    /// calibration is fitted to
+    pub fn filter(&mut self, frame: &mut Frame) {
+        self.trace.clear();
+        pipeline::run(
+            &mut [&mut self.median, &mut self.average],
+            frame,
+            &(),
+            &mut self.trace,
+        );
+    }
+
+    /// The stages after [`Self::filter`], in the order a reading goes
+    /// through them
+    fn stages<'a>(&mut self) -> Vec<&mut dyn Stage<Inputs, Context<'a>>, MAX_STAGES> {
+        let mut stages: Vec<&mut dyn Stage<Inputs, Context<'a>>, MAX_STAGES> = Vec::new();
+        let _ = stages.push(&mut self.calibrate);
+        let _ = stages.push(&mut self.remap);
+        let _ = stages.push(&mut self.gravity);
+        let _ = stages.push(&mut self.tilt);
+        let _ = stages.push(&mut self.deviation);
+        #[cfg(feature = "gyro")]
+        let _ = stages.push(&mut self.gyro);
+        #[cfg(feature = "gps")]
+        let _ = stages.push(&mut self.course);
+        let _ = stages.push(&mut self.hold);
+        let _ = stages.push(&mut self.declination);
+        let _ = stages.push(&mut self.units);
+        stages
+    }
+
+    /// Take the filtered `frame` through the rest of the stages
+    pub fn run(&mut self, frame: &mut Frame, context: &Context) {
+        let mut trace = core::mem::take(&mut self.trace);
+        pipeline::run(&mut self.stages(), frame, context, &mut trace);
+        self.trace = trace;
+    }
+
+    /// Reply with a line per stage, what it made of the last reading
+    pub async fn report(&self) {
+        for step in self.trace.lines() {
+            let mut line: String<{ console::MAX_REPLY }> = String::new();
+            let _ = write!(line, "{step}");
+            console::REPLIES.send(line).await;
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use core::fmt::Write;
+
+use defmt::{info, warn};
+use heapless::String;
+
+use crate::{
+    calibration::{self, Calibration},
+    console,
+    storage::{self, Storage},
+};
+
+/// Calibration profiles kept in flash
+pub const MAX_PROFILES: usize = 4;
+
+/// Longest profile name
+pub const MAX_NAME_LEN: usize = 8;
+
+/// What the first profile is called, also for a calibration saved before
+/// there were profiles, which it takes over
+const DEFAULT_NAME: &str = "default";
+
+/// calibration record + name, NUL padded, erased for a free slot
+const SLOT_LEN: usize = calibration::RECORD_LEN + MAX_NAME_LEN;
+
+const _: () = assert!(SLOT_LEN <= storage::MAX_RECORD_LEN);
+
+/// Where the index of the profile in use is kept, after the slots
+const ACTIVE_AT: usize = MAX_PROFILES * SLOT_LEN;
+
+/// The slots and the active index, which fill the start of the page
+pub const PAGE_LEN: usize = ACTIVE_AT + 4;
+
+pub type ProfileName = String<MAX_NAME_LEN>;
+
+/// Named magnetometer calibrations, one per place the board is used, e.g.
+/// handheld or strapped to a robot chassis, whose hard- and soft-iron
+/// distortions have nothing in common. Each calibration, however it is
+/// made, goes into the profile in use.
+///
+/// They share the calibration page, one slot each. Saving rewrites the page:
+/// the first slot with an erase, then the others and the active index into
+/// the erased flash after it.
+pub struct Profiles {
+    names: [Option<ProfileName>; MAX_PROFILES],
+    calibrations: [Calibration; MAX_PROFILES],
+    active: usize,
+}
+
+impl Profiles {
+    /// Restore the profiles, starting with only the default one, uncalibrated,
+    /// if flash holds none
+    pub fn load(storage: &mut Storage<'_>) -> Self {
+        let mut profiles = Self {
+            names: [const { None }; MAX_PROFILES],
+            calibrations: [Calibration::IDENTITY; MAX_PROFILES],
+            active: 0,
+        };
+        profiles.names[0] = validate(DEFAULT_NAME);
+        let mut buf = [0u8; PAGE_LEN];
+        if let Err(e) = storage.load(storage::CALIBRATION_PAGE, &mut buf) {
+            warn!("failed to read calibration: {}", e);
+            return profiles;
+        }
+        for (i, slot) in buf[..ACTIVE_AT].chunks_exact(SLOT_LEN).enumerate() {
+            let (record, stored_name) = slot.split_at(calibration::RECORD_LEN);
+            let stored_name = stored_name.split(|&b| b == 0).next().unwrap_or_default();
+            if let Some(stored_name) = core::str::from_utf8(stored_name).ok().and_then(validate) {
+                profiles.names[i] = Some(stored_name);
+            }
+            if let Some(calibration) = record.try_into().ok().and_then(Calibration::from_record) {
+                profiles.calibrations[i] = calibration;
+            }
+        }
+        let active = u32::from_le_bytes([
+            buf[ACTIVE_AT],
+            buf[ACTIVE_AT + 1],
+            buf[ACTIVE_AT + 2],
+            buf[ACTIVE_AT + 3],
+        ]) as usize;
+        // Erased before any switch
+        if profiles.names.get(active).is_some_and(Option::is_some) {
+            profiles.active = active;
+        }
+        info!(
+            "calibration profile {}, {}",
+            profiles.name(),
+            if profiles.calibration().is_calibrated() {
+                "calibrated"
+            } else {
+                "not calibrated"
+            }
+        );
+        profiles
+    }
+
+    /// The calibration of the profile in use
+    pub fn calibration(&self) -> Calibration {
+        self.calibrations[self.active]
+    }
+
+    pub fn name(&self) -> &str {
+        self.names[self.active].as_deref().unwrap_or(DEFAULT_NAME)
+    }
+
+    /// Store `calibration` in the profile in use
+    pub fn save(&mut self, calibration: Calibration) {
+        self.calibrations[self.active] = calibration;
+        self.write();
+    }
+
+    /// Switch to the profile called `name`, made afresh and uncalibrated if
+    /// there is none yet, returning its calibration
+    pub fn select(&mut self, name: &str) -> Result<Calibration, &'static str> {
+        let name = validate(name).ok_or("profile names are 1 to 8 letters, digits or -")?;
+        let existing = self.names.iter().position(|n| n.as_ref() == Some(&name));
+        let slot = match existing {
+            Some(slot) => slot,
+            None => {
+                let free = self
+                    .names
+                    .iter()
+                    .position(Option::is_none)
+                    .ok_or("no free profile, delete one first")?;
+                self.names[free] = Some(name);
+                self.calibrations[free] = Calibration::IDENTITY;
+                free
+            }
+        };
+        self.active = slot;
+        self.write();
+        info!("calibration profile {}", self.name());
+        Ok(self.calibration())
+    }
+
+    /// Free the profile called `name`, which mustn't be the one in use or
+    /// the default
+    pub fn delete(&mut self, name: &str) -> Result<(), &'static str> {
+        let slot = self
+            .names
+            .iter()
+            .position(|n| n.as_deref() == Some(name))
+            .ok_or("no such profile")?;
+        if slot == 0 {
+            return Err("the default profile can't be deleted");
+        }
+        if slot == self.active {
+            return Err("profile in use, switch to another first");
+        }
+        self.names[slot] = None;
+        self.calibrations[slot] = Calibration::IDENTITY;
+        self.write();
+        Ok(())
+    }
+
+    /// Reply with a line per profile, the one in use marked with `*`
+    pub async fn list(&self) {
+        for (slot, name) in self.names.iter().enumerate() {
+            let Some(name) = name else {
+                continue;
+            };
+            let mut line: String<{ console::MAX_REPLY }> = String::new();
+            let _ = write!(
+                line,
+                "{}{} {}",
+                if slot == self.active { "*" } else { " " },
+                name,
+                if self.calibrations[slot].is_calibrated() {
+                    "calibrated"
+                } else {
+                    "not calibrated"
+                }
+            );
+            console::REPLIES.send(line).await;
+        }
+    }
+
+    fn write(&self) {
+        let slot = |i: usize| {
+            let mut buf = [0xFF; SLOT_LEN];
+            if let Some(name) = &self.names[i] {
+                buf[..calibration::RECORD_LEN].copy_from_slice(&self.calibrations[i].record());
+                let name_buf = &mut buf[calibration::RECORD_LEN..];
+                name_buf.fill(0);
+                name_buf[..name.len()].copy_from_slice(name.as_bytes());
+            }
+            buf
+        };
+        storage::save(storage::CALIBRATION_PAGE, &slot(0));
+        for i in 1..MAX_PROFILES {
+            if self.names[i].is_some() {
+                storage::write(storage::CALIBRATION_PAGE + (i * SLOT_LEN) as u32, &slot(i));
+            }
+        }
+        storage::write(
+            storage::CALIBRATION_PAGE + ACTIVE_AT as u32,
+            &(self.active as u32).to_le_bytes(),
+        );
+    }
+}
+
+fn validate(name: &str) -> Option<ProfileName> {
+    let valid = name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+    (valid && !name.is_empty())
+        .then(|| String::try_from(name).ok())
+        .flatten()
+}
@@ -0,0 +1,115 @@
+use embedded_hal_async::i2c::I2c;
+
+use crate::{
+    error::{Error, SensorOp},
+    sensor::{Bus, Magnetometer},
+};
+
+const ADDRESS: u8 = 0x0D;
+
+const CHIP_ID: u8 = 0x0D;
+const ID: u8 = 0xFF;
+
+/// X, Y and Z, little-endian
+const OUT: u8 = 0x00;
+const STATUS: u8 = 0x06;
+const CONTROL_1: u8 = 0x09;
+const SET_RESET_PERIOD: u8 = 0x0B;
+
+/// New data is ready
+const DRDY: u8 = 0b0000_0001;
+
+/// Fields of CONTROL_1: oversampling ratio, range, output data rate and mode
+const OSR_512: u8 = 0b00 << 6;
+const OSR_64: u8 = 0b11 << 6;
+const RNG_2_GAUSS: u8 = 0b00 << 4;
+const MODE_STANDBY: u8 = 0b00;
+const MODE_CONTINUOUS: u8 = 0b01;
+/// Recommended by the datasheet
+const SET_RESET_PERIOD_VALUE: u8 = 0x01;
+
+/// nT per LSB at ±2 gauss, 12000 LSB/gauss
+const NT_PER_LSB: f32 = 100_000.0 / 12_000.0;
+
+/// Output data rates with their ODR bits in CONTROL_1, slowest first
+const RATES: [(u16, u8); 4] = [(10, 0b00), (50, 0b01), (100, 0b10), (200, 0b11)];
+
+/// A QST QMC5883L magnetometer on the shared bus, found on many HMC5883L
+/// breakouts
+pub struct Qmc5883l {
+    i2c: Bus,
+    /// CONTROL_1 without the mode, to measure again after sleeping
+    control: u8,
+}
+
+impl Qmc5883l {
+    /// `None` unless a QMC5883L answers, otherwise configured to measure
+    /// continuously at `hz`
+    pub async fn probe(mut i2c: Bus, hz: u16) -> Result<Option<Self>, Error> {
+        let mut id = [0];
+        if i2c.write_read(ADDRESS, &[CHIP_ID], &mut id).await.is_err() || id[0] != ID {
+            return Ok(None);
+        }
+        let mut mag = Self { i2c, control: 0 };
+        let op = SensorOp::ConfigureMagnetometer;
+        mag.write(SET_RESET_PERIOD, SET_RESET_PERIOD_VALUE, op)
+            .await?;
+        mag.set_rate(hz, false).await?;
+        Ok(Some(mag))
+    }
+
+    async fn write(&mut self, register: u8, value: u8, op: SensorOp) -> Result<(), Error> {
+        self.i2c
+            .write(ADDRESS, &[register, value])
+            .await
+            .map_err(Error::bus(op))
+    }
+}
+
+impl Magnetometer for Qmc5883l {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        let op = SensorOp::ReadMagnetometer;
+        let mut status = [0];
+        self.i2c
+            .write_read(ADDRESS, &[STATUS], &mut status)
+            .await
+            .map_err(Error::bus(op))?;
+        if status[0] & DRDY == 0 {
+            return Ok(None);
+        }
+        let mut buf = [0u8; 6];
+        self.i2c
+            .write_read(ADDRESS, &[OUT], &mut buf)
+            .await
+            .map_err(Error::bus(op))?;
+        Ok(Some([0, 1, 2].map(|axis| {
+            i16::from_le_bytes([buf[2 * axis], buf[2 * axis + 1]]) as f32 * NT_PER_LSB
+        })))
+    }
+
+    /// 10, 50, 100 or 200 Hz
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error> {
+        let (osr, rate) = if low_power {
+            (OSR_64, RATES[0].1)
+        } else {
+            let (_, rate) = RATES
+                .into_iter()
+                .find(|&(rate_hz, _)| rate_hz >= hz)
+                .unwrap_or(RATES[RATES.len() - 1]);
+            (OSR_512, rate)
+        };
+        self.control = osr | RNG_2_GAUSS | rate << 2;
+        self.write(CONTROL_1, self.control | MODE_CONTINUOUS, SensorOp::SetRate)
+            .await
+    }
+
+    async fn sleep(&mut self) -> Result<(), Error> {
+        self.write(CONTROL_1, self.control | MODE_STANDBY, SensorOp::Idle)
+            .await
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        self.write(CONTROL_1, self.control | MODE_CONTINUOUS, SensorOp::Wake)
+            .await
+    }
+}
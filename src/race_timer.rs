@@ -0,0 +1,80 @@
+use core::cell::Cell;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+/// The start sequence, from the warning signal to the start
+const SEQUENCE: Duration = Duration::from_secs(5 * 60);
+
+/// How long the start stays on the display after the gun
+const SHOW_START_FOR: Duration = Duration::from_secs(2);
+
+/// When the start gun goes, while a countdown is running
+static GUN: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+pub fn gun() -> Option<Instant> {
+    GUN.lock(Cell::get)
+}
+
+/// Begin the 5-4-1-0 sequence: warning signal now, preparatory signal at
+/// four minutes, one minute, then the start
+pub fn start() {
+    info!("race: countdown started");
+    GUN.lock(|gun| gun.set(Some(Instant::now() + SEQUENCE)));
+}
+
+pub fn stop() -> bool {
+    GUN.lock(|gun| gun.take()).is_some()
+}
+
+/// Round the countdown to the nearest whole minute, on hearing a signal from
+/// the committee boat. `false` if no countdown is running.
+pub fn sync() -> bool {
+    GUN.lock(|gun| {
+        let Some(at) = gun.get() else {
+            return false;
+        };
+        let remaining = at.saturating_duration_since(Instant::now()).as_millis();
+        let minutes = (remaining + 30_000) / 60_000;
+        info!("race: synced to {} minutes", minutes);
+        gun.set(Some(Instant::now() + Duration::from_secs(minutes * 60)));
+        true
+    })
+}
+
+/// Time left to the start, `None` without a countdown. The countdown ends a
+/// little after the gun, so the start has been seen.
+pub fn remaining() -> Option<Duration> {
+    let at = gun()?;
+    if Instant::now() > at + SHOW_START_FOR {
+        info!("race: started");
+        stop();
+        return None;
+    }
+    Some(at.saturating_duration_since(Instant::now()))
+}
+
+/// Digit shown over the compass: the minutes left rounded up, then the
+/// seconds over the last few
+pub fn digit(remaining: Duration) -> u32 {
+    let ms = remaining.as_millis();
+    if ms <= 9_000 {
+        ms.div_ceil(1000) as u32
+    } else {
+        ms.div_ceil(60_000) as u32
+    }
+}
+
+/// Beep, in ms, with `seconds` left to the start: long ones on the signals,
+/// short ones every 10 s over the last minute and every second over the last
+/// five, and the longest for the start
+pub fn beep_ms(seconds: u64) -> Option<u32> {
+    match seconds {
+        0 => Some(1000),
+        60 | 240 | 300 => Some(500),
+        1..=5 => Some(100),
+        10..=50 if seconds.is_multiple_of(10) => Some(100),
+        _ => None,
+    }
+}
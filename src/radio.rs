@@ -0,0 +1,315 @@
+use defmt::{info, warn, Format};
+use embassy_nrf::{
+    pac::{self, radio::vals},
+    peripherals::RADIO,
+    radio::{ble::Radio, TxPower},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use micromath::F32Ext;
+
+use crate::{
+    error::{Error, RadioOp},
+    health::{self, Subsystem, Task},
+    time_sync,
+};
+
+/// Address shared by every micro:bit, "ubit"
+const BASE_ADDRESS: u32 = 0x7562_6974;
+
+/// 2407 MHz, the micro:bit default
+const FREQUENCY: u8 = 7;
+
+/// Longest packet after the length byte
+const MAX_PACKET_LEN: usize = 32;
+
+/// Length byte, then the rest of the packet
+const FRAME_LEN: usize = 1 + MAX_PACKET_LEN;
+
+/// The micro:bit runtime's header after the length byte: version, group,
+/// protocol
+const VERSION: u8 = 1;
+const PROTOCOL_DATAGRAM: u8 = 1;
+const HEADER_LEN: usize = 3;
+
+/// Time sync beacons, not one of the runtime's protocols so MakeCode
+/// programs ignore them. The payload is the sender's time, µs, `u64`.
+const PROTOCOL_TIME: u8 = 0x54;
+
+/// MakeCode's packet types for `radio.sendValue(name, value)`, with an integer
+/// or a floating point value
+const PACKET_TYPE_VALUE: u8 = 1;
+const PACKET_TYPE_DOUBLE_VALUE: u8 = 5;
+
+/// Packet type, send time (ms) and serial number, both `i32`
+const MAKECODE_HEADER_LEN: usize = 9;
+
+/// Name the heading is sent under
+const VALUE_NAME: &[u8] = b"heading";
+
+/// Headings are sent at most this often
+const SEND_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long to listen before checking for a new configuration
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Spread of the time listened for between sends in [`RadioMode::Compare`]
+const COMPARE_JITTER: Duration = Duration::from_millis(100);
+
+/// Time between beacons in [`RadioMode::Clock`]
+const BEACON_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the radio does
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum RadioMode {
+    Off,
+    /// Broadcast the heading to other boards in the group
+    Send,
+    /// Listen for another board's heading
+    Receive,
+    /// Send and listen in turn, for two boards to compare headings
+    Compare,
+    /// Beacon this board's time, for the boards in [`RadioMode::Sync`] to
+    /// log on
+    Clock,
+    /// Take the time from the group's [`RadioMode::Clock`] board, see
+    /// [`time_sync`]
+    Sync,
+}
+
+/// Mode and group (0-255), boards only hear others in the same group
+pub static CONFIG: Signal<CriticalSectionRawMutex, (RadioMode, u8)> = Signal::new();
+
+/// Heading to broadcast in [`RadioMode::Send`] and [`RadioMode::Compare`]
+pub static HEADING: Signal<CriticalSectionRawMutex, f32> = Signal::new();
+
+/// Heading received from another board in [`RadioMode::Receive`] and
+/// [`RadioMode::Compare`]
+pub static REMOTE_HEADING: Signal<CriticalSectionRawMutex, f32> = Signal::new();
+
+/// Broadcast or receive headings with the micro:bit runtime's radio settings
+/// (Nordic proprietary 1 Mbit mode), so nearby boards can follow each other
+#[embassy_executor::task]
+pub async fn radio_task(mut radio: Radio<'static, RADIO>) {
+    radio.set_tx_power(TxPower::POS4_DBM);
+    let (mut mode, mut group) = CONFIG.wait().await;
+    configure(group);
+    time_sync::follow(mode == RadioMode::Sync);
+
+    let mut frame = [0u8; FRAME_LEN];
+    loop {
+        if let Some((new_mode, new_group)) = CONFIG.try_take() {
+            info!("radio: {}, group {}", new_mode, new_group);
+            (mode, group) = (new_mode, new_group);
+            configure(group);
+            time_sync::follow(mode == RadioMode::Sync);
+        }
+
+        match mode {
+            RadioMode::Off => {
+                health::report(Subsystem::Radio, true);
+                // Sleep until reconfigured, applied at the top of the loop
+                let config = CONFIG.wait().await;
+                CONFIG.signal(config);
+            }
+            RadioMode::Send => {
+                let heading = HEADING.wait().await;
+                send(&mut radio, &mut frame, group, heading).await;
+                Timer::after(SEND_INTERVAL).await;
+            }
+            RadioMode::Receive => listen(&mut radio, &mut frame, group, RECEIVE_TIMEOUT).await,
+            RadioMode::Compare => {
+                if let Some(heading) = HEADING.try_take() {
+                    send(&mut radio, &mut frame, group, heading).await;
+                }
+                // Listen until the next send, for a little longer or shorter
+                // each time so two boards don't keep sending over each other
+                let jitter = Instant::now().as_ticks() % COMPARE_JITTER.as_ticks();
+                let timeout = SEND_INTERVAL - COMPARE_JITTER / 2 + Duration::from_ticks(jitter);
+                listen(&mut radio, &mut frame, group, timeout).await;
+            }
+            RadioMode::Clock => {
+                encode_beacon(&mut frame, group);
+                transmit(&mut radio, &frame, group).await;
+                Timer::after(BEACON_INTERVAL).await;
+            }
+            RadioMode::Sync => listen(&mut radio, &mut frame, group, RECEIVE_TIMEOUT).await,
+        }
+    }
+}
+
+/// Broadcast `heading`, starting the radio over if the transfer stalls
+async fn send(
+    radio: &mut Radio<'static, RADIO>,
+    frame: &mut [u8; FRAME_LEN],
+    group: u8,
+    heading: f32,
+) {
+    encode(frame, group, heading);
+    transmit(radio, frame, group).await;
+    trace!("radio heading sent");
+}
+
+/// Send the packet in `frame`, starting the radio over if the transfer
+/// stalls
+async fn transmit(radio: &mut Radio<'static, RADIO>, frame: &[u8; FRAME_LEN], group: u8) {
+    let Some(sent) = health::watch(Task::Radio, radio.transmit(frame)).await else {
+        restart(group);
+        return;
+    };
+    if sent.is_err() {
+        warn!("{}", Error::Radio(RadioOp::Transmit));
+    }
+    health::report(Subsystem::Radio, sent.is_ok());
+}
+
+/// Listen for another board's heading for up to `timeout`, passing it on as
+/// [`REMOTE_HEADING`], or the clock board's beacon, passed on to
+/// [`time_sync`]
+async fn listen(
+    radio: &mut Radio<'static, RADIO>,
+    frame: &mut [u8; FRAME_LEN],
+    group: u8,
+    timeout: Duration,
+) {
+    let received = health::watch(Task::Radio, with_timeout(timeout, radio.receive(frame))).await;
+    let Some(received) = received else {
+        restart(group);
+        return;
+    };
+    match received {
+        Ok(Ok(())) => {
+            let received = Instant::now();
+            health::report(Subsystem::Radio, true);
+            let crc_ok = pac::RADIO.crcstatus().read().crcstatus() == vals::Crcstatus::CRCOK;
+            if let Some(clock_us) = decode_beacon(frame, group).filter(|_| crc_ok) {
+                time_sync::beacon(clock_us, received);
+                trace!("radio beacon received");
+            } else if let Some(heading) = decode(frame, group).filter(|_| crc_ok) {
+                REMOTE_HEADING.signal(heading);
+                trace!("radio heading received");
+            }
+        }
+        Ok(Err(_)) => {
+            warn!("{}", Error::Radio(RadioOp::Receive));
+            health::report(Subsystem::Radio, false);
+        }
+        // Nothing heard, the receive was stopped when dropped
+        Err(_) => disable(),
+    }
+}
+
+/// Packet layout and addressing of the micro:bit runtime. The driver sets up
+/// BLE packets, which differ in almost every field, so the registers are
+/// written directly. The radio must be disabled.
+fn configure(group: u8) {
+    let r = pac::RADIO;
+    r.mode().write(|w| w.set_mode(vals::Mode::NRF_1MBIT));
+    r.frequency().write(|w| {
+        w.set_frequency(FREQUENCY);
+        w.set_map(vals::Map::DEFAULT);
+    });
+    r.pcnf0().write(|w| {
+        w.set_lflen(8);
+        w.set_s0len(false);
+        w.set_s1len(0);
+        w.set_plen(vals::Plen::_8BIT);
+    });
+    r.pcnf1().write(|w| {
+        w.set_maxlen(MAX_PACKET_LEN as u8);
+        w.set_statlen(0);
+        w.set_balen(4);
+        w.set_endian(vals::Endian::LITTLE);
+        w.set_whiteen(true);
+    });
+    r.base0().write_value(BASE_ADDRESS);
+    r.prefix0().write(|w| w.set_ap0(group));
+    r.txaddress().write(|w| w.set_txaddress(0));
+    r.rxaddresses().write(|w| w.set_addr0(true));
+    r.crccnf().write(|w| {
+        w.set_len(vals::Len::TWO);
+        w.set_skipaddr(vals::Skipaddr::INCLUDE);
+    });
+    r.crcinit().write(|w| w.set_crcinit(0xFFFF));
+    r.crcpoly().write(|w| w.set_crcpoly(0x1_1021));
+    r.datawhiteiv().write(|w| w.set_datawhiteiv(0x18));
+}
+
+/// Start over after the supervisor gave up on a stalled transfer
+fn restart(group: u8) {
+    health::report(Subsystem::Radio, false);
+    disable();
+    configure(group);
+}
+
+/// Bring the radio back to disabled after an interrupted receive
+fn disable() {
+    let r = pac::RADIO;
+    r.events_disabled().write_value(0);
+    r.tasks_disable().write_value(1);
+    while r.events_disabled().read() == 0 {}
+    r.events_disabled().write_value(0);
+}
+
+/// Datagram laid out like MakeCode's `radio.sendValue("heading", x)`, with the
+/// heading in whole degrees, so stock MakeCode programs receive it with
+/// `radio.onReceivedValue`. No serial number is sent.
+fn encode(frame: &mut [u8; FRAME_LEN], group: u8, heading: f32) {
+    let time = Instant::now().as_millis() as i32;
+    let value = heading.round() as i32 % 360;
+
+    let mut payload = [0u8; MAKECODE_HEADER_LEN + 4 + 1 + VALUE_NAME.len()];
+    payload[0] = PACKET_TYPE_VALUE;
+    payload[1..5].copy_from_slice(&time.to_le_bytes());
+    payload[5..9].copy_from_slice(&0i32.to_le_bytes());
+    payload[9..13].copy_from_slice(&value.to_le_bytes());
+    payload[13] = VALUE_NAME.len() as u8;
+    payload[14..].copy_from_slice(VALUE_NAME);
+
+    frame[0] = (HEADER_LEN + payload.len()) as u8;
+    frame[1..1 + HEADER_LEN].copy_from_slice(&[VERSION, group, PROTOCOL_DATAGRAM]);
+    frame[1 + HEADER_LEN..1 + HEADER_LEN + payload.len()].copy_from_slice(&payload);
+}
+
+/// A time sync beacon with this board's time now
+fn encode_beacon(frame: &mut [u8; FRAME_LEN], group: u8) {
+    let time = Instant::now().as_micros();
+    frame[0] = (HEADER_LEN + 8) as u8;
+    frame[1..1 + HEADER_LEN].copy_from_slice(&[VERSION, group, PROTOCOL_TIME]);
+    frame[1 + HEADER_LEN..1 + HEADER_LEN + 8].copy_from_slice(&time.to_le_bytes());
+}
+
+/// The time in a beacon from the group's clock board, µs
+fn decode_beacon(frame: &[u8; FRAME_LEN], group: u8) -> Option<u64> {
+    let len = frame[0] as usize;
+    if len != HEADER_LEN + 8 || frame[1..1 + HEADER_LEN] != [VERSION, group, PROTOCOL_TIME] {
+        return None;
+    }
+    let time = &frame[1 + HEADER_LEN..1 + len];
+    Some(u64::from_le_bytes(time.try_into().ok()?))
+}
+
+/// The value of a MakeCode `sendValue` datagram named `heading`, whether from
+/// another compass or a MakeCode program
+fn decode(frame: &[u8; FRAME_LEN], group: u8) -> Option<f32> {
+    let len = (frame[0] as usize).min(MAX_PACKET_LEN);
+    if len < HEADER_LEN || frame[1..1 + HEADER_LEN] != [VERSION, group, PROTOCOL_DATAGRAM] {
+        return None;
+    }
+    let payload = &frame[1 + HEADER_LEN..1 + len];
+    let (value, name) = match *payload.first()? {
+        PACKET_TYPE_VALUE => {
+            let value = payload.get(MAKECODE_HEADER_LEN..MAKECODE_HEADER_LEN + 4)?;
+            let value = i32::from_le_bytes(value.try_into().ok()?) as f32;
+            (value, &payload[MAKECODE_HEADER_LEN + 4..])
+        }
+        PACKET_TYPE_DOUBLE_VALUE => {
+            let value = payload.get(MAKECODE_HEADER_LEN..MAKECODE_HEADER_LEN + 8)?;
+            let value = f64::from_le_bytes(value.try_into().ok()?) as f32;
+            (value, &payload[MAKECODE_HEADER_LEN + 8..])
+        }
+        _ => return None,
+    };
+    let (&name_len, name) = name.split_first()?;
+    (name.get(..name_len as usize)? == VALUE_NAME).then_some(value)
+}
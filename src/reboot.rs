@@ -0,0 +1,30 @@
+use cortex_m::peripheral::SCB;
+use defmt::{info, Format};
+use embassy_nrf::pac;
+
+/// Left in GPREGRET, which survives a soft reset, to ask a bootloader to stay
+/// in firmware update mode: `BOOTLOADER_DFU_START` of the nRF5 SDK's
+/// buttonless DFU, which the Adafruit nRF52 bootloader accepts too
+const DFU_MAGIC: u8 = 0xB1;
+
+/// What to start after the reset
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Target {
+    /// This firmware again
+    Application,
+    /// The bootloader's update mode, to load new firmware over BLE or USB
+    /// without a debug probe
+    Bootloader,
+}
+
+/// Reset the board into `target`. Callers shut the peripherals down first,
+/// as for [`crate::sleep::system_off`].
+pub fn reset(target: Target) -> ! {
+    info!("rebooting into {}", target);
+    let magic = match target {
+        Target::Application => 0,
+        Target::Bootloader => DFU_MAGIC,
+    };
+    pac::POWER.gpregret().write(|w| w.set_gpregret(magic));
+    SCB::sys_reset()
+}
@@ -0,0 +1,269 @@
+use core::fmt::Write;
+
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Instant};
+use heapless::String;
+use micro_compass_core::heading::{angle_diff, compute_heading};
+use micromath::F32Ext;
+
+use crate::{
+    calibration::Calibration, console, error::Error, mounting::Mounting, settings::Settings,
+    telemetry,
+};
+
+/// Readings the backup's offset is first averaged over before its heading
+/// counts
+const LEARN_SAMPLES: u32 = 50;
+
+/// Weight of each later reading in the backup's offset, so it follows a slow
+/// drift over some minutes but not a sudden disturbance
+const LEARN_GAIN: f32 = 0.002;
+
+/// How long the headings have to disagree before it is a fault, and agree
+/// again before it clears, so a single bad reading flags nothing
+const DWELL: Duration = Duration::from_secs(2);
+
+/// Longest without a reading from the backup before it counts as gone
+const STALE: Duration = Duration::from_secs(2);
+
+/// Which magnetometer the heading comes from
+#[derive(Clone, Copy, PartialEq, Debug, Format)]
+pub enum Source {
+    /// The one a lone magnetometer would be: the first external part found
+    Primary,
+    /// The next external part found, or the board's own
+    Backup,
+}
+
+/// The cross-check's verdict, sent as a telemetry event whenever it changes
+#[derive(Clone, Copy, Debug, Format)]
+pub struct Status {
+    /// The headings disagree by more than `set redundancy`
+    pub fault: bool,
+    pub selected: Source,
+    /// Backup heading less the primary's, degrees
+    pub difference: f32,
+}
+
+/// Runs a second magnetometer alongside the first and compares the two
+/// tilt-compensated headings. The backup's hard-iron offset is learned from
+/// the calibrated primary while they agree, so it needs no calibration of
+/// its own, but its axes have to line up with the primary's. A disagreement
+/// beyond the limit for [`DWELL`] is a fault, and the heading then comes
+/// from whichever of the two reads a field strength closer to the one
+/// calibrated, until they agree again.
+pub struct CrossCheck {
+    /// The backup's hard-iron offset, nT, in its own frame
+    offset: [f32; 3],
+    /// Readings the offset has been learned from, up to [`LEARN_SAMPLES`]
+    learned: u32,
+    /// The backup's latest reading and when it came
+    backup: Option<([f32; 3], Instant)>,
+    difference: Option<f32>,
+    /// Since when the difference has been on the other side of the limit
+    /// from `fault`
+    changing: Option<Instant>,
+    fault: bool,
+    selected: Source,
+}
+
+impl CrossCheck {
+    pub const fn new() -> Self {
+        Self {
+            offset: [0.0; 3],
+            learned: 0,
+            backup: None,
+            difference: None,
+            changing: None,
+            fault: false,
+            selected: Source::Primary,
+        }
+    }
+
+    /// Take the primary's raw reading, the backup's latest, gravity in the
+    /// flat board frame and the limit, degrees. The backup's reading and
+    /// calibration when it is the one to use.
+    pub fn update(
+        &mut self,
+        primary: [f32; 3],
+        backup: Result<Option<[f32; 3]>, Error>,
+        calibration: &Calibration,
+        gravity: [f32; 3],
+        mounting: Mounting,
+        limit: u8,
+    ) -> Option<([f32; 3], Calibration)> {
+        match backup {
+            Ok(Some(field)) => self.backup = Some((field, Instant::now())),
+            Ok(None) => {}
+            Err(e) => {
+                warn!("backup magnetometer: {}", e);
+                self.backup = None;
+            }
+        }
+        let backup = self
+            .backup
+            .filter(|(_, at)| at.elapsed() < STALE)
+            .map(|(field, _)| field);
+        // Nothing to check against, or nothing to learn the offset from
+        let (Some(backup), true) = (backup, calibration.is_calibrated()) else {
+            self.difference = None;
+            self.changing = None;
+            if self.fault {
+                warn!("nothing to check the magnetometer against, using the primary");
+                self.switch(false, Source::Primary, 0.0);
+            }
+            return None;
+        };
+        let backup_calibration = self.calibration(calibration);
+        if self.learned < LEARN_SAMPLES {
+            self.learn(primary, backup, calibration);
+            return None;
+        }
+
+        let heading = |mag, calibration: &Calibration| {
+            let [grav_x, grav_y, grav_z] = gravity;
+            let [mag_x, mag_y, mag_z] = mounting.apply(calibration.apply(mag));
+            compute_heading(grav_x, grav_y, grav_z, mag_x, mag_y, mag_z)
+        };
+        let difference = angle_diff(
+            heading(backup, &backup_calibration),
+            heading(primary, calibration),
+        );
+        self.difference = Some(difference);
+        let disagree = difference.abs() > f32::from(limit);
+        if disagree == self.fault {
+            self.changing = None;
+        } else if self.changing.get_or_insert_with(Instant::now).elapsed() >= DWELL {
+            self.changing = None;
+            let selected = match disagree {
+                true => healthier(
+                    calibration.apply(primary),
+                    backup_calibration.apply(backup),
+                    calibration.field_strength(),
+                ),
+                false => Source::Primary,
+            };
+            match disagree {
+                true => warn!(
+                    "magnetometers disagree by {}°, using the {}",
+                    difference, selected
+                ),
+                false => info!("magnetometers agree again, using the primary"),
+            }
+            self.switch(disagree, selected, difference);
+        }
+        if !self.fault {
+            self.learn(primary, backup, calibration);
+        }
+        match self.selected {
+            Source::Primary => None,
+            Source::Backup => Some((backup, backup_calibration)),
+        }
+    }
+
+    /// Flag or clear the fault, report it and use `selected` from now on
+    fn switch(&mut self, fault: bool, selected: Source, difference: f32) {
+        self.fault = fault;
+        self.selected = selected;
+        // Dropped if the telemetry task has fallen behind
+        let _ = telemetry::EVENTS.try_send(telemetry::Event::CrossCheck(Status {
+            fault,
+            selected,
+            difference,
+        }));
+    }
+
+    /// Move the backup's offset towards what makes its reading the
+    /// primary's calibrated one
+    fn learn(&mut self, primary: [f32; 3], backup: [f32; 3], calibration: &Calibration) {
+        let corrected = calibration.apply(primary);
+        let gain = match self.learned < LEARN_SAMPLES {
+            true => 1.0 / (self.learned + 1) as f32,
+            false => LEARN_GAIN,
+        };
+        for ((offset, backup), corrected) in self.offset.iter_mut().zip(backup).zip(corrected) {
+            *offset += (backup - corrected - *offset) * gain;
+        }
+        self.learned = (self.learned + 1).min(LEARN_SAMPLES);
+    }
+
+    /// The backup's calibration: its learned offset, and the primary's
+    /// field strength
+    fn calibration(&self, primary: &Calibration) -> Calibration {
+        Calibration::new(
+            self.offset,
+            Calibration::IDENTITY.soft_iron(),
+            primary.field_strength(),
+        )
+    }
+
+    /// The last verdict, `None` while there is nothing to compare or the
+    /// offset is still being learned
+    pub fn status(&self) -> Option<Status> {
+        self.difference.map(|difference| Status {
+            fault: self.fault,
+            selected: self.selected,
+            difference,
+        })
+    }
+
+    /// Share of [`LEARN_SAMPLES`] the backup's offset has been learned from,
+    /// 0 to 1
+    pub fn learned(&self) -> f32 {
+        self.learned as f32 / LEARN_SAMPLES as f32
+    }
+}
+
+/// The one whose calibrated field is nearer `strength`, nT. A part reading
+/// a magnet, saturated or stuck is usually far off it.
+fn healthier(primary: [f32; 3], backup: [f32; 3], strength: f32) -> Source {
+    if strength <= 0.0 {
+        return Source::Primary;
+    }
+    let error = |[x, y, z]: [f32; 3]| ((x * x + y * y + z * z).sqrt() / strength - 1.0).abs();
+    match error(backup) < error(primary) {
+        true => Source::Backup,
+        false => Source::Primary,
+    }
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Primary => "primary",
+            Self::Backup => "backup",
+        }
+    }
+}
+
+/// Reply to `redundancy` with the magnetometers, the primary first, then
+/// how far apart their headings are and which is in use, or how far the
+/// backup's offset has been learned
+pub async fn report(
+    check: &CrossCheck,
+    (primary, backup): (&str, Option<&str>),
+    settings: &Settings,
+) {
+    let mut line: String<{ console::MAX_REPLY }> = String::new();
+    let _ = match backup {
+        Some(backup) => write!(line, "magnetometers {primary} {backup}"),
+        None => write!(line, "magnetometers {primary}, no backup"),
+    };
+    console::REPLIES.send(line).await;
+    if backup.is_none() {
+        return;
+    }
+    let mut line: String<{ console::MAX_REPLY }> = String::new();
+    let _ = match check.status() {
+        Some(status) => write!(
+            line,
+            "difference {:.1} limit {} {} using {}",
+            status.difference,
+            settings.redundancy_limit,
+            if status.fault { "fault" } else { "ok" },
+            status.selected.name()
+        ),
+        None => write!(line, "learning {:.0}%", check.learned() * 100.0),
+    };
+    console::REPLIES.send(line).await;
+}
@@ -0,0 +1,119 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::warn;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{with_timeout, Duration};
+
+use crate::{
+    error::Error,
+    sensor::{AccelPower, Accelerometer, Magnetometer},
+};
+
+/// Longest wait for the next replayed reading before the main loop comes
+/// round again, e.g. to feed the watchdog
+const WAIT: Duration = Duration::from_millis(250);
+
+/// One accelerometer and magnetometer reading, mg and nT
+#[derive(Clone, Copy)]
+pub struct Reading {
+    accel: [f32; 3],
+    mag: [f32; 3],
+}
+
+/// Readings received on the console, waiting to be replayed
+static READINGS: Channel<CriticalSectionRawMutex, Reading, 8> = Channel::new();
+
+/// Whether readings come from the console rather than the sensors. Kept
+/// apart from [`crate::sensor::Sensor`] so replay carries on across sensor
+/// restarts.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Switch replay on or off, dropping anything left over from before
+pub fn set_active(active: bool) {
+    READINGS.clear();
+    ACTIVE.store(active, Ordering::Relaxed);
+}
+
+/// The raw readings in a text telemetry line:
+///
+/// ```text
+/// HDG,<heading>,<reference>,<display reference>,<pitch>,<roll>,<ax>,<ay>,<az>,<mx>,<my>,<mz>,<rate of turn>,<ms>
+/// ```
+pub fn parse(line: &str) -> Option<Reading> {
+    let mut fields = line.strip_prefix("HDG,")?.split(',').skip(5);
+    let mut next = || fields.next()?.parse::<f32>().ok();
+    Some(Reading {
+        accel: [next()?, next()?, next()?],
+        mag: [next()?, next()?, next()?],
+    })
+}
+
+/// Queue a reading from the console, while replay is on
+pub fn feed(reading: Reading) {
+    if active() && READINGS.try_send(reading).is_err() {
+        warn!("replayed reading dropped, send them more slowly");
+    }
+}
+
+/// Replayed readings standing in for the sensors. Each reading's field comes
+/// with its acceleration; further magnetometer reads, e.g. for averaging,
+/// take the next reading's.
+pub struct Replay {
+    mag: Option<[f32; 3]>,
+}
+
+impl Replay {
+    pub const fn new() -> Self {
+        Self { mag: None }
+    }
+}
+
+impl Accelerometer for Replay {
+    async fn acceleration(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        Ok(with_timeout(WAIT, READINGS.receive())
+            .await
+            .ok()
+            .map(|reading| {
+                self.mag = Some(reading.mag);
+                reading.accel
+            }))
+    }
+
+    async fn set_accel_rate(&mut self, _hz: u16, _power: AccelPower) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_wake_on_motion(&mut self, _enabled: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Magnetometer for Replay {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        Ok(self
+            .mag
+            .take()
+            .or_else(|| READINGS.try_receive().ok().map(|reading| reading.mag)))
+    }
+
+    /// Readings come as fast as they're sent
+    async fn set_rate(&mut self, _hz: u16, _low_power: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn sleep(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
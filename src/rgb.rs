@@ -0,0 +1,134 @@
+use defmt::warn;
+use embassy_nrf::{peripherals::SPI2, spim::Spim};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+
+use crate::{error::Error, night, settings::Palette};
+
+/// Number of WS2812 LEDs chained on the data pin, all showing the same color
+const LED_COUNT: usize = 1;
+
+/// Global brightness scale applied to every color, out of 255
+const BRIGHTNESS: u8 = 64;
+
+/// Further scale on the night's dim red, out of 255
+const NIGHT_BRIGHTNESS: u8 = 16;
+
+/// At 4 MHz each WS2812 bit is sent as 4 SPI bits: `1000` for a 0 (250 ns
+/// high) and `1110` for a 1 (750 ns high)
+const WS2812_ZERO: u8 = 0b1000;
+const WS2812_ONE: u8 = 0b1110;
+
+/// 3 color bytes per LED, each expanded to 4 SPI bytes
+const FRAME_LEN: usize = LED_COUNT * 3 * 4;
+
+/// At least 50 µs low latches the colors, 40 zero bytes at 4 MHz is 80 µs
+const RESET_LEN: usize = 40;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    /// Dim red as bright as the color's strongest channel, which spoils
+    /// dark-adapted eyes the least
+    fn red_shifted(self) -> Self {
+        let level = self.r.max(self.g).max(self.b);
+        Self {
+            r: (u16::from(level) * u16::from(NIGHT_BRIGHTNESS) / 255) as u8,
+            g: 0,
+            b: 0,
+        }
+    }
+}
+
+/// Latest color for the external LEDs
+pub static COLOR: Signal<CriticalSectionRawMutex, Rgb> = Signal::new();
+
+/// Map a heading, 0 to 360, to a color in `palette`
+pub fn heading_color(palette: Palette, heading: f32) -> Rgb {
+    match palette {
+        Palette::Rainbow => hue(heading),
+        Palette::Cardinal => match crate::get_cardinal_direction(heading) {
+            "N" => hue(0.0),
+            "E" => hue(60.0),
+            "S" => hue(120.0),
+            _ => hue(240.0),
+        },
+    }
+}
+
+/// Map a signed course error to a color, green (120°) on course through
+/// yellow down to red (0°) when facing away
+pub fn course_color(error: f32) -> Rgb {
+    hue(120.0 * (1.0 - (error.abs() / 180.0).min(1.0)))
+}
+
+/// Fully saturated color for a hue in degrees
+fn hue(degrees: f32) -> Rgb {
+    let h = (degrees % 360.0 + 360.0) % 360.0 / 60.0;
+    let x = 1.0 - ((h % 2.0) - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let scale = |c: f32| (c * BRIGHTNESS as f32) as u8;
+    Rgb {
+        r: scale(r),
+        g: scale(g),
+        b: scale(b),
+    }
+}
+
+/// Drive a WS2812 chain from SPIM MOSI, updating whenever a new [`COLOR`]
+/// arrives.
+#[embassy_executor::task]
+pub async fn rgb_task(mut spim: Spim<'static, SPI2>) {
+    let mut frame = [0u8; FRAME_LEN + RESET_LEN];
+    let mut last = None;
+    loop {
+        let color = COLOR.wait().await;
+        let color = if night::active() {
+            color.red_shifted()
+        } else {
+            color
+        };
+        if last == Some(color) {
+            continue;
+        }
+        last = Some(color);
+
+        // WS2812 expects green, red, blue
+        for chunk in frame[..FRAME_LEN].chunks_exact_mut(12) {
+            for (byte, out) in [color.g, color.r, color.b]
+                .iter()
+                .zip(chunk.chunks_exact_mut(4))
+            {
+                encode_byte(*byte, out);
+            }
+        }
+        if spim.write(&frame).await.is_err() {
+            warn!("failed to update rgb leds: {}", Error::Display);
+        }
+    }
+}
+
+/// Expand one color byte into 4 SPI bytes, two WS2812 bits per SPI byte
+fn encode_byte(byte: u8, out: &mut [u8]) {
+    for (i, out) in out.iter_mut().enumerate() {
+        let bit = |n: usize| {
+            if byte & (0x80 >> n) != 0 {
+                WS2812_ONE
+            } else {
+                WS2812_ZERO
+            }
+        };
+        *out = (bit(i * 2) << 4) | bit(i * 2 + 1);
+    }
+}
@@ -0,0 +1,322 @@
+use defmt::{info, warn, Format};
+use embassy_nrf::{
+    peripherals::TWISPI0,
+    twim::{self, Twim},
+};
+use embassy_time::Timer;
+
+use crate::{
+    animation::Frame,
+    error::{Error, SensorOp},
+    fxos8700,
+    sensor::BusPins,
+    tap::ACCEL_ADDRESS,
+    Irqs,
+};
+
+const MAG_ADDRESS: u8 = 0x1E;
+
+const WHO_AM_I_A: u8 = 0x0F;
+const WHO_AM_I_M: u8 = 0x4F;
+const ACCEL_ID: u8 = 0x33;
+const MAG_ID: u8 = 0x40;
+
+// Accelerometer registers
+const CTRL_REG1_A: u8 = 0x20;
+const CTRL_REG2_A: u8 = 0x21;
+const CTRL_REG3_A: u8 = 0x22;
+const CTRL_REG4_A: u8 = 0x23;
+const STATUS_REG_A: u8 = 0x27;
+/// OUT_X_L_A with the top bit set, so a read goes on through all six output
+/// registers
+const OUT_A: u8 = 0x28 | 0x80;
+
+// Magnetometer registers, reads go on through the output registers without
+// asking
+const CFG_REG_A_M: u8 = 0x60;
+const CFG_REG_B_M: u8 = 0x61;
+const CFG_REG_C_M: u8 = 0x62;
+const STATUS_REG_M: u8 = 0x67;
+const OUT_M: u8 = 0x68;
+
+/// 100 Hz, normal mode, all axes
+const CTRL_REG1_100HZ: u8 = 0x57;
+/// Block data update, ±2 g, and self-test 0 if `ST0` is added
+const CTRL_REG4_BDU: u8 = 0x80;
+const CTRL_REG4_ST0: u8 = 0x02;
+/// Continuous mode at 100 Hz
+const CFG_REG_A_100HZ: u8 = 0x0C;
+/// Idle mode
+const CFG_REG_A_IDLE: u8 = 0x03;
+const CFG_REG_B_OFFSET_CANCELLATION: u8 = 0x02;
+/// Block data update, and self-test if `SELF_TEST` is added
+const CFG_REG_C_BDU: u8 = 0x10;
+const CFG_REG_C_SELF_TEST: u8 = 0x02;
+
+/// X, Y and Z all have new data, in either status register
+const ZYXDA: u8 = 0b0000_1000;
+
+/// Samples averaged with and without the self-test force, per the datasheet
+const ACCEL_SAMPLES: i32 = 5;
+const MAG_SAMPLES: i32 = 50;
+
+/// How long the output takes to settle after a change, ms
+const ACCEL_SETTLE_MS: u64 = 90;
+const MAG_SETTLE_MS: u64 = 20;
+const MAG_SELF_TEST_SETTLE_MS: u64 = 60;
+
+/// Shift from the left-justified accelerometer output to 10 bits
+const ACCEL_SHIFT: u32 = 6;
+
+/// Change in every axis the self-test force must cause, LSB (4 mg at 10 bits
+/// for the accelerometer, 1.5 mG for the magnetometer)
+const ACCEL_SELF_TEST_RANGE: core::ops::RangeInclusive<i32> = 17..=360;
+const MAG_SELF_TEST_RANGE: core::ops::RangeInclusive<i32> = 15..=500;
+
+/// Polls of the status register, 1 ms apart, before giving up on new data
+const DATA_POLLS: usize = 50;
+
+const AXES: [char; 3] = ['X', 'Y', 'Z'];
+
+/// A power-on check, numbered as shown on the display when it fails
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub enum Check {
+    AccelerometerId = 1,
+    MagnetometerId = 2,
+    AccelerometerSelfTest = 3,
+    MagnetometerSelfTest = 4,
+    Settings = 5,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Format)]
+pub struct Failure {
+    pub check: Check,
+    /// What went wrong, unless the check just read back the wrong values
+    pub error: Option<Error>,
+    /// X, Y and Z, whether each axis is out of its limits, for the
+    /// self-tests that got as far as comparing them
+    pub axes: [bool; 3],
+}
+
+impl Failure {
+    /// The failed axes in columns 0, 2 and 4 of a frame, X on the left,
+    /// `None` if the check didn't get as far as the axes
+    pub fn axes_frame(&self) -> Option<Frame> {
+        if !self.axes.contains(&true) {
+            return None;
+        }
+        let columns = [0b10000, 0b00100, 0b00001];
+        let row = (0..3)
+            .filter(|&axis| self.axes[axis])
+            .fold(0, |row, axis| row | columns[axis]);
+        Some([row; 5])
+    }
+}
+
+impl Check {
+    fn failed(self, error: Option<Error>) -> Failure {
+        Failure {
+            check: self,
+            error,
+            axes: [false; 3],
+        }
+    }
+
+    /// For `map_err` on I2C transfers made during the check
+    fn bus(self) -> impl FnOnce(twim::Error) -> Failure {
+        move |e| self.failed(Some(Error::bus(SensorOp::SelfTest)(e)))
+    }
+}
+
+/// Check the LSM303AGR answers with the right WHO_AM_I values and passes the
+/// datasheet self-tests of both its sensors, then that the settings read
+/// from flash were intact, stopping at the first failure. On board
+/// revisions with an FXOS8700 instead, only the settings are checked.
+///
+/// This runs before [`crate::sensor::start`], which configures the sensor
+/// from scratch afterwards.
+pub async fn run(pins: &mut BusPins, settings: Result<(), Error>) -> Result<(), Failure> {
+    let mut twim = Twim::new(
+        &mut pins.twi,
+        Irqs,
+        &mut pins.sda,
+        &mut pins.scl,
+        twim::Config::default(),
+    );
+
+    let id = read(&mut twim, ACCEL_ADDRESS, WHO_AM_I_A).await;
+    if !matches!(id, Ok(ACCEL_ID)) && fxos8700::answers(&mut twim).await {
+        // Only the board's LSM303AGR has its self-tests here
+        info!("FXOS8700 found, self-tests skipped");
+        settings.map_err(|e| Check::Settings.failed(Some(e)))?;
+        return Ok(());
+    }
+    let id = id.map_err(Check::AccelerometerId.bus())?;
+    if id != ACCEL_ID {
+        warn!("accelerometer id {=u8:#x}", id);
+        return Err(Check::AccelerometerId.failed(None));
+    }
+    let id = read(&mut twim, MAG_ADDRESS, WHO_AM_I_M)
+        .await
+        .map_err(Check::MagnetometerId.bus())?;
+    if id != MAG_ID {
+        warn!("magnetometer id {=u8:#x}", id);
+        return Err(Check::MagnetometerId.failed(None));
+    }
+
+    accel_self_test(&mut twim).await?;
+    mag_self_test(&mut twim).await?;
+
+    settings.map_err(|e| Check::Settings.failed(Some(e)))?;
+    info!("self-test passed");
+    Ok(())
+}
+
+async fn accel_self_test(twim: &mut Twim<'_, TWISPI0>) -> Result<(), Failure> {
+    let check = Check::AccelerometerSelfTest;
+    for (register, value) in [
+        (CTRL_REG2_A, 0),
+        (CTRL_REG3_A, 0),
+        (CTRL_REG4_A, CTRL_REG4_BDU),
+        (CTRL_REG1_A, CTRL_REG1_100HZ),
+    ] {
+        twim.write(ACCEL_ADDRESS, &[register, value])
+            .await
+            .map_err(check.bus())?;
+    }
+    Timer::after_millis(ACCEL_SETTLE_MS).await;
+    let without = average(twim, ACCEL_ADDRESS, STATUS_REG_A, OUT_A, ACCEL_SAMPLES)
+        .await
+        .map_err(check.bus())?;
+
+    twim.write(ACCEL_ADDRESS, &[CTRL_REG4_A, CTRL_REG4_BDU | CTRL_REG4_ST0])
+        .await
+        .map_err(check.bus())?;
+    Timer::after_millis(ACCEL_SETTLE_MS).await;
+    let with = average(twim, ACCEL_ADDRESS, STATUS_REG_A, OUT_A, ACCEL_SAMPLES)
+        .await
+        .map_err(check.bus())?;
+
+    // Power down again
+    for (register, value) in [(CTRL_REG1_A, 0), (CTRL_REG4_A, 0)] {
+        twim.write(ACCEL_ADDRESS, &[register, value])
+            .await
+            .map_err(check.bus())?;
+    }
+
+    let (Some(without), Some(with)) = (without, with) else {
+        warn!("accelerometer has no data");
+        return Err(check.failed(None));
+    };
+    let change = [0, 1, 2].map(|i| ((with[i] - without[i]) >> ACCEL_SHIFT).abs());
+    check_axes(check, change, ACCEL_SELF_TEST_RANGE)
+}
+
+async fn mag_self_test(twim: &mut Twim<'_, TWISPI0>) -> Result<(), Failure> {
+    let check = Check::MagnetometerSelfTest;
+    for (register, value) in [
+        (CFG_REG_A_M, CFG_REG_A_100HZ),
+        (CFG_REG_B_M, CFG_REG_B_OFFSET_CANCELLATION),
+        (CFG_REG_C_M, CFG_REG_C_BDU),
+    ] {
+        twim.write(MAG_ADDRESS, &[register, value])
+            .await
+            .map_err(check.bus())?;
+    }
+    Timer::after_millis(MAG_SETTLE_MS).await;
+    let without = average(twim, MAG_ADDRESS, STATUS_REG_M, OUT_M, MAG_SAMPLES)
+        .await
+        .map_err(check.bus())?;
+
+    twim.write(
+        MAG_ADDRESS,
+        &[CFG_REG_C_M, CFG_REG_C_BDU | CFG_REG_C_SELF_TEST],
+    )
+    .await
+    .map_err(check.bus())?;
+    Timer::after_millis(MAG_SELF_TEST_SETTLE_MS).await;
+    let with = average(twim, MAG_ADDRESS, STATUS_REG_M, OUT_M, MAG_SAMPLES)
+        .await
+        .map_err(check.bus())?;
+
+    for (register, value) in [(CFG_REG_C_M, CFG_REG_C_BDU), (CFG_REG_A_M, CFG_REG_A_IDLE)] {
+        twim.write(MAG_ADDRESS, &[register, value])
+            .await
+            .map_err(check.bus())?;
+    }
+
+    let (Some(without), Some(with)) = (without, with) else {
+        warn!("magnetometer has no data");
+        return Err(check.failed(None));
+    };
+    let change = [0, 1, 2].map(|i| (with[i] - without[i]).abs());
+    check_axes(check, change, MAG_SELF_TEST_RANGE)
+}
+
+/// Log the self-test `change` of each axis against the datasheet's `limits`,
+/// failing if any is outside them
+fn check_axes(
+    check: Check,
+    change: [i32; 3],
+    limits: core::ops::RangeInclusive<i32>,
+) -> Result<(), Failure> {
+    let axes = change.map(|change| !limits.contains(&change));
+    for (axis, (change, failed)) in AXES.into_iter().zip(change.into_iter().zip(axes)) {
+        if failed {
+            warn!(
+                "{} {}: change {} LSB, outside {} to {}, failed",
+                check,
+                axis,
+                change,
+                limits.start(),
+                limits.end()
+            );
+        } else {
+            info!("{} {}: change {} LSB, passed", check, axis, change);
+        }
+    }
+    if axes.contains(&true) {
+        return Err(Failure {
+            axes,
+            ..check.failed(None)
+        });
+    }
+    Ok(())
+}
+
+async fn read(twim: &mut Twim<'_, TWISPI0>, address: u8, register: u8) -> Result<u8, twim::Error> {
+    let mut value = [0];
+    twim.write_read(address, &[register], &mut value).await?;
+    Ok(value[0])
+}
+
+/// Average `samples` X, Y, Z readings as they come in, after throwing away
+/// the first, which may be stale. `None` if the sensor stops producing data.
+async fn average(
+    twim: &mut Twim<'_, TWISPI0>,
+    address: u8,
+    status: u8,
+    out: u8,
+    samples: i32,
+) -> Result<Option<[i32; 3]>, twim::Error> {
+    let mut sum = [0i32; 3];
+    for sample in 0..=samples {
+        let mut polls = 0;
+        while read(twim, address, status).await? & ZYXDA == 0 {
+            polls += 1;
+            if polls == DATA_POLLS {
+                return Ok(None);
+            }
+            Timer::after_millis(1).await;
+        }
+        let mut buf = [0u8; 6];
+        twim.write_read(address, &[out], &mut buf).await?;
+        if sample == 0 {
+            continue;
+        }
+        for (axis, total) in sum.iter_mut().enumerate() {
+            *total += i16::from_le_bytes([buf[2 * axis], buf[2 * axis + 1]]) as i32;
+        }
+    }
+    Ok(Some(sum.map(|total| total / samples)))
+}
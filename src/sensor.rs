@@ -0,0 +1,682 @@
+use defmt::{info, warn, Format};
+use embassy_nrf::{
+    gpio::{AnyPin, Flex, OutputDrive, Pin, Pull},
+    peripherals::TWISPI0,
+};
+
+#[cfg(feature = "lis3mdl")]
+use crate::lis3mdl::Lis3mdl;
+#[cfg(feature = "mmc5603")]
+use crate::mmc5603::Mmc5603;
+#[cfg(feature = "gyro")]
+use crate::mpu6050::Mpu6050;
+#[cfg(feature = "qmc5883l")]
+use crate::qmc5883l::Qmc5883l;
+use crate::{
+    bus,
+    error::Error,
+    fxos8700::Fxos8700,
+    lsm303::{self, Lsm303},
+    replay::{self, Replay},
+    settings::Settings,
+};
+
+/// A driver's handle on the shared bus, with faults injected when testing
+#[cfg(not(feature = "fault-injection"))]
+pub type Bus = bus::Device;
+#[cfg(feature = "fault-injection")]
+pub type Bus = crate::fault::FaultyI2c<bus::Device>;
+
+/// A handle on the shared bus for a driver
+pub fn device() -> Bus {
+    #[cfg(not(feature = "fault-injection"))]
+    return bus::device();
+    #[cfg(feature = "fault-injection")]
+    return crate::fault::FaultyI2c::new(bus::device());
+}
+
+/// Accelerometer output data rates that can be set, Hz. Tap and free-fall
+/// detection are tuned for 50 Hz and their timings scale with the rate.
+pub const ACCEL_RATES_HZ: [u16; 6] = [10, 25, 50, 100, 200, 400];
+
+/// Accelerometer resolution traded for current draw
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum AccelPower {
+    /// 12 bits on the LSM303AGR, 14 on the FXOS8700
+    HighResolution,
+    /// 10 bits on the LSM303AGR
+    Normal,
+    /// 8 bits on the LSM303AGR
+    LowPower,
+}
+
+/// The accelerometer's built-in high-pass filter, applied to what the tap
+/// and motion detectors see. The readings themselves are never filtered, as
+/// tilt compensation needs gravity in them.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub struct AccelHighPass {
+    /// Filter what the click engine sees, so slow changes such as leaning
+    /// into a corner don't add to a tap
+    pub tap: bool,
+    /// 0 to [`MAX_HIGH_PASS_CUTOFF`], each step lowering the cut-off: 1,
+    /// 0.5, 0.2 and 0.1 Hz at 50 Hz, scaling with the rate
+    pub cutoff: u8,
+}
+
+pub const MAX_HIGH_PASS_CUTOFF: u8 = 3;
+
+/// What the main loop needs from an accelerometer, in the sensor's own
+/// frame
+pub trait Accelerometer {
+    /// The latest reading in mg, `None` if there hasn't been a new one since
+    /// the last
+    async fn acceleration(&mut self) -> Result<Option<[f32; 3]>, Error>;
+
+    /// Measure at `hz`, one of [`ACCEL_RATES_HZ`], with the resolution of
+    /// `power`
+    async fn set_accel_rate(&mut self, hz: u16, power: AccelPower) -> Result<(), Error>;
+
+    /// Parts without a filter to set up ignore it
+    async fn set_high_pass(&mut self, _filter: AccelHighPass) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Raise the wake interrupt when the board is moved, see
+    /// [`crate::idle`]
+    async fn set_wake_on_motion(&mut self, enabled: bool) -> Result<(), Error>;
+
+    async fn power_down(&mut self) -> Result<(), Error>;
+}
+
+/// What the main loop needs from a magnetometer, in the sensor's own frame.
+/// Other parts are added by implementing this and probing for them in
+/// [`start`].
+pub trait Magnetometer {
+    /// The latest reading in nT, `None` if there hasn't been a new one since
+    /// the last
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error>;
+
+    /// Measure continuously at the supported rate nearest `hz`, at full
+    /// resolution or, with `low_power`, as cheaply as the part allows. Parts
+    /// without a low-power mode of their own then measure as slowly as they
+    /// can too.
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error>;
+
+    /// Filter the readings in the part itself, trading a little lag for less
+    /// noise. Parts without a filter to switch ignore it.
+    async fn set_low_pass(&mut self, _enabled: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Stop measuring, drawing as little as the part allows
+    async fn sleep(&mut self) -> Result<(), Error>;
+
+    /// Measure again as before [`Self::sleep`]
+    async fn wake(&mut self) -> Result<(), Error>;
+}
+
+/// A gyroscope, in its own frame
+#[cfg(feature = "gyro")]
+pub trait Gyroscope {
+    /// The latest reading in °/s, right-handed, `None` if there hasn't been a
+    /// new one since the last
+    async fn angular_rate(&mut self) -> Result<Option<[f32; 3]>, Error>;
+
+    async fn set_asleep(&mut self, asleep: bool) -> Result<(), Error>;
+}
+
+/// The sensors as the main loop uses them: the board's IMU, whose
+/// magnetometer is parked when another one is found on the bus, and a
+/// gyroscope if one is. With `redundant-mag` a second magnetometer is kept
+/// running as the backup instead. While replay is on, readings come from the
+/// console instead and the hardware only follows rate and power changes.
+pub struct Sensor {
+    imu: Imu,
+    replay: Replay,
+    #[cfg(feature = "gyro")]
+    gyro: Option<Mpu6050>,
+    #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+    external: Option<External>,
+    #[cfg(all(
+        feature = "redundant-mag",
+        any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+    ))]
+    backup: Option<Backup>,
+}
+
+/// The accelerometer and magnetometer the board revision carries
+enum Imu {
+    Lsm303(Lsm303),
+    Fxos8700(Fxos8700),
+}
+
+/// A magnetometer on the bus, with a driver built in by its Cargo feature
+#[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+enum External {
+    #[cfg(feature = "lis3mdl")]
+    Lis3mdl(Lis3mdl),
+    #[cfg(feature = "qmc5883l")]
+    Qmc5883l(Qmc5883l),
+    #[cfg(feature = "mmc5603")]
+    Mmc5603(Mmc5603),
+}
+
+/// The magnetometer cross-checked against the one in use, see
+/// [`crate::redundancy`]
+#[cfg(all(
+    feature = "redundant-mag",
+    any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+))]
+enum Backup {
+    /// The IMU's own, when only one external part answered
+    Imu,
+    /// A second external part, of another kind than the first
+    External(External),
+}
+
+/// Half a period of the clock [`BusPins::recover`] sends, about 100 kHz at
+/// 64 MHz
+const RECOVERY_HALF_PERIOD_CYCLES: u32 = 320;
+
+/// The peripherals of the I2C bus to the sensor, pins as wired on the
+/// [`crate::board`]
+pub struct BusPins {
+    pub twi: TWISPI0,
+    pub sda: AnyPin,
+    pub scl: AnyPin,
+}
+
+impl BusPins {
+    /// Port and pin numbers of SDA and SCL, to [`Self::steal`] them back by
+    pub fn pin_ports(&self) -> [u8; 2] {
+        [&self.sda, &self.scl].map(|pin| pin.port() as u8 * 32 + pin.pin())
+    }
+
+    /// Take the bus back to recover it
+    ///
+    /// # Safety
+    ///
+    /// The [`embassy_nrf::twim::Twim`] that owned them must have been
+    /// dropped, nothing else may use these peripherals.
+    pub unsafe fn steal([sda, scl]: [u8; 2]) -> Self {
+        Self {
+            twi: TWISPI0::steal(),
+            sda: AnyPin::steal(sda),
+            scl: AnyPin::steal(scl),
+        }
+    }
+
+    /// Free a bus left stuck by a transfer cut short: a sensor still sending
+    /// holds SDA low until it has clocked out its byte. Up to nine clock
+    /// pulses let it finish, then a STOP condition resets the bus.
+    pub fn recover(&mut self) {
+        let mut scl = Flex::new(&mut self.scl);
+        let mut sda = Flex::new(&mut self.sda);
+        // Open drain, the board pulls the lines up
+        scl.set_high();
+        sda.set_high();
+        scl.set_as_input_output(Pull::None, OutputDrive::Standard0Disconnect1);
+        sda.set_as_input_output(Pull::None, OutputDrive::Standard0Disconnect1);
+        half_period();
+
+        for _ in 0..9 {
+            if sda.is_high() {
+                break;
+            }
+            scl.set_low();
+            half_period();
+            scl.set_high();
+            half_period();
+        }
+        if sda.is_low() {
+            warn!("I2C SDA still held low");
+        }
+
+        // STOP: SDA rising while SCL is high
+        scl.set_low();
+        half_period();
+        sda.set_low();
+        half_period();
+        scl.set_high();
+        half_period();
+        sda.set_high();
+        half_period();
+    }
+}
+
+fn half_period() {
+    cortex_m::asm::delay(RECOVERY_HALF_PERIOD_CYCLES);
+}
+
+/// Configure the board's IMU from scratch over the shared [`bus`], the
+/// LSM303AGR or, on revisions without one, an FXOS8700, then look for a
+/// magnetometer built in by a Cargo feature, which is used instead of the
+/// IMU's own when it answers
+pub async fn start(settings: &Settings) -> Result<Sensor, Error> {
+    #[allow(unused_mut)] // Only parked with another magnetometer built in
+    let mut imu = if lsm303::answers(&mut bus::device()).await {
+        Imu::Lsm303(Lsm303::start(settings).await?)
+    } else if let Some(fxos8700) = Fxos8700::probe(settings).await? {
+        info!("using an FXOS8700");
+        Imu::Fxos8700(fxos8700)
+    } else {
+        // Neither answered, fail the way the LSM303AGR does
+        Imu::Lsm303(Lsm303::start(settings).await?)
+    };
+
+    #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+    let mut external = External::probe(settings.mag_odr_hz, 0).await?;
+    #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+    if external.is_none() {
+        warn!("no external magnetometer, using the LSM303AGR");
+    }
+    // The part after the first in the probing order, or else the IMU's own
+    #[cfg(all(
+        feature = "redundant-mag",
+        any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+    ))]
+    let mut backup = match &external {
+        Some(first) => match External::probe(settings.mag_odr_hz, first.index() + 1).await? {
+            Some(second) => Some(Backup::External(second)),
+            None => Some(Backup::Imu),
+        },
+        None => {
+            warn!("no second magnetometer to cross-check against");
+            None
+        }
+    };
+    #[cfg(all(
+        feature = "redundant-mag",
+        any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+    ))]
+    if let Some(Backup::External(backup)) = &mut backup {
+        if settings.mag_low_power {
+            backup.set_rate(settings.mag_odr_hz, true).await?;
+        }
+    }
+    #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+    if let Some(external) = &mut external {
+        #[cfg(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        ))]
+        let park = !matches!(backup, Some(Backup::Imu));
+        #[cfg(not(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        )))]
+        let park = true;
+        if park {
+            imu.sleep().await?;
+        }
+        if settings.mag_low_power {
+            external.set_rate(settings.mag_odr_hz, true).await?;
+        }
+    }
+
+    #[cfg(feature = "gyro")]
+    let gyro = Mpu6050::probe(device()).await?;
+    #[cfg(feature = "gyro")]
+    match gyro {
+        Some(_) => info!("using an MPU-6050 gyroscope"),
+        None => warn!("no gyroscope"),
+    }
+
+    Ok(Sensor {
+        imu,
+        replay: Replay::new(),
+        #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+        external,
+        #[cfg(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        ))]
+        backup,
+        #[cfg(feature = "gyro")]
+        gyro,
+    })
+}
+
+#[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+impl External {
+    /// The first of the built-in parts from the [`Self::index`] `from` on
+    /// that answers with its ID, configured to measure at `mag_odr_hz`
+    async fn probe(mag_odr_hz: u16, from: u8) -> Result<Option<Self>, Error> {
+        #[cfg(feature = "lis3mdl")]
+        if from == 0 {
+            if let Some(mag) = Lis3mdl::probe(device(), mag_odr_hz).await? {
+                info!("using a LIS3MDL magnetometer");
+                return Ok(Some(Self::Lis3mdl(mag)));
+            }
+        }
+        #[cfg(feature = "qmc5883l")]
+        if from <= 1 {
+            if let Some(mag) = Qmc5883l::probe(device(), mag_odr_hz).await? {
+                info!("using a QMC5883L magnetometer");
+                return Ok(Some(Self::Qmc5883l(mag)));
+            }
+        }
+        #[cfg(feature = "mmc5603")]
+        if from <= 2 {
+            if let Some(mag) = Mmc5603::probe(device(), mag_odr_hz).await? {
+                info!("using an MMC5603 magnetometer");
+                return Ok(Some(Self::Mmc5603(mag)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Place in the probing order
+    #[cfg(all(
+        feature = "redundant-mag",
+        any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+    ))]
+    fn index(&self) -> u8 {
+        match self {
+            #[cfg(feature = "lis3mdl")]
+            Self::Lis3mdl(_) => 0,
+            #[cfg(feature = "qmc5883l")]
+            Self::Qmc5883l(_) => 1,
+            #[cfg(feature = "mmc5603")]
+            Self::Mmc5603(_) => 2,
+        }
+    }
+
+    #[cfg(all(
+        feature = "redundant-mag",
+        any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+    ))]
+    fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "lis3mdl")]
+            Self::Lis3mdl(_) => "LIS3MDL",
+            #[cfg(feature = "qmc5883l")]
+            Self::Qmc5883l(_) => "QMC5883L",
+            #[cfg(feature = "mmc5603")]
+            Self::Mmc5603(_) => "MMC5603",
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "redundant-mag",
+    any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+))]
+impl Imu {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Lsm303(_) => "LSM303AGR",
+            Self::Fxos8700(_) => "FXOS8700",
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "redundant-mag",
+    any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+))]
+impl Sensor {
+    /// The backup magnetometer's latest reading, `None` without one, if
+    /// there hasn't been a new one since the last, or while replaying
+    pub async fn backup_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        if replay::active() {
+            return Ok(None);
+        }
+        match &mut self.backup {
+            Some(Backup::Imu) => self.imu.magnetic_field().await,
+            Some(Backup::External(mag)) => mag.magnetic_field().await,
+            None => Ok(None),
+        }
+    }
+
+    /// Names of the magnetometer in use and of its backup, if any
+    pub fn magnetometers(&self) -> (&'static str, Option<&'static str>) {
+        let primary = self
+            .external
+            .as_ref()
+            .map_or(self.imu.name(), External::name);
+        let backup = match &self.backup {
+            Some(Backup::Imu) => Some(self.imu.name()),
+            Some(Backup::External(mag)) => Some(mag.name()),
+            None => None,
+        };
+        (primary, backup)
+    }
+}
+
+#[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+impl Magnetometer for External {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        match self {
+            #[cfg(feature = "lis3mdl")]
+            Self::Lis3mdl(mag) => mag.magnetic_field().await,
+            #[cfg(feature = "qmc5883l")]
+            Self::Qmc5883l(mag) => mag.magnetic_field().await,
+            #[cfg(feature = "mmc5603")]
+            Self::Mmc5603(mag) => mag.magnetic_field().await,
+        }
+    }
+
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "lis3mdl")]
+            Self::Lis3mdl(mag) => mag.set_rate(hz, low_power).await,
+            #[cfg(feature = "qmc5883l")]
+            Self::Qmc5883l(mag) => mag.set_rate(hz, low_power).await,
+            #[cfg(feature = "mmc5603")]
+            Self::Mmc5603(mag) => mag.set_rate(hz, low_power).await,
+        }
+    }
+
+    async fn sleep(&mut self) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "lis3mdl")]
+            Self::Lis3mdl(mag) => mag.sleep().await,
+            #[cfg(feature = "qmc5883l")]
+            Self::Qmc5883l(mag) => mag.sleep().await,
+            #[cfg(feature = "mmc5603")]
+            Self::Mmc5603(mag) => mag.sleep().await,
+        }
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "lis3mdl")]
+            Self::Lis3mdl(mag) => mag.wake().await,
+            #[cfg(feature = "qmc5883l")]
+            Self::Qmc5883l(mag) => mag.wake().await,
+            #[cfg(feature = "mmc5603")]
+            Self::Mmc5603(mag) => mag.wake().await,
+        }
+    }
+}
+
+impl Accelerometer for Imu {
+    async fn acceleration(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        match self {
+            Self::Lsm303(imu) => imu.acceleration().await,
+            Self::Fxos8700(imu) => imu.acceleration().await,
+        }
+    }
+
+    async fn set_accel_rate(&mut self, hz: u16, power: AccelPower) -> Result<(), Error> {
+        match self {
+            Self::Lsm303(imu) => imu.set_accel_rate(hz, power).await,
+            Self::Fxos8700(imu) => imu.set_accel_rate(hz, power).await,
+        }
+    }
+
+    async fn set_high_pass(&mut self, filter: AccelHighPass) -> Result<(), Error> {
+        match self {
+            Self::Lsm303(imu) => imu.set_high_pass(filter).await,
+            Self::Fxos8700(imu) => imu.set_high_pass(filter).await,
+        }
+    }
+
+    async fn set_wake_on_motion(&mut self, enabled: bool) -> Result<(), Error> {
+        match self {
+            Self::Lsm303(imu) => imu.set_wake_on_motion(enabled).await,
+            Self::Fxos8700(imu) => imu.set_wake_on_motion(enabled).await,
+        }
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        match self {
+            Self::Lsm303(imu) => imu.power_down().await,
+            Self::Fxos8700(imu) => imu.power_down().await,
+        }
+    }
+}
+
+impl Magnetometer for Imu {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        match self {
+            Self::Lsm303(imu) => imu.magnetic_field().await,
+            Self::Fxos8700(imu) => imu.magnetic_field().await,
+        }
+    }
+
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error> {
+        match self {
+            Self::Lsm303(imu) => imu.set_rate(hz, low_power).await,
+            Self::Fxos8700(imu) => imu.set_rate(hz, low_power).await,
+        }
+    }
+
+    async fn set_low_pass(&mut self, enabled: bool) -> Result<(), Error> {
+        match self {
+            Self::Lsm303(imu) => imu.set_low_pass(enabled).await,
+            Self::Fxos8700(imu) => imu.set_low_pass(enabled).await,
+        }
+    }
+
+    async fn sleep(&mut self) -> Result<(), Error> {
+        match self {
+            Self::Lsm303(imu) => imu.sleep().await,
+            Self::Fxos8700(imu) => imu.sleep().await,
+        }
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        match self {
+            Self::Lsm303(imu) => imu.wake().await,
+            Self::Fxos8700(imu) => imu.wake().await,
+        }
+    }
+}
+
+impl Accelerometer for Sensor {
+    async fn acceleration(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        if replay::active() {
+            return self.replay.acceleration().await;
+        }
+        self.imu.acceleration().await
+    }
+
+    async fn set_accel_rate(&mut self, hz: u16, power: AccelPower) -> Result<(), Error> {
+        self.imu.set_accel_rate(hz, power).await
+    }
+
+    async fn set_high_pass(&mut self, filter: AccelHighPass) -> Result<(), Error> {
+        self.imu.set_high_pass(filter).await
+    }
+
+    async fn set_wake_on_motion(&mut self, enabled: bool) -> Result<(), Error> {
+        self.imu.set_wake_on_motion(enabled).await
+    }
+
+    async fn power_down(&mut self) -> Result<(), Error> {
+        self.imu.power_down().await
+    }
+}
+
+/// Through the magnetometer found on the bus, if any, otherwise the
+/// LSM303AGR's. Rate and power changes go to the backup as well.
+impl Magnetometer for Sensor {
+    async fn magnetic_field(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        if replay::active() {
+            return self.replay.magnetic_field().await;
+        }
+        #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+        if let Some(external) = &mut self.external {
+            return external.magnetic_field().await;
+        }
+        self.imu.magnetic_field().await
+    }
+
+    async fn set_rate(&mut self, hz: u16, low_power: bool) -> Result<(), Error> {
+        #[cfg(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        ))]
+        match &mut self.backup {
+            Some(Backup::Imu) => self.imu.set_rate(hz, low_power).await?,
+            Some(Backup::External(mag)) => mag.set_rate(hz, low_power).await?,
+            None => {}
+        }
+        #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+        if let Some(external) = &mut self.external {
+            return external.set_rate(hz, low_power).await;
+        }
+        self.imu.set_rate(hz, low_power).await
+    }
+
+    /// Only the LSM303AGR has a filter to switch, so it goes to the IMU
+    /// whichever magnetometer is in use
+    async fn set_low_pass(&mut self, enabled: bool) -> Result<(), Error> {
+        self.imu.set_low_pass(enabled).await
+    }
+
+    async fn sleep(&mut self) -> Result<(), Error> {
+        #[cfg(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        ))]
+        match &mut self.backup {
+            Some(Backup::Imu) => self.imu.sleep().await?,
+            Some(Backup::External(mag)) => mag.sleep().await?,
+            None => {}
+        }
+        #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+        if let Some(external) = &mut self.external {
+            return external.sleep().await;
+        }
+        self.imu.sleep().await
+    }
+
+    async fn wake(&mut self) -> Result<(), Error> {
+        #[cfg(all(
+            feature = "redundant-mag",
+            any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603")
+        ))]
+        match &mut self.backup {
+            Some(Backup::Imu) => self.imu.wake().await?,
+            Some(Backup::External(mag)) => mag.wake().await?,
+            None => {}
+        }
+        #[cfg(any(feature = "lis3mdl", feature = "qmc5883l", feature = "mmc5603"))]
+        if let Some(external) = &mut self.external {
+            return external.wake().await;
+        }
+        self.imu.wake().await
+    }
+}
+
+/// Nothing without a gyroscope found on the bus, or while replaying
+#[cfg(feature = "gyro")]
+impl Gyroscope for Sensor {
+    async fn angular_rate(&mut self) -> Result<Option<[f32; 3]>, Error> {
+        match &mut self.gyro {
+            Some(_) if replay::active() => Ok(None),
+            Some(gyro) => gyro.angular_rate().await,
+            None => Ok(None),
+        }
+    }
+
+    async fn set_asleep(&mut self, asleep: bool) -> Result<(), Error> {
+        match &mut self.gyro {
+            Some(gyro) => gyro.set_asleep(asleep).await,
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use core::cell::Cell;
+
+use defmt::{info, warn};
+use embassy_nrf::{
+    peripherals::PWM1,
+    pwm::{Prescaler, SimplePwm},
+};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{with_timeout, Duration, Instant};
+use micro_compass_core::heading_hold::{Gains, PidHold};
+
+use crate::settings::Steering;
+
+/// 1 MHz PWM clock, so the counter counts microseconds
+const PERIOD_US: u16 = 20_000;
+
+/// Pulse widths of a standard RC servo: centered, and at either end of its
+/// travel. A speed controller takes the same, stopped when centered.
+const CENTER_US: u16 = 1500;
+const TRAVEL_US: u16 = 500;
+
+/// Speed both motors run at on course in differential steering, as a fraction
+/// of full ahead. Steering hard over stops one and runs the other flat out.
+const CRUISE: f32 = 0.5;
+
+/// Longest the main loop may go without sending a heading before the servo
+/// centers, e.g. while the sensor is restarted
+const FAILSAFE: Duration = Duration::from_millis(1000);
+
+/// What the servo follows
+#[derive(Clone, Copy)]
+pub enum Command {
+    /// The heading, 0 to 360, over the whole travel: 0 at one end, 180
+    /// centered, 360 at the other
+    Heading(f32),
+    /// Steering from the [`Autopilot`], -1 hard left to 1 hard right
+    Steer(f32),
+}
+
+/// Latest heading or steering, `None` when there is no heading, e.g. in
+/// tilt-only mode
+pub static COMMAND: Signal<CriticalSectionRawMutex, Option<Command>> = Signal::new();
+
+static STEERING: Mutex<CriticalSectionRawMutex, Cell<Steering>> =
+    Mutex::new(Cell::new(Steering::Rudder));
+
+pub fn set_steering(steering: Steering) {
+    STEERING.lock(|cell| cell.set(steering));
+}
+
+/// Holds the active bearing by steering towards it, timed by the main loop
+pub struct Autopilot {
+    hold: PidHold,
+    updated: Option<Instant>,
+}
+
+impl Autopilot {
+    pub const fn new(gains: Gains) -> Self {
+        Self {
+            hold: PidHold::new(gains),
+            updated: None,
+        }
+    }
+
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.hold.set_gains(gains);
+    }
+
+    /// Steering towards `target` when there is one, or else the heading
+    pub fn command(&mut self, target: Option<f32>, heading: f32) -> Command {
+        let Some(target) = target else {
+            self.reset();
+            return Command::Heading(heading);
+        };
+        // After a gap the vehicle has wandered off unsteered, so the
+        // integral and rate start over
+        let dt = match self.updated {
+            Some(at) if at.elapsed() < FAILSAFE => at.elapsed().as_micros() as f32 / 1e6,
+            _ => {
+                self.hold.reset();
+                0.0
+            }
+        };
+        self.updated = Some(Instant::now());
+        Command::Steer(self.hold.update(target, heading, dt))
+    }
+
+    /// Start afresh once there is a heading again
+    pub fn reset(&mut self) {
+        self.hold.reset();
+        self.updated = None;
+    }
+}
+
+/// Pulse width for `travel`, -1 to 1, µs
+fn pulse_us(travel: f32) -> u16 {
+    let offset = travel.clamp(-1.0, 1.0) * f32::from(TRAVEL_US);
+    (f32::from(CENTER_US) + offset) as u16
+}
+
+/// Pulse widths for the first and second outputs
+fn pulses_us(command: Option<Command>, steering: Steering) -> [u16; 2] {
+    match (command, steering) {
+        (None, _) => [CENTER_US; 2],
+        (Some(Command::Heading(heading)), Steering::Rudder) => {
+            [pulse_us(heading / 180.0 - 1.0), CENTER_US]
+        }
+        (Some(Command::Steer(steer)), Steering::Rudder) => [pulse_us(steer), CENTER_US],
+        // Nothing to hold, so the motors stop
+        (Some(Command::Heading(_)), Steering::Differential) => [CENTER_US; 2],
+        (Some(Command::Steer(steer)), Steering::Differential) => {
+            [pulse_us(CRUISE + steer), pulse_us(CRUISE - steer)]
+        }
+    }
+}
+
+/// Drive an RC servo on the edge connector with a 50 Hz pulse whose width
+/// follows the [`COMMAND`]s, so the board can steer a rudder or the front
+/// wheels itself, or two motors' speed controllers for differential
+/// steering, the second on its own pin. Without a heading, or none for
+/// [`FAILSAFE`], the servo centers rather than holding its last position and
+/// the motors stop. With the `analog` feature the third output is the analog
+/// heading, sharing the PWM.
+#[embassy_executor::task]
+pub async fn servo_task(mut pwm: SimplePwm<'static, PWM1>) {
+    pwm.set_prescaler(Prescaler::Div16);
+    pwm.set_max_duty(PERIOD_US);
+    let mut failsafe = false;
+    #[cfg(feature = "analog")]
+    let mut analog = None;
+    loop {
+        let command = match with_timeout(FAILSAFE, COMMAND.wait()).await {
+            Ok(command) => command,
+            Err(_) => {
+                if !failsafe {
+                    warn!(
+                        "servo: no heading for {} ms, centering",
+                        FAILSAFE.as_millis()
+                    );
+                }
+                None
+            }
+        };
+        if failsafe != command.is_none() {
+            failsafe = command.is_none();
+            if !failsafe {
+                info!("servo: following the heading");
+            }
+        }
+        let pulses = pulses_us(command, STEERING.lock(Cell::get));
+        for (channel, pulse) in pulses.into_iter().enumerate() {
+            // The output is low for the duty and high for the rest of the
+            // period
+            pwm.set_duty(channel, PERIOD_US - pulse);
+        }
+        #[cfg(feature = "analog")]
+        {
+            if let Some(heading) = crate::analog::HEADING.try_take() {
+                analog = heading;
+            }
+            if failsafe {
+                analog = None;
+            }
+            let high = crate::analog::high_counts(analog, PERIOD_US);
+            pwm.set_duty(2, PERIOD_US - high);
+        }
+    }
+}
@@ -0,0 +1,766 @@
+use core::{cell::Cell, fmt::Write};
+
+use defmt::{info, warn, Format};
+use embassy_nrf::nvmc::PAGE_SIZE;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use heapless::String;
+use micro_compass_core::{
+    deviation::{self, DeviationTable},
+    heading_hold::Gains,
+    pipeline,
+    protocol::Reference,
+    units::HeadingUnit,
+};
+
+use crate::{
+    alarm,
+    animation::GREY_LEVELS,
+    audio::AudioMode,
+    console,
+    display::REFRESH_RANGE_HZ,
+    error::{Error, StorageError},
+    haptic,
+    heading_change::MAX_THRESHOLD,
+    logger::LogMode,
+    mounting::{MountMode, Mounting},
+    needle::DAMPING_RANGE,
+    radio::RadioMode,
+    sensor::{AccelHighPass, AccelPower, ACCEL_RATES_HZ, MAX_HIGH_PASS_CUTOFF},
+    storage::{self, Storage},
+    telemetry::{AttitudeOutput, OutputFormat, MAX_ATTITUDE_RATE_HZ},
+};
+
+/// Marks a valid record, erased flash reads back as `0xFFFF_FFFF`
+const MAGIC: u32 = 0x5E77_0016;
+
+/// magic (4) + declination (4) + magnetometer ODR (4) + display mode (4) +
+/// display reference (4) + telemetry reference (4) + output format (4) +
+/// tilt only (4) + audio mode (4) + radio mode (4) + radio group (4) +
+/// stride (4) + log mode (4) + log interval (4) + heading unit (4) +
+/// magnetometer median window (4) + magnetometer average (4) + adaptive rate
+/// (4) + idle timeout (4) + auto-calibration (4) + mounting (4) + attitude
+/// output (4) + attitude rate (4) + heading change threshold (4) +
+/// accelerometer ODR (4) + accelerometer power mode (4) + magnetometer low
+/// power (4) + haptic window (4) + heartbeat (4) + magnetometer low-pass
+/// filter (4) + tap high-pass filter (4) + high-pass cut-off (4) + vibration
+/// rejection (4) + latitude (4) + longitude (4) + deviation table (64) +
+/// alarm sector (4) + display brightness (4) + night mode (4) + heading hold
+/// gains (12) + steering output (4) + display refresh rate (4) + needle
+/// damping (4) + startup mode (4) + redundancy limit (4) + GPS heading offset
+/// (4) + RGB palette (4) + CRC-32 of the rest (4)
+pub const RECORD_LEN: usize = 260;
+
+const _: () = assert!(RECORD_LEN <= storage::MAX_RECORD_LEN);
+
+/// Offset of the CRC-32 at the end of a record
+const CRC_AT: usize = RECORD_LEN - 4;
+
+/// Magnetometer samples that can be averaged per heading
+pub const MAG_AVERAGE_RANGE: core::ops::RangeInclusive<u8> = 1..=pipeline::MAX_SAMPLES as u8;
+
+/// Degrees two magnetometers' headings may differ by before it is a fault,
+/// with the `redundant-mag` feature
+pub const REDUNDANCY_RANGE: core::ops::RangeInclusive<u8> = 5..=90;
+
+/// Records that fit in the settings page. Each save goes in the next free
+/// slot, and the page is only erased once they are all used, so changing a
+/// setting often doesn't wear out the flash.
+const SLOTS: usize = PAGE_SIZE / RECORD_LEN;
+
+/// Slot the next save goes in, past the last one holding a record
+static NEXT_SLOT: Mutex<CriticalSectionRawMutex, Cell<usize>> = Mutex::new(Cell::new(0));
+
+/// Longest interval between logged samples, s
+pub const MAX_LOG_INTERVAL_S: u32 = 3600;
+
+/// Longest time without use before going idle, s
+pub const MAX_IDLE_TIMEOUT_S: u32 = 3600;
+
+/// Shortest and longest stride accepted, m
+pub const STRIDE_RANGE: core::ops::RangeInclusive<f32> = 0.2..=2.0;
+
+/// What the LED matrix shows
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum DisplayMode {
+    /// An arrow for the nearest cardinal direction
+    Arrow,
+    /// A dot on the outer ring of LEDs, in 22.5° steps
+    Degrees,
+    /// As [`DisplayMode::Degrees`], with the last few seconds of headings
+    /// fading out behind the dot
+    Trail,
+}
+
+/// When the display goes into [`crate::night`] mode
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum NightMode {
+    Off,
+    On,
+    /// When the ambient light falls to dusk, out again by dawn
+    Auto,
+}
+
+/// What the servo output drives while holding a heading
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Steering {
+    /// One servo on a rudder or the front wheels
+    Rudder,
+    /// Two drive motors' speed controllers, left and right, steering by
+    /// running one faster than the other
+    Differential,
+}
+
+/// How the external RGB LEDs color the heading. While following a bearing
+/// they go from green on course to red facing away instead.
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum Palette {
+    /// Around the hue wheel: red north, green east-ish, blue west-ish
+    Rainbow,
+    /// One color per cardinal direction: N red, E yellow, S green, W blue
+    Cardinal,
+}
+
+/// Parameters that can be changed at runtime from the serial console and
+/// persist across resets
+#[derive(Clone, Copy)]
+pub struct Settings {
+    /// Magnetic declination at the user's location, in degrees (east
+    /// positive), added to the magnetic heading when showing true north
+    pub declination: f32,
+    /// Magnetometer output data rate, Hz: 10, 20, 50 or 100
+    pub mag_odr_hz: u16,
+    pub display: DisplayMode,
+    /// North the display and the bearings follow
+    pub display_reference: Reference,
+    /// North of the heading sent as telemetry and logged, e.g. magnetic for
+    /// an autopilot while the display shows true north
+    pub telemetry_reference: Reference,
+    pub output_format: OutputFormat,
+    /// Report pitch, roll and which side is up from the accelerometer alone,
+    /// ignoring the magnetometer
+    pub tilt_only: bool,
+    pub audio: AudioMode,
+    pub radio: RadioMode,
+    /// Boards only hear others in the same group, 0-255
+    pub radio_group: u8,
+    /// Distance covered per step for dead reckoning, m
+    pub stride_m: f32,
+    pub log_mode: LogMode,
+    /// Shortest time between logged samples, s. Zero logs every sample
+    pub log_interval_s: u32,
+    /// Unit of headings in text telemetry and log dumps
+    pub heading_unit: HeadingUnit,
+    /// Magnetometer samples each axis's median is taken over: 1 (off), 3 or 5
+    pub mag_median: u8,
+    /// Consecutive magnetometer samples averaged per heading, 1 to 16. Worth
+    /// raising along with the ODR: at 100 Hz, 10 samples still give a fresh
+    /// heading every loop.
+    pub mag_average: u8,
+    /// Drop the magnetometer ODR and display refresh while the board lies
+    /// still
+    pub adaptive_rate: bool,
+    /// Blank the display and idle the magnetometer after this long without
+    /// motion or a heading change, s. Zero never goes idle
+    pub idle_timeout_s: u32,
+    /// Refine the hard-iron offsets in the background during normal use
+    pub auto_calibration: bool,
+    /// Which way up the board is mounted, applied to both sensors after
+    /// calibration
+    pub mounting: MountMode,
+    pub attitude: AttitudeOutput,
+    /// Attitude messages per second, 1 to [`MAX_ATTITUDE_RATE_HZ`]
+    pub attitude_rate_hz: u8,
+    /// Degrees the heading has to move, or a new cardinal direction, before
+    /// it is sent as telemetry or logged over defmt again, 0 to
+    /// [`MAX_THRESHOLD`]. Zero sends every sample
+    pub heading_threshold: u8,
+    /// Accelerometer output data rate, Hz, one of [`ACCEL_RATES_HZ`]. Tap
+    /// and free-fall detection are tuned for 50 Hz
+    pub accel_odr_hz: u16,
+    pub accel_power: AccelPower,
+    /// Run the magnetometer in its low-power mode, noisier but drawing less.
+    /// Parts without one drop to their slowest rate instead
+    pub mag_low_power: bool,
+    /// Degrees either side of north or the active bearing within which the
+    /// haptic output pulses, 0 to [`haptic::MAX_WINDOW`]. Zero turns it off
+    pub haptic_window: u8,
+    /// Blink the subsystem health on a corner of the LED matrix
+    pub heartbeat: bool,
+    /// The LSM303AGR magnetometer's low-pass filter, less noise for a little
+    /// more lag
+    pub mag_low_pass: bool,
+    pub accel_high_pass: AccelHighPass,
+    /// Leave readings far from 1 g out of tilt compensation, for a board
+    /// mounted on a bicycle or a car
+    pub vibration_rejection: bool,
+    /// Latitude and longitude in degrees, north and east positive, for the
+    /// sun check when there is no GPS fix
+    pub location: Option<[f32; 2]>,
+    /// Corrections for the deviation the installation adds on each heading
+    pub deviation: DeviationTable,
+    /// Headings the heading alarm allows, `None` for no alarm
+    pub alarm: Option<alarm::Sector>,
+    /// Display brightness, 1 to [`GREY_LEVELS`], `None` to follow the
+    /// ambient light
+    pub brightness: Option<u8>,
+    pub night: NightMode,
+    /// PID gains of the heading hold
+    pub gains: Gains,
+    pub steering: Steering,
+    pub palette: Palette,
+    /// Needle redraws per second, see [`crate::display::frames_per_draw`]
+    pub refresh_hz: u8,
+    /// Fastest the needle swings, degrees per second, `None` to jump
+    /// straight to each new heading
+    pub damping: Option<u16>,
+    /// Display mode to start in, with no stored bearing being followed,
+    /// `None` to carry on as the board was left
+    pub startup: Option<DisplayMode>,
+    /// Degrees the backup magnetometer's heading may differ from the
+    /// primary's by, within [`REDUNDANCY_RANGE`]
+    pub redundancy_limit: u8,
+    /// Degrees added to the magnetic heading, learned from the GPS course
+    /// and confirmed by holding A
+    pub course_offset: f32,
+}
+
+impl Settings {
+    pub const DEFAULT: Self = Self {
+        declination: crate::board::DEFAULT_DECLINATION,
+        mag_odr_hz: 10,
+        display: DisplayMode::Arrow,
+        display_reference: Reference::Magnetic,
+        telemetry_reference: Reference::Magnetic,
+        output_format: OutputFormat::Text,
+        tilt_only: false,
+        audio: AudioMode::Proximity,
+        radio: RadioMode::Off,
+        radio_group: 0,
+        stride_m: 0.75,
+        log_mode: LogMode::Continuous,
+        log_interval_s: 0,
+        heading_unit: HeadingUnit::Degrees,
+        mag_median: 3,
+        mag_average: 1,
+        adaptive_rate: true,
+        idle_timeout_s: 120,
+        auto_calibration: false,
+        mounting: MountMode::Fixed(Mounting::FLAT),
+        attitude: AttitudeOutput::Off,
+        attitude_rate_hz: 5,
+        heading_threshold: 0,
+        accel_odr_hz: 50,
+        accel_power: AccelPower::HighResolution,
+        mag_low_power: false,
+        haptic_window: 5,
+        heartbeat: true,
+        mag_low_pass: true,
+        accel_high_pass: AccelHighPass {
+            tap: false,
+            cutoff: 0,
+        },
+        vibration_rejection: false,
+        location: None,
+        deviation: DeviationTable::EMPTY,
+        alarm: None,
+        brightness: None,
+        night: NightMode::Off,
+        gains: Gains::DEFAULT,
+        steering: Steering::Rudder,
+        palette: Palette::Rainbow,
+        refresh_hz: 10,
+        damping: None,
+        startup: None,
+        redundancy_limit: 15,
+        course_offset: 0.0,
+    };
+
+    /// Restore the settings saved last, or if they were corrupted the ones
+    /// saved before them. An error means none could be read, and the
+    /// defaults should be used instead.
+    pub fn load(storage: &mut Storage<'_>) -> Result<Self, Error> {
+        let mut buf = [0u8; RECORD_LEN];
+        let mut latest = None;
+        let mut valid = None;
+        for slot in 0..SLOTS {
+            let mut record = [0u8; RECORD_LEN];
+            storage.load(slot_address(slot), &mut record)?;
+            if record[0..4] != MAGIC.to_le_bytes() {
+                break;
+            }
+            latest = Some(slot);
+            let crc = u32::from_le_bytes([
+                record[CRC_AT],
+                record[CRC_AT + 1],
+                record[CRC_AT + 2],
+                record[CRC_AT + 3],
+            ]);
+            if crc == crc32(&record[..CRC_AT]) {
+                buf = record;
+                valid = Some(slot);
+            } else {
+                warn!("settings in slot {} are corrupt", slot);
+            }
+        }
+        let Some(latest) = latest else {
+            info!("using default settings");
+            // The page may not be erased, have the first save erase it
+            NEXT_SLOT.lock(|next| next.set(SLOTS));
+            return Ok(Self::DEFAULT);
+        };
+        NEXT_SLOT.lock(|next| next.set(latest + 1));
+        let Some(slot) = valid else {
+            return Err(Error::Storage(StorageError::Corrupt));
+        };
+        let word = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+
+        info!("restored settings from slot {}", slot);
+        Ok(Self {
+            declination: f32::from_bits(word(4)),
+            mag_odr_hz: word(8) as u16,
+            display: match word(12) {
+                1 => DisplayMode::Degrees,
+                2 => DisplayMode::Trail,
+                _ => DisplayMode::Arrow,
+            },
+            display_reference: reference(word(16)),
+            telemetry_reference: reference(word(20)),
+            output_format: match word(24) {
+                1 => OutputFormat::Nmea,
+                2 => OutputFormat::Binary,
+                3 => OutputFormat::MotionCal,
+                4 => OutputFormat::Mavlink,
+                5 => OutputFormat::Json,
+                _ => OutputFormat::Text,
+            },
+            tilt_only: word(28) == 1,
+            audio: match word(32) {
+                1 => AudioMode::Clicks,
+                2 => AudioMode::Geiger,
+                _ => AudioMode::Proximity,
+            },
+            radio: match word(36) {
+                1 => RadioMode::Send,
+                2 => RadioMode::Receive,
+                3 => RadioMode::Compare,
+                4 => RadioMode::Clock,
+                5 => RadioMode::Sync,
+                _ => RadioMode::Off,
+            },
+            radio_group: u8::try_from(word(40)).unwrap_or(0),
+            stride_m: Some(f32::from_bits(word(44)))
+                .filter(|stride| STRIDE_RANGE.contains(stride))
+                .unwrap_or(Self::DEFAULT.stride_m),
+            log_mode: match word(48) {
+                0 => LogMode::Off,
+                2 => LogMode::Motion,
+                _ => LogMode::Continuous,
+            },
+            log_interval_s: Some(word(52))
+                .filter(|&interval| interval <= MAX_LOG_INTERVAL_S)
+                .unwrap_or(0),
+            heading_unit: match word(56) {
+                1 => HeadingUnit::Radians,
+                2 => HeadingUnit::Mils,
+                3 => HeadingUnit::Grads,
+                _ => HeadingUnit::Degrees,
+            },
+            mag_median: match word(60) {
+                1 => 1,
+                5 => 5,
+                _ => 3,
+            },
+            mag_average: u8::try_from(word(64))
+                .ok()
+                .filter(|samples| MAG_AVERAGE_RANGE.contains(samples))
+                .unwrap_or(1),
+            adaptive_rate: word(68) != 0,
+            idle_timeout_s: Some(word(72))
+                .filter(|&timeout| timeout <= MAX_IDLE_TIMEOUT_S)
+                .unwrap_or(Self::DEFAULT.idle_timeout_s),
+            auto_calibration: word(76) == 1,
+            mounting: MountMode::from_bits(word(80)).unwrap_or(Self::DEFAULT.mounting),
+            attitude: match word(84) {
+                1 => AttitudeOutput::Quaternion,
+                2 => AttitudeOutput::Euler,
+                _ => AttitudeOutput::Off,
+            },
+            attitude_rate_hz: u8::try_from(word(88))
+                .ok()
+                .filter(|hz| (1..=MAX_ATTITUDE_RATE_HZ).contains(hz))
+                .unwrap_or(Self::DEFAULT.attitude_rate_hz),
+            heading_threshold: u8::try_from(word(92))
+                .ok()
+                .filter(|&threshold| threshold <= MAX_THRESHOLD)
+                .unwrap_or(Self::DEFAULT.heading_threshold),
+            accel_odr_hz: u16::try_from(word(96))
+                .ok()
+                .filter(|hz| ACCEL_RATES_HZ.contains(hz))
+                .unwrap_or(Self::DEFAULT.accel_odr_hz),
+            accel_power: match word(100) {
+                1 => AccelPower::Normal,
+                2 => AccelPower::LowPower,
+                _ => AccelPower::HighResolution,
+            },
+            mag_low_power: word(104) == 1,
+            haptic_window: u8::try_from(word(108))
+                .ok()
+                .filter(|&window| window <= haptic::MAX_WINDOW)
+                .unwrap_or(Self::DEFAULT.haptic_window),
+            heartbeat: word(112) == 1,
+            mag_low_pass: word(116) == 1,
+            accel_high_pass: AccelHighPass {
+                tap: word(120) == 1,
+                cutoff: u8::try_from(word(124))
+                    .ok()
+                    .filter(|&cutoff| cutoff <= MAX_HIGH_PASS_CUTOFF)
+                    .unwrap_or(Self::DEFAULT.accel_high_pass.cutoff),
+            },
+            vibration_rejection: word(128) == 1,
+            // NaN when unset
+            location: Some([f32::from_bits(word(132)), f32::from_bits(word(136))])
+                .filter(|location| location.iter().all(|degrees| degrees.is_finite())),
+            deviation: DeviationTable::from_bits(core::array::from_fn(|point| {
+                word(140 + point * 4)
+            })),
+            alarm: alarm::Sector::from_bits(word(204)),
+            // Zero is automatic
+            brightness: u8::try_from(word(208))
+                .ok()
+                .filter(|level| (1..=GREY_LEVELS).contains(level)),
+            night: match word(212) {
+                1 => NightMode::On,
+                2 => NightMode::Auto,
+                _ => NightMode::Off,
+            },
+            gains: Some(Gains {
+                kp: f32::from_bits(word(216)),
+                ki: f32::from_bits(word(220)),
+                kd: f32::from_bits(word(224)),
+            })
+            .filter(Gains::is_valid)
+            .unwrap_or(Gains::DEFAULT),
+            steering: match word(228) {
+                1 => Steering::Differential,
+                _ => Steering::Rudder,
+            },
+            palette: match word(252) {
+                1 => Palette::Cardinal,
+                _ => Palette::Rainbow,
+            },
+            refresh_hz: u8::try_from(word(232))
+                .ok()
+                .filter(|hz| REFRESH_RANGE_HZ.contains(hz))
+                .unwrap_or(Self::DEFAULT.refresh_hz),
+            // Zero is off
+            damping: u16::try_from(word(236))
+                .ok()
+                .filter(|rate| DAMPING_RANGE.contains(rate)),
+            // Zero is the last state
+            startup: match word(240) {
+                1 => Some(DisplayMode::Arrow),
+                2 => Some(DisplayMode::Degrees),
+                3 => Some(DisplayMode::Trail),
+                _ => None,
+            },
+            redundancy_limit: u8::try_from(word(244))
+                .ok()
+                .filter(|limit| REDUNDANCY_RANGE.contains(limit))
+                .unwrap_or(Self::DEFAULT.redundancy_limit),
+            course_offset: Some(f32::from_bits(word(248)))
+                .filter(|offset| (-180.0..=180.0).contains(offset))
+                .unwrap_or(Self::DEFAULT.course_offset),
+        })
+    }
+
+    pub fn save(&self) {
+        let mut buf = [0u8; RECORD_LEN];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.declination.to_le_bytes());
+        buf[8..12].copy_from_slice(&(self.mag_odr_hz as u32).to_le_bytes());
+        buf[12..16].copy_from_slice(&(self.display as u32).to_le_bytes());
+        buf[16..20].copy_from_slice(&(self.display_reference as u32).to_le_bytes());
+        buf[20..24].copy_from_slice(&(self.telemetry_reference as u32).to_le_bytes());
+        buf[24..28].copy_from_slice(&(self.output_format as u32).to_le_bytes());
+        buf[28..32].copy_from_slice(&(self.tilt_only as u32).to_le_bytes());
+        buf[32..36].copy_from_slice(&(self.audio as u32).to_le_bytes());
+        buf[36..40].copy_from_slice(&(self.radio as u32).to_le_bytes());
+        buf[40..44].copy_from_slice(&(self.radio_group as u32).to_le_bytes());
+        buf[44..48].copy_from_slice(&self.stride_m.to_le_bytes());
+        buf[48..52].copy_from_slice(&(self.log_mode as u32).to_le_bytes());
+        buf[52..56].copy_from_slice(&self.log_interval_s.to_le_bytes());
+        buf[56..60].copy_from_slice(&(self.heading_unit as u32).to_le_bytes());
+        buf[60..64].copy_from_slice(&(self.mag_median as u32).to_le_bytes());
+        buf[64..68].copy_from_slice(&(self.mag_average as u32).to_le_bytes());
+        buf[68..72].copy_from_slice(&(self.adaptive_rate as u32).to_le_bytes());
+        buf[72..76].copy_from_slice(&self.idle_timeout_s.to_le_bytes());
+        buf[76..80].copy_from_slice(&(self.auto_calibration as u32).to_le_bytes());
+        buf[80..84].copy_from_slice(&self.mounting.to_bits().to_le_bytes());
+        buf[84..88].copy_from_slice(&(self.attitude as u32).to_le_bytes());
+        buf[88..92].copy_from_slice(&(self.attitude_rate_hz as u32).to_le_bytes());
+        buf[92..96].copy_from_slice(&(self.heading_threshold as u32).to_le_bytes());
+        buf[96..100].copy_from_slice(&(self.accel_odr_hz as u32).to_le_bytes());
+        buf[100..104].copy_from_slice(&(self.accel_power as u32).to_le_bytes());
+        buf[104..108].copy_from_slice(&(self.mag_low_power as u32).to_le_bytes());
+        buf[108..112].copy_from_slice(&(self.haptic_window as u32).to_le_bytes());
+        buf[112..116].copy_from_slice(&(self.heartbeat as u32).to_le_bytes());
+        buf[116..120].copy_from_slice(&(self.mag_low_pass as u32).to_le_bytes());
+        buf[120..124].copy_from_slice(&(self.accel_high_pass.tap as u32).to_le_bytes());
+        buf[124..128].copy_from_slice(&(self.accel_high_pass.cutoff as u32).to_le_bytes());
+        buf[128..132].copy_from_slice(&(self.vibration_rejection as u32).to_le_bytes());
+        let [latitude, longitude] = self.location.unwrap_or([f32::NAN; 2]);
+        buf[132..136].copy_from_slice(&latitude.to_bits().to_le_bytes());
+        buf[136..140].copy_from_slice(&longitude.to_bits().to_le_bytes());
+        for (point, bits) in self.deviation.to_bits().into_iter().enumerate() {
+            let at = 140 + point * 4;
+            buf[at..at + 4].copy_from_slice(&bits.to_le_bytes());
+        }
+        buf[204..208].copy_from_slice(&alarm::Sector::to_bits(self.alarm).to_le_bytes());
+        buf[208..212].copy_from_slice(&u32::from(self.brightness.unwrap_or(0)).to_le_bytes());
+        buf[212..216].copy_from_slice(&(self.night as u32).to_le_bytes());
+        buf[216..220].copy_from_slice(&self.gains.kp.to_le_bytes());
+        buf[220..224].copy_from_slice(&self.gains.ki.to_le_bytes());
+        buf[224..228].copy_from_slice(&self.gains.kd.to_le_bytes());
+        buf[228..232].copy_from_slice(&(self.steering as u32).to_le_bytes());
+        buf[232..236].copy_from_slice(&u32::from(self.refresh_hz).to_le_bytes());
+        buf[236..240].copy_from_slice(&u32::from(self.damping.unwrap_or(0)).to_le_bytes());
+        let startup = self.startup.map_or(0, |mode| mode as u32 + 1);
+        buf[240..244].copy_from_slice(&startup.to_le_bytes());
+        buf[244..248].copy_from_slice(&u32::from(self.redundancy_limit).to_le_bytes());
+        buf[248..252].copy_from_slice(&self.course_offset.to_le_bytes());
+        buf[252..256].copy_from_slice(&(self.palette as u32).to_le_bytes());
+        let crc = crc32(&buf[..CRC_AT]);
+        buf[CRC_AT..].copy_from_slice(&crc.to_le_bytes());
+
+        let slot = NEXT_SLOT.lock(|next| {
+            let slot = next.get();
+            next.set(if slot < SLOTS { slot + 1 } else { 1 });
+            slot
+        });
+        if slot < SLOTS {
+            storage::write(slot_address(slot), &buf);
+        } else {
+            // Every slot used, start the page over
+            storage::save(storage::SETTINGS_PAGE, &buf);
+        }
+    }
+
+    /// Reply with one line per setting, each the console command that sets
+    /// it, so the output can be pasted back to restore them
+    pub async fn report(&self) {
+        let log = match self.log_mode {
+            LogMode::Off => "log stop",
+            LogMode::Continuous => "log start",
+            LogMode::Motion => "log start motion",
+        };
+        let mut location: String<24> = String::new();
+        // Two coordinates to four places fit
+        let _ = match self.location {
+            Some([latitude, longitude]) => write!(location, "{latitude:.4} {longitude:.4}"),
+            None => write!(location, "none"),
+        };
+        let mut alarm: String<8> = String::new();
+        let _ = match self.alarm {
+            Some(sector) => write!(alarm, "{} {}", sector.from, sector.to),
+            None => write!(alarm, "off"),
+        };
+        let display = display_mode_name(self.display);
+        let startup = self.startup.map_or("last", display_mode_name);
+        let format = match self.output_format {
+            OutputFormat::Text => "text",
+            OutputFormat::Nmea => "nmea",
+            OutputFormat::Binary => "binary",
+            OutputFormat::MotionCal => "motioncal",
+            OutputFormat::Mavlink => "mavlink",
+            OutputFormat::Json => "json",
+        };
+        let audio = match self.audio {
+            AudioMode::Proximity => "proximity",
+            AudioMode::Clicks => "clicks",
+            AudioMode::Geiger => "geiger",
+        };
+        let radio = match self.radio {
+            RadioMode::Off => "off",
+            RadioMode::Send => "send",
+            RadioMode::Receive => "receive",
+            RadioMode::Compare => "compare",
+            RadioMode::Clock => "clock",
+            RadioMode::Sync => "sync",
+        };
+        let attitude = match self.attitude {
+            AttitudeOutput::Off => "off",
+            AttitudeOutput::Quaternion => "quaternion",
+            AttitudeOutput::Euler => "euler",
+        };
+        let accel_power = match self.accel_power {
+            AccelPower::HighResolution => "high",
+            AccelPower::Normal => "normal",
+            AccelPower::LowPower => "low",
+        };
+        let mut brightness: String<4> = String::new();
+        let _ = match self.brightness {
+            Some(level) => write!(brightness, "{level}"),
+            None => write!(brightness, "auto"),
+        };
+        let night = match self.night {
+            NightMode::Off => "off",
+            NightMode::On => "on",
+            NightMode::Auto => "auto",
+        };
+        let mut gains: String<32> = String::new();
+        // Three gains of at most 1 to four places fit
+        let _ = write!(
+            gains,
+            "{:.4} {:.4} {:.4}",
+            self.gains.kp, self.gains.ki, self.gains.kd
+        );
+        let steering = match self.steering {
+            Steering::Rudder => "rudder",
+            Steering::Differential => "differential",
+        };
+        let palette = match self.palette {
+            Palette::Rainbow => "rainbow",
+            Palette::Cardinal => "cardinal",
+        };
+        let mut damping: String<4> = String::new();
+        let _ = match self.damping {
+            Some(rate) => write!(damping, "{rate}"),
+            None => write!(damping, "off"),
+        };
+        let lines: [(&str, &dyn core::fmt::Display); 43] = [
+            ("set declination ", &self.declination),
+            ("set location ", &location),
+            ("set odr ", &self.mag_odr_hz),
+            ("set accel ", &self.accel_odr_hz),
+            ("set power accel ", &accel_power),
+            (
+                "set power mag ",
+                &if self.mag_low_power { "low" } else { "high" },
+            ),
+            (
+                "set lowpass ",
+                &if self.mag_low_pass { "on" } else { "off" },
+            ),
+            (
+                "set hpf tap ",
+                &if self.accel_high_pass.tap {
+                    "on"
+                } else {
+                    "off"
+                },
+            ),
+            ("set hpf cutoff ", &self.accel_high_pass.cutoff),
+            (
+                "set vibration ",
+                &if self.vibration_rejection {
+                    "on"
+                } else {
+                    "off"
+                },
+            ),
+            (
+                "set reference display ",
+                &reference_name(self.display_reference),
+            ),
+            (
+                "set reference telemetry ",
+                &reference_name(self.telemetry_reference),
+            ),
+            ("set stride ", &self.stride_m),
+            ("set median ", &self.mag_median),
+            ("set average ", &self.mag_average),
+            (
+                "set adaptive ",
+                &if self.adaptive_rate { "on" } else { "off" },
+            ),
+            ("set idle ", &self.idle_timeout_s),
+            ("set threshold ", &self.heading_threshold),
+            ("set brightness ", &brightness),
+            ("set refresh ", &self.refresh_hz),
+            ("set damping ", &damping),
+            ("night ", &night),
+            ("pid ", &gains),
+            ("steering ", &steering),
+            ("set palette ", &palette),
+            ("set haptic ", &self.haptic_window),
+            ("set redundancy ", &self.redundancy_limit),
+            ("alarm ", &alarm),
+            ("set heartbeat ", &if self.heartbeat { "on" } else { "off" }),
+            (
+                "set autocal ",
+                &if self.auto_calibration { "on" } else { "off" },
+            ),
+            ("mount ", &self.mounting),
+            ("mode ", &display),
+            ("startup ", &startup),
+            ("format ", &format),
+            ("units ", &self.heading_unit.name()),
+            ("attitude ", &attitude),
+            ("attitude rate ", &self.attitude_rate_hz),
+            ("tilt ", &if self.tilt_only { "on" } else { "off" }),
+            ("audio ", &audio),
+            ("radio ", &radio),
+            ("radio group ", &self.radio_group),
+            (log, &""),
+            ("log interval ", &self.log_interval_s),
+        ];
+        for (command, value) in lines {
+            let mut line: String<{ console::MAX_REPLY }> = String::new();
+            let _ = write!(line, "{command}{value}");
+            // Wait for room, there are more lines than the queue holds
+            console::REPLIES.send(line).await;
+        }
+        // The table a point at a time, after clearing whatever is there
+        console::REPLIES
+            .send(String::try_from("deviation clear").unwrap())
+            .await;
+        for point in 0..deviation::POINTS {
+            if let Some(correction) = self.deviation.get(point) {
+                let mut line: String<{ console::MAX_REPLY }> = String::new();
+                let heading = DeviationTable::heading(point);
+                let _ = write!(line, "deviation {heading} {correction}");
+                console::REPLIES.send(line).await;
+            }
+        }
+    }
+}
+
+fn display_mode_name(mode: DisplayMode) -> &'static str {
+    match mode {
+        DisplayMode::Arrow => "arrow",
+        DisplayMode::Degrees => "degrees",
+        DisplayMode::Trail => "trail",
+    }
+}
+
+/// CRC-32 (IEEE), bit by bit as records are short and rarely checked
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn slot_address(slot: usize) -> u32 {
+    storage::SETTINGS_PAGE + (slot * RECORD_LEN) as u32
+}
+
+pub fn reference_name(reference: Reference) -> &'static str {
+    match reference {
+        Reference::Magnetic => "magnetic",
+        Reference::True => "true",
+    }
+}
+
+/// Anything but `1` is magnetic
+fn reference(word: u32) -> Reference {
+    match word {
+        1 => Reference::True,
+        _ => Reference::Magnetic,
+    }
+}
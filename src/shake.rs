@@ -0,0 +1,52 @@
+use micromath::F32Ext;
+
+/// Number of accelerometer samples the variance is computed over
+const WINDOW: usize = 8;
+
+/// Standard deviation of the acceleration magnitude (mg) that counts as a shake
+const SHAKE_THRESHOLD_MG: f32 = 500.0;
+
+/// Detects a shake gesture from the variance of the acceleration magnitude
+/// over a short window of samples
+pub struct ShakeDetector {
+    magnitudes: [f32; WINDOW],
+    next: usize,
+    filled: bool,
+}
+
+impl ShakeDetector {
+    pub const fn new() -> Self {
+        Self {
+            magnitudes: [0.0; WINDOW],
+            next: 0,
+            filled: false,
+        }
+    }
+
+    /// Add an accelerometer sample (mg), returning `true` when the board is
+    /// being shaken. The window is cleared after a shake so one gesture is
+    /// only reported once.
+    pub fn update(&mut self, x: f32, y: f32, z: f32) -> bool {
+        self.magnitudes[self.next] = (x * x + y * y + z * z).sqrt();
+        self.next = (self.next + 1) % WINDOW;
+        self.filled |= self.next == 0;
+        if !self.filled {
+            return false;
+        }
+
+        let mean = self.magnitudes.iter().sum::<f32>() / WINDOW as f32;
+        let variance = self
+            .magnitudes
+            .iter()
+            .map(|m| (m - mean) * (m - mean))
+            .sum::<f32>()
+            / WINDOW as f32;
+
+        let shaking = variance > SHAKE_THRESHOLD_MG * SHAKE_THRESHOLD_MG;
+        if shaking {
+            self.next = 0;
+            self.filled = false;
+        }
+        shaking
+    }
+}
@@ -0,0 +1,100 @@
+use defmt::{info, warn, Format};
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use micromath::F32Ext;
+
+use crate::normalize_heading;
+
+/// How long the board is held still for a bearing
+const DURATION: Duration = Duration::from_secs(2);
+
+/// Give up if the board hasn't been still for [`DURATION`] by then
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Headings held, well over [`DURATION`] at the main loop's rate
+const CAPACITY: usize = 32;
+
+/// z for a 95% confidence interval
+const Z_95: f32 = 1.96;
+
+/// A bearing taken by [`Sighting`]
+#[derive(Clone, Copy, Format)]
+pub struct Bearing {
+    /// Circular mean, degrees
+    pub mean: f32,
+    /// Half-width of the 95% confidence interval of the mean, degrees
+    pub within: f32,
+    pub samples: usize,
+}
+
+impl Bearing {
+    /// The mean to the nearest whole degree, 0 to 359
+    pub fn degrees(&self) -> u32 {
+        self.mean.round() as u32 % 360
+    }
+}
+
+/// Takes a bearing the way a surveyor does with a sighting compass: held
+/// still on the mark, the heading is averaged over a couple of seconds
+/// rather than read off once, and the spread of the readings tells how far
+/// the result can be trusted. Moving starts the collection over.
+pub struct Sighting {
+    started: Instant,
+    /// Since when the board has been still, with the headings since
+    still_since: Option<Instant>,
+    headings: Vec<f32, CAPACITY>,
+}
+
+impl Sighting {
+    pub fn new() -> Self {
+        info!("sighting: hold still");
+        Self {
+            started: Instant::now(),
+            still_since: None,
+            headings: Vec::new(),
+        }
+    }
+
+    /// Feed the heading and whether the board is moving. `None` while
+    /// collecting, then the bearing, or an error if the board never held
+    /// still for long enough.
+    pub fn update(&mut self, heading: f32, moving: bool) -> Option<Result<Bearing, &'static str>> {
+        if moving {
+            self.still_since = None;
+            self.headings.clear();
+        } else {
+            let since = *self.still_since.get_or_insert_with(Instant::now);
+            let _ = self.headings.push(heading);
+            if since.elapsed() >= DURATION || self.headings.is_full() {
+                let bearing = mean(&self.headings);
+                info!("sighting: {}", bearing);
+                return Some(Ok(bearing));
+            }
+        }
+        if self.started.elapsed() >= TIMEOUT {
+            warn!("sighting: never held still");
+            return Some(Err("not held still"));
+        }
+        None
+    }
+}
+
+/// Circular mean of `headings`, with the confidence interval from their
+/// circular standard deviation
+fn mean(headings: &[f32]) -> Bearing {
+    // Averaged as unit vectors, so readings either side of north don't
+    // average to south
+    let (sin, cos) = headings.iter().fold((0.0, 0.0), |(sin, cos), h| {
+        let h = h.to_radians();
+        (sin + h.sin(), cos + h.cos())
+    });
+    let n = headings.len() as f32;
+    // Length of the mean vector, 1 when every reading agrees
+    let r = ((sin * sin + cos * cos).sqrt() / n).clamp(f32::MIN_POSITIVE, 1.0);
+    let deviation = (-2.0 * r.ln()).sqrt().to_degrees();
+    Bearing {
+        mean: normalize_heading(sin.atan2(cos).to_degrees()),
+        within: Z_95 * deviation / n.sqrt(),
+        samples: headings.len(),
+    }
+}
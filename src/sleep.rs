@@ -0,0 +1,52 @@
+use defmt::info;
+use embassy_nrf::{
+    gpio::{self, Level},
+    pac::{
+        self,
+        gpio::vals::{Dir, Input, Pull, Sense},
+    },
+};
+
+use crate::board;
+
+/// Pins per GPIO port
+const PORT_PINS: [(pac::gpio::Gpio, usize); 2] = [(pac::P0, 32), (pac::P1, 10)];
+
+/// Enter SYSTEM OFF, the nRF52833's deepest sleep, drawing a few µA until
+/// button A is pressed. Waking resets the board, so this never returns.
+///
+/// Callers shut the sensors down and let flash writes finish first. Pins
+/// being awaited by other tasks are set to sense too, which would wake the
+/// board straight away, so sensing is turned off on every pin but button A.
+pub fn system_off() -> ! {
+    info!("entering system off, press A to wake");
+    cortex_m::interrupt::disable();
+
+    for (port, pins) in PORT_PINS {
+        for pin in 0..pins {
+            port.pin_cnf(pin).modify(|w| w.set_sense(Sense::DISABLED));
+        }
+    }
+    // Held and sensed as the board file has the buttons
+    let (port, pin) = board::registers(board::BUTTON_A_PIN);
+    port.pin_cnf(pin).write(|w| {
+        w.set_dir(Dir::INPUT);
+        w.set_input(Input::CONNECT);
+        w.set_pull(match board::BUTTON_PULL {
+            gpio::Pull::None => Pull::DISABLED,
+            gpio::Pull::Up => Pull::PULLUP,
+            gpio::Pull::Down => Pull::PULLDOWN,
+        });
+        w.set_sense(match board::BUTTON_PRESSED {
+            Level::Low => Sense::LOW,
+            Level::High => Sense::HIGH,
+        });
+    });
+
+    pac::POWER.systemoff().write(|w| w.set_systemoff(true));
+    // With a debugger attached SYSTEM OFF is only emulated and the CPU carries
+    // on, so wait here for the wake-up reset
+    loop {
+        cortex_m::asm::wfe();
+    }
+}
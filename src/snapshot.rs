@@ -0,0 +1,66 @@
+use defmt::info;
+use micro_compass_core::pipeline;
+
+use crate::{
+    error::Error,
+    sensor::{Magnetometer as _, Sensor},
+    settings::Settings,
+};
+
+/// Magnetometer rate during a burst, Hz, the fastest every supported part
+/// has
+pub const ODR_HZ: u16 = 100;
+
+/// Samples averaged into each heading while a bearing is taken, 80 ms'
+/// worth at [`ODR_HZ`]
+pub const BEARING_SAMPLES: u8 = 8;
+
+const _: () = assert!(BEARING_SAMPLES as usize <= pipeline::MAX_SAMPLES);
+
+/// Runs the magnetometer at [`ODR_HZ`] and full resolution for as long as a
+/// flow needs many good samples: taking a bearing, which averages
+/// [`BEARING_SAMPLES`] of them into each heading, or calibrating, which gets
+/// a fresh one every loop rather than one in five at the default rate. The
+/// rate goes back to the configured one, or the adaptive low rate, as soon
+/// as the flow ends, so the extra current is only drawn meanwhile.
+pub struct Burst {
+    active: bool,
+}
+
+impl Burst {
+    pub const fn new() -> Self {
+        Self { active: false }
+    }
+
+    /// Start or end the burst as `wanted` changes, going back to the low
+    /// rate at the end if `low_rate`
+    pub async fn hold(
+        &mut self,
+        sensor: &mut Sensor,
+        wanted: bool,
+        settings: &Settings,
+        low_rate: bool,
+    ) -> Result<(), Error> {
+        if wanted == self.active {
+            return Ok(());
+        }
+        self.active = wanted;
+        if wanted {
+            info!("magnetometer burst");
+            sensor.set_rate(ODR_HZ, false).await
+        } else {
+            info!("magnetometer burst over");
+            crate::set_mag_rate(sensor, settings, low_rate).await
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Forget the burst once the rate has been set some other way, so the
+    /// next [`Self::hold`] starts it again if still wanted
+    pub fn reset(&mut self) {
+        self.active = false;
+    }
+}
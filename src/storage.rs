@@ -0,0 +1,239 @@
+use defmt::warn;
+use embassy_futures::yield_now;
+use embassy_nrf::{
+    nvmc::{Nvmc, PAGE_SIZE},
+    pac,
+};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal,
+};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::Vec;
+
+use crate::error::{Error, StorageError};
+
+/// Start of the flash region reserved in `memory.x` for persistent data
+const STORAGE_START: u32 = 0x0007_8000;
+
+/// Flash page holding the stored bearings
+pub const BEARINGS_PAGE: u32 = STORAGE_START;
+
+/// Flash page holding the record of the last panic or hard fault
+pub const CRASH_PAGE: u32 = STORAGE_START + PAGE_SIZE as u32;
+
+/// Flash page holding the magnetometer calibration
+pub const CALIBRATION_PAGE: u32 = STORAGE_START + 2 * PAGE_SIZE as u32;
+
+/// Flash page holding the user-assigned device name
+pub const NAME_PAGE: u32 = STORAGE_START + 3 * PAGE_SIZE as u32;
+
+/// Flash page holding the settings changed from the serial console
+pub const SETTINGS_PAGE: u32 = STORAGE_START + 4 * PAGE_SIZE as u32;
+
+/// Flash page holding the stored GPS waypoints
+#[cfg(feature = "gps")]
+pub const WAYPOINTS_PAGE: u32 = STORAGE_START + 5 * PAGE_SIZE as u32;
+
+/// Longest record [`save`] accepts, that of the settings. Every record type
+/// asserts that it fits.
+pub const MAX_RECORD_LEN: usize = crate::settings::RECORD_LEN;
+
+/// A page erase is split into partial erases this long, ms. The CPU stalls
+/// while one is in progress, so they are kept short enough for the other
+/// tasks not to notice.
+const ERASE_CHUNK_MS: u8 = 10;
+
+/// Partial erases adding up to at least a full page erase (85 ms)
+const ERASE_CHUNKS: usize = 9;
+
+/// Flash operations queued for [`flash_task`]
+enum Request {
+    Save {
+        page: u32,
+        record: Vec<u8, MAX_RECORD_LEN>,
+    },
+    Append {
+        offset: u32,
+        word: [u8; 4],
+    },
+    Write {
+        offset: u32,
+        record: Vec<u8, MAX_RECORD_LEN>,
+    },
+    Erase {
+        from: u32,
+        to: u32,
+    },
+    /// Signal [`SYNCED`] once everything queued before is done
+    Sync,
+}
+
+static REQUESTS: Channel<CriticalSectionRawMutex, Request, 8> = Channel::new();
+
+static SYNCED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Thin wrapper over the NVMC that reads and rewrites whole records at the
+/// start of a reserved flash page.
+///
+/// Loading and the panic handler use it directly. At runtime it is handed to
+/// [`flash_task`], and writes go through [`save`], [`append`] and [`erase`],
+/// which return immediately so sampling and the display carry on while flash
+/// is busy.
+pub struct Storage<'d> {
+    nvmc: Nvmc<'d>,
+}
+
+impl<'d> Storage<'d> {
+    pub fn new(nvmc: Nvmc<'d>) -> Self {
+        Self { nvmc }
+    }
+
+    /// Read `buf.len()` bytes from the start of `page`
+    pub fn load(&mut self, page: u32, buf: &mut [u8]) -> Result<(), Error> {
+        Ok(self.nvmc.read(page, buf)?)
+    }
+
+    /// Erase `page` and write `data` to its start, blocking until done. `data`
+    /// must be a multiple of 4 bytes long, the NVMC write granularity.
+    pub fn store(&mut self, page: u32, data: &[u8]) -> Result<(), Error> {
+        self.nvmc.erase(page, page + PAGE_SIZE as u32)?;
+        Ok(self.nvmc.write(page, data)?)
+    }
+
+    /// Write `data` at `offset` without erasing, blocking until done. Flash
+    /// bits can only be cleared this way, so it suits already erased flash or
+    /// clearing a flag word.
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        Ok(self.nvmc.write(offset, data)?)
+    }
+
+    /// Carry out every queued operation, blocking, for the panic handler,
+    /// which takes flash over from [`flash_task`] and would otherwise lose
+    /// them
+    pub fn drain(&mut self) {
+        while let Ok(request) = REQUESTS.try_receive() {
+            let result = match request {
+                Request::Save { page, record } => self.store(page, &record),
+                Request::Append { offset, word } => self.write(offset, &word),
+                Request::Write { offset, record } => self.write(offset, &record),
+                Request::Erase { from, to } => self.nvmc.erase(from, to).map_err(Error::from),
+                Request::Sync => Ok(()),
+            };
+            if let Err(e) = result {
+                defmt::error!("queued flash write lost: {}", e);
+            }
+        }
+    }
+
+    /// Erase the pages from `from` up to `to` in short partial erases,
+    /// letting other tasks run in between
+    async fn erase_cooperatively(&mut self, from: u32, to: u32) {
+        let nvmc = pac::NVMC;
+        nvmc.erasepagepartialcfg()
+            .write(|w| w.set_duration(ERASE_CHUNK_MS));
+        for page in (from..to).step_by(PAGE_SIZE) {
+            for _ in 0..ERASE_CHUNKS {
+                nvmc.config()
+                    .write(|w| w.set_wen(pac::nvmc::vals::Wen::EEN));
+                nvmc.erasepagepartial().write_value(page);
+                while !nvmc.ready().read().ready() {}
+                nvmc.config()
+                    .write(|w| w.set_wen(pac::nvmc::vals::Wen::REN));
+                yield_now().await;
+            }
+        }
+    }
+}
+
+/// Erase `page` and write `data` to its start, in the background. `data` must
+/// be a multiple of 4 bytes long.
+pub fn save(page: u32, data: &[u8]) {
+    let Ok(record) = Vec::from_slice(data) else {
+        warn!(
+            "record not saved: {}",
+            Error::Storage(StorageError::RecordTooLong)
+        );
+        return;
+    };
+    if REQUESTS.try_send(Request::Save { page, record }).is_err() {
+        warn!(
+            "record not saved: {}",
+            Error::Storage(StorageError::QueueFull)
+        );
+    }
+}
+
+/// Write one word to already erased flash at `offset`, in the background.
+/// Returns `false` if the queue is full, e.g. during a long erase, in which
+/// case the caller should hold on to the word and try again later.
+pub fn append(offset: u32, word: [u8; 4]) -> bool {
+    REQUESTS.try_send(Request::Append { offset, word }).is_ok()
+}
+
+/// Write `data` to already erased flash at `offset`, in the background,
+/// without erasing the page first. `data` must be a multiple of 4 bytes long.
+pub fn write(offset: u32, data: &[u8]) {
+    let Ok(record) = Vec::from_slice(data) else {
+        warn!(
+            "record not written: {}",
+            Error::Storage(StorageError::RecordTooLong)
+        );
+        return;
+    };
+    if REQUESTS
+        .try_send(Request::Write { offset, record })
+        .is_err()
+    {
+        warn!(
+            "record not written: {}",
+            Error::Storage(StorageError::QueueFull)
+        );
+    }
+}
+
+/// Erase the pages from `from` up to `to`, both page aligned, in the
+/// background. Queued operations complete in order, so appends made after
+/// this land in erased flash.
+pub fn erase(from: u32, to: u32) {
+    if REQUESTS.try_send(Request::Erase { from, to }).is_err() {
+        warn!("not erased: {}", Error::Storage(StorageError::QueueFull));
+    }
+}
+
+/// Wait for every flash operation queued so far to complete, e.g. before
+/// powering off
+pub async fn sync() {
+    SYNCED.reset();
+    REQUESTS.send(Request::Sync).await;
+    SYNCED.wait().await;
+}
+
+/// Carry out queued flash operations one at a time
+#[embassy_executor::task]
+pub async fn flash_task(mut storage: Storage<'static>) {
+    loop {
+        match REQUESTS.receive().await {
+            Request::Save { page, record } => {
+                storage
+                    .erase_cooperatively(page, page + PAGE_SIZE as u32)
+                    .await;
+                if let Err(e) = storage.nvmc.write(page, &record) {
+                    warn!("failed to write record: {}", Error::from(e));
+                }
+            }
+            Request::Append { offset, word } => {
+                if let Err(e) = storage.nvmc.write(offset, &word) {
+                    warn!("failed to append to flash: {}", Error::from(e));
+                }
+            }
+            Request::Write { offset, record } => {
+                if let Err(e) = storage.nvmc.write(offset, &record) {
+                    warn!("failed to write record: {}", Error::from(e));
+                }
+            }
+            Request::Erase { from, to } => storage.erase_cooperatively(from, to).await,
+            Request::Sync => SYNCED.signal(()),
+        }
+        trace!("flash request done");
+    }
+}
@@ -0,0 +1,52 @@
+use embassy_nrf::gpio;
+use embassy_time::Delay;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::angle_diff;
+
+/// Width of the pulse emitted on each crossing
+pub const STROBE_PULSE_MS: u32 = 5;
+
+/// Pulses a GPIO on the edge connector every time the heading crosses a
+/// configured bearing, so external counters/loggers can record crossings
+/// without parsing telemetry.
+pub struct HeadingStrobe<'d> {
+    pin: gpio::Output<'d>,
+    bearing: f32,
+    last_heading: Option<f32>,
+}
+
+impl<'d> HeadingStrobe<'d> {
+    pub fn new(pin: gpio::Output<'d>, bearing: f32) -> Self {
+        Self {
+            pin,
+            bearing,
+            last_heading: None,
+        }
+    }
+
+    /// Feed a new heading, pulsing the pin if the bearing was crossed since
+    /// the previous heading.
+    pub async fn update(&mut self, heading: f32) {
+        let crossed = match self.last_heading {
+            Some(last) => crosses(last, heading, self.bearing),
+            None => false,
+        };
+        self.last_heading = Some(heading);
+
+        if crossed {
+            self.pin.set_high();
+            Delay.delay_ms(STROBE_PULSE_MS).await;
+            self.pin.set_low();
+        }
+    }
+}
+
+/// Whether moving from `from` to `to` passes over `bearing`. Both headings must
+/// be on the near side of the bearing, otherwise a swing through the opposite
+/// direction (bearing + 180°) would also count as a crossing.
+fn crosses(from: f32, to: f32, bearing: f32) -> bool {
+    let before = angle_diff(from, bearing);
+    let after = angle_diff(to, bearing);
+    before.abs() < 90.0 && after.abs() < 90.0 && (before < 0.0) != (after < 0.0)
+}
@@ -0,0 +1,80 @@
+use defmt::info;
+use micro_compass_core::sun::SunPosition;
+use micromath::F32Ext;
+
+use crate::{angle_diff, console, normalize_heading};
+
+/// Headings averaged while pointing at the sun
+const SAMPLES: usize = 20;
+
+/// Lowest sun the check is offered for, degrees. Near the horizon refraction
+/// and terrain get in the way
+pub const MIN_ELEVATION: f32 = 5.0;
+
+/// Errors up to this are within what the compass can do, degrees
+const TOLERANCE: f32 = 5.0;
+
+/// Field check of the declination and calibration against the sun: with
+/// the sun's azimuth worked out from the time and place, the board is held
+/// flat with its top edge towards the sun, button A is pressed, and the
+/// difference between the true heading and the azimuth is reported over the
+/// serial console.
+///
+/// A large error that stays the same at other times of day is the
+/// declination; one that changes with the direction faced is the
+/// calibration.
+pub struct SunCheck {
+    sun: SunPosition,
+    /// Sums of the sines and cosines of the headings averaged so far, once A
+    /// has been pressed
+    recording: Option<(f32, f32, usize)>,
+}
+
+impl SunCheck {
+    pub fn new(sun: SunPosition) -> Self {
+        info!("sun check started");
+        console::reply(format_args!(
+            "sun: azimuth {:.1} elevation {:.1}, aim top edge, press A",
+            sun.azimuth, sun.elevation
+        ));
+        Self {
+            sun,
+            recording: None,
+        }
+    }
+
+    /// Start averaging headings
+    pub fn record(&mut self) {
+        if self.recording.is_none() {
+            self.recording = Some((0.0, 0.0, 0));
+        }
+    }
+
+    /// Feed a true heading, in degrees. Returns `true` once the result has
+    /// been sent.
+    pub fn update(&mut self, heading: f32) -> bool {
+        let Some((sin, cos, count)) = &mut self.recording else {
+            return false;
+        };
+        let radians = heading.to_radians();
+        *sin += radians.sin();
+        *cos += radians.cos();
+        *count += 1;
+        if *count < SAMPLES {
+            return false;
+        }
+
+        // Circular mean, so headings either side of north don't average to south
+        let measured = normalize_heading(sin.atan2(*cos).to_degrees());
+        let error = angle_diff(measured, self.sun.azimuth);
+        info!("sun check complete, error {}", error);
+        console::reply(format_args!(
+            "sun: expected {:.1} measured {measured:.1} error {error:.1}",
+            self.sun.azimuth
+        ));
+        if error.abs() > TOLERANCE {
+            console::reply(format_args!("sun: check the declination or calibrate"));
+        }
+        true
+    }
+}
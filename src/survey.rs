@@ -0,0 +1,152 @@
+use core::fmt::Write;
+
+use defmt::{info, Format};
+use heapless::{Deque, String};
+use micromath::F32Ext;
+
+use crate::{animation::Frame, console};
+
+/// Points kept for `survey list`, the oldest dropped first
+const CAPACITY: usize = 120;
+
+/// Rise of the field above its running average that makes a peak an
+/// anomaly, nT. Well clear of the wobble as the board swings with each step.
+const ANOMALY_NT: f32 = 2_000.0;
+
+/// How quickly the running average follows the field, per step. Slow enough
+/// that walking past an anomaly hardly moves it.
+const BASELINE_GAIN: f32 = 0.05;
+
+/// A field reading taken as a step lands, as logged over defmt
+#[derive(Clone, Copy, Format)]
+pub struct Point {
+    /// Steps since the survey started
+    pub step: u32,
+    /// Degrees, against the display's north
+    pub heading: f32,
+    /// Calibrated field magnitude, nT
+    pub field: f32,
+    /// The field peaked here, [`ANOMALY_NT`] or more above its running average
+    pub anomaly: bool,
+}
+
+/// What to show while surveying
+pub struct Reading {
+    /// How far the field is above its running average, as a bar of 0 to 5 rows
+    pub level: u8,
+    /// The last step turned out to be an anomaly
+    pub anomaly: bool,
+}
+
+/// A "metal detector lite": walking with the board, the field magnitude is
+/// logged at each step against the step count and heading, and the peaks
+/// that rise well above the surrounding field are marked, e.g. iron under a
+/// floor or in a wall. The quietest stretches are good places to calibrate.
+pub struct Survey {
+    points: Deque<Point, CAPACITY>,
+    /// Running average of the field, nT, `None` until the first step
+    baseline: Option<f32>,
+    steps: u32,
+    anomalies: u32,
+}
+
+impl Survey {
+    pub const fn new() -> Self {
+        Self {
+            points: Deque::new(),
+            baseline: None,
+            steps: 0,
+            anomalies: 0,
+        }
+    }
+
+    /// Feed the calibrated field, the heading, and whether a step was just
+    /// taken
+    pub fn update(&mut self, mag: [f32; 3], heading: f32, stepped: bool) -> Reading {
+        let [x, y, z] = mag;
+        let field = (x * x + y * y + z * z).sqrt();
+        let mut anomaly = false;
+        if stepped {
+            self.steps += 1;
+            anomaly = self.peaked(field);
+            let baseline = self.baseline.get_or_insert(field);
+            *baseline += BASELINE_GAIN * (field - *baseline);
+            if self.points.is_full() {
+                self.points.pop_front();
+            }
+            let point = Point {
+                step: self.steps,
+                heading,
+                field,
+                anomaly: false,
+            };
+            info!("survey: {}", point);
+            let _ = self.points.push_back(point);
+        }
+
+        let rise = field - self.baseline.unwrap_or(field);
+        Reading {
+            level: (rise / ANOMALY_NT * 2.5).round().clamp(0.0, 5.0) as u8,
+            anomaly,
+        }
+    }
+
+    /// Whether the last point, before `field` is added, was a local maximum
+    /// standing out from the baseline, marking it if so
+    fn peaked(&mut self, field: f32) -> bool {
+        let (Some(baseline), Some(&last)) = (self.baseline, self.points.back()) else {
+            return false;
+        };
+        let before = self.points.iter().rev().nth(1).map(|point| point.field);
+        let peak = last.field > before.unwrap_or(baseline) && last.field >= field;
+        if !peak || last.field - baseline < ANOMALY_NT {
+            return false;
+        }
+        if let Some(last) = self.points.back_mut() {
+            last.anomaly = true;
+        }
+        self.anomalies += 1;
+        info!(
+            "survey: anomaly at step {}, {} nT above {} nT",
+            last.step,
+            last.field - baseline,
+            baseline
+        );
+        true
+    }
+
+    /// Reply with a line per point, `step,heading,field`, anomalies marked
+    /// with `*`, then the count
+    pub async fn list(&self) {
+        for point in &self.points {
+            let mut line: String<{ console::MAX_REPLY }> = String::new();
+            let _ = write!(
+                line,
+                "{},{:.0},{:.0}{}",
+                point.step,
+                point.heading,
+                point.field,
+                if point.anomaly { " *" } else { "" }
+            );
+            console::REPLIES.send(line).await;
+        }
+        console::reply(format_args!(
+            "{} steps, {} anomalies",
+            self.steps, self.anomalies
+        ));
+    }
+}
+
+/// The bar shown for `level`, lit from the bottom row up
+pub fn bar(level: u8) -> Frame {
+    core::array::from_fn(|row| {
+        if 5 - row <= usize::from(level) {
+            0b11111
+        } else {
+            0
+        }
+    })
+}
+
+/// Shown as a step turns out to be an anomaly
+pub const ANOMALY: Frame = [0b11111, 0b10001, 0b10101, 0b10001, 0b11111];
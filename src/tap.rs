@@ -0,0 +1,95 @@
+use defmt::{info, Format};
+use embassy_futures::select::{select, Either};
+use embassy_nrf::gpio::Input;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{with_timeout, Duration, Timer};
+use embedded_hal_async::i2c::I2c;
+
+use crate::{freefall, idle};
+
+/// I2C address of the LSM303AGR accelerometer
+pub const ACCEL_ADDRESS: u8 = 0x19;
+
+// Accelerometer click engine registers
+const CTRL_REG6_A: u8 = 0x25;
+const CLICK_CFG_A: u8 = 0x38;
+const CLICK_THS_A: u8 = 0x3A;
+const TIME_LIMIT_A: u8 = 0x3B;
+const TIME_LATENCY_A: u8 = 0x3C;
+
+/// Single click on any axis
+const CLICK_CFG_SINGLE_XYZ: u8 = 0b0001_0101;
+
+/// Interrupt pins active low, the micro:bit's sensor interrupt line is pulled up
+const CTRL_REG6_INT_ACTIVE_LOW: u8 = 0b0000_0010;
+
+/// 16 mg per LSB at the default ±2 g full scale
+const CLICK_THRESHOLD: u8 = 48;
+
+/// At 50 Hz ODR one LSB is 20 ms: the spike must be shorter than 60 ms, and
+/// further clicks are ignored for 100 ms afterwards
+const CLICK_TIME_LIMIT: u8 = 3;
+const CLICK_LATENCY: u8 = 5;
+
+/// A second tap within this long of the first makes a double tap
+const DOUBLE_TAP_WINDOW: Duration = Duration::from_millis(400);
+
+#[derive(Clone, Copy, Debug, Format)]
+pub enum Tap {
+    Single,
+    Double,
+}
+
+/// Taps on the board, in the order they happened
+pub static TAPS: Channel<CriticalSectionRawMutex, Tap, 4> = Channel::new();
+
+/// Configure the accelerometer's click engine to detect single taps. This
+/// talks to the bus directly as the `lsm303agr` driver doesn't expose the click
+/// registers, so it must run before the driver configures the sensor; the
+/// click interrupt itself is enabled through the driver afterwards.
+pub async fn configure_click<I: I2c>(i2c: &mut I) -> Result<(), I::Error> {
+    for (register, value) in [
+        (CTRL_REG6_A, CTRL_REG6_INT_ACTIVE_LOW),
+        (CLICK_CFG_A, CLICK_CFG_SINGLE_XYZ),
+        (CLICK_THS_A, CLICK_THRESHOLD),
+        (TIME_LIMIT_A, CLICK_TIME_LIMIT),
+        (TIME_LATENCY_A, CLICK_LATENCY),
+    ] {
+        i2c.write(ACCEL_ADDRESS, &[register, value]).await?;
+    }
+    Ok(())
+}
+
+/// Turn click interrupts on the sensor interrupt line (P0.25) into single and
+/// double taps. The hardware reports each tap, a second tap within
+/// [`DOUBLE_TAP_WINDOW`] makes it a double tap. The free-fall interrupt shares
+/// the line and is told apart by how long it holds it low. Every interrupt
+/// also counts as activity, waking the board from idle.
+#[embassy_executor::task]
+pub async fn tap_task(mut int: Input<'static>) {
+    loop {
+        int.wait_for_falling_edge().await;
+        idle::ACTIVITY.signal(());
+        if with_timeout(freefall::MIN_FALL_LOW, int.wait_for_high())
+            .await
+            .is_err()
+        {
+            info!("free fall");
+            freefall::FREE_FALL.signal(());
+            int.wait_for_high().await;
+            continue;
+        }
+
+        let tap = match select(int.wait_for_falling_edge(), Timer::after(DOUBLE_TAP_WINDOW)).await {
+            Either::First(_) => {
+                int.wait_for_high().await;
+                Tap::Double
+            }
+            Either::Second(_) => Tap::Single,
+        };
+        info!("tap: {}", tap);
+        // Drop taps if the main loop has fallen behind
+        let _ = TAPS.try_send(tap);
+        trace!("tap {:?} sent", tap);
+    }
+}
@@ -0,0 +1,849 @@
+use core::fmt::Write;
+
+use core::cell::Cell;
+
+use defmt::{warn, Format};
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_nrf::{
+    buffered_uarte::{self, BufferedUarteTx},
+    peripherals::UARTE0,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+use embedded_io_async::Write as _;
+use heapless::String;
+use micro_compass_core::{
+    mavlink,
+    protocol::{Message, Orientation, Reference, MAX_FRAME_LEN},
+    units::HeadingUnit,
+};
+
+use crate::{
+    angle_diff, attitude,
+    battery::Battery,
+    calibration::{Calibration, Quality},
+    clock, console,
+    error::{Error, SerialOp},
+    heading_change::HeadingChange,
+    heading_stats::Stats,
+    log_bridge, motioncal, name, nmea,
+    tilt::Tilt,
+};
+use micromath::F32Ext;
+
+/// Minimum time between telemetry lines
+pub const TELEMETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How samples are written to the serial port
+#[derive(Clone, Copy, Format)]
+pub enum OutputFormat {
+    /// `HDG,...` lines with attitude and raw sensor values
+    Text,
+    /// NMEA-0183 `$HCHDM`, plus `$HCHDT` when a declination is set, for
+    /// marine navigation software such as OpenCPN
+    Nmea,
+    /// COBS-framed postcard messages, see `core/src/protocol.rs`
+    Binary,
+    /// `Raw:` lines of raw accelerometer and magnetometer counts for
+    /// MotionCal, which sends its calibration back over the console
+    MotionCal,
+    /// MAVLink 1 `ATTITUDE` and `VFR_HUD` messages with a heartbeat, for
+    /// drone ground stations, see `core/src/mavlink.rs`
+    Mavlink,
+    /// One JSON object per line, for plotting and scripts on the host
+    Json,
+}
+
+/// The format currently in use, set from the settings
+static OUTPUT_FORMAT: Mutex<CriticalSectionRawMutex, Cell<OutputFormat>> =
+    Mutex::new(Cell::new(OutputFormat::Text));
+
+pub fn set_output_format(format: OutputFormat) {
+    OUTPUT_FORMAT.lock(|current| current.set(format));
+}
+
+fn output_format() -> OutputFormat {
+    OUTPUT_FORMAT.lock(Cell::get)
+}
+
+/// Orientation sent alongside the samples, for use as a general AHRS
+#[derive(Clone, Copy, PartialEq, Format)]
+pub enum AttitudeOutput {
+    Off,
+    Quaternion,
+    /// The quaternion followed by yaw, pitch and roll
+    Euler,
+}
+
+/// Fastest attitude rate, Hz. Samples come from the main loop at about this
+/// rate.
+pub const MAX_ATTITUDE_RATE_HZ: u8 = 10;
+
+/// The attitude output and its rate in Hz, set from the settings
+static ATTITUDE_OUTPUT: Mutex<CriticalSectionRawMutex, Cell<(AttitudeOutput, u8)>> =
+    Mutex::new(Cell::new((AttitudeOutput::Off, 1)));
+
+pub fn set_attitude_output(output: AttitudeOutput, rate_hz: u8) {
+    ATTITUDE_OUTPUT.lock(|current| current.set((output, rate_hz)));
+}
+
+fn attitude_output() -> (AttitudeOutput, u8) {
+    ATTITUDE_OUTPUT.lock(Cell::get)
+}
+
+/// Unit of headings in text output, the binary and NMEA formats always use
+/// degrees
+static HEADING_UNIT: Mutex<CriticalSectionRawMutex, Cell<HeadingUnit>> =
+    Mutex::new(Cell::new(HeadingUnit::Degrees));
+
+pub fn set_heading_unit(unit: HeadingUnit) {
+    HEADING_UNIT.lock(|current| current.set(unit));
+}
+
+pub fn heading_unit() -> HeadingUnit {
+    HEADING_UNIT.lock(Cell::get)
+}
+
+/// Degrees a heading has to move before it is sent again, set from the
+/// settings
+static HEADING_THRESHOLD: Mutex<CriticalSectionRawMutex, Cell<u8>> = Mutex::new(Cell::new(0));
+
+pub fn set_heading_threshold(degrees: u8) {
+    HEADING_THRESHOLD.lock(|current| current.set(degrees));
+}
+
+fn heading_threshold() -> u8 {
+    HEADING_THRESHOLD.lock(Cell::get)
+}
+
+/// A reading with when the sensor data it was worked out from was read, so
+/// consumers downstream can work out rates and spot gaps
+#[derive(Clone, Copy)]
+pub struct Timestamped<T> {
+    pub taken: Instant,
+    pub value: T,
+}
+
+/// Everything reported for one sensor reading
+#[derive(Clone, Copy)]
+pub struct Sample {
+    /// Degrees, 0 to 360, relative to `reference`
+    pub heading: f32,
+    /// North picked for telemetry
+    pub reference: Reference,
+    /// North shown on the display
+    pub display_reference: Reference,
+    /// Degrees, 0 to 360, relative to magnetic north
+    pub magnetic_heading: f32,
+    /// Degrees, 0 to 360, relative to true north. `None` without a declination
+    pub true_heading: Option<f32>,
+    /// Degrees
+    pub pitch: f32,
+    /// Degrees
+    pub roll: f32,
+    /// Raw acceleration, mg
+    pub accel: [f32; 3],
+    /// Raw magnetic field, nT
+    pub mag: [f32; 3],
+    /// Steps since the dead-reckoned position was reset
+    pub steps: u32,
+    /// Dead-reckoned meters east and north of the start
+    pub position: [f32; 2],
+    /// Degrees per second, positive clockwise
+    pub rate_of_turn: f32,
+    /// Whether the heading alarm is going off, `None` without an alarm sector
+    pub alarm: Option<bool>,
+}
+
+/// Latest sample, picked up by the telemetry task at its own rate
+pub static SAMPLE: Signal<CriticalSectionRawMutex, Timestamped<Sample>> = Signal::new();
+
+/// Latest attitude in tilt-only mode, sent instead of samples
+pub static TILT: Signal<CriticalSectionRawMutex, Timestamped<Tilt>> = Signal::new();
+
+/// Magnetometer calibration state, reported by the binary and MotionCal
+/// formats
+#[derive(Clone, Copy)]
+pub struct CalibrationStatus {
+    pub calibrating: bool,
+    pub calibration: Calibration,
+}
+
+/// Signalled at startup and whenever calibration starts or finishes
+pub static CALIBRATION: Signal<CriticalSectionRawMutex, CalibrationStatus> = Signal::new();
+
+/// Something that happened to the board, reported as it happens rather than
+/// with each sample
+#[derive(Clone, Copy, Debug, Format)]
+pub enum Event {
+    FreeFall,
+    /// Spread of the heading over the last few seconds
+    HeadingStats(Stats),
+    /// Supply voltage, measured every minute
+    Battery(Battery),
+    /// How good a just finished calibration is
+    CalibrationQuality(Quality),
+    /// The two magnetometers started or stopped disagreeing
+    #[cfg(feature = "redundant-mag")]
+    CrossCheck(crate::redundancy::Status),
+}
+
+pub static EVENTS: Channel<CriticalSectionRawMutex, Event, 4> = Channel::new();
+
+const SEND_ERROR: Error = Error::Serial(SerialOp::SendTelemetry);
+
+/// Stream samples over the UART to the interface MCU (the micro:bit's USB
+/// serial port, 115200 baud) in the configured [`OutputFormat`], along with
+/// console replies. While `log uart on` it sends the defmt logs instead, see
+/// [`log_bridge`].
+#[embassy_executor::task]
+pub async fn telemetry_task(mut tx: BufferedUarteTx<'static, UARTE0>) {
+    let mut last_sent: Option<Instant> = None;
+    let mut last_attitude: Option<Instant> = None;
+    let mut header_name = None;
+    let mut heading_change = HeadingChange::new();
+    let mut mavlink = Mavlink::new();
+    let mut frames = [0u8; 64];
+    loop {
+        let next = select(
+            select4(
+                SAMPLE.wait(),
+                TILT.wait(),
+                select(CALIBRATION.wait(), EVENTS.receive()),
+                console::REPLIES.receive(),
+            ),
+            async {
+                // Left alone until then, keeping the logs since boot
+                match log_bridge::mirroring() {
+                    true => log_bridge::FRAMES.read(&mut frames).await,
+                    false => core::future::pending().await,
+                }
+            },
+        )
+        .await;
+        let next = match next {
+            Either::First(next) => next,
+            Either::Second(len) => {
+                if tx.write_all(&frames[..len]).await.is_err() {
+                    warn!("defmt frames: {}", SEND_ERROR);
+                }
+                continue;
+            }
+        };
+        // Anything else would corrupt the frames, and is dropped meanwhile
+        if log_bridge::mirroring() {
+            continue;
+        }
+        let reading = match next {
+            Either4::First(sample) => {
+                trace!("sample received");
+                let (output, rate_hz) = attitude_output();
+                let interval = Duration::from_hz(rate_hz.max(1).into());
+                if output != AttitudeOutput::Off
+                    && last_attitude.is_none_or(|sent| sent.elapsed() >= interval)
+                {
+                    last_attitude = Some(Instant::now());
+                    if send_attitude(&mut tx, &sample.value, output).await.is_err() {
+                        warn!("attitude: {}", SEND_ERROR);
+                    }
+                }
+                Reading::Sample(sample)
+            }
+            Either4::Second(tilt) => {
+                trace!("tilt received");
+                Reading::Tilt(tilt)
+            }
+            Either4::Third(Either::First(status)) => {
+                if send_calibration(&mut tx, &status).await.is_err() {
+                    warn!("calibration status: {}", SEND_ERROR);
+                }
+                trace!("calibration status sent");
+                continue;
+            }
+            Either4::Third(Either::Second(event)) => {
+                if send_event(&mut tx, event).await.is_err() {
+                    warn!("event: {}", SEND_ERROR);
+                }
+                trace!("event {:?} sent", event);
+                continue;
+            }
+            Either4::Fourth(reply) => {
+                // Text lines would corrupt the binary stream, and MAVLink
+                // has a message of its own for them
+                let sent = match output_format() {
+                    OutputFormat::Binary => Ok(()),
+                    OutputFormat::Mavlink => {
+                        mavlink
+                            .send(&mut tx, &mavlink::Message::StatusText(&reply))
+                            .await
+                    }
+                    OutputFormat::Json => tx.write_all(json_reply(&reply).as_bytes()).await,
+                    _ => {
+                        async {
+                            tx.write_all(reply.as_bytes()).await?;
+                            tx.write_all(b"\r\n").await
+                        }
+                        .await
+                    }
+                };
+                if sent.is_err() {
+                    warn!("{}", Error::Serial(SerialOp::SendReply));
+                }
+                continue;
+            }
+        };
+        if last_sent.is_some_and(|sent| sent.elapsed() < TELEMETRY_INTERVAL) {
+            continue;
+        }
+        // MotionCal fits its calibration to every sample
+        if let Reading::Sample(sample) = &reading {
+            if !matches!(output_format(), OutputFormat::MotionCal)
+                && !heading_change.update(sample.value.heading, heading_threshold())
+            {
+                continue;
+            }
+        }
+        last_sent = Some(Instant::now());
+
+        // Start the stream, and mark renames, with a header naming the device
+        let name = name::current();
+        if header_name.as_ref() != Some(&name) {
+            if let OutputFormat::Text = output_format() {
+                let mut header: String<64> = String::new();
+                let _ = write!(header, "# micro-compass {}\r\n", name);
+                if tx.write_all(header.as_bytes()).await.is_err() {
+                    warn!("header: {}", SEND_ERROR);
+                }
+            }
+            header_name = Some(name);
+        }
+
+        if send(&mut tx, &reading, &mut mavlink).await.is_err() {
+            warn!("sample: {}", SEND_ERROR);
+        }
+        trace!("frame sent");
+    }
+}
+
+/// What the main loop last published, depending on the tilt-only setting
+enum Reading {
+    Sample(Timestamped<Sample>),
+    Tilt(Timestamped<Tilt>),
+}
+
+async fn send(
+    tx: &mut BufferedUarteTx<'static, UARTE0>,
+    reading: &Reading,
+    mavlink: &mut Mavlink,
+) -> Result<(), buffered_uarte::Error> {
+    let (sample, taken) = match reading {
+        Reading::Sample(sample) => (&sample.value, sample.taken),
+        Reading::Tilt(tilt) => return send_tilt(tx, tilt).await,
+    };
+    match output_format() {
+        OutputFormat::Text => {
+            tx.write_all(text_line(sample, taken).as_bytes()).await?;
+            tx.write_all(position_line(sample).as_bytes()).await
+        }
+        OutputFormat::Nmea => {
+            tx.write_all(nmea::hdm(sample.magnetic_heading).as_bytes())
+                .await?;
+            if let Some(true_heading) = sample.true_heading {
+                tx.write_all(nmea::hdt(true_heading).as_bytes()).await?;
+            }
+            tx.write_all(nmea::rot(sample.rate_of_turn).as_bytes())
+                .await?;
+            if let Some(active) = sample.alarm {
+                tx.write_all(nmea::alr(active, clock::now()).as_bytes())
+                    .await?;
+            }
+            Ok(())
+        }
+        OutputFormat::Binary => {
+            send_message(tx, &timestamp(taken)).await?;
+            let heading = Message::Heading {
+                heading: sample.heading,
+                reference: sample.reference,
+                display_reference: sample.display_reference,
+                magnetic_heading: sample.magnetic_heading,
+                true_heading: sample.true_heading,
+                pitch: sample.pitch,
+                roll: sample.roll,
+            };
+            send_message(tx, &heading).await?;
+            let raw = Message::RawSample {
+                accel: sample.accel,
+                mag: sample.mag,
+            };
+            send_message(tx, &raw).await?;
+            let [east, north] = sample.position;
+            let position = Message::Position {
+                steps: sample.steps,
+                east,
+                north,
+            };
+            send_message(tx, &position).await?;
+            let rate = Message::RateOfTurn {
+                rate: sample.rate_of_turn,
+            };
+            send_message(tx, &rate).await
+        }
+        OutputFormat::MotionCal => {
+            let line = motioncal::raw_line(sample.accel, sample.mag);
+            tx.write_all(line.as_bytes()).await
+        }
+        OutputFormat::Mavlink => mavlink.send_sample(tx, sample, taken).await,
+        OutputFormat::Json => tx.write_all(json_line(sample, taken).as_bytes()).await,
+    }
+}
+
+async fn send_tilt(
+    tx: &mut BufferedUarteTx<'static, UARTE0>,
+    tilt: &Timestamped<Tilt>,
+) -> Result<(), buffered_uarte::Error> {
+    let Timestamped { taken, value: tilt } = tilt;
+    match output_format() {
+        OutputFormat::Text => tx.write_all(tilt_line(tilt, *taken).as_bytes()).await,
+        OutputFormat::Json => tx.write_all(json_tilt_line(tilt, *taken).as_bytes()).await,
+        OutputFormat::Binary => {
+            send_message(tx, &timestamp(*taken)).await?;
+            let message = Message::Tilt {
+                pitch: tilt.pitch,
+                roll: tilt.roll,
+                orientation: tilt.orientation,
+            };
+            send_message(tx, &message).await
+        }
+        // All need a heading or magnetometer readings
+        OutputFormat::Nmea | OutputFormat::MotionCal | OutputFormat::Mavlink => Ok(()),
+    }
+}
+
+/// In the text format:
+///
+/// ```text
+/// ATT,<w>,<x>,<y>,<z>[,<yaw>,<pitch>,<roll>]
+/// ```
+///
+/// The quaternion's components, then for [`AttitudeOutput::Euler`] the angles
+/// it was built from in degrees, the yaw being the telemetry heading
+async fn send_attitude(
+    tx: &mut BufferedUarteTx<'static, UARTE0>,
+    sample: &Sample,
+    output: AttitudeOutput,
+) -> Result<(), buffered_uarte::Error> {
+    let euler = [sample.heading, sample.pitch, sample.roll];
+    let [yaw, pitch, roll] = euler;
+    let quaternion = attitude::quaternion(yaw, pitch, roll);
+    let euler = (output == AttitudeOutput::Euler).then_some(euler);
+    match output_format() {
+        OutputFormat::Text => {
+            let [w, x, y, z] = quaternion;
+            let mut line: String<80> = String::new();
+            let _ = write!(line, "ATT,{w:.4},{x:.4},{y:.4},{z:.4}");
+            if let Some([yaw, pitch, roll]) = euler {
+                let _ = write!(line, ",{yaw:.1},{pitch:.1},{roll:.1}");
+            }
+            let _ = line.push_str("\r\n");
+            tx.write_all(line.as_bytes()).await
+        }
+        OutputFormat::Binary => send_message(tx, &Message::Attitude { quaternion, euler }).await,
+        // MAVLink's attitude goes with every sample, and the JSON objects
+        // have the angles already
+        OutputFormat::Nmea
+        | OutputFormat::MotionCal
+        | OutputFormat::Mavlink
+        | OutputFormat::Json => Ok(()),
+    }
+}
+
+async fn send_calibration(
+    tx: &mut BufferedUarteTx<'static, UARTE0>,
+    status: &CalibrationStatus,
+) -> Result<(), buffered_uarte::Error> {
+    match output_format() {
+        OutputFormat::Binary => {
+            let message = Message::Calibration {
+                calibrating: status.calibrating,
+                calibrated: status.calibration.is_calibrated(),
+                offset: status.calibration.offset(),
+                soft_iron: status.calibration.soft_iron(),
+            };
+            send_message(tx, &message).await
+        }
+        // Echoed so MotionCal can confirm the calibration it sent arrived
+        OutputFormat::MotionCal => {
+            let lines = motioncal::calibration_lines(&status.calibration);
+            tx.write_all(lines.as_bytes()).await
+        }
+        OutputFormat::Text | OutputFormat::Nmea | OutputFormat::Mavlink | OutputFormat::Json => {
+            Ok(())
+        }
+    }
+}
+
+/// Events go out in the text format as
+///
+/// ```text
+/// EVT,<event>
+/// ```
+///
+/// where the event is `free-fall`, and heading statistics as
+///
+/// ```text
+/// STAT,<mean>,<min>,<max>,<variance>
+/// ```
+///
+/// in the [`heading_unit`] (squared for the variance), and battery
+/// measurements as
+///
+/// ```text
+/// BAT,<millivolts>,<percent>
+/// ```
+///
+/// and the quality of a finished calibration, with the coverage and residual
+/// in percent, as
+///
+/// ```text
+/// CAL,<score>,<coverage>,<residual>,<kept|rejected>
+/// ```
+///
+/// and, with the `redundant-mag` feature, the magnetometers starting or
+/// stopping disagreeing as
+///
+/// ```text
+/// XCHK,<fault|ok>,<primary|backup>,<difference>
+/// ```
+async fn send_event(
+    tx: &mut BufferedUarteTx<'static, UARTE0>,
+    event: Event,
+) -> Result<(), buffered_uarte::Error> {
+    match (output_format(), event) {
+        (OutputFormat::Text, Event::FreeFall) => tx.write_all(b"EVT,free-fall\r\n").await,
+        (OutputFormat::Text, Event::HeadingStats(stats)) => {
+            let unit = heading_unit();
+            let scale = unit.from_degrees(1.0);
+            let mut line: String<64> = String::new();
+            let _ = write!(
+                line,
+                "STAT,{},{},{},{:.*}\r\n",
+                unit.format(stats.mean),
+                unit.format(stats.min),
+                unit.format(stats.max),
+                unit.decimals() + 1,
+                stats.variance * scale * scale
+            );
+            tx.write_all(line.as_bytes()).await
+        }
+        (OutputFormat::Text, Event::Battery(battery)) => {
+            let mut line: String<32> = String::new();
+            let _ = write!(line, "BAT,{},{}\r\n", battery.millivolts, battery.percent);
+            tx.write_all(line.as_bytes()).await
+        }
+        (OutputFormat::Text, Event::CalibrationQuality(quality)) => {
+            let mut line: String<48> = String::new();
+            let _ = write!(
+                line,
+                "CAL,{},{:.0},{:.1},{}\r\n",
+                quality.score,
+                quality.coverage * 100.0,
+                quality.residual * 100.0,
+                if quality.is_acceptable() {
+                    "kept"
+                } else {
+                    "rejected"
+                }
+            );
+            tx.write_all(line.as_bytes()).await
+        }
+        #[cfg(feature = "redundant-mag")]
+        (OutputFormat::Text, Event::CrossCheck(status)) => {
+            let mut line: String<32> = String::new();
+            let _ = write!(
+                line,
+                "XCHK,{},{},{:.1}\r\n",
+                if status.fault { "fault" } else { "ok" },
+                status.selected.name(),
+                status.difference
+            );
+            tx.write_all(line.as_bytes()).await
+        }
+        (OutputFormat::Binary, Event::FreeFall) => send_message(tx, &Message::FreeFall).await,
+        (OutputFormat::Binary, Event::HeadingStats(stats)) => {
+            let message = Message::HeadingStats {
+                mean: stats.mean,
+                min: stats.min,
+                max: stats.max,
+                variance: stats.variance,
+            };
+            send_message(tx, &message).await
+        }
+        (OutputFormat::Binary, Event::Battery(battery)) => {
+            let message = Message::Battery {
+                millivolts: battery.millivolts,
+                percent: battery.percent,
+            };
+            send_message(tx, &message).await
+        }
+        (OutputFormat::Binary, Event::CalibrationQuality(quality)) => {
+            let message = Message::CalibrationQuality {
+                score: quality.score,
+                coverage: quality.coverage,
+                residual: quality.residual,
+                kept: quality.is_acceptable(),
+            };
+            send_message(tx, &message).await
+        }
+        #[cfg(feature = "redundant-mag")]
+        (OutputFormat::Binary, Event::CrossCheck(status)) => {
+            let message = Message::CrossCheck {
+                fault: status.fault,
+                backup: status.selected == crate::redundancy::Source::Backup,
+                difference: status.difference,
+            };
+            send_message(tx, &message).await
+        }
+        (
+            OutputFormat::Nmea
+            | OutputFormat::MotionCal
+            | OutputFormat::Mavlink
+            | OutputFormat::Json,
+            _,
+        ) => Ok(()),
+    }
+}
+
+/// MAVLink system and component ids: the first system's autopilot, so a
+/// ground station connected to the board alone shows its attitude
+const MAVLINK_SYSTEM: u8 = 1;
+const MAVLINK_COMPONENT: u8 = 1;
+
+/// Longest time between MAVLink heartbeats, which ground stations expect
+/// every second
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The MAVLink message sequence, and when the last heartbeat went out
+struct Mavlink {
+    encoder: mavlink::Encoder,
+    heartbeat: Option<Instant>,
+}
+
+impl Mavlink {
+    const fn new() -> Self {
+        Self {
+            encoder: mavlink::Encoder::new(MAVLINK_SYSTEM, MAVLINK_COMPONENT),
+            heartbeat: None,
+        }
+    }
+
+    async fn send(
+        &mut self,
+        tx: &mut BufferedUarteTx<'static, UARTE0>,
+        message: &mavlink::Message<'_>,
+    ) -> Result<(), buffered_uarte::Error> {
+        let mut buf = [0u8; mavlink::MAX_FRAME];
+        tx.write_all(self.encoder.encode(message, &mut buf)).await
+    }
+
+    /// `ATTITUDE` and `VFR_HUD`, after a heartbeat when one is due. The yaw
+    /// and the heading are the telemetry heading; only the yaw rate is known.
+    async fn send_sample(
+        &mut self,
+        tx: &mut BufferedUarteTx<'static, UARTE0>,
+        sample: &Sample,
+        taken: Instant,
+    ) -> Result<(), buffered_uarte::Error> {
+        if self
+            .heartbeat
+            .is_none_or(|at| at.elapsed() >= HEARTBEAT_INTERVAL)
+        {
+            self.heartbeat = Some(Instant::now());
+            self.send(tx, &mavlink::Message::Heartbeat).await?;
+        }
+        let attitude = mavlink::Message::Attitude {
+            time_boot_ms: taken.as_millis() as u32,
+            roll: sample.roll.to_radians(),
+            pitch: sample.pitch.to_radians(),
+            yaw: angle_diff(sample.heading, 0.0).to_radians(),
+            roll_speed: 0.0,
+            pitch_speed: 0.0,
+            yaw_speed: sample.rate_of_turn.to_radians(),
+        };
+        self.send(tx, &attitude).await?;
+        let heading = sample.heading.round() as i16 % 360;
+        self.send(tx, &mavlink::Message::VfrHud { heading }).await
+    }
+}
+
+fn timestamp(taken: Instant) -> Message {
+    Message::Timestamp {
+        ms: taken.as_millis() as u32,
+    }
+}
+
+async fn send_message(
+    tx: &mut BufferedUarteTx<'static, UARTE0>,
+    message: &Message,
+) -> Result<(), buffered_uarte::Error> {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    tx.write_all(message.encode(&mut buf)).await
+}
+
+/// One line per sample:
+///
+/// ```text
+/// HDG,<heading>,<reference>,<display reference>,<pitch>,<roll>,<ax>,<ay>,<az>,<mx>,<my>,<mz>,<rate of turn>,<ms>
+/// ```
+///
+/// References are `M` (magnetic) or `T` (true). The heading is in the
+/// [`heading_unit`], other angles in degrees, acceleration in mg, magnetic
+/// field in nT and the rate of turn in degrees per second, positive
+/// clockwise. Last comes when the reading was taken, ms since boot.
+fn text_line(sample: &Sample, taken: Instant) -> String<144> {
+    let mut line = String::new();
+    let [ax, ay, az] = sample.accel;
+    let [mx, my, mz] = sample.mag;
+    // Fits comfortably, a failed write would only truncate the line
+    let _ = write!(
+        line,
+        "HDG,{},{},{},{:.1},{:.1},{:.0},{:.0},{:.0},{:.0},{:.0},{:.0},{:.1},{}\r\n",
+        heading_unit().format(sample.heading),
+        reference_flag(sample.reference),
+        reference_flag(sample.display_reference),
+        sample.pitch,
+        sample.roll,
+        ax,
+        ay,
+        az,
+        mx,
+        my,
+        mz,
+        sample.rate_of_turn,
+        taken.as_millis()
+    );
+    line
+}
+
+/// Follows each `HDG` line:
+///
+/// ```text
+/// POS,<steps>,<east>,<north>
+/// ```
+///
+/// The dead-reckoned position in meters from where it was last reset.
+fn position_line(sample: &Sample) -> String<64> {
+    let [east, north] = sample.position;
+    let mut line = String::new();
+    let _ = write!(line, "POS,{},{:.1},{:.1}\r\n", sample.steps, east, north);
+    line
+}
+
+/// In the JSON format, one object per line:
+///
+/// ```text
+/// {"t":<ms>,"heading":<deg>,"reference":"M","magnetic":<deg>,"true":<deg|null>,
+///  "pitch":<deg>,"roll":<deg>,"accel":[<x>,<y>,<z>],"mag":[<x>,<y>,<z>],"rot":<deg/s>}
+/// ```
+///
+/// `t` is when the reading was taken, milliseconds since boot. Headings are always degrees, whatever the
+/// heading unit; accelerations are mg and magnetic fields nT.
+fn json_line(sample: &Sample, taken: Instant) -> String<320> {
+    let [ax, ay, az] = sample.accel;
+    let [mx, my, mz] = sample.mag;
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        "{{\"t\":{},\"heading\":{:.1},\"reference\":\"{}\",\"magnetic\":{:.1},\"true\":",
+        taken.as_millis(),
+        sample.heading,
+        reference_flag(sample.reference),
+        sample.magnetic_heading,
+    );
+    let _ = match sample.true_heading {
+        Some(true_heading) => write!(line, "{true_heading:.1}"),
+        None => write!(line, "null"),
+    };
+    let _ = write!(
+        line,
+        ",\"pitch\":{:.1},\"roll\":{:.1},\"accel\":[{ax:.0},{ay:.0},{az:.0}],\"mag\":[{mx:.0},{my:.0},{mz:.0}],\"rot\":{:.1}}}\r\n",
+        sample.pitch, sample.roll, sample.rate_of_turn
+    );
+    line
+}
+
+/// In the JSON format in tilt-only mode:
+///
+/// ```text
+/// {"t":<ms>,"pitch":<deg>,"roll":<deg>,"orientation":"<orientation>"}
+/// ```
+fn json_tilt_line(tilt: &Tilt, taken: Instant) -> String<96> {
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        "{{\"t\":{},\"pitch\":{:.1},\"roll\":{:.1},\"orientation\":\"{}\"}}\r\n",
+        taken.as_millis(),
+        tilt.pitch,
+        tilt.roll,
+        orientation_name(tilt.orientation)
+    );
+    line
+}
+
+/// A console reply in the JSON format, `{"reply":"<text>"}`, so a reader
+/// parsing every line copes with it
+fn json_reply(reply: &str) -> String<{ 2 * console::MAX_REPLY + 16 }> {
+    let mut line = String::new();
+    let _ = line.push_str("{\"reply\":\"");
+    for c in reply.chars() {
+        if matches!(c, '"' | '\\') {
+            let _ = line.push('\\');
+        }
+        let _ = line.push(c);
+    }
+    let _ = line.push_str("\"}\r\n");
+    line
+}
+
+fn reference_flag(reference: Reference) -> char {
+    match reference {
+        Reference::Magnetic => 'M',
+        Reference::True => 'T',
+    }
+}
+
+/// One line per reading in tilt-only mode:
+///
+/// ```text
+/// TILT,<pitch>,<roll>,<orientation>,<ms>
+/// ```
+///
+/// Angles are in degrees. The orientation is `face-up`, `face-down`,
+/// `logo-up`, `logo-down`, `left-up` or `right-up`, followed by when the
+/// reading was taken, ms since boot.
+fn tilt_line(tilt: &Tilt, taken: Instant) -> String<64> {
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        "TILT,{:.1},{:.1},{},{}\r\n",
+        tilt.pitch,
+        tilt.roll,
+        orientation_name(tilt.orientation),
+        taken.as_millis()
+    );
+    line
+}
+
+fn orientation_name(orientation: Orientation) -> &'static str {
+    match orientation {
+        Orientation::FaceUp => "face-up",
+        Orientation::FaceDown => "face-down",
+        Orientation::LogoUp => "logo-up",
+        Orientation::LogoDown => "logo-down",
+        Orientation::LeftUp => "left-up",
+        Orientation::RightUp => "right-up",
+    }
+}
@@ -0,0 +1,61 @@
+use micro_compass_core::protocol::Orientation;
+use micromath::F32Ext;
+
+/// Degrees of tilt that move the bubble one LED from the center
+const BUBBLE_STEP: f32 = 15.0;
+
+/// Attitude from the accelerometer alone, for places where the magnetic field
+/// can't be trusted (inside vehicles, next to speakers)
+#[derive(Clone, Copy)]
+pub struct Tilt {
+    /// Degrees
+    pub pitch: f32,
+    /// Degrees
+    pub roll: f32,
+    pub orientation: Orientation,
+}
+
+impl Tilt {
+    /// From raw acceleration in mg
+    pub fn new(accel: [f32; 3]) -> Self {
+        let [x, y, z] = accel;
+        let (pitch, roll) = crate::compute_pitch_roll(x, y, z);
+        Self {
+            pitch: pitch.to_degrees(),
+            roll: roll.to_degrees(),
+            orientation: orientation(accel),
+        }
+    }
+
+    /// LED of a spirit-level bubble for the flat orientations, drifting from
+    /// the center towards the raised side
+    pub fn bubble(&self) -> (usize, usize) {
+        let offset = |angle: f32| (2.0 + (angle / BUBBLE_STEP).round()).clamp(0.0, 4.0) as usize;
+        (offset(self.pitch), offset(-self.roll))
+    }
+}
+
+/// The side facing up is the axis gravity pulls along most strongly. The
+/// LSM303AGR's +Y points towards the logo, +X towards the right edge and +Z
+/// out of the display.
+pub fn orientation(accel: [f32; 3]) -> Orientation {
+    let [x, y, z] = accel;
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    if az >= ax && az >= ay {
+        if z > 0.0 {
+            Orientation::FaceUp
+        } else {
+            Orientation::FaceDown
+        }
+    } else if ay >= ax {
+        if y > 0.0 {
+            Orientation::LogoUp
+        } else {
+            Orientation::LogoDown
+        }
+    } else if x > 0.0 {
+        Orientation::RightUp
+    } else {
+        Orientation::LeftUp
+    }
+}
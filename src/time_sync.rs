@@ -0,0 +1,107 @@
+use core::cell::RefCell;
+
+use defmt::{info, Format};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+/// Beacons the offset is estimated over, the last few seconds' worth
+const WINDOW: usize = 8;
+
+/// From the clock board taking its time to this board taking its own, µs:
+/// the radio ramping up to send, then the beacon's 19 bytes on air at 1 Mbit
+const LATENCY_US: i64 = 130 + 8 * 19;
+
+/// Where this board's timeline comes from
+#[derive(Clone, Copy, PartialEq, Format)]
+enum Role {
+    /// Its own clock, since boot
+    Own,
+    /// The clock board's, once one of its beacons has been heard
+    Follow,
+}
+
+struct State {
+    role: Role,
+    /// The clock board's time less this board's at each recent beacon, µs
+    offsets: Deque<i64, WINDOW>,
+    last_beacon: Option<Instant>,
+}
+
+static STATE: Mutex<CriticalSectionRawMutex, RefCell<State>> = Mutex::new(RefCell::new(State {
+    role: Role::Own,
+    offsets: Deque::new(),
+    last_beacon: None,
+}));
+
+/// How this board's clock stands against the clock board's
+#[derive(Clone, Copy, Format)]
+pub struct Status {
+    /// To add to this board's time for the clock board's, µs
+    pub offset_us: i64,
+    pub since_beacon: Duration,
+}
+
+/// Follow the clock board's time from its beacons, or go back to this
+/// board's own, starting over either way
+pub fn follow(follow: bool) {
+    STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        state.role = if follow { Role::Follow } else { Role::Own };
+        state.offsets.clear();
+        state.last_beacon = None;
+    });
+}
+
+/// A beacon saying the clock board's time was `clock_us` as it sent it,
+/// heard at `received`.
+///
+/// Each estimate of the offset can only come out low, never high: the
+/// clock board takes its time before sending and this board its own after
+/// the beacon is in, and the executor may run late either side. So the
+/// largest of the recent ones is taken, rather than their average.
+pub fn beacon(clock_us: u64, received: Instant) {
+    let offset = clock_us as i64 + LATENCY_US - received.as_micros() as i64;
+    STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        if state.role != Role::Follow {
+            return;
+        }
+        if state.offsets.is_full() {
+            state.offsets.pop_front();
+        }
+        let _ = state.offsets.push_back(offset);
+        if state.last_beacon.is_none() {
+            info!("time sync: offset {} µs", offset);
+        }
+        state.last_beacon = Some(received);
+    });
+}
+
+/// The offset from the clock board while following it, `None` until its
+/// first beacon
+pub fn status() -> Option<Status> {
+    STATE.lock(|state| {
+        let state = state.borrow();
+        Some(Status {
+            offset_us: state.offsets.iter().copied().max()?,
+            since_beacon: state.last_beacon?.elapsed(),
+        })
+    })
+}
+
+/// Whether this board takes its time from the clock board
+pub fn following() -> bool {
+    STATE.lock(|state| state.borrow().role == Role::Follow)
+}
+
+/// `taken` on the shared timeline, ms: this board's own time, or while
+/// following, the clock board's, `None` until it has been heard
+pub fn timeline_ms(taken: Instant) -> Option<u32> {
+    if !following() {
+        return Some(taken.as_millis() as u32);
+    }
+    let offset_us = status()?.offset_us;
+    let us = taken.as_micros() as i64 + offset_us;
+    Some((us.max(0) / 1000) as u32)
+}
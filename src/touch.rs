@@ -0,0 +1,68 @@
+use defmt::{debug, info};
+use embassy_nrf::gpio::{Flex, OutputDrive, Pull};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+
+/// How often the logo is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Touching the logo for this long counts as a hold
+const HOLD_TIME: Duration = Duration::from_millis(1000);
+
+/// Charge time, relative to the untouched baseline, that counts as a touch
+const TOUCH_RATIO: u32 = 2;
+
+/// Give up waiting for the pin to charge after this many polls
+const MAX_CHARGE_POLLS: u32 = 10_000;
+
+/// Signalled each time the logo is held
+pub static LOGO_HELD: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Capacitive touch sensing on the micro:bit v2 logo (P1.04) by charge timing:
+/// the pad is discharged, then released to charge through its external
+/// pull-up. A finger adds capacitance, so the pin takes longer to read high.
+#[embassy_executor::task]
+pub async fn touch_task(mut pin: Flex<'static>) {
+    let baseline = (0..8)
+        .map(|_| charge_time(&mut pin))
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    info!("logo touch baseline: {} polls", baseline);
+
+    let mut touched_since = None;
+    // Report each hold once, the finger has to lift before the next one
+    let mut reported = false;
+    loop {
+        if charge_time(&mut pin) > baseline * TOUCH_RATIO {
+            let since = *touched_since.get_or_insert_with(Instant::now);
+            if !reported && since.elapsed() >= HOLD_TIME {
+                debug!("logo held");
+                LOGO_HELD.signal(());
+                trace!("logo held signalled");
+                reported = true;
+            }
+        } else {
+            touched_since = None;
+            reported = false;
+        }
+        Timer::after(SAMPLE_INTERVAL).await;
+    }
+}
+
+/// Discharge the pad and count polls until it charges back up to a high level
+fn charge_time(pin: &mut Flex<'_>) -> u32 {
+    pin.set_as_output(OutputDrive::Standard);
+    pin.set_low();
+    cortex_m::asm::delay(1_000);
+
+    // Interrupts would add jitter to the count
+    cortex_m::interrupt::free(|_| {
+        pin.set_as_input(Pull::None);
+        let mut polls = 0;
+        while pin.is_low() && polls < MAX_CHARGE_POLLS {
+            polls += 1;
+        }
+        polls
+    })
+}
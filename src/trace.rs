@@ -0,0 +1,47 @@
+/// Record an inter-task message event on the trace RTT channel, e.g.
+/// `trace!("sample published")`. Compiles to nothing without the `trace`
+/// feature.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        $crate::trace::event(format_args!($($arg)*));
+    };
+}
+
+#[cfg(feature = "trace")]
+mod channel {
+    use core::{
+        cell::RefCell,
+        fmt::{self, Write},
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+    use embassy_time::Instant;
+    use rtt_target::UpChannel;
+
+    /// RTT up channel the trace is written to, separate from the defmt logs
+    static CHANNEL: Mutex<CriticalSectionRawMutex, RefCell<Option<UpChannel>>> =
+        Mutex::new(RefCell::new(None));
+
+    /// Numbers every event, so gaps show where the channel overflowed
+    static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+    pub fn init(channel: UpChannel) {
+        CHANNEL.lock(|current| *current.borrow_mut() = Some(channel));
+    }
+
+    /// Write one line: `<sequence> <uptime µs> <event>`
+    pub fn event(args: fmt::Arguments) {
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now().as_micros();
+        CHANNEL.lock(|channel| {
+            if let Some(channel) = channel.borrow_mut().as_mut() {
+                let _ = writeln!(channel, "{sequence} {now} {args}");
+            }
+        });
+    }
+}
+
+#[cfg(feature = "trace")]
+pub use channel::{event, init};
@@ -0,0 +1,55 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use heapless::HistoryBuffer;
+use micro_compass_core::matrix;
+
+use crate::animation::{Greys, GREY_LEVELS};
+
+/// How far back the trail reaches
+const LENGTH: Duration = Duration::from_secs(3);
+
+/// Headings kept, enough for [`LENGTH`] at the full 5 Hz loop rate with the
+/// needle redrawn five times a loop at the fastest refresh rate
+const CAPACITY: usize = 80;
+
+/// Recent headings in degrees with when they were drawn, oldest first.
+/// Kept here rather than in [`crate::display::Matrix`], which only lives for
+/// one loop iteration.
+static HEADINGS: Mutex<CriticalSectionRawMutex, RefCell<HistoryBuffer<(Instant, f32), CAPACITY>>> =
+    Mutex::new(RefCell::new(HistoryBuffer::new()));
+
+/// Add the heading just drawn
+pub fn push(angle: f32) {
+    HEADINGS.lock(|headings| headings.borrow_mut().write((Instant::now(), angle)));
+}
+
+/// The outer ring lit for each heading of the last few seconds, the newest
+/// at full brightness and older ones fading out. Where headings share a dot
+/// the brightest wins, so a steady needle stays bright and a wandering one
+/// smears across its neighbours.
+pub fn greys() -> Greys {
+    let mut greys = [[0; 5]; 5];
+    let now = Instant::now();
+    HEADINGS.lock(|headings| {
+        for &(at, angle) in headings.borrow().oldest_ordered() {
+            let age = now.saturating_duration_since(at);
+            if age >= LENGTH {
+                continue;
+            }
+            let (row, col) = matrix::ring_dot(angle);
+            let level = brightness(age);
+            greys[row][col] = greys[row][col].max(level);
+        }
+    });
+    greys
+}
+
+/// Brightness for a heading `age` old. The eye sees brightness roughly as
+/// the square root of the duty cycle, so the levels fall off as a square to
+/// fade evenly.
+fn brightness(age: Duration) -> u8 {
+    let fresh = 1.0 - age.as_millis() as f32 / LENGTH.as_millis() as f32;
+    ((fresh * fresh * GREY_LEVELS as f32) as u8).max(1)
+}
@@ -0,0 +1,46 @@
+use embassy_time::{Duration, Instant};
+
+use crate::angle_diff;
+
+/// Share of each new reading in the smoothed rate, the rest is the previous
+/// rate. Differencing amplifies the heading noise, so it needs smoothing.
+const SMOOTHING: f32 = 0.3;
+
+/// Headings further apart than this don't give a meaningful rate, e.g. after
+/// a calibration or a spell in tilt-only mode
+const MAX_GAP: Duration = Duration::from_secs(1);
+
+/// Rate of turn from successive headings, for closing a steering loop
+pub struct TurnRate {
+    last: Option<(f32, Instant)>,
+    /// Smoothed rate, degrees per second
+    rate: f32,
+}
+
+impl TurnRate {
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            rate: 0.0,
+        }
+    }
+
+    /// Feed a heading in degrees, returning the rate of turn in degrees per
+    /// second, positive clockwise. Changes are taken the short way round, so
+    /// crossing north is a small turn rather than a jump of 360°.
+    pub fn update(&mut self, heading: f32) -> f32 {
+        let now = Instant::now();
+        match self.last {
+            Some((last, at)) if now - at <= MAX_GAP => {
+                let dt = (now - at).as_micros() as f32 / 1_000_000.0;
+                if dt > 0.0 {
+                    let rate = angle_diff(heading, last) / dt;
+                    self.rate += SMOOTHING * (rate - self.rate);
+                }
+            }
+            _ => self.rate = 0.0,
+        }
+        self.last = Some((heading, now));
+        self.rate
+    }
+}
@@ -0,0 +1,53 @@
+use defmt::debug;
+use micromath::F32Ext;
+
+/// How far the acceleration's magnitude may stray from 1 g before the
+/// reading is taken to be mostly the vehicle's own acceleration, mg
+const LINEAR_LIMIT_MG: f32 = 150.0;
+
+/// Share of each accepted reading blended into the estimate, smoothing out
+/// vibration that stays within the limit
+const SMOOTHING: f32 = 0.3;
+
+const GRAVITY_MG: f32 = 1000.0;
+
+/// Where down is, for tilt compensation on something that shakes and
+/// accelerates, such as a bicycle or an RC car. Readings far from 1 g are
+/// left out, so a bump or braking doesn't tip the heading, and the rest are
+/// smoothed.
+pub struct GravityEstimate {
+    gravity: Option<[f32; 3]>,
+    /// Readings left out since the last one accepted
+    rejected: u32,
+}
+
+impl GravityEstimate {
+    pub const fn new() -> Self {
+        Self {
+            gravity: None,
+            rejected: 0,
+        }
+    }
+
+    /// Feed acceleration in mg, returning the gravity to tilt-compensate with
+    pub fn update(&mut self, accel: [f32; 3]) -> [f32; 3] {
+        let magnitude = (accel[0] * accel[0] + accel[1] * accel[1] + accel[2] * accel[2]).sqrt();
+        let gravity = match self.gravity {
+            Some(gravity) if (magnitude - GRAVITY_MG).abs() > LINEAR_LIMIT_MG => {
+                self.rejected += 1;
+                if self.rejected == 1 {
+                    debug!("holding the tilt, acceleration {} mg", magnitude);
+                }
+                gravity
+            }
+            Some(gravity) => {
+                self.rejected = 0;
+                core::array::from_fn(|i| gravity[i] + SMOOTHING * (accel[i] - gravity[i]))
+            }
+            // Something to start from, however shaken
+            None => accel,
+        };
+        self.gravity = Some(gravity);
+        gravity
+    }
+}
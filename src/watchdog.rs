@@ -0,0 +1,74 @@
+use defmt::{info, warn};
+use embassy_nrf::{
+    pac,
+    peripherals::WDT,
+    wdt::{self, HaltConfig, SleepConfig, WatchdogHandle},
+};
+
+/// The board resets if the main loop doesn't come round within this long, ms.
+/// Its longest regular pause is showing a long step count a digit at a time.
+const TIMEOUT_MS: u32 = 10_000;
+
+/// Ticks of the 32.768 kHz clock the watchdog counts
+const TICKS_PER_MS: u32 = 32768 / 1000;
+
+/// Resets the board if the main loop stalls, e.g. on a locked up I2C bus,
+/// rather than leave a frozen arrow on the display. Once started the hardware
+/// watchdog can't be stopped, only fed.
+pub struct Watchdog {
+    handle: Option<WatchdogHandle>,
+}
+
+impl Watchdog {
+    /// Start the watchdog. It keeps counting while the CPU sleeps between
+    /// events, and pauses while halted by a debugger.
+    pub fn start(wdt: WDT) -> Self {
+        let mut config = wdt::Config::default();
+        config.timeout_ticks = TIMEOUT_MS * TICKS_PER_MS;
+        config.action_during_sleep = SleepConfig::RUN;
+        config.action_during_debug_halt = HaltConfig::PAUSE;
+        let handle = match wdt::Watchdog::try_new::<1>(wdt, config) {
+            Ok((_, [handle])) => Some(handle),
+            Err(_) => {
+                warn!("watchdog already running with another configuration");
+                None
+            }
+        };
+        Self { handle }
+    }
+
+    pub fn feed(&mut self) {
+        if let Some(handle) = &mut self.handle {
+            handle.pet();
+        }
+    }
+}
+
+/// Log what caused the last reset, warning about watchdog resets and
+/// lockups, then clear the reasons so the next boot reports afresh
+pub fn log_reset_reason() {
+    let power = pac::POWER;
+    let reasons = power.resetreas().read();
+    let reason = if reasons.dog() {
+        "the watchdog, the main loop stalled"
+    } else if reasons.lockup() {
+        "a CPU lockup"
+    } else if reasons.off() {
+        "waking from system off"
+    } else if reasons.sreq() {
+        "a soft reset request"
+    } else if reasons.resetpin() {
+        "the reset pin"
+    } else if reasons.dif() {
+        "entering debug interface mode"
+    } else {
+        "power on"
+    };
+    if reasons.dog() || reasons.lockup() {
+        warn!("reset by {}", reason);
+    } else {
+        info!("reset by {}", reason);
+    }
+    // Reasons accumulate until cleared by writing ones
+    power.resetreas().write_value(reasons);
+}
@@ -0,0 +1,116 @@
+use defmt::{info, warn};
+
+use crate::{
+    geo::Position,
+    storage::{self, Storage},
+};
+
+/// Maximum number of waypoints that can be stored
+pub const MAX_WAYPOINTS: usize = 8;
+
+/// Marks a valid record, erased flash reads back as `0xFFFF_FFFF`
+const MAGIC: u32 = 0x3A7F_0001;
+
+/// magic (4) + count (1) + active (1) + padding (2) + latitude and longitude
+/// (8 + 8) per waypoint
+const RECORD_LEN: usize = 8 + MAX_WAYPOINTS * 16;
+
+const _: () = assert!(RECORD_LEN <= storage::MAX_RECORD_LEN);
+
+/// Waypoints entered from the serial console, persisted to flash. While one
+/// is active and the GPS has a fix, the display points towards it.
+pub struct WaypointMemory {
+    waypoints: [Position; MAX_WAYPOINTS],
+    count: usize,
+    /// Index of the waypoint being navigated to
+    active: Option<usize>,
+}
+
+impl WaypointMemory {
+    pub const fn new() -> Self {
+        Self {
+            waypoints: [Position {
+                latitude: 0.0,
+                longitude: 0.0,
+            }; MAX_WAYPOINTS],
+            count: 0,
+            active: None,
+        }
+    }
+
+    /// Restore stored waypoints, starting empty if flash holds no valid record
+    pub fn load(storage: &mut Storage<'_>) -> Self {
+        let mut memory = Self::new();
+        let mut buf = [0u8; RECORD_LEN];
+        if let Err(e) = storage.load(storage::WAYPOINTS_PAGE, &mut buf) {
+            warn!("failed to read waypoints: {}", e);
+            return memory;
+        }
+
+        let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let count = buf[4] as usize;
+        if magic != MAGIC || count > MAX_WAYPOINTS {
+            info!("no stored waypoints");
+            return memory;
+        }
+
+        let active = buf[5] as usize;
+        memory.count = count;
+        memory.active = (active < count).then_some(active);
+        let float = |at: usize| f64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+        for (i, waypoint) in memory.waypoints.iter_mut().take(count).enumerate() {
+            let at = 8 + i * 16;
+            *waypoint = Position {
+                latitude: float(at),
+                longitude: float(at + 8),
+            };
+        }
+        info!("restored {} waypoints", count);
+        memory
+    }
+
+    /// Persist the waypoints and the active one
+    pub fn save(&self) {
+        let mut buf = [0u8; RECORD_LEN];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4] = self.count as u8;
+        buf[5] = self.active.map_or(u8::MAX, |i| i as u8);
+        for (i, waypoint) in self.waypoints.iter().take(self.count).enumerate() {
+            let at = 8 + i * 16;
+            buf[at..at + 8].copy_from_slice(&waypoint.latitude.to_le_bytes());
+            buf[at + 8..at + 16].copy_from_slice(&waypoint.longitude.to_le_bytes());
+        }
+        storage::save(storage::WAYPOINTS_PAGE, &buf);
+    }
+
+    /// Waypoint being navigated to, if any
+    pub fn active(&self) -> Option<Position> {
+        self.active.map(|i| self.waypoints[i])
+    }
+
+    /// Append a waypoint and start navigating to it. When memory is full the
+    /// last one is replaced.
+    pub fn add(&mut self, waypoint: Position) {
+        if self.count < MAX_WAYPOINTS {
+            self.count += 1;
+        }
+        let index = self.count - 1;
+        self.waypoints[index] = waypoint;
+        self.active = Some(index);
+    }
+
+    /// Advance to the next waypoint, stopping navigation after the last one
+    pub fn next(&mut self) {
+        self.active = match self.active {
+            None if self.count > 0 => Some(0),
+            Some(i) if i + 1 < self.count => Some(i + 1),
+            _ => None,
+        };
+    }
+
+    /// Forget all waypoints
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.active = None;
+    }
+}
@@ -0,0 +1,135 @@
+use defmt::info;
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    animation::{self, Animation, Easing, Frame, Repeat},
+    buttons::{Button, Event, Press},
+    calibration::{Calibration, Calibrator, Quality},
+    display::OnBoardLedMatrix,
+};
+
+/// The intro moves on by itself after this long, so calibrations started
+/// from the console don't wait for a button
+const INTRO_TIME: Duration = Duration::from_secs(3);
+
+/// Sampling stops after this long even if the sphere isn't covered
+const SAMPLING_TIME: Duration = Duration::from_secs(30);
+
+/// Coverage at which sampling stops by itself
+const COVERAGE_DONE: f32 = 0.9;
+
+/// How long each frame is shown, per sample
+const FRAME_MS: u32 = 100;
+
+/// How long the result is shown once the wizard has finished
+pub const RESULT_MS: u32 = 1500;
+
+/// A dot tracing a figure-8, prompting the calibration motion
+const PROMPT: Animation = Animation {
+    frames: &[
+        ([0b00000, 0b00000, 0b00100, 0b00000, 0b00000], 100),
+        ([0b00000, 0b00010, 0b00000, 0b00000, 0b00000], 100),
+        ([0b00100, 0b00000, 0b00000, 0b00000, 0b00000], 100),
+        ([0b00000, 0b01000, 0b00000, 0b00000, 0b00000], 100),
+        ([0b00000, 0b00000, 0b00100, 0b00000, 0b00000], 100),
+        ([0b00000, 0b00000, 0b00000, 0b00010, 0b00000], 100),
+        ([0b00000, 0b00000, 0b00000, 0b00000, 0b00100], 100),
+        ([0b00000, 0b00000, 0b00000, 0b01000, 0b00000], 100),
+    ],
+    easing: Easing::EaseInOut,
+    repeat: Repeat::Forever,
+};
+
+enum Step {
+    /// Showing the figure-8 prompt until A is pressed
+    Intro,
+    /// Collecting samples, the display filling up as they cover the sphere
+    Sampling,
+}
+
+pub enum Outcome {
+    Continue,
+    /// B was pressed
+    Cancelled,
+    /// `None` if some axis was not covered
+    Finished(Option<(Calibration, Quality)>),
+}
+
+/// Guides a magnetometer calibration: a figure-8 prompt, then a progress bar
+/// filling up as the samples cover the sphere. A moves on to the next step,
+/// B cancels.
+pub struct Wizard {
+    step: Step,
+    /// Start of the current step
+    started: Instant,
+    calibrator: Calibrator,
+}
+
+impl Wizard {
+    pub fn new() -> Self {
+        Self {
+            step: Step::Intro,
+            started: Instant::now(),
+            calibrator: Calibrator::new(),
+        }
+    }
+
+    /// Feed in a raw magnetometer sample and the button event since the
+    /// last one, if any
+    pub fn update(&mut self, mag: [f32; 3], button: Option<Event>) -> Outcome {
+        let clicked = |which| {
+            button.is_some_and(|event| event.button == which && event.press == Press::Click)
+        };
+        if clicked(Button::B) {
+            return Outcome::Cancelled;
+        }
+        let next = clicked(Button::A);
+        match self.step {
+            Step::Intro => {
+                if next || self.started.elapsed() >= INTRO_TIME {
+                    info!("calibration: sampling");
+                    self.step = Step::Sampling;
+                    self.started = Instant::now();
+                }
+            }
+            Step::Sampling => {
+                self.calibrator.add_sample(mag);
+                if next
+                    || self.calibrator.coverage() >= COVERAGE_DONE
+                    || self.started.elapsed() >= SAMPLING_TIME
+                {
+                    return Outcome::Finished(self.calibrator.finish());
+                }
+            }
+        }
+        Outcome::Continue
+    }
+
+    /// Show the current step, the prompt moving over the progress bar while
+    /// sampling
+    pub async fn show(&self, matrix: &mut OnBoardLedMatrix) {
+        let elapsed_ms = self.started.elapsed().as_millis() as u32;
+        let prompt = PROMPT.frame_at(elapsed_ms).copied().unwrap_or_default();
+        let frame = match self.step {
+            Step::Intro => prompt,
+            Step::Sampling => {
+                let bar = progress_bar(self.calibrator.coverage() / COVERAGE_DONE);
+                core::array::from_fn(|row| bar[row] ^ prompt[row])
+            }
+        };
+        animation::show(matrix, &frame, FRAME_MS).await;
+    }
+}
+
+/// Light `fraction` of the matrix, filling rows from the bottom and each row
+/// from the left
+fn progress_bar(fraction: f32) -> Frame {
+    let mut lit = (fraction.clamp(0.0, 1.0) * 25.0) as u32;
+    let mut frame = [0; 5];
+    for row in frame.iter_mut().rev() {
+        let n = lit.min(5);
+        *row = (0b11111_u8 << (5 - n)) & 0b11111;
+        lit -= n;
+    }
+    frame
+}